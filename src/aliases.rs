@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+
+// Expansion stops after this many rounds so aliases that refer to each other
+// can't loop forever.
+const MAX_DEPTH: usize = 10;
+
+// Command-line abbreviations (`:cabbrev W w`) and user commands
+// (`:command Fmt %!rustfmt`). Both replace the command name typed at the
+// start of an Ex command; user commands must start with an uppercase letter
+// so they can't shadow built-in ones.
+pub struct Aliases {
+    abbreviations: BTreeMap<String, String>,
+    commands: BTreeMap<String, String>,
+}
+
+impl Default for Aliases {
+    fn default() -> Self {
+        Aliases::new()
+    }
+}
+
+impl Aliases {
+    pub fn new() -> Self {
+        Aliases {
+            abbreviations: BTreeMap::new(),
+            commands: BTreeMap::new(),
+        }
+    }
+
+    pub fn abbreviate(&mut self, lhs: &str, rhs: &str) {
+        self.abbreviations.insert(lhs.to_string(), rhs.to_string());
+    }
+
+    pub fn unabbreviate(&mut self, lhs: &str) -> bool {
+        self.abbreviations.remove(lhs).is_some()
+    }
+
+    pub fn define(&mut self, name: &str, replacement: &str) -> Result<(), String> {
+        if !name.starts_with(|c: char| c.is_ascii_uppercase()) {
+            return Err("User defined commands must start with an uppercase letter".to_string());
+        }
+        if !name.chars().all(|c| c.is_alphanumeric()) {
+            return Err(format!("Invalid command name: {}", name));
+        }
+        self.commands
+            .insert(name.to_string(), replacement.to_string());
+        Ok(())
+    }
+
+    pub fn undefine(&mut self, name: &str) -> bool {
+        self.commands.remove(name).is_some()
+    }
+
+    pub fn abbreviation_lines(&self) -> Vec<String> {
+        listing(&self.abbreviations)
+    }
+
+    pub fn command_lines(&self) -> Vec<String> {
+        listing(&self.commands)
+    }
+
+    // Replaces the command name at the start of `command`, keeping whatever
+    // follows it (`!`, arguments).
+    pub fn expand(&self, command: &str) -> String {
+        let mut command = command.to_string();
+        for _ in 0..MAX_DEPTH {
+            let end = command
+                .find(|c: char| !c.is_alphanumeric() && c != '_')
+                .unwrap_or(command.len());
+            let (name, rest) = command.split_at(end);
+            let replacement = self
+                .abbreviations
+                .get(name)
+                .or_else(|| self.commands.get(name));
+            match replacement {
+                Some(replacement) if !name.is_empty() && replacement != name => {
+                    command = format!("{}{}", replacement, rest);
+                }
+                _ => break,
+            }
+        }
+        command
+    }
+}
+
+pub fn listing(map: &BTreeMap<String, String>) -> Vec<String> {
+    let width = map.keys().map(|k| k.chars().count()).max().unwrap_or(0);
+    map.iter()
+        .map(|(name, value)| format!("{:<width$}  {}", name, value, width = width))
+        .collect()
+}
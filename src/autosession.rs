@@ -0,0 +1,122 @@
+use crate::{session, swap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+
+// Every running editor keeps a lock, `<pid>.lock`, and a session it saves
+// every `autosession` seconds, `<pid>.rvex`, in the sessions directory.
+// Quitting removes both, so a lock left behind by an editor that is gone
+// means it crashed, was killed or lost its terminal.
+fn lock_path(dir: &Path, pid: u32) -> PathBuf {
+    dir.join(format!("{}.lock", pid))
+}
+
+fn session_path(dir: &Path, pid: u32) -> PathBuf {
+    dir.join(format!("{}.rvex", pid))
+}
+
+// A session whose editor ended without quitting. Its commands bring back
+// the files, layout and cursor; what wasn't saved in the files is in their
+// swap files.
+pub struct Crashed {
+    pub pid: u32,
+    pub saved: Option<SystemTime>,
+    pub commands: Vec<String>,
+}
+
+impl Crashed {
+    // The files the session had open, relative ones resolved against the
+    // directory it was in.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut dir = PathBuf::new();
+        let mut files: Vec<PathBuf> = Vec::new();
+        for command in &self.commands {
+            if let Some(path) = command.strip_prefix("cd ") {
+                dir = PathBuf::from(path);
+            } else if let Some(file) = command.strip_prefix("edit ") {
+                let file = dir.join(file);
+                if !file.as_os_str().is_empty() && !files.contains(&file) {
+                    files.push(file);
+                }
+            }
+        }
+        files
+    }
+
+    // The file the session was editing: the last one it opens.
+    pub fn current(&self) -> Option<PathBuf> {
+        let mut dir = PathBuf::new();
+        let mut current = None;
+        for command in &self.commands {
+            if let Some(path) = command.strip_prefix("cd ") {
+                dir = PathBuf::from(path);
+            } else if let Some(file) = command.strip_prefix("edit ") {
+                current = Some(dir.join(file));
+            }
+        }
+        current
+    }
+}
+
+pub fn lock(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    fs::write(
+        lock_path(dir, process::id()),
+        format!("{}\n", process::id()),
+    )
+}
+
+pub fn save(dir: &Path, commands: &[String]) -> io::Result<()> {
+    let path = session_path(dir, process::id());
+    fs::create_dir_all(dir)?;
+    // Written aside and renamed, so a crash while saving leaves the last
+    // session whole.
+    let tmp = path.with_extension("rvex.tmp");
+    session::save(&tmp.to_string_lossy(), commands, true).map_err(io::Error::other)?;
+    fs::rename(tmp, path)
+}
+
+// Removes this editor's lock and session.
+pub fn unlock(dir: &Path) {
+    discard(dir, process::id());
+}
+
+pub fn discard(dir: &Path, pid: u32) {
+    let _ = fs::remove_file(lock_path(dir, pid));
+    let _ = fs::remove_file(session_path(dir, pid));
+}
+
+// The last saved session of the editors that crashed. Locks of those that
+// crashed before saving one have nothing to restore and are cleared.
+pub fn crashed(dir: &Path) -> Option<Crashed> {
+    let mut last: Option<Crashed> = None;
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        let name = entry.file_name();
+        let Some(pid) = name
+            .to_str()
+            .and_then(|n| n.strip_suffix(".lock"))
+            .and_then(|pid| pid.parse().ok())
+        else {
+            continue;
+        };
+        if swap::process_running(pid) {
+            continue;
+        }
+        let path = session_path(dir, pid);
+        let Ok(commands) = session::load(&path.to_string_lossy()) else {
+            discard(dir, pid);
+            continue;
+        };
+        let saved = fs::metadata(&path).and_then(|m| m.modified()).ok();
+        if last.as_ref().is_none_or(|l| saved > l.saved) {
+            last = Some(Crashed {
+                pid,
+                saved,
+                commands,
+            });
+        }
+    }
+    last
+}
@@ -0,0 +1,90 @@
+use crate::buffer::Buffer;
+use crate::indent::{self, Language};
+use crate::position::Position;
+
+const PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+// Finds the bracket matching the one at `pos`. With `search_line`, a cursor
+// that isn't on a bracket uses the next bracket on its line, like Vim's `%`.
+// Brackets inside strings and comments are skipped when the starting bracket
+// is code, using the same line-local rules as the indenter. At most
+// `max_lines` lines are scanned in either direction.
+// Returns the starting bracket and its match.
+pub fn matching(
+    lines: &Buffer,
+    pos: Position,
+    language: Language,
+    search_line: bool,
+    max_lines: usize,
+) -> Option<(Position, Position)> {
+    let row = pos.line();
+    let col = pos.char_col(&lines[row]);
+    let chars: Vec<char> = lines[row].chars().collect();
+    let mask = indent::code_mask(&lines[row], language, &mut false);
+    let is_bracket = |c: char| PAIRS.iter().any(|&(o, cl)| c == o || c == cl);
+    let start = if chars.get(col).is_some_and(|&c| is_bracket(c)) {
+        col
+    } else if search_line {
+        (col..chars.len()).find(|&i| mask[i] && is_bracket(chars[i]))?
+    } else {
+        return None;
+    };
+    let bracket = chars[start];
+    let skip = mask[start];
+    let (open, close, forward) = PAIRS.iter().find_map(|&(o, c)| {
+        if bracket == o {
+            Some((o, c, true))
+        } else if bracket == c {
+            Some((o, c, false))
+        } else {
+            None
+        }
+    })?;
+    let (target, nested) = if forward {
+        (close, open)
+    } else {
+        (open, close)
+    };
+
+    let mut depth = 0;
+    let mut in_comment = false;
+    let last_row = if forward {
+        row.saturating_add(max_lines).min(lines.len() - 1)
+    } else {
+        row.saturating_sub(max_lines)
+    };
+    let mut r = row;
+    loop {
+        let line_chars: Vec<char> = lines[r].chars().collect();
+        // Block comments can only be followed forwards; going backwards each
+        // line is judged on its own.
+        if !forward {
+            in_comment = false;
+        }
+        let line_mask = indent::code_mask(&lines[r], language, &mut in_comment);
+        let cols: Box<dyn Iterator<Item = usize>> = match (r == row, forward) {
+            (true, true) => Box::new(start + 1..line_chars.len()),
+            (true, false) => Box::new((0..start).rev()),
+            (false, true) => Box::new(0..line_chars.len()),
+            (false, false) => Box::new((0..line_chars.len()).rev()),
+        };
+        for c in cols {
+            if skip && !line_mask[c] {
+                continue;
+            }
+            if line_chars[c] == nested {
+                depth += 1;
+            } else if line_chars[c] == target {
+                if depth == 0 {
+                    let start = Position::from_char(&lines[row], row, start);
+                    return Some((start, Position::from_char(&lines[r], r, c)));
+                }
+                depth -= 1;
+            }
+        }
+        if r == last_row {
+            return None;
+        }
+        r = if forward { r + 1 } else { r - 1 };
+    }
+}
@@ -0,0 +1,361 @@
+use crate::encoding::{self, Decoded, Encoding, LineEnding};
+use memmap2::Mmap;
+use std::fs::File;
+use std::io;
+use std::ops::{Index, Range};
+
+// Files at least this big are memory-mapped instead of being read and split
+// up front.
+pub const LARGE_FILE: u64 = 32 * 1024 * 1024;
+
+// How many bytes of a mapped file are split into lines per main-loop tick.
+pub const INDEX_CHUNK: usize = 8 * 1024 * 1024;
+
+// A line either still lives in the mapped file, as a byte range without its
+// line ending, or has been copied out because it was edited or needed
+// decoding.
+enum Line {
+    Mapped { start: usize, len: u32 },
+    Owned(String),
+}
+
+// The lines of the file being edited. Small files are plain owned strings.
+// Large files start out memory-mapped: lines are split off lazily, a chunk at
+// a time, and only turned into owned strings when they are changed.
+pub struct Buffer {
+    lines: Vec<Line>,
+    map: Option<Mmap>,
+    indexed: usize,
+    encoding: Encoding,
+    invalid_lines: usize,
+    changes: u64,
+}
+
+impl Buffer {
+    pub fn from_lines(lines: Vec<String>) -> Self {
+        Buffer {
+            lines: lines.into_iter().map(Line::Owned).collect(),
+            map: None,
+            indexed: 0,
+            encoding: Encoding::Utf8,
+            invalid_lines: 0,
+            changes: 0,
+        }
+    }
+
+    // Maps `file` and indexes its first chunk. The encoding is guessed from
+    // that chunk: UTF-8 if it is valid, Latin-1 otherwise.
+    pub fn map(file: &File) -> io::Result<Self> {
+        // SAFETY: the map is only read, and through `&[u8]` slices that
+        // never outlive a call. What the OS can't promise is that nobody else
+        // changes the file meanwhile: its bytes then change under us, and
+        // pages cut off by truncating it fault with SIGBUS when touched. The
+        // editor writes a mapped file by replacing it instead of truncating
+        // it, and calls `detach` as soon as it sees the file change on disk,
+        // which leaves only the time until it looks unguarded.
+        let map = unsafe { Mmap::map(file)? };
+        let sample = &map[..map.len().min(INDEX_CHUNK)];
+        let encoding = match std::str::from_utf8(sample) {
+            Err(e) if e.error_len().is_some() => Encoding::Latin1,
+            _ => Encoding::Utf8,
+        };
+        let mut buffer = Buffer {
+            lines: Vec::new(),
+            map: Some(map),
+            indexed: 0,
+            encoding,
+            invalid_lines: 0,
+            changes: 0,
+        };
+        buffer.index_more(INDEX_CHUNK);
+        if buffer.lines.is_empty() {
+            buffer.lines.push(Line::Owned(String::new()));
+        }
+        Ok(buffer)
+    }
+
+    pub fn mapped_bytes(&self) -> Option<&[u8]> {
+        self.map.as_deref()
+    }
+
+    // Copies the lines still in the mapped file into memory and drops the
+    // map, for when the file changed on disk and is `file_len` bytes long
+    // now. Nothing past that is touched: those pages are gone, and so are
+    // the lines that were in them.
+    pub fn detach(&mut self, file_len: u64) {
+        let Some(len) = self.map.as_ref().map(|m| m.len()) else {
+            return;
+        };
+        let safe = len.min(usize::try_from(file_len).unwrap_or(usize::MAX));
+        while self.indexed < safe {
+            self.index_until(safe, INDEX_CHUNK);
+        }
+        let map = self.map.take().expect("checked above");
+        for line in &mut self.lines {
+            if let Line::Mapped { start, len } = *line {
+                let end = start + len as usize;
+                let bytes = if end <= safe {
+                    &map[start..end]
+                } else {
+                    &[][..]
+                };
+                *line = Line::Owned(String::from_utf8_lossy(bytes).into_owned());
+            }
+        }
+        self.indexed = 0;
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    // Lines that were not valid in the detected encoding and had to be
+    // decoded as Latin-1.
+    pub fn invalid_lines(&self) -> usize {
+        self.invalid_lines
+    }
+
+    // Counts edits, so that others can tell whether the text changed since
+    // they last looked.
+    pub fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    // Bytes held in memory for the lines. A mapped file's own pages are
+    // left to the OS and not counted.
+    pub fn memory(&self) -> usize {
+        let owned: usize = self
+            .lines
+            .iter()
+            .map(|line| match line {
+                Line::Owned(s) => s.capacity(),
+                Line::Mapped { .. } => 0,
+            })
+            .sum();
+        owned + self.lines.capacity() * std::mem::size_of::<Line>()
+    }
+
+    pub fn loading(&self) -> bool {
+        self.map.as_ref().is_some_and(|m| self.indexed < m.len())
+    }
+
+    // Fraction of the mapped file that has been split into lines, in percent.
+    pub fn progress(&self) -> usize {
+        match &self.map {
+            Some(m) if !m.is_empty() => self.indexed * 100 / m.len(),
+            _ => 100,
+        }
+    }
+
+    // Splits up to `budget` more bytes of the mapped file into lines, always
+    // stopping at a line boundary. Returns the number of lines added.
+    pub fn index_more(&mut self, budget: usize) -> usize {
+        let len = self.map.as_ref().map_or(0, |m| m.len());
+        self.index_until(len, budget)
+    }
+
+    // Indexes as if the file ended at byte `limit`.
+    fn index_until(&mut self, limit: usize, budget: usize) -> usize {
+        let Some(map) = &self.map else {
+            return 0;
+        };
+        let bytes = &map[..limit];
+        let before = self.lines.len();
+        let stop = (self.indexed + budget).min(bytes.len());
+        while self.indexed < stop {
+            let start = self.indexed;
+            let end = memchr::memchr(b'\n', &bytes[start..]).map_or(bytes.len(), |i| start + i);
+            let mut text = &bytes[start..end];
+            if let Some(stripped) = text.strip_suffix(b"\r") {
+                text = stripped;
+            }
+            let mapped = match self.encoding {
+                Encoding::Utf8 => std::str::from_utf8(text).is_ok(),
+                _ => text.is_ascii(),
+            };
+            let line = if mapped && text.len() <= u32::MAX as usize {
+                Line::Mapped {
+                    start,
+                    len: text.len() as u32,
+                }
+            } else {
+                if self.encoding == Encoding::Utf8 {
+                    self.invalid_lines += 1;
+                }
+                let encoding = match self.encoding {
+                    Encoding::Utf8 => Encoding::Latin1,
+                    other => other,
+                };
+                Line::Owned(encoding::decode_bytes(text, encoding))
+            };
+            self.lines.push(line);
+            self.indexed = end + 1;
+        }
+        self.indexed = self.indexed.min(bytes.len());
+        self.lines.len() - before
+    }
+
+    // Indexes whatever is left of a mapped file.
+    pub fn finish_loading(&mut self) {
+        while self.loading() {
+            self.index_more(INDEX_CHUNK);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    fn get<'a>(&'a self, line: &'a Line) -> &'a str {
+        match line {
+            Line::Owned(s) => s,
+            Line::Mapped { start, len } => {
+                let bytes = &self.map.as_ref().expect("mapped line without a map")
+                    [*start..*start + *len as usize];
+                // Lines were checked when they were indexed, so this only
+                // fails if the file changed since; until `detach` copies it
+                // out lossily, the part still valid is shown.
+                std::str::from_utf8(bytes).unwrap_or_else(|e| {
+                    std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default()
+                })
+            }
+        }
+    }
+
+    // The line as an owned string, with whatever is no longer valid in it
+    // replaced rather than dropped.
+    fn lossy(&self, line: &Line) -> String {
+        match line {
+            Line::Owned(s) => s.clone(),
+            Line::Mapped { start, len } => {
+                let map = self.map.as_ref().expect("mapped line without a map");
+                String::from_utf8_lossy(&map[*start..*start + *len as usize]).into_owned()
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &str> + ExactSizeIterator + '_ {
+        self.lines.iter().map(|l| self.get(l))
+    }
+
+    pub fn range(&self, range: Range<usize>) -> impl DoubleEndedIterator<Item = &str> + '_ {
+        self.lines[range].iter().map(|l| self.get(l))
+    }
+
+    pub fn join(&self, range: Range<usize>, separator: &str) -> String {
+        let mut text = String::new();
+        for (i, line) in self.range(range).enumerate() {
+            if i > 0 {
+                text.push_str(separator);
+            }
+            text.push_str(line);
+        }
+        text
+    }
+
+    pub fn line_mut(&mut self, row: usize) -> &mut String {
+        self.changes += 1;
+        if let Line::Mapped { .. } = self.lines[row] {
+            let owned = self.lossy(&self.lines[row]);
+            self.lines[row] = Line::Owned(owned);
+        }
+        match &mut self.lines[row] {
+            Line::Owned(s) => s,
+            Line::Mapped { .. } => unreachable!(),
+        }
+    }
+
+    pub fn set(&mut self, row: usize, text: String) {
+        self.changes += 1;
+        self.lines[row] = Line::Owned(text);
+    }
+
+    pub fn insert(&mut self, row: usize, text: String) {
+        self.changes += 1;
+        self.lines.insert(row, Line::Owned(text));
+    }
+
+    pub fn push(&mut self, text: String) {
+        self.changes += 1;
+        self.lines.push(Line::Owned(text));
+    }
+
+    pub fn remove(&mut self, row: usize) -> String {
+        self.changes += 1;
+        let line = self.lines.remove(row);
+        match line {
+            Line::Owned(s) => s,
+            mapped => self.lossy(&mapped),
+        }
+    }
+
+    pub fn splice(&mut self, range: Range<usize>, lines: Vec<String>) {
+        self.changes += 1;
+        self.lines.splice(range, lines.into_iter().map(Line::Owned));
+    }
+}
+
+impl Index<usize> for Buffer {
+    type Output = str;
+
+    fn index(&self, row: usize) -> &str {
+        self.get(&self.lines[row])
+    }
+}
+
+// Opens `path` as a mapped buffer if it is large enough to need one. Files
+// with a byte order mark are left to the normal path: UTF-16 can't be mapped
+// line by line, and a UTF-8 mark would end up in the first line.
+pub fn open_large(path: &str) -> Option<Decoded> {
+    let file = File::open(path).ok()?;
+    if file.metadata().ok()?.len() < LARGE_FILE {
+        return None;
+    }
+    let lines = Buffer::map(&file).ok()?;
+    let bytes = lines.mapped_bytes()?;
+    if bytes.starts_with(&[0xFF, 0xFE])
+        || bytes.starts_with(&[0xFE, 0xFF])
+        || bytes.starts_with(encoding::UTF8_BOM)
+    {
+        return None;
+    }
+    let line_ending = match memchr::memchr(b'\n', bytes) {
+        Some(i) if i > 0 && bytes[i - 1] == b'\r' => LineEnding::Dos,
+        _ => LineEnding::Unix,
+    };
+    let final_newline = bytes.last() == Some(&b'\n');
+    Some(Decoded {
+        encoding: lines.encoding(),
+        line_ending,
+        final_newline,
+        bom: false,
+        lines,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, OpenOptions};
+
+    #[test]
+    fn detaching_keeps_what_is_left_of_a_truncated_file() {
+        let path = std::env::temp_dir().join(format!("rvex-detach-{}.txt", std::process::id()));
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        let mut buffer = Buffer::map(&File::open(&path).unwrap()).unwrap();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(4)
+            .unwrap();
+        buffer.detach(4);
+        let _ = fs::remove_file(&path);
+        assert!(buffer.mapped_bytes().is_none());
+        assert_eq!(buffer.iter().collect::<Vec<_>>(), ["one", "", ""]);
+    }
+}
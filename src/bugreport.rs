@@ -0,0 +1,121 @@
+use crate::trash;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+pub const DEFAULT_FILE: &str = "rvex-bugreport.txt";
+
+// The environment variables that say which terminal this is.
+const TERMINAL_VARS: [&str; 5] = [
+    "TERM",
+    "COLORTERM",
+    "TERM_PROGRAM",
+    "TERM_PROGRAM_VERSION",
+    "TMUX",
+];
+
+// `:bugreport` writes what is useful in an issue to a plain text file and
+// nothing more: it is never sent anywhere, and the home directory is written
+// as `~` so the user name doesn't end up in it.
+pub struct Report {
+    sections: Vec<(String, Vec<String>)>,
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Report::new()
+    }
+}
+
+impl Report {
+    pub fn new() -> Self {
+        let mut system = vec![
+            format!("rvex {}", env!("CARGO_PKG_VERSION")),
+            format!("os: {} {}", env::consts::OS, env::consts::ARCH),
+            format!("written: {}", trash::now_iso8601()),
+        ];
+        for var in TERMINAL_VARS {
+            if let Some(value) = env::var_os(var) {
+                system.push(format!("{}={}", var, value.to_string_lossy()));
+            }
+        }
+        Report {
+            sections: vec![("System".to_string(), system)],
+        }
+    }
+
+    pub fn section(&mut self, title: &str, lines: Vec<String>) {
+        self.sections.push((title.to_string(), lines));
+    }
+
+    // Lines from the user's setup or commands, which can name any file on
+    // the machine. Absolute paths outside the home directory are left out.
+    pub fn private_section(&mut self, title: &str, lines: Vec<String>) {
+        let lines = lines
+            .iter()
+            .map(|line| redact_paths(&sanitize(line)))
+            .collect();
+        self.section(title, lines);
+    }
+
+    pub fn text(&self) -> String {
+        let mut text =
+            String::from("RVex bug report. Check it over before attaching it to an issue.\n");
+        for (title, lines) in &self.sections {
+            text.push_str(&format!("\n== {} ==\n", title));
+            if lines.is_empty() {
+                text.push_str("(none)\n");
+            }
+            for line in lines {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+        sanitize(&text)
+    }
+
+    pub fn write(&self, path: &str, force: bool) -> Result<(), String> {
+        if !force && Path::new(path).exists() {
+            return Err(format!("{} exists (add ! to override)", path));
+        }
+        fs::write(path, self.text()).map_err(|e| format!("Can't write {}: {}", path, e))
+    }
+}
+
+fn sanitize(text: &str) -> String {
+    match env::var("HOME") {
+        Ok(home) if home.len() > 1 => text.replace(home.trim_end_matches('/'), "~"),
+        _ => text.to_string(),
+    }
+}
+
+// Writes each absolute path in `line` as `<path>`. One starts with a `/` at
+// the start of a word or after `=`, `:`, a quote or a bracket, but not with
+// the `//` of a URL, and runs up to a blank, quote, bracket or `:`, so line
+// numbers after it stay.
+fn redact_paths(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    let mut prev = ' ';
+    while let Some(c) = chars.next() {
+        let starts = c == '/'
+            && (prev.is_whitespace() || "=:\"'([<".contains(prev))
+            && chars
+                .peek()
+                .is_some_and(|&n| n != '/' && !n.is_whitespace());
+        if starts {
+            out.push_str("<path>");
+            while chars
+                .peek()
+                .is_some_and(|&n| !n.is_whitespace() && !"\"':)]>,".contains(n))
+            {
+                chars.next();
+            }
+            prev = '>';
+        } else {
+            out.push(c);
+            prev = c;
+        }
+    }
+    out
+}
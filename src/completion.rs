@@ -0,0 +1,500 @@
+use crate::aliases;
+use crate::search_index::{self, SearchIndex};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+// The sources a file's completions come from when none are set for its
+// type, best first.
+const DEFAULT_SOURCES: [&str; 4] = ["lsp", "buffer", "path", "snippet"];
+
+// How many candidates one source adds at most.
+const MAX_CANDIDATES: usize = 1000;
+
+// What sources complete: the line up to the cursor, in the file at `path`.
+pub struct Request<'a> {
+    pub before: &'a str,
+    pub path: &'a str,
+    pub words: &'a SearchIndex,
+    // The word list the dictionary source reads.
+    pub dictionary: &'a str,
+}
+
+impl Request<'_> {
+    // The char column the run of `is_part` characters before the cursor
+    // starts at, and the run.
+    pub fn typed(&self, is_part: impl Fn(char) -> bool) -> (usize, &str) {
+        let start = self
+            .before
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| is_part(c))
+            .last()
+            .map_or(self.before.len(), |(i, _)| i);
+        (self.before[..start].chars().count(), &self.before[start..])
+    }
+}
+
+pub struct Candidate {
+    // What is matched against what is typed.
+    pub word: String,
+    // What goes in for it, which for a snippet is more than the word.
+    pub text: String,
+    // The char column it goes in at, replacing what was typed from there.
+    pub start: usize,
+    // The place of its source in the file's list; lower ranks go first.
+    pub rank: usize,
+}
+
+impl Candidate {
+    pub fn new(word: String, start: usize) -> Self {
+        Candidate {
+            text: word.clone(),
+            word,
+            start,
+            rank: 0,
+        }
+    }
+
+    fn label(&self) -> String {
+        if self.text == self.word {
+            self.word.clone()
+        } else {
+            format!("{}  {}", self.word, self.text)
+        }
+    }
+}
+
+// What a source needs the editor to ask for, when its words come from
+// elsewhere than the request. The answer joins the menu at the source's
+// place in the file's list.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Ask {
+    // The language server, which answers later.
+    Server,
+    // The function a plugin registered as the source of this name.
+    Plugin(String),
+}
+
+// A provider of completions. Each finds the word it completes before the
+// cursor itself, so a path and a word can be completed at one place.
+// Sources whose words come from elsewhere, like the language server, return
+// nothing from `complete` and say with `ask` where to get them.
+pub trait CompletionSource {
+    // How `:completesources` refers to it.
+    fn name(&self) -> &str;
+    fn complete(&mut self, request: &Request) -> Vec<Candidate>;
+
+    fn ask(&self) -> Option<Ask> {
+        None
+    }
+}
+
+// Words already in the buffer.
+pub struct BufferWords;
+
+impl CompletionSource for BufferWords {
+    fn name(&self) -> &str {
+        "buffer"
+    }
+
+    fn complete(&mut self, request: &Request) -> Vec<Candidate> {
+        let (start, prefix) = request.typed(search_index::is_word_char);
+        if prefix.is_empty() {
+            return Vec::new();
+        }
+        request
+            .words
+            .words_with_prefix(prefix)
+            .take(MAX_CANDIDATES)
+            .map(|word| Candidate::new(word.to_string(), start))
+            .collect()
+    }
+}
+
+// Files and directories, once what is typed has a `/` in it. Relative
+// paths are taken from the working directory.
+pub struct Paths;
+
+impl CompletionSource for Paths {
+    fn name(&self) -> &str {
+        "path"
+    }
+
+    fn complete(&mut self, request: &Request) -> Vec<Candidate> {
+        let (start, typed) = request.typed(|c| !c.is_whitespace() && !"\"'()<>[]{},;=".contains(c));
+        let Some(slash) = typed.rfind('/') else {
+            return Vec::new();
+        };
+        let (dir, name) = typed.split_at(slash + 1);
+        let Ok(entries) = fs::read_dir(if dir.starts_with('/') {
+            dir.to_string()
+        } else {
+            format!("./{}", dir)
+        }) else {
+            return Vec::new();
+        };
+        let mut paths: Vec<String> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let file = entry.file_name().to_string_lossy().into_owned();
+                let hidden = file.starts_with('.') && !name.starts_with('.');
+                if hidden || !file.starts_with(name) {
+                    return None;
+                }
+                let slash = if entry.path().is_dir() { "/" } else { "" };
+                Some(format!("{}{}{}", dir, file, slash))
+            })
+            .collect();
+        paths.sort();
+        paths.truncate(MAX_CANDIDATES);
+        paths
+            .into_iter()
+            .map(|path| Candidate::new(path, start))
+            .collect()
+    }
+}
+
+// The language server's suggestions. The editor asks the server when the
+// menu opens and adds what comes back.
+pub struct Lsp;
+
+impl CompletionSource for Lsp {
+    fn name(&self) -> &str {
+        "lsp"
+    }
+
+    fn complete(&mut self, _: &Request) -> Vec<Candidate> {
+        Vec::new()
+    }
+
+    fn ask(&self) -> Option<Ask> {
+        Some(Ask::Server)
+    }
+}
+
+// A source a plugin registered with `register_completion`. The editor calls
+// the plugin's function, lending it the buffer.
+pub struct Plugin {
+    name: String,
+}
+
+impl Plugin {
+    pub fn new(name: &str) -> Self {
+        Plugin {
+            name: name.to_string(),
+        }
+    }
+}
+
+impl CompletionSource for Plugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn complete(&mut self, _: &Request) -> Vec<Candidate> {
+        Vec::new()
+    }
+
+    fn ask(&self) -> Option<Ask> {
+        Some(Ask::Plugin(self.name.clone()))
+    }
+}
+
+// Words from a word list, one per line, read when first needed and again
+// when `dictionary` names another.
+pub struct Dictionary {
+    path: String,
+    words: Vec<String>,
+}
+
+impl CompletionSource for Dictionary {
+    fn name(&self) -> &str {
+        "dictionary"
+    }
+
+    fn complete(&mut self, request: &Request) -> Vec<Candidate> {
+        if self.path != request.dictionary {
+            self.path = request.dictionary.to_string();
+            let text = fs::read_to_string(&self.path).unwrap_or_default();
+            self.words = text
+                .lines()
+                .map(|w| w.trim().to_string())
+                .filter(|w| !w.is_empty())
+                .collect();
+            self.words.sort();
+            self.words.dedup();
+        }
+        let (start, prefix) = request.typed(char::is_alphanumeric);
+        if prefix.is_empty() {
+            return Vec::new();
+        }
+        let first = self.words.partition_point(|w| w.as_str() < prefix);
+        self.words[first..]
+            .iter()
+            .take_while(|w| w.starts_with(prefix))
+            .take(MAX_CANDIDATES)
+            .map(|w| Candidate::new(w.clone(), start))
+            .collect()
+    }
+}
+
+// Text put in for a short name, from `:snippet NAME TEXT`.
+pub struct Snippets {
+    snippets: BTreeMap<String, String>,
+}
+
+impl CompletionSource for Snippets {
+    fn name(&self) -> &str {
+        "snippet"
+    }
+
+    fn complete(&mut self, request: &Request) -> Vec<Candidate> {
+        let (start, prefix) = request.typed(search_index::is_word_char);
+        if prefix.is_empty() {
+            return Vec::new();
+        }
+        self.snippets
+            .range(prefix.to_string()..)
+            .take_while(|(name, _)| name.starts_with(prefix))
+            .map(|(name, text)| Candidate {
+                text: text.clone(),
+                ..Candidate::new(name.clone(), start)
+            })
+            .collect()
+    }
+}
+
+// The sources completions come from and which of them each type of file
+// uses, in order: `:completesources md buffer dictionary`. Sources besides
+// the built-in ones are registered by plugins.
+pub struct Sources {
+    sources: Vec<Box<dyn CompletionSource>>,
+    snippets: Snippets,
+    // By extension or file name; `default` for the rest.
+    filetypes: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for Sources {
+    fn default() -> Self {
+        Sources::new()
+    }
+}
+
+impl Sources {
+    pub fn new() -> Self {
+        Sources {
+            sources: vec![
+                Box::new(Lsp),
+                Box::new(BufferWords),
+                Box::new(Paths),
+                Box::new(Dictionary {
+                    path: String::new(),
+                    words: Vec::new(),
+                }),
+            ],
+            snippets: Snippets {
+                snippets: BTreeMap::new(),
+            },
+            filetypes: BTreeMap::new(),
+        }
+    }
+
+    // Replaces any source going by the same name.
+    pub fn register(&mut self, source: Box<dyn CompletionSource>) {
+        self.sources.retain(|s| s.name() != source.name());
+        self.sources.push(source);
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        name == self.snippets.name() || self.sources.iter().any(|s| s.name() == name)
+    }
+
+    pub fn filetypes(&self) -> impl Iterator<Item = (&str, &[String])> {
+        self.filetypes
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_slice()))
+    }
+
+    pub fn get(&mut self, name: &str) -> Option<&mut dyn CompletionSource> {
+        if name == self.snippets.name() {
+            return Some(&mut self.snippets);
+        }
+        self.sources
+            .iter_mut()
+            .find(|s| s.name() == name)
+            .map(|s| s.as_mut() as &mut dyn CompletionSource)
+    }
+
+    // The sources of the file at `path`: those set for its name win over
+    // those for its extension.
+    pub fn for_path(&self, path: &str) -> Vec<String> {
+        let path = Path::new(path);
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned());
+        let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+        match [name, ext]
+            .into_iter()
+            .flatten()
+            .find(|key| self.filetypes.contains_key(key))
+        {
+            Some(key) => self.get_filetype(&key),
+            None => self.get_filetype("default"),
+        }
+    }
+
+    pub fn get_filetype(&self, filetype: &str) -> Vec<String> {
+        self.filetypes
+            .get(filetype)
+            .or_else(|| self.filetypes.get("default"))
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_SOURCES.map(String::from).to_vec())
+    }
+
+    pub fn set(&mut self, filetype: &str, sources: Vec<String>) {
+        self.filetypes.insert(filetype.to_string(), sources);
+    }
+
+    pub fn remove(&mut self, filetype: &str) -> bool {
+        self.filetypes.remove(filetype).is_some()
+    }
+
+    pub fn define_snippet(&mut self, name: &str, text: &str) {
+        self.snippets
+            .snippets
+            .insert(name.to_string(), text.to_string());
+    }
+
+    pub fn remove_snippet(&mut self, name: &str) -> bool {
+        self.snippets.snippets.remove(name).is_some()
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let filetypes: BTreeMap<String, String> = self
+            .filetypes
+            .iter()
+            .map(|(k, v)| (k.clone(), v.join(" ")))
+            .collect();
+        aliases::listing(&filetypes)
+    }
+
+    pub fn snippet_lines(&self) -> Vec<String> {
+        aliases::listing(&self.snippets.snippets)
+    }
+
+    // The commands that set the sources and snippets again, for sessions.
+    pub fn commands(&self) -> Vec<String> {
+        let sources = self.filetypes.iter().map(|(filetype, sources)| {
+            format!("completesources {} {}", filetype, sources.join(" "))
+        });
+        let snippets = self
+            .snippets
+            .snippets
+            .iter()
+            .map(|(name, text)| format!("snippet {} {}", name, text));
+        sources.chain(snippets).collect()
+    }
+}
+
+// The insert-mode completion menu: candidates for what is typed before the
+// cursor, best first, narrowed down as more of it is typed. `start` is the
+// char column the menu is drawn at, where the longest of the typed words
+// starts.
+pub struct Completion {
+    pub row: usize,
+    pub start: usize,
+    candidates: Vec<Candidate>,
+    items: Vec<usize>,
+    pub labels: Vec<String>,
+    pub selected: usize,
+    // Sources that answer later and haven't yet, with their ranks.
+    pub waiting: Vec<(Ask, usize)>,
+}
+
+impl Completion {
+    pub fn new(row: usize, start: usize, candidates: Vec<Candidate>, before: &str) -> Self {
+        let mut completion = Completion {
+            row,
+            start,
+            candidates: Vec::new(),
+            items: Vec::new(),
+            labels: Vec::new(),
+            selected: 0,
+            waiting: Vec::new(),
+        };
+        completion.add(candidates, before);
+        completion
+    }
+
+    // Merges in candidates, as when a source answers late: each word is
+    // kept once, from the best source that has it, and better sources go
+    // first.
+    pub fn add(&mut self, candidates: Vec<Candidate>, before: &str) {
+        for candidate in candidates {
+            match self
+                .candidates
+                .iter_mut()
+                .find(|c| c.word == candidate.word)
+            {
+                Some(c) if c.rank > candidate.rank => *c = candidate,
+                Some(_) => {}
+                None => self.candidates.push(candidate),
+            }
+        }
+        self.candidates.sort_by_key(|c| c.rank);
+        self.filter(before);
+    }
+
+    // The rank of the source that was waiting on `ask`, which has answered.
+    pub fn answered(&mut self, ask: &Ask) -> Option<usize> {
+        let i = self.waiting.iter().position(|(a, _)| a == ask)?;
+        Some(self.waiting.remove(i).1)
+    }
+
+    pub fn filter(&mut self, before: &str) {
+        let selected = self.current_index();
+        let typed = |start: usize| {
+            before
+                .char_indices()
+                .nth(start)
+                .map_or("", |(i, _)| &before[i..])
+        };
+        self.items = (0..self.candidates.len())
+            .filter(|&i| {
+                let c = &self.candidates[i];
+                let typed = typed(c.start);
+                c.start <= before.chars().count()
+                    && c.word.starts_with(typed)
+                    && (c.word != typed || c.text != typed)
+            })
+            .collect();
+        self.labels = self
+            .items
+            .iter()
+            .map(|&i| self.candidates[i].label())
+            .collect();
+        self.selected = selected
+            .and_then(|s| self.items.iter().position(|&i| i == s))
+            .unwrap_or(0);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn select(&mut self, forward: bool) {
+        let n = self.items.len().max(1);
+        self.selected = if forward {
+            (self.selected + 1) % n
+        } else {
+            (self.selected + n - 1) % n
+        };
+    }
+
+    fn current_index(&self) -> Option<usize> {
+        self.items.get(self.selected).copied()
+    }
+
+    pub fn current(&self) -> Option<&Candidate> {
+        Some(&self.candidates[self.current_index()?])
+    }
+}
@@ -0,0 +1,21 @@
+use std::env;
+use std::path::PathBuf;
+
+fn config_dir() -> Option<PathBuf> {
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+    Some(config.join("rvex"))
+}
+
+// The user's startup file, read before anything else unless `--clean` or
+// `--config` say otherwise. Like a session it is a list of Ex commands.
+pub fn default_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("init.rvex"))
+}
+
+// Where the Rhai plugins loaded at startup live.
+pub fn plugins_dir() -> Option<PathBuf> {
+    Some(config_dir()?.join("plugins"))
+}
@@ -0,0 +1,64 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Where RVex keeps the files it writes for itself between runs, each kind
+// in a subdirectory: `$XDG_STATE_HOME/rvex` on Linux and the BSDs,
+// Application Support on macOS and the local AppData on Windows. `custom`
+// is the `statedir` option, empty for the platform's place.
+pub fn state_dir(custom: &str) -> PathBuf {
+    if !custom.is_empty() {
+        return PathBuf::from(custom);
+    }
+    let home = env::var_os("HOME")
+        .filter(|h| !h.is_empty())
+        .map(PathBuf::from);
+    let base = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home.map(|h| h.join("Library/Application Support"))
+    } else {
+        env::var_os("XDG_STATE_HOME")
+            .filter(|d| !d.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| home.map(|h| h.join(".local/state")))
+    };
+    base.unwrap_or_else(env::temp_dir).join("rvex")
+}
+
+// Where earlier versions kept swap files, under the data directory.
+pub fn legacy_state_dir() -> PathBuf {
+    env::var_os("XDG_DATA_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/share")))
+        .unwrap_or_else(env::temp_dir)
+        .join("rvex")
+}
+
+// Moves the files in `from` to `to` and removes `from` if that empties it.
+// A file already in `to` is newer and stays; its old copy is left behind.
+// Returns how many files were moved.
+pub fn migrate(from: &Path, to: &Path) -> io::Result<usize> {
+    if from == to || !from.is_dir() {
+        return Ok(0);
+    }
+    fs::create_dir_all(to)?;
+    let mut moved = 0;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if target.exists() || !entry.file_type()?.is_file() {
+            continue;
+        }
+        if fs::rename(entry.path(), &target).is_err() {
+            // Cross-device moves can't be renamed; fall back to copy + remove.
+            fs::copy(entry.path(), &target)?;
+            fs::remove_file(entry.path())?;
+        }
+        moved += 1;
+    }
+    let _ = fs::remove_dir(from);
+    Ok(moved)
+}
@@ -0,0 +1,5390 @@
+use crate::aliases::Aliases;
+use crate::autosession::{self, Crashed};
+use crate::buffer::Buffer;
+use crate::bugreport::{self, Report};
+use crate::completion::{self, Ask, Candidate, Completion};
+use crate::encoding::{Decoded, Encoding, LineEnding};
+use crate::events::{Task, Waker};
+use crate::finder::FileFinder;
+use crate::gutter::{Gutter, Marks, Sign};
+use crate::health::{self, Health, Tool};
+use crate::indent::Indenter;
+use crate::keymap::{self, Keymap, MapMode, Step};
+use crate::keys::{self, Key, KeyCode, KeyEvent, KeyModifiers};
+use crate::lint::Linters;
+use crate::macros::{self, Macros};
+use crate::merge::{Merge, Pick};
+use crate::operators::{self, Operators};
+use crate::options::{self, Options};
+use crate::output::OutputBuffer;
+use crate::pins::Pins;
+use crate::popup::{Menu, Popup};
+use crate::position::{self, Position};
+use crate::quickfix::{QuickfixEntry, QuickfixList};
+use crate::range::LineRange;
+use crate::render::{Cell, Color, GridRenderer, Renderer, Style};
+use crate::repl::{self, Repl};
+use crate::scripting::{self, Scripts};
+use crate::search_index::SearchIndex;
+use crate::snapshot::{self, Snapshots};
+use crate::substitute::{Confirm, Substitute};
+use crate::swap::SwapInfo;
+use crate::write_filters::WriteFilters;
+use crate::yanks::{self, YankKind, Yanks};
+use crate::{
+    brackets, buffer, dirs, encoding, expand, export, finder, git, indent, lint, lsp, merge,
+    output, paths, prose, range, render, search_index, session, substitute, swap, trash,
+    whitespace, width,
+};
+use std::{
+    collections::VecDeque,
+    fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime},
+};
+use unicode_segmentation::UnicodeSegmentation;
+
+// Exit statuses scripts and git's difftool/mergetool can rely on.
+pub const EXIT_CQUIT: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_WRITE_FAILED: i32 = 3;
+pub const EXIT_NOT_FOUND: i32 = 4;
+pub const EXIT_MISSING_FILE: i32 = 5;
+
+// How deep mappings may expand into other mappings before we give up.
+const MAX_MAP_DEPTH: usize = 100;
+
+// How many keys macros may play in a row before a macro that plays
+// itself is stopped.
+const MAX_MACRO_KEYS: usize = 100_000;
+
+// How many keys `keytrace` keeps, and how much of the output pane goes into
+// a bug report.
+const KEY_TRACE_LEN: usize = 200;
+const BUG_REPORT_LOG_LINES: usize = 50;
+
+// How many lines exports draw at once.
+const EXPORT_CHUNK: usize = 256;
+
+// Buffers bigger than this are not copied to the trash when their changes
+// are thrown away.
+const MAX_TRASH_BYTES: usize = 8 * 1024 * 1024;
+
+pub struct EditorState {
+    mode: Mode,
+    cursor: Position,
+    content: Buffer,
+    file_path: String,
+    status_message: Option<String>,
+    screen_size: (usize, usize),
+    should_exit: bool,
+    command_buffer: String,
+    modified: bool,
+    row_offset: usize,
+    output: OutputBuffer,
+    quickfix: QuickfixList,
+    finder: FileFinder,
+    indenter: Indenter,
+    pending: Option<char>,
+    // The file's modification time and size when it was last read or
+    // written.
+    disk_stamp: Option<(SystemTime, u64)>,
+    last_disk_check: Instant,
+    last_memory_check: Instant,
+    // The startup file that was read, if any, for `:ConfigShow`.
+    config: Option<String>,
+    // The last keys typed, with `keytrace` on.
+    key_trace: VecDeque<KeyEvent>,
+    disk_change_reported: bool,
+    options: Options,
+    prompt: Option<Prompt>,
+    last_edit: Option<Instant>,
+    last_autosave: Instant,
+    // When the session to restore after a crash was last saved, once this
+    // editor holds a lock.
+    last_autosession: Option<Instant>,
+    encoding: Encoding,
+    line_ending: LineEnding,
+    final_newline: bool,
+    // Whether the file has a byte order mark: `bomb`.
+    bom: bool,
+    index: SearchIndex,
+    last_search: Option<(String, bool)>,
+    search_forward: bool,
+    // Set by `:nohlsearch` until the next search.
+    highlight_cleared: bool,
+    suspend_requested: bool,
+    gutter: Gutter,
+    readonly: bool,
+    view_mode: bool,
+    pending_keys: VecDeque<KeyEvent>,
+    aliases: Aliases,
+    replaying: bool,
+    macros: Macros,
+    yanks: Yanks,
+    // The selected entry of the `:Yanks` picker while it is open.
+    yank_picker: Option<usize>,
+    // How many of the pending keys at the front come from a macro, whether
+    // the key being handled is one and how many have been played since the
+    // last one that wasn't.
+    macro_keys: usize,
+    playing: bool,
+    played: usize,
+    exit_code: i32,
+    merge: Option<Merge>,
+    write_filters: WriteFilters,
+    git_hunks: Vec<git::Hunk>,
+    alternate_file: Option<String>,
+    shell_request: Option<String>,
+    preview_origin: Option<(Position, usize)>,
+    keymap: Keymap,
+    recursive_mapping: bool,
+    linters: Linters,
+    lint_run: Option<lint::Run>,
+    lint_after: Option<Instant>,
+    // The command `:ProseChecker` set, `%` standing for the file.
+    prose_checker: Option<String>,
+    prose_run: Option<prose::Run>,
+    prose_after: Option<Instant>,
+    prose_issues: Vec<prose::Issue>,
+    // The code-action menu and its selected entry.
+    code_actions: Option<(Vec<prose::Fix>, usize)>,
+    snapshots: Snapshots,
+    // The pins of the last project they were needed for.
+    pins: Option<Pins>,
+    servers: lsp::Servers,
+    lsp: Option<lsp::Client>,
+    // Whether the language server for this file has been started yet, and
+    // the `Buffer::changes` count it was last sent.
+    lsp_started: bool,
+    lsp_synced: u64,
+    diagnostics: Vec<lsp::Diagnostic>,
+    completion: Option<Completion>,
+    completion_sources: completion::Sources,
+    // The visual selection while in visual mode, and the last one after.
+    visual: Option<Selection>,
+    // Extra cursors that edits are repeated at, besides `cursor`.
+    cursors: Vec<Position>,
+    waker: Waker,
+    // A git diff in the background, and whether to report its hunk count.
+    git_task: Option<(Task<Vec<git::Hunk>>, bool)>,
+    finder_task: Option<Task<Vec<String>>>,
+    // `printprg` making a PDF, and the file it writes.
+    print_task: Option<(Task<Result<(), String>>, String)>,
+    operators: Operators,
+    operator: Option<PendingOperator>,
+    repl: Option<Repl>,
+    scripts: Scripts,
+}
+
+// An operator waiting for its motion or text object. `object` is the `i`
+// or `a` of a text object that has been started.
+struct PendingOperator {
+    keys: String,
+    command: String,
+    object: Option<char>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum VisualKind {
+    Char,
+    Line,
+    Block,
+}
+
+// A visual selection from where it was started to the cursor.
+#[derive(Clone, Copy)]
+struct Selection {
+    kind: VisualKind,
+    anchor: Position,
+    cursor: Position,
+}
+
+impl Selection {
+    fn rows(&self) -> LineRange {
+        (
+            self.anchor.line().min(self.cursor.line()),
+            self.anchor.line().max(self.cursor.line()),
+        )
+    }
+
+    // The grapheme columns of a block, end exclusive.
+    fn columns(&self) -> Range<usize> {
+        self.anchor.grapheme().min(self.cursor.grapheme())
+            ..self.anchor.grapheme().max(self.cursor.grapheme()) + 1
+    }
+
+    // The grapheme columns selected on `row`, which is within `rows()`.
+    fn columns_on(&self, row: usize, line_len: usize) -> Range<usize> {
+        let (first, last) = self.rows();
+        let (start, end) = if self.anchor <= self.cursor {
+            (self.anchor, self.cursor)
+        } else {
+            (self.cursor, self.anchor)
+        };
+        match self.kind {
+            VisualKind::Line => 0..line_len + 1,
+            VisualKind::Block => self.columns(),
+            VisualKind::Char => {
+                let from = if row == first { start.grapheme() } else { 0 };
+                let to = if row == last {
+                    end.grapheme() + 1
+                } else {
+                    line_len + 1
+                };
+                from..to
+            }
+        }
+    }
+}
+
+// What one `:set` argument does: an option with its new value, or what to
+// show for a query.
+enum SetArg {
+    Show(String),
+    Readonly(bool),
+    Bomb(bool),
+    Encoding(Encoding),
+    LineEnding(LineEnding),
+    Gutter(Gutter, Option<String>),
+    Options(Box<Options>, Option<String>),
+}
+
+enum Prompt {
+    RecoverSwap(SwapInfo),
+    RestoreSession(Crashed, Vec<String>),
+    Substitute(Confirm),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Visual,
+    Command,
+    Finder,
+    Prompt,
+    Search,
+}
+
+impl EditorState {
+    pub fn new(file_path: String) -> Self {
+        let decoded = read_file(&file_path);
+        let content = decoded.lines;
+        let content_len = content.len();
+        let indenter = Indenter::new(&file_path, &content);
+        let disk_stamp = file_stamp(&file_path);
+        let readonly = read_only_on_disk(&file_path);
+        EditorState {
+            mode: Mode::Normal,
+            cursor: Position::default(),
+            content,
+            file_path,
+            status_message: None,
+            screen_size: (24, 80),
+            should_exit: false,
+            command_buffer: String::new(),
+            modified: false,
+            row_offset: 0,
+            output: OutputBuffer::new(),
+            quickfix: QuickfixList::new(),
+            finder: FileFinder::new(),
+            indenter,
+            pending: None,
+            disk_stamp,
+            last_disk_check: Instant::now(),
+            last_memory_check: Instant::now(),
+            config: None,
+            key_trace: VecDeque::new(),
+            disk_change_reported: false,
+            options: Options::new(),
+            prompt: None,
+            last_edit: None,
+            last_autosave: Instant::now(),
+            last_autosession: None,
+            encoding: decoded.encoding,
+            line_ending: decoded.line_ending,
+            final_newline: decoded.final_newline,
+            bom: decoded.bom,
+            index: SearchIndex::new(content_len),
+            last_search: None,
+            search_forward: true,
+            highlight_cleared: false,
+            suspend_requested: false,
+            gutter: Gutter::new(),
+            readonly,
+            view_mode: false,
+            pending_keys: VecDeque::new(),
+            aliases: Aliases::new(),
+            replaying: false,
+            macros: Macros::new(),
+            yanks: Yanks::new(),
+            yank_picker: None,
+            macro_keys: 0,
+            playing: false,
+            played: 0,
+            exit_code: 0,
+            merge: None,
+            write_filters: WriteFilters::new(),
+            git_hunks: Vec::new(),
+            alternate_file: None,
+            shell_request: None,
+            preview_origin: None,
+            keymap: Keymap::new(),
+            recursive_mapping: false,
+            linters: Linters::new(),
+            lint_run: None,
+            lint_after: None,
+            prose_checker: None,
+            prose_run: None,
+            prose_after: None,
+            prose_issues: Vec::new(),
+            code_actions: None,
+            snapshots: Snapshots::new(),
+            pins: None,
+            servers: lsp::Servers::new(),
+            lsp: None,
+            lsp_started: false,
+            lsp_synced: 0,
+            diagnostics: Vec::new(),
+            completion: None,
+            completion_sources: completion::Sources::new(),
+            visual: None,
+            cursors: Vec::new(),
+            waker: Waker::default(),
+            git_task: None,
+            finder_task: None,
+            print_task: None,
+            operators: Operators::new(),
+            operator: None,
+            repl: None,
+            scripts: Scripts::new(),
+        }
+    }
+
+    // Command-line setup: `-R` and `--merge`.
+    pub fn set_view_mode(&mut self) {
+        self.view_mode = true;
+        self.readonly = true;
+    }
+
+    pub fn start_merge(&mut self, merge: Merge) {
+        self.merge = Some(merge);
+    }
+
+    pub fn replay(&mut self, keys: Vec<Key>) {
+        let leader = self.options.leader();
+        self.pending_keys
+            .extend(keys.into_iter().map(|key| match key {
+                Key::Press(event) => event,
+                Key::Leader => leader,
+            }));
+    }
+
+    // Plays keys ahead of any still waiting, as if typed.
+    fn play(&mut self, keys: Vec<Key>) {
+        self.played += keys.len();
+        if self.played > MAX_MACRO_KEYS {
+            self.pending_keys.drain(..self.macro_keys);
+            self.macro_keys = 0;
+            self.status_message = Some(format!("Macro stopped after {} keys", MAX_MACRO_KEYS));
+            return;
+        }
+        let leader = self.options.leader();
+        self.macro_keys += keys.len();
+        for key in keys.into_iter().rev() {
+            self.pending_keys.push_front(match key {
+                Key::Press(event) => event,
+                Key::Leader => leader,
+            });
+        }
+    }
+
+    fn play_register(&mut self, register: char) {
+        let register = if register == '@' {
+            match self.macros.last {
+                Some(last) => last,
+                None => {
+                    self.status_message = Some("No previous macro".to_string());
+                    return;
+                }
+            }
+        } else {
+            register
+        };
+        match self.macros.register(register) {
+            Some(keys) => {
+                let keys = keys.to_vec();
+                self.macros.last = Some(register);
+                self.play(keys);
+            }
+            None => self.status_message = Some(format!("Nothing recorded in @{}", register)),
+        }
+    }
+
+    fn stop_recording(&mut self) {
+        if let Some(register) = self.macros.stop() {
+            self.status_message = Some(format!("Recorded @{}", register));
+        }
+    }
+
+    // `:trigger`: lists macros and triggers, runs a trigger or defines one.
+    fn trigger(&mut self, args: &str) {
+        match args.split_once(' ') {
+            _ if args.is_empty() => {
+                let lines = self.macros.lines();
+                if lines.is_empty() {
+                    self.status_message = Some("No macros or triggers".to_string());
+                } else {
+                    self.output.show("Macros", lines);
+                }
+            }
+            Some((name, keys)) => {
+                if let Err(e) = self.macros.define(name, keys.trim_start()) {
+                    self.status_message = Some(e);
+                }
+            }
+            None => match self.macros.trigger(args) {
+                Some(keys) => {
+                    let keys = keys.to_vec();
+                    self.play(keys);
+                }
+                None => self.status_message = Some(format!("No trigger {}", args)),
+            },
+        }
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        if !self.playing {
+            self.played = 0;
+            self.macros.record(key);
+        }
+        if self.options.keytrace {
+            if self.key_trace.len() == KEY_TRACE_LEN {
+                self.key_trace.pop_front();
+            }
+            self.key_trace.push_back(key);
+        }
+        self.recursive_mapping = false;
+        self.feed_key(key, 0);
+    }
+
+    // Handles the next replayed key, if any are left. Replayed keys don't
+    // wait for the rest of a mapping once they run out.
+    pub fn handle_pending_key(&mut self) -> bool {
+        let Some(key) = self.pending_keys.pop_front() else {
+            return false;
+        };
+        self.playing = self.macro_keys > 0;
+        self.macro_keys = self.macro_keys.saturating_sub(1);
+        self.replaying = true;
+        self.handle_key(key);
+        if self.pending_keys.is_empty() {
+            self.flush_keymap();
+        }
+        self.replaying = false;
+        self.playing = false;
+        true
+    }
+
+    // The finder and prompts take their keys as typed.
+    fn map_mode(&self) -> Option<MapMode> {
+        match self.mode {
+            // Like `:map` in Vim, normal-mode mappings apply to visual mode.
+            Mode::Normal | Mode::Visual => Some(MapMode::Normal),
+            Mode::Insert => Some(MapMode::Insert),
+            Mode::Command | Mode::Search => Some(MapMode::Command),
+            Mode::Finder | Mode::Prompt => None,
+        }
+    }
+
+    // Passes a key through the mappings of the current mode on its way to
+    // the mode handlers.
+    fn feed_key(&mut self, key: KeyEvent, depth: usize) {
+        match self.map_mode() {
+            Some(mode) => {
+                let step = self.keymap.feed(mode, key, self.options.leader());
+                self.take_step(step, depth);
+            }
+            None => handle_key(self, key),
+        }
+    }
+
+    fn flush_keymap(&mut self) {
+        if let Some(mode) = self.map_mode().filter(|_| self.keymap.is_pending()) {
+            let step = self.keymap.flush(mode, self.options.leader());
+            self.take_step(step, 0);
+        }
+    }
+
+    fn take_step(&mut self, step: Step, depth: usize) {
+        match step {
+            Step::Wait => {}
+            Step::Mapped {
+                keys,
+                remap_from,
+                rest,
+            } => {
+                if depth >= MAX_MAP_DEPTH && !self.recursive_mapping {
+                    self.recursive_mapping = true;
+                    self.status_message = Some("Recursive mapping".to_string());
+                }
+                if !self.recursive_mapping {
+                    for (i, key) in keys.into_iter().enumerate() {
+                        if i < remap_from {
+                            handle_key(self, key);
+                        } else {
+                            self.feed_key(key, depth + 1);
+                        }
+                    }
+                }
+                for key in rest {
+                    self.feed_key(key, depth);
+                }
+            }
+            Step::Unmapped { key, rest } => {
+                handle_key(self, key);
+                for key in rest {
+                    self.feed_key(key, depth);
+                }
+            }
+        }
+    }
+
+    pub fn resize(&mut self, rows: usize, cols: usize) {
+        self.screen_size = (rows, cols);
+    }
+
+    // Housekeeping before every frame.
+    pub fn update(&mut self) {
+        if self.keymap.timed_out() {
+            self.flush_keymap();
+        }
+        self.scroll();
+        self.check_disk_change();
+        self.persist_unsaved();
+        self.persist_session();
+        self.check_lint();
+        self.poll_prose();
+        self.sync_lsp();
+        self.check_memory();
+        self.check_tasks();
+        self.check_repl();
+    }
+
+    // Background work gets a slice of every tick. Returns true while there
+    // is some left.
+    pub fn background_work(&mut self) -> bool {
+        if self.content.loading() {
+            self.index_large_file();
+            true
+        } else {
+            !self.index.refresh(&self.content, 16)
+        }
+    }
+
+    // Whether a job such as a linter is still running in the background.
+    pub fn busy(&self) -> bool {
+        self.lint_run.is_some()
+            || self.prose_run.is_some()
+            || self.git_task.is_some()
+            || self.finder_task.is_some()
+            || self.print_task.is_some()
+    }
+
+    // Rough byte counts for what the editor holds in memory.
+    fn memory_usage(&self) -> Vec<(&'static str, usize)> {
+        let quickfix: usize = self
+            .quickfix
+            .entries
+            .iter()
+            .map(|e| std::mem::size_of::<QuickfixEntry>() + e.path.len() + e.message.len())
+            .sum();
+        let diagnostics: usize = self
+            .diagnostics
+            .iter()
+            .map(|d| std::mem::size_of::<lsp::Diagnostic>() + d.message.len())
+            .sum();
+        vec![
+            ("buffer", self.content.memory()),
+            ("search index", self.index.memory()),
+            ("output", self.output.memory()),
+            ("quickfix", quickfix),
+            ("diagnostics", diagnostics),
+            ("snapshots", self.snapshots.memory()),
+        ]
+    }
+
+    fn show_memory(&mut self) {
+        let usage = self.memory_usage();
+        let total: usize = usage.iter().map(|(_, n)| n).sum();
+        let mut lines: Vec<String> = usage
+            .iter()
+            .map(|(name, n)| format!("{:<14}{:>10}", name, human_size(*n)))
+            .collect();
+        lines.push(format!("{:<14}{:>10}", "total", human_size(total)));
+        if self.index.disabled() {
+            lines.push("search index dropped for maxmemory".to_string());
+        }
+        lines.push(match self.options.maxmemory {
+            0 => "maxmemory=0 (no limit)".to_string(),
+            mb => format!("maxmemory={} (MB)", mb),
+        });
+        self.output.show("Memory", lines);
+    }
+
+    // Past `maxmemory` the search index is the one thing that can go
+    // without losing anything; searches fall back to scanning lines.
+    fn check_memory(&mut self) {
+        if self.options.maxmemory == 0
+            || self.index.disabled()
+            || self.last_memory_check.elapsed().as_secs() < 2
+        {
+            return;
+        }
+        self.last_memory_check = Instant::now();
+        let total: usize = self.memory_usage().iter().map(|(_, n)| n).sum();
+        if total as u64 > self.options.maxmemory * 1024 * 1024 {
+            self.index.disable();
+            self.status_message = Some(format!(
+                "Memory use of {} is over maxmemory; dropped the search index (see :memory)",
+                human_size(total)
+            ));
+        }
+    }
+
+    fn text(&self) -> String {
+        self.content.join(0..self.content.len(), "\n") + "\n"
+    }
+
+    // Starts the language server for the file the first time round, keeps
+    // it up to date with the text and handles what it sends back.
+    fn sync_lsp(&mut self) {
+        if !self.lsp_started {
+            self.lsp_started = true;
+            self.start_lsp();
+        }
+        self.send_lsp_changes();
+        let Some(client) = &mut self.lsp else {
+            return;
+        };
+        for event in client.poll() {
+            // A jump to another file restarts the server.
+            if !self.lsp_started {
+                break;
+            }
+            self.lsp_event(event);
+        }
+    }
+
+    // Mapped files are too big to send whole on every change.
+    fn start_lsp(&mut self) {
+        self.lsp = None;
+        self.diagnostics.clear();
+        if self.content.mapped_bytes().is_some() {
+            return;
+        }
+        let Some(cmd) = self.servers.for_path(&self.file_path) else {
+            return;
+        };
+        match lsp::Client::start(cmd, &self.file_path, self.text(), &self.waker) {
+            Ok(client) => {
+                self.lsp = Some(client);
+                self.lsp_synced = self.content.changes();
+            }
+            Err(e) => self.status_message = Some(e),
+        }
+    }
+
+    fn send_lsp_changes(&mut self) {
+        if self.lsp.is_none() || self.content.changes() == self.lsp_synced {
+            return;
+        }
+        self.lsp_synced = self.content.changes();
+        let text = self.text();
+        if let Some(client) = &mut self.lsp {
+            client.change(text);
+        }
+    }
+
+    fn lsp_event(&mut self, event: lsp::Event) {
+        match event {
+            lsp::Event::Diagnostics(diagnostics) => self.diagnostics = diagnostics,
+            lsp::Event::Definition(Some(location)) => {
+                if !self.open_file(&location.path) {
+                    return;
+                }
+                let row = location.line.min(self.content.len() - 1);
+                self.cursor =
+                    self.at_char(row, lsp::char_col(&self.content[row], location.character));
+                self.adjust_column();
+            }
+            lsp::Event::Definition(None) => {
+                self.status_message = Some("No definition found".to_string());
+            }
+            lsp::Event::Hover(Some(text)) => {
+                let lines: Vec<String> = text.lines().map(|l| l.to_string()).collect();
+                if lines.len() == 1 {
+                    self.status_message = Some(text);
+                } else {
+                    self.output.show("Hover", lines);
+                }
+            }
+            lsp::Event::Hover(None) => {
+                self.status_message = Some("No information available".to_string());
+            }
+            lsp::Event::Completion(items) => {
+                let (start, _) = self.word_before_cursor();
+                let before = self.text_before_cursor();
+                if let Some(completion) = &mut self.completion {
+                    let Some(rank) = completion.answered(&Ask::Server) else {
+                        return;
+                    };
+                    let items = items
+                        .into_iter()
+                        .map(|item| Candidate {
+                            rank,
+                            ..Candidate::new(item, start)
+                        })
+                        .collect();
+                    completion.add(items, &before);
+                    if completion.is_empty() {
+                        self.completion = None;
+                        self.status_message = Some("No matches".to_string());
+                    }
+                }
+            }
+            lsp::Event::Error(e) => self.status_message = Some(e),
+            lsp::Event::Exited => {
+                if let Some(client) = self.lsp.take() {
+                    self.status_message =
+                        Some(format!("Language server '{}' exited", client.command));
+                }
+            }
+        }
+    }
+
+    // The cursor's column in chars, for the helpers that count them.
+    fn cursor_col(&self) -> usize {
+        self.cursor.char_col(&self.content[self.cursor.line()])
+    }
+
+    // Where char `col` of line `row` is.
+    fn at_char(&self, row: usize, col: usize) -> Position {
+        Position::from_char(&self.content[row], row, col)
+    }
+
+    // The start column, in chars, and text of the word that ends at the
+    // cursor.
+    fn word_before_cursor(&self) -> (usize, String) {
+        let line = &self.content[self.cursor.line()];
+        let before: Vec<char> = line.chars().take(self.cursor_col()).collect();
+        let start = before
+            .iter()
+            .rposition(|&c| !search_index::is_word_char(c))
+            .map_or(0, |i| i + 1);
+        (start, before[start..].iter().collect())
+    }
+
+    fn text_before_cursor(&self) -> String {
+        self.content[self.cursor.line()]
+            .chars()
+            .take(self.cursor_col())
+            .collect()
+    }
+
+    // What the file's completion sources offer at the cursor, ranked by
+    // where each source is in its list, and the sources still to answer.
+    fn completion_candidates(&mut self) -> (Vec<Candidate>, Vec<(Ask, usize)>) {
+        self.index.refresh(&self.content, usize::MAX);
+        let before = self.text_before_cursor();
+        let mut candidates = Vec::new();
+        let mut asks = Vec::new();
+        for (rank, name) in self
+            .completion_sources
+            .for_path(&self.file_path)
+            .iter()
+            .enumerate()
+        {
+            let Some(source) = self.completion_sources.get(name) else {
+                continue;
+            };
+            let found = source.complete(&completion::Request {
+                before: &before,
+                path: &self.file_path,
+                words: &self.index,
+                dictionary: &self.options.dictionary,
+            });
+            candidates.extend(found.into_iter().map(|c| Candidate { rank, ..c }));
+            asks.extend(source.ask().map(|ask| (ask, rank)));
+        }
+        let mut waiting = Vec::new();
+        for (ask, rank) in asks {
+            match ask {
+                Ask::Plugin(name) => {
+                    let (start, word) = self.word_before_cursor();
+                    let words = self.with_scripts(|scripts| scripts.complete(&name, &word));
+                    let found = words.unwrap_or_default().into_iter();
+                    candidates.extend(found.map(|w| Candidate {
+                        rank,
+                        ..Candidate::new(w, start)
+                    }));
+                }
+                Ask::Server => waiting.push((ask, rank)),
+            }
+        }
+        (candidates, waiting)
+    }
+
+    // Ctrl-N/Ctrl-P in insert mode: what the file's completion sources
+    // offer for the text before the cursor, joined by the language server's
+    // suggestions once they come in.
+    fn start_completion(&mut self, forward: bool) {
+        let (candidates, waiting) = self.completion_candidates();
+        let before = self.text_before_cursor();
+        let start = candidates
+            .iter()
+            .map(|c| c.start)
+            .min()
+            .unwrap_or_else(|| self.word_before_cursor().0);
+        let mut completion = Completion::new(self.cursor.line(), start, candidates, &before);
+        if !forward {
+            completion.select(false);
+        }
+        let row = self.cursor.line();
+        if self.lsp.is_some() && waiting.iter().any(|(ask, _)| *ask == Ask::Server) {
+            self.send_lsp_changes();
+            let character = lsp::utf16_col(&self.content[row], self.cursor_col());
+            if let Some(client) = &mut self.lsp {
+                client.completion(row, character);
+            }
+            completion.waiting = waiting;
+        } else if completion.is_empty() {
+            self.status_message = Some("No matches".to_string());
+            return;
+        }
+        self.completion = Some(completion);
+    }
+
+    // Typing narrows the menu down; leaving what was completed closes it.
+    fn filter_completion(&mut self) {
+        let before = self.text_before_cursor();
+        let (row, col) = (self.cursor.line(), self.cursor_col());
+        let Some(completion) = &mut self.completion else {
+            return;
+        };
+        if completion.row != row || col < completion.start {
+            self.completion = None;
+            return;
+        }
+        completion.filter(&before);
+        if completion.is_empty() {
+            self.completion = None;
+        }
+    }
+
+    fn accept_completion(&mut self) {
+        let Some(completion) = self.completion.take() else {
+            return;
+        };
+        let Some(item) = completion.current() else {
+            return;
+        };
+        let end = self.cursor_col();
+        let line = self.content.line_mut(completion.row);
+        let range = byte_index(line, item.start)..byte_index(line, end);
+        line.replace_range(range, &item.text);
+        self.cursor = self.at_char(completion.row, item.start + item.text.chars().count());
+        self.index.changed(completion.row);
+    }
+
+    // `gd` and `K`.
+    fn ask_lsp(&mut self, hover: bool) {
+        self.send_lsp_changes();
+        let row = self.cursor.line();
+        let character = lsp::utf16_col(&self.content[row], self.cursor_col());
+        match &mut self.lsp {
+            Some(client) if hover => client.hover(row, character),
+            Some(client) => client.definition(row, character),
+            None => self.status_message = Some("No language server".to_string()),
+        }
+    }
+
+    pub fn draw(&self, renderer: &mut dyn Renderer) {
+        draw_frame(self, renderer);
+    }
+
+    pub fn renderer_name(&self) -> &str {
+        &self.options.renderer
+    }
+
+    pub fn should_exit(&self) -> bool {
+        self.should_exit
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        self.exit_code
+    }
+
+    pub fn take_suspend_request(&mut self) -> bool {
+        std::mem::take(&mut self.suspend_requested)
+    }
+
+    pub fn take_shell_request(&mut self) -> Option<String> {
+        self.shell_request.take()
+    }
+
+    pub fn shell_finished(&mut self, cmd: &str, result: io::Result<Option<i32>>) {
+        match result {
+            Ok(Some(0)) => {}
+            Ok(Some(code)) => self.status_message = Some(format!("'{}' exited with {}", cmd, code)),
+            Ok(None) => self.status_message = Some(format!("'{}' was terminated", cmd)),
+            Err(e) => self.status_message = Some(format!("'{}' failed: {}", cmd, e)),
+        }
+    }
+
+    // Keeps unsaved edits in the swap file when we are killed.
+    pub fn save_swap(&self) {
+        if self.modified && self.options.swapfile {
+            let _ = swap::write(&self.swap_dir(), &self.file_path, &self.content);
+        }
+        if self.last_autosession.is_some() {
+            let _ = autosession::save(&self.sessions_dir(), &self.session_commands());
+        }
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &str> + '_ {
+        self.content.iter()
+    }
+
+    pub fn cursor(&self) -> Position {
+        self.cursor
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn status_message(&self) -> Option<&str> {
+        self.status_message.as_deref()
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    fn output_height(&self) -> usize {
+        if self.output.visible || self.split_preview() {
+            output::PANE_HEIGHT.min(self.screen_size.0.saturating_sub(1) / 2)
+        } else {
+            0
+        }
+    }
+
+    // In merge mode the LOCAL, BASE and REMOTE panes take the top third of
+    // the space above the output pane.
+    fn merge_height(&self) -> usize {
+        if self.merge.is_some() {
+            self.screen_size.0.saturating_sub(1 + self.output_height()) / 3
+        } else {
+            0
+        }
+    }
+
+    fn content_height(&self) -> usize {
+        self.screen_size
+            .0
+            .saturating_sub(1 + self.output_height() + self.merge_height())
+            .max(1)
+    }
+
+    fn scroll(&mut self) {
+        let height = self.content_height();
+        if self.cursor.line() < self.row_offset {
+            self.row_offset = self.cursor.line();
+        } else if self.cursor.line() >= self.row_offset + height {
+            self.row_offset = self.cursor.line() + 1 - height;
+        }
+        // Wrapped rows above the cursor can push it off the bottom too.
+        while self.options.wrap && self.row_offset < self.cursor.line() {
+            let above: usize = (self.row_offset..self.cursor.line())
+                .map(|r| self.line_breaks(r).len())
+                .sum();
+            if above + self.wrapped_position(self.cursor).0 < height {
+                break;
+            }
+            self.row_offset += 1;
+        }
+    }
+
+    fn text_width(&self) -> usize {
+        self.screen_size
+            .1
+            .saturating_sub(self.gutter.width(self.content.len()))
+    }
+
+    // The character columns at which a buffer row is broken into screen
+    // rows. Without `wrap` every row takes one.
+    fn line_breaks(&self, row: usize) -> Vec<usize> {
+        let cells = self.text_width();
+        if !self.options.wrap || cells == 0 {
+            return vec![0];
+        }
+        width::breaks(&self.content[row], cells)
+    }
+
+    // Which part of its wrapped row a position is drawn in, and the cell
+    // within that part. The end of a full part goes to the next screen row.
+    fn wrapped_position(&self, position: Position) -> (usize, usize) {
+        let line = &self.content[position.line()];
+        let col = position.char_col(line);
+        let breaks = self.line_breaks(position.line());
+        let part = breaks.iter().rposition(|&b| b <= col).unwrap_or(0);
+        let cell = position.cell(line) - width::column(line, breaks[part]);
+        if self.options.wrap && cell >= self.text_width() {
+            (part + 1, 0)
+        } else {
+            (part, cell)
+        }
+    }
+
+    // The visible buffer rows with their breaks and the screen row, counted
+    // from the top of the text, of their first part.
+    fn view(&self) -> Vec<(usize, Vec<usize>, usize)> {
+        let height = self.content_height();
+        let mut view = Vec::new();
+        let mut screen_row = 0;
+        for row in self.row_offset..self.content.len() {
+            if screen_row >= height {
+                break;
+            }
+            let breaks = self.line_breaks(row);
+            let parts = breaks.len();
+            view.push((row, breaks, screen_row));
+            screen_row += parts;
+        }
+        view
+    }
+
+    // Where a visible position is drawn: the screen row counted from the
+    // top of the text and the cell after the gutter.
+    fn screen_position(&self, position: Position) -> (usize, usize) {
+        let row = position.line();
+        let above: usize = (self.row_offset.min(row)..row)
+            .map(|r| self.line_breaks(r).len())
+            .sum();
+        let (part, cell) = self.wrapped_position(position);
+        (above + part, cell)
+    }
+
+    fn run_command(&mut self, cmd: &str) {
+        let (start, status) = self.output.run(cmd);
+        self.quickfix.set_from_output(&self.output.lines, start);
+        if !self.quickfix.entries.is_empty() {
+            self.jump_to_quickfix(0);
+            return;
+        }
+        self.status_message = Some(match status {
+            Some(code) => format!("'{}' exited with {}", cmd, code),
+            None => format!("'{}' failed", cmd),
+        });
+    }
+
+    // `:!cmd` needs the terminal, so the front end runs it and reports back
+    // through `shell_finished`.
+    fn run_shell(&mut self, cmd: &str) {
+        if cmd.is_empty() {
+            self.status_message = Some("No command given".to_string());
+            return;
+        }
+        self.shell_request = Some(cmd.to_string());
+    }
+
+    // `columns` narrows `:s` and `:sort` down to a block-wise selection.
+    fn run_ranged(
+        &mut self,
+        (start, end): LineRange,
+        command: &str,
+        columns: Option<Range<usize>>,
+    ) {
+        if let Some(cmd) = command.strip_prefix('!') {
+            self.filter_lines(start, end, cmd.trim());
+        } else if let Some(cmd) = write_command(command) {
+            self.write_to_command(start, end, cmd);
+        } else if let Some(args) = substitute::strip_name(command) {
+            self.substitute(start, end, args, columns);
+        } else if command == "ReplSend" {
+            self.send_to_repl(start, end);
+        } else if matches!(command, "sort" | "sor" | "sort!" | "sor!") {
+            self.sort_lines(start, end, command.ends_with('!'), columns);
+        } else if let Some(right) = shift_command(command) {
+            self.shift_lines(start, end, command.len(), right);
+        } else if command == "Reindent" {
+            self.reindent_lines(start, end);
+        } else if command == "StripWhitespace" {
+            if self.editable() {
+                let count = self.strip_whitespace(start, end);
+                self.status_message = Some(format!(
+                    "Stripped trailing whitespace from {} line(s)",
+                    count
+                ));
+            }
+        } else if command.is_empty() {
+            self.cursor = Position::clamped(&self.content, end, 0);
+        } else {
+            self.status_message = Some(format!("No range allowed: {}", command));
+        }
+    }
+
+    fn substitute(&mut self, start: usize, end: usize, args: &str, columns: Option<Range<usize>>) {
+        let mut substitute = match substitute::parse(args) {
+            Ok(substitute) => substitute,
+            Err(e) => {
+                self.status_message = Some(e);
+                return;
+            }
+        };
+        substitute.columns = columns;
+        // An empty pattern reuses the last search, and a new one becomes it.
+        if substitute.pattern.is_empty() {
+            match &self.last_search {
+                Some((pattern, _)) => substitute.pattern = pattern.clone(),
+                None => {
+                    self.status_message = Some("No previous search pattern".to_string());
+                    return;
+                }
+            }
+        } else {
+            self.last_search = Some((substitute.pattern.clone(), false));
+        }
+        self.highlight_cleared = false;
+        if !self.editable() {
+            return;
+        }
+        let mut confirm = Confirm {
+            substitute,
+            at: (start, 0),
+            last: end,
+            count: 0,
+            lines: 0,
+            last_changed: None,
+        };
+        if self.next_substitution(&confirm).is_none() {
+            if self.replaying {
+                self.exit_code = EXIT_NOT_FOUND;
+            }
+            self.status_message =
+                Some(format!("Pattern not found: {}", confirm.substitute.pattern));
+            return;
+        }
+        if confirm.substitute.confirm {
+            self.ask_substitute(confirm);
+            return;
+        }
+        for row in start..=end {
+            let (line, count) = confirm.substitute.apply(&self.content[row]);
+            if count > 0 {
+                self.content.set(row, line);
+                self.index.changed(row);
+                confirm.count += count;
+                confirm.lines += 1;
+                self.cursor = Position::clamped(&self.content, row, 0);
+            }
+        }
+        self.finish_substitute(&confirm);
+    }
+
+    fn next_substitution(&self, confirm: &Confirm) -> Option<(usize, usize)> {
+        let (mut row, mut from) = confirm.at;
+        while row <= confirm.last {
+            if let Some(byte) = confirm.substitute.find(&self.content[row], from) {
+                return Some((row, byte));
+            }
+            row += 1;
+            from = 0;
+        }
+        None
+    }
+
+    // Replaces the match at `confirm.at` and moves past it; without `g` that
+    // means on to the next line.
+    fn substitute_at(&mut self, confirm: &mut Confirm) {
+        let (row, byte) = confirm.at;
+        let substitute = &confirm.substitute;
+        let line = self.content.line_mut(row);
+        line.replace_range(
+            byte..byte + substitute.pattern.len(),
+            &substitute.replacement,
+        );
+        self.index.changed(row);
+        self.modified = true;
+        confirm.count += 1;
+        if confirm.last_changed != Some(row) {
+            confirm.lines += 1;
+            confirm.last_changed = Some(row);
+        }
+        confirm.at = if substitute.global {
+            (row, byte + substitute.replacement.len())
+        } else {
+            (row + 1, 0)
+        };
+    }
+
+    // Puts the cursor on the next match and asks what to do with it, or
+    // reports the result once there are none left.
+    fn ask_substitute(&mut self, mut confirm: Confirm) {
+        match self.next_substitution(&confirm) {
+            Some((row, byte)) => {
+                confirm.at = (row, byte);
+                self.cursor = Position::from_byte(&self.content[row], row, byte);
+                self.prompt = Some(Prompt::Substitute(confirm));
+                self.mode = Mode::Prompt;
+            }
+            None => self.finish_substitute(&confirm),
+        }
+    }
+
+    fn answer_substitute(&mut self, mut confirm: Confirm, key: KeyCode) {
+        self.mode = Mode::Normal;
+        match key {
+            KeyCode::Char('y') => {
+                self.substitute_at(&mut confirm);
+                self.ask_substitute(confirm);
+            }
+            KeyCode::Char('n') => {
+                let (row, byte) = confirm.at;
+                confirm.at = if confirm.substitute.global {
+                    (row, byte + confirm.substitute.pattern.len())
+                } else {
+                    (row + 1, 0)
+                };
+                self.ask_substitute(confirm);
+            }
+            KeyCode::Char('a') => {
+                self.substitute_at(&mut confirm);
+                while let Some(at) = self.next_substitution(&confirm) {
+                    confirm.at = at;
+                    self.substitute_at(&mut confirm);
+                }
+                self.finish_substitute(&confirm);
+            }
+            KeyCode::Char('l') => {
+                self.substitute_at(&mut confirm);
+                self.finish_substitute(&confirm);
+            }
+            KeyCode::Char('q') | KeyCode::Esc => self.finish_substitute(&confirm),
+            _ => {
+                self.prompt = Some(Prompt::Substitute(confirm));
+                self.mode = Mode::Prompt;
+            }
+        }
+    }
+
+    fn finish_substitute(&mut self, confirm: &Confirm) {
+        if confirm.count > 0 {
+            self.modified = true;
+        }
+        self.adjust_column();
+        self.status_message = Some(format!(
+            "{} substitution(s) on {} line(s)",
+            confirm.count, confirm.lines
+        ));
+    }
+
+    // `:w !cmd`: feeds lines `start..=end` to `cmd` without saving anything.
+    fn write_to_command(&mut self, start: usize, end: usize, cmd: &str) {
+        let mut input = self.content.join(start..end + 1, "\n");
+        input.push('\n');
+        match output::filter(cmd, input.into_bytes()) {
+            Ok(text) if text.is_empty() => {
+                self.status_message =
+                    Some(format!("{} lines written to '{}'", end - start + 1, cmd));
+            }
+            Ok(text) => {
+                let title = format!("w !{}", cmd);
+                self.output
+                    .show(&title, text.lines().map(|l| l.to_string()).collect());
+            }
+            Err(e) => self.status_message = Some(e),
+        }
+    }
+
+    // Replaces lines `start..=end` with the output of `cmd` run on them.
+    // Sorts lines by their text, or by the text in `columns` of a block.
+    fn sort_lines(
+        &mut self,
+        start: usize,
+        end: usize,
+        reverse: bool,
+        columns: Option<Range<usize>>,
+    ) {
+        if !self.editable() {
+            return;
+        }
+        let key = |line: &str| -> String {
+            match &columns {
+                Some(columns) => line
+                    .graphemes(true)
+                    .skip(columns.start)
+                    .take(columns.len())
+                    .collect(),
+                None => line.to_string(),
+            }
+        };
+        let mut lines: Vec<String> = self
+            .content
+            .range(start..end + 1)
+            .map(|l| l.to_string())
+            .collect();
+        lines.sort_by_cached_key(|line| key(line));
+        if reverse {
+            lines.reverse();
+        }
+        self.replace_lines(start..end + 1, lines);
+        self.status_message = Some(format!("{} lines sorted", end - start + 1));
+    }
+
+    // `:>` and `:<`, each `>` or `<` one shiftwidth. Blank lines stay as they
+    // are.
+    fn shift_lines(&mut self, start: usize, end: usize, count: usize, right: bool) {
+        if !self.editable() {
+            return;
+        }
+        let step = match self.options.shiftwidth {
+            0 => self.indenter.unit_width(),
+            sw => sw as usize,
+        } * count;
+        for row in start..=end {
+            let line = &self.content[row];
+            if line.trim().is_empty() {
+                continue;
+            }
+            let ws = indent::leading_ws(line);
+            let width = self.indenter.width(ws);
+            let width = if right {
+                width + step
+            } else {
+                width.saturating_sub(step)
+            };
+            let new_line = format!("{}{}", self.indenter.render(width), &line[ws.len()..]);
+            if new_line != *line {
+                self.content.set(row, new_line);
+                self.index.changed(row);
+                self.modified = true;
+            }
+        }
+        self.move_to_indent(start);
+        if end > start {
+            let (op, times) = (
+                if right { '>' } else { '<' },
+                if count == 1 { "time" } else { "times" },
+            );
+            self.status_message = Some(format!(
+                "{} lines {}ed {} {}",
+                end - start + 1,
+                op,
+                count,
+                times
+            ));
+        }
+    }
+
+    fn reindent_lines(&mut self, start: usize, end: usize) {
+        if !self.editable() {
+            return;
+        }
+        for row in start..=end {
+            self.reindent_line(row);
+        }
+        self.move_to_indent(start);
+        if end > start {
+            self.status_message = Some(format!("{} lines indented", end - start + 1));
+        }
+    }
+
+    fn move_to_indent(&mut self, row: usize) {
+        let col = position::graphemes(indent::leading_ws(&self.content[row]));
+        self.cursor = Position::clamped(&self.content, row, col);
+    }
+
+    // Returns how many lines had trailing whitespace.
+    fn strip_whitespace(&mut self, start: usize, end: usize) -> usize {
+        let mut count = 0;
+        for row in start..=end {
+            if let Some(line) = whitespace::strip(&self.content[row]) {
+                self.content.set(row, line);
+                self.index.changed(row);
+                count += 1;
+            }
+        }
+        if count > 0 {
+            self.modified = true;
+            self.adjust_column();
+        }
+        count
+    }
+
+    fn filter_lines(&mut self, start: usize, end: usize, cmd: &str) {
+        if !self.editable() {
+            return;
+        }
+        let mut input = self.content.join(start..end + 1, "\n");
+        input.push('\n');
+        let lines: Vec<String> = match output::filter(cmd, input.into_bytes()) {
+            Ok(text) => text.lines().map(|l| l.to_string()).collect(),
+            Err(e) => {
+                self.status_message = Some(e);
+                return;
+            }
+        };
+        self.replace_lines(start..end + 1, lines);
+        self.status_message = Some(format!("{} lines filtered", end - start + 1));
+    }
+
+    fn replace_lines(&mut self, range: Range<usize>, lines: Vec<String>) {
+        let (start, removed, count) = (range.start, range.len(), lines.len());
+        self.content.splice(range, lines);
+        self.index.removed(start, removed);
+        if count > 0 {
+            self.index.inserted(start, count);
+        }
+        if self.content.is_empty() {
+            self.content.push(String::new());
+            self.index.reset(1);
+        }
+        self.modified = true;
+        self.cursor = Position::clamped(&self.content, start, 0);
+    }
+
+    // `:diffget`: replaces the conflict under or after the cursor with one
+    // side of it.
+    fn pick_conflict(&mut self, args: &str) {
+        let Some(pick) = Pick::parse(args) else {
+            self.status_message = Some("Usage: :diffget local|base|remote|both".to_string());
+            return;
+        };
+        if !self.editable() {
+            return;
+        }
+        let conflicts = merge::conflicts(&self.content);
+        let Some(conflict) = merge::current(&conflicts, self.cursor.line()).map(|i| &conflicts[i])
+        else {
+            self.status_message = Some("No conflicts left".to_string());
+            return;
+        };
+        match conflict.resolve(&self.content, pick) {
+            Ok(lines) => {
+                self.replace_lines(conflict.lines(), lines);
+                let left = conflicts.len() - 1;
+                self.status_message = Some(format!("{} conflict(s) left", left));
+            }
+            Err(e) => self.status_message = Some(e),
+        }
+    }
+
+    // `:snapshot`: saves the buffer's lines under a name, compares the
+    // buffer with them or puts them back. Without arguments lists the
+    // snapshots of this file.
+    fn snapshot(&mut self, args: &str) {
+        let file = paths::key(&self.file_path, paths::Style::current());
+        let (action, name) = match args.split_once(' ') {
+            Some((action, name)) => (action, name.trim()),
+            None => (args, ""),
+        };
+        if action.is_empty() || action == "list" {
+            let lines = self.snapshots.lines(&file);
+            if lines.is_empty() {
+                self.status_message = Some("No snapshots".to_string());
+            } else {
+                self.output.show("Snapshots", lines);
+            }
+            return;
+        }
+        if name.is_empty() {
+            self.status_message =
+                Some("Usage: :snapshot save|diff|restore|delete NAME".to_string());
+            return;
+        }
+        if action == "save" {
+            let lines = self
+                .content
+                .iter()
+                .map(|l| l.to_string())
+                .collect::<Vec<_>>();
+            let count = lines.len();
+            self.snapshots.save(&file, name, lines);
+            self.status_message = Some(format!("Snapshot {} saved ({} lines)", name, count));
+            return;
+        }
+        if action == "delete" {
+            if !self.snapshots.remove(&file, name) {
+                self.status_message = Some(format!("No snapshot {}", name));
+            }
+            return;
+        }
+        let Some(lines) = self.snapshots.get(&file, name) else {
+            self.status_message = Some(format!("No snapshot {}", name));
+            return;
+        };
+        match action {
+            "diff" => {
+                let current = self
+                    .content
+                    .iter()
+                    .map(|l| l.to_string())
+                    .collect::<Vec<_>>();
+                let diff = snapshot::diff(name, lines, &current);
+                if diff.is_empty() {
+                    self.status_message = Some(format!("No changes since snapshot {}", name));
+                } else {
+                    self.output
+                        .show(&format!("Changes since snapshot {}", name), diff);
+                }
+            }
+            "restore" => {
+                let lines = lines.to_vec();
+                if !self.editable() {
+                    return;
+                }
+                let cursor = self.cursor;
+                let count = lines.len();
+                self.replace_lines(0..self.content.len(), lines);
+                self.cursor = Position::clamped(&self.content, cursor.line(), cursor.grapheme());
+                self.adjust_column();
+                self.status_message = Some(format!("Restored snapshot {} ({} lines)", name, count));
+            }
+            _ => self.status_message = Some(format!("Unknown snapshot action: {}", action)),
+        }
+    }
+
+    fn jump_to_conflict(&mut self, forward: bool) {
+        let conflicts = merge::conflicts(&self.content);
+        let row = self.cursor.line();
+        let target = if forward {
+            conflicts.iter().find(|c| c.start > row)
+        } else {
+            conflicts.iter().rev().find(|c| c.start < row)
+        };
+        match target {
+            Some(conflict) => self.cursor = Position::clamped(&self.content, conflict.start, 0),
+            None => self.status_message = Some("No more conflicts".to_string()),
+        }
+    }
+
+    fn read_command(&mut self, cmd: &str) {
+        if !self.editable() {
+            return;
+        }
+        match output::filter(cmd, Vec::new()) {
+            Ok(text) => self.insert_below(text.lines().map(|l| l.to_string()).collect()),
+            Err(e) => self.status_message = Some(e),
+        }
+    }
+
+    fn read_file_below(&mut self, path: &str) {
+        match fs::read(path) {
+            Ok(bytes) => {
+                let decoded = encoding::decode(&bytes);
+                self.insert_below(decoded.lines.iter().map(|l| l.to_string()).collect())
+            }
+            Err(e) => self.status_message = Some(format!("Can't open file {}: {}", path, e)),
+        }
+    }
+
+    fn insert_below(&mut self, lines: Vec<String>) {
+        if lines.is_empty() || !self.editable() {
+            return;
+        }
+        let row = self.cursor.line() + 1;
+        let count = lines.len();
+        self.content.splice(row..row, lines);
+        self.index.inserted(row, count);
+        self.modified = true;
+        self.cursor = Position::clamped(&self.content, row, 0);
+    }
+
+    // Quickfix entries in the current file show up in the sign column, and
+    // changes since HEAD in the git column.
+    fn gutter_marks(&self) -> Marks {
+        let mut marks = Marks::default();
+        for hunk in &self.git_hunks {
+            let (text, color) = match hunk.change {
+                git::Change::Added => ('+', render::GREEN),
+                git::Change::Modified => ('~', render::YELLOW),
+                git::Change::Deleted if hunk.start == 0 => ('‾', render::RED),
+                git::Change::Deleted => ('_', render::RED),
+            };
+            for row in hunk.row()..hunk.row() + hunk.len.max(1) {
+                marks.git.insert(row, Sign { text, color });
+            }
+        }
+        // The worst diagnostic on a line gets its sign.
+        for diagnostic in &self.diagnostics {
+            let kind = diagnostic.kind;
+            let shown = marks.signs.get(&diagnostic.start.line).map(|s| s.text);
+            if shown.is_none() || kind == 'E' || (kind == 'W' && shown == Some('I')) {
+                let sign = Sign {
+                    text: kind,
+                    color: kind_color(kind),
+                };
+                marks.signs.insert(diagnostic.start.line, sign);
+            }
+        }
+        for (i, entry) in self.quickfix.entries.iter().enumerate() {
+            if entry.line > 0 && paths::same_file(&entry.path, &self.file_path) {
+                let current = i == self.quickfix.current;
+                marks.signs.insert(
+                    entry.line - 1,
+                    Sign {
+                        text: if current { '>' } else { entry.kind },
+                        color: kind_color(entry.kind),
+                    },
+                );
+            }
+        }
+        marks
+    }
+
+    fn rerun_command(&mut self) {
+        match self.output.last_command().map(|c| c.to_string()) {
+            Some(cmd) => self.run_command(&cmd),
+            None => self.status_message = Some("No previous command".to_string()),
+        }
+    }
+
+    fn jump_to_quickfix(&mut self, index: usize) {
+        let Some(entry) = self.quickfix.entries.get(index) else {
+            self.status_message = Some("No more items".to_string());
+            return;
+        };
+        let (path, line, col, message) = (
+            entry.path.clone(),
+            entry.line,
+            entry.col,
+            entry.message.clone(),
+        );
+        if !self.open_file(&path) {
+            return;
+        }
+        self.quickfix.current = index;
+        self.output.scroll = self.quickfix.entries[index].output_line.saturating_sub(1);
+        let row = line.saturating_sub(1).min(self.content.len() - 1);
+        self.cursor = self.at_char(row, col.saturating_sub(1));
+        self.adjust_column();
+        self.status_message = Some(format!(
+            "({} of {}) {}",
+            index + 1,
+            self.quickfix.entries.len(),
+            message
+        ));
+    }
+
+    fn open_file(&mut self, path: &str) -> bool {
+        if paths::same_file(path, &self.file_path) {
+            return true;
+        }
+        if self.modified {
+            self.status_message = Some("No write since last change".to_string());
+            return false;
+        }
+        if let Some(name) = paths::reserved_name(path, paths::Style::current()) {
+            self.status_message = Some(format!("Can't edit {}: reserved device name", name));
+            return false;
+        }
+        self.remember_pin();
+        // A swap file some other editor left stays, to be offered again.
+        if swap::read(&self.swap_dir(), &self.file_path)
+            .is_some_and(|info| info.pid == std::process::id())
+        {
+            swap::remove(&self.swap_dir(), &self.file_path);
+        }
+        let previous = std::mem::replace(&mut self.file_path, path.to_string());
+        self.alternate_file = Some(previous).filter(|p| !p.is_empty());
+        self.load_from_disk();
+        self.cursor = Position::default();
+        self.row_offset = 0;
+        self.check_swap();
+        self.check_prose(false);
+        self.run_hook("on_open");
+        true
+    }
+
+    fn swap_dir(&self) -> PathBuf {
+        dirs::state_dir(&self.options.statedir).join("swap")
+    }
+
+    fn pins_dir(&self) -> PathBuf {
+        dirs::state_dir(&self.options.statedir).join("pins")
+    }
+
+    fn sessions_dir(&self) -> PathBuf {
+        dirs::state_dir(&self.options.statedir).join("sessions")
+    }
+
+    // The current file with its full path, or the working directory when
+    // there is none.
+    fn absolute_path(&self) -> PathBuf {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        fs::canonicalize(&self.file_path).unwrap_or_else(|_| cwd.join(&self.file_path))
+    }
+
+    // The pins of the current file's project, loaded when first needed and
+    // again after moving to another project.
+    fn pins(&mut self) -> &mut Pins {
+        let file = self.absolute_path();
+        let root = if self.file_path.is_empty() {
+            file
+        } else {
+            lsp::root(&file)
+        };
+        if self.pins.as_ref().is_none_or(|pins| pins.root != root) {
+            self.pins = Some(Pins::load(&self.pins_dir(), &root));
+        }
+        self.pins.as_mut().unwrap()
+    }
+
+    fn save_pins(&mut self) {
+        if let Some(Err(e)) = self.pins.as_ref().map(|pins| pins.save(&self.pins_dir())) {
+            self.status_message = Some(format!("Can't save pins: {}", e));
+        }
+    }
+
+    // `:Pin`: adds the current file to the project's pins, or moves its pin
+    // to the cursor.
+    fn pin(&mut self) {
+        if self.file_path.is_empty() {
+            self.status_message = Some("No file name".to_string());
+            return;
+        }
+        let (file, cursor) = (self.absolute_path(), self.cursor);
+        let slot = self.pins().add(&file, cursor);
+        self.status_message = Some(format!("Pinned as {}", slot));
+        self.save_pins();
+    }
+
+    // `:Unpin [N]`: unpins slot N, or the current file.
+    fn unpin(&mut self, args: &str) {
+        let file = self.absolute_path();
+        let pins = self.pins();
+        let slot = if args.is_empty() {
+            pins.slot(&file)
+        } else {
+            args.parse().ok()
+        };
+        let message = match slot.and_then(|slot| pins.remove(slot)) {
+            Some(pin) => format!("Unpinned {}", pins.display(&pin)),
+            None => "Not pinned".to_string(),
+        };
+        self.status_message = Some(message);
+        self.save_pins();
+    }
+
+    // Pinned files are reopened where they were left.
+    fn remember_pin(&mut self) {
+        if self.file_path.is_empty() || self.pins.is_none() {
+            return;
+        }
+        let (file, cursor) = (self.absolute_path(), self.cursor);
+        if self.pins().remember(&file, cursor).is_some() {
+            self.save_pins();
+        }
+    }
+
+    fn jump_to_pin(&mut self, slot: usize) {
+        let pins = self.pins();
+        let Some(pin) = pins.get(slot) else {
+            self.status_message = Some(format!("Nothing pinned as {}", slot));
+            return;
+        };
+        let (path, (line, grapheme)) = (pin.path.clone(), pin.cursor);
+        let message = format!("[{}/{}] {}", slot, pins.len(), pins.display(pin));
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let path = path
+            .strip_prefix(&cwd)
+            .unwrap_or(&path)
+            .display()
+            .to_string();
+        if paths::same_file(&path, &self.file_path) {
+            self.remember_pin();
+        } else if !self.open_file(&path) {
+            return;
+        }
+        self.cursor = Position::clamped(&self.content, line, grapheme);
+        self.status_message = Some(message);
+    }
+
+    // Moves swap files and pins from where earlier versions, or an earlier
+    // `statedir`, kept them.
+    pub fn migrate_state(&mut self, from: &Path) {
+        if let Err(e) = dirs::migrate(&from.join("pins"), &self.pins_dir()) {
+            self.status_message = Some(format!("Can't move pins: {}", e));
+        }
+        if let Err(e) = dirs::migrate(&from.join("sessions"), &self.sessions_dir()) {
+            self.status_message = Some(format!("Can't move sessions: {}", e));
+        }
+        match dirs::migrate(&from.join("swap"), &self.swap_dir()) {
+            Ok(0) => {}
+            Ok(moved) => {
+                self.status_message = Some(format!(
+                    "Moved {} swap file(s) to {}",
+                    moved,
+                    self.swap_dir().display()
+                ))
+            }
+            Err(e) => self.status_message = Some(format!("Can't move swap files: {}", e)),
+        }
+    }
+
+    pub fn check_swap(&mut self) {
+        if !self.options.swapfile || self.prompt.is_some() {
+            return;
+        }
+        if let Some(info) = swap::read(&self.swap_dir(), &self.file_path) {
+            self.prompt = Some(Prompt::RecoverSwap(info));
+            self.mode = Mode::Prompt;
+        }
+    }
+
+    // Offers to restore the session of an editor that crashed, before any
+    // swap file of the file opened now is asked about.
+    pub fn check_crash(&mut self) {
+        if let Some(crashed) = autosession::crashed(&self.sessions_dir()) {
+            let current = crashed
+                .current()
+                .map_or(self.file_path.clone(), |p| p.to_string_lossy().into_owned());
+            let pending = self.unrecovered_files(&crashed.files(), &current);
+            self.prompt = Some(Prompt::RestoreSession(crashed, pending));
+            self.mode = Mode::Prompt;
+        }
+    }
+
+    // Takes a lock that quitting gives back, so the next start can tell
+    // whether this editor crashed, and saves the session to restore then.
+    pub fn start_autosession(&mut self) {
+        match autosession::lock(&self.sessions_dir()) {
+            Ok(()) => self.save_session(),
+            Err(e) => self.status_message = Some(format!("Could not lock session: {}", e)),
+        }
+    }
+
+    fn save_session(&mut self) {
+        self.last_autosession = Some(Instant::now());
+        if let Err(e) = autosession::save(&self.sessions_dir(), &self.session_commands()) {
+            self.status_message = Some(format!("Could not save session: {}", e));
+        }
+    }
+
+    fn persist_session(&mut self) {
+        let due = self.last_autosession.is_some_and(|t| {
+            self.options.autosession > 0 && t.elapsed().as_secs() >= self.options.autosession
+        });
+        if due {
+            self.save_session();
+        }
+    }
+
+    // The files of the crashed session that still have unsaved changes in
+    // swap files, other than `current`, whose changes restoring brings back.
+    fn unrecovered_files(&self, files: &[PathBuf], current: &str) -> Vec<String> {
+        files
+            .iter()
+            .map(|file| file.to_string_lossy().into_owned())
+            .filter(|file| !paths::same_file(file, current))
+            .filter(|file| {
+                swap::read(&self.swap_dir(), file)
+                    .is_some_and(|info| !swap::process_running(info.pid))
+            })
+            .collect()
+    }
+
+    // Shows what restoring the crashed session would bring back that its
+    // files on disk don't have.
+    fn preview_recovery(&mut self, crashed: &Crashed) {
+        let mut lines = Vec::new();
+        for file in crashed.files() {
+            let file = file.to_string_lossy();
+            let Some(info) = swap::read(&self.swap_dir(), &file) else {
+                continue;
+            };
+            let saved: Vec<String> = match fs::read(&*file) {
+                Ok(bytes) => encoding::decode(&bytes)
+                    .lines
+                    .iter()
+                    .map(|l| l.to_string())
+                    .collect(),
+                Err(_) => Vec::new(),
+            };
+            let unsaved = format!("{} (unsaved)", file);
+            lines.extend(snapshot::unified(&file, &unsaved, &saved, &info.content));
+        }
+        if lines.is_empty() {
+            self.status_message = Some("No unsaved changes to recover".to_string());
+        } else {
+            self.output.show("Recovery preview", lines);
+        }
+    }
+
+    // The cursor goes back last, as it may be on lines only the unsaved
+    // changes have. Only the file being edited gets its changes back; while
+    // other files still have theirs in swap files, the crashed session is
+    // kept, to be offered again, and each file offers its swap when opened.
+    fn restore_session(&mut self, crashed: Crashed) {
+        let (pid, files) = (crashed.pid, crashed.files());
+        let (cursor, commands): (Vec<String>, Vec<String>) = crashed
+            .commands
+            .into_iter()
+            .partition(|c| c.starts_with("cursor "));
+        for command in commands {
+            self.command_buffer = command;
+            self.mode = Mode::Command;
+            handle_command_mode(self);
+        }
+        // Opening the session's files offers their swap files; the one being
+        // edited is recovered below, and the others are offered when opened.
+        self.prompt = None;
+        let message = match swap::read(&self.swap_dir(), &self.file_path) {
+            Some(info) if self.options.swapfile => {
+                self.content = Buffer::from_lines(info.content);
+                self.index.reset(self.content.len());
+                self.modified = true;
+                "Restored session and unsaved changes; :w to keep them"
+            }
+            _ => "Restored session",
+        };
+        for command in cursor {
+            self.command_buffer = command;
+            self.mode = Mode::Command;
+            handle_command_mode(self);
+        }
+        let pending = self.unrecovered_files(&files, &self.file_path);
+        self.status_message = Some(if pending.is_empty() {
+            autosession::discard(&self.sessions_dir(), pid);
+            message.to_string()
+        } else {
+            format!(
+                "{}; not restored yet: {} (open to recover)",
+                message,
+                pending.join(", ")
+            )
+        });
+    }
+
+    fn prompt_text(&self) -> String {
+        match &self.prompt {
+            Some(Prompt::RecoverSwap(info)) => {
+                let age = info
+                    .modified
+                    .and_then(|m| m.elapsed().ok())
+                    .map_or(String::new(), |d| format!(", {} min old", d.as_secs() / 60));
+                let running = if swap::process_running(info.pid) {
+                    " STILL RUNNING"
+                } else {
+                    ""
+                };
+                format!(
+                    "Swap file found (pid {}{}{}): [r]ecover [d]elete [e]dit anyway [q]uit",
+                    info.pid, running, age
+                )
+            }
+            Some(Prompt::RestoreSession(crashed, pending)) => {
+                let age = crashed
+                    .saved
+                    .and_then(|m| m.elapsed().ok())
+                    .map_or(String::new(), |d| format!(", {} min ago", d.as_secs() / 60));
+                // Only the file the session was editing gets its changes
+                // back now; say which others keep theirs in swap files.
+                let unrestored = match pending.len() {
+                    0 => String::new(),
+                    1 => {
+                        let file = Path::new(&pending[0]);
+                        let name = file.file_name().unwrap_or(file.as_os_str());
+                        format!("; {} is not restored until opened", name.to_string_lossy())
+                    }
+                    n => format!("; {} other files are not restored until opened", n),
+                };
+                format!(
+                    "RVex exited abnormally (pid {}{}): [r]estore session [p]review [d]iscard [e]dit anyway{}",
+                    crashed.pid, age, unrestored
+                )
+            }
+            Some(Prompt::Substitute(confirm)) => format!(
+                "replace with {} (y/n/a/q/l)?",
+                confirm.substitute.replacement
+            ),
+            None => String::new(),
+        }
+    }
+
+    // Writes the swap file once the user has paused typing for a moment, and
+    // saves the file itself when the autosave interval has elapsed.
+    fn persist_unsaved(&mut self) {
+        if !self.modified {
+            return;
+        }
+        // Rewriting a whole mapped file into the swap file on every pause
+        // would cost more than the edit itself, so large files go without.
+        let mapped = self.content.mapped_bytes().is_some();
+        if self.options.swapfile
+            && !mapped
+            && self.last_edit.is_some_and(|t| t.elapsed().as_secs() >= 1)
+        {
+            self.last_edit = None;
+            if let Err(e) = swap::write(&self.swap_dir(), &self.file_path, &self.content) {
+                self.status_message = Some(format!("Could not write swap file: {}", e));
+            }
+        }
+        if self.options.autosave > 0
+            && self.last_autosave.elapsed().as_secs() >= self.options.autosave
+        {
+            self.last_autosave = Instant::now();
+            if self.save_file(false) {
+                self.status_message = Some("Autosaved".to_string());
+            }
+        }
+    }
+
+    // Indexes the next chunk of a mapped file between frames, so the first
+    // screen shows up right away and keys keep working while it loads.
+    fn index_large_file(&mut self) {
+        self.content.index_more(buffer::INDEX_CHUNK);
+        if !self.content.loading() && self.content.invalid_lines() > 0 {
+            self.status_message = Some(format!(
+                "{} line(s) were not valid UTF-8 and were read as latin1",
+                self.content.invalid_lines()
+            ));
+        }
+    }
+
+    fn load_from_disk(&mut self) {
+        let decoded = read_file(&self.file_path);
+        self.content = decoded.lines;
+        self.index.reset(self.content.len());
+        self.encoding = decoded.encoding;
+        self.line_ending = decoded.line_ending;
+        self.final_newline = decoded.final_newline;
+        self.bom = decoded.bom;
+        self.indenter = Indenter::new(&self.file_path, &self.content);
+        self.disk_stamp = file_stamp(&self.file_path);
+        self.disk_change_reported = false;
+        self.modified = false;
+        self.cursors.clear();
+        self.prose_issues.clear();
+        self.code_actions = None;
+        self.readonly = self.view_mode || read_only_on_disk(&self.file_path);
+        self.refresh_git();
+        self.lsp_started = false;
+    }
+
+    // Diffs the buffer against HEAD on another thread; `check_tasks` picks
+    // up the hunks.
+    pub fn refresh_git(&mut self) {
+        self.start_git_diff(false);
+    }
+
+    fn start_git_diff(&mut self, report: bool) {
+        // Diffing a mapped file would read all of it, so those go without.
+        if self.content.mapped_bytes().is_some() {
+            self.git_hunks = Vec::new();
+            return;
+        }
+        let path = self.file_path.clone();
+        let lines: Vec<String> = self.content.iter().map(|l| l.to_string()).collect();
+        let task = Task::spawn(&self.waker, move || git::hunks(&path, &lines));
+        self.git_task = Some((task, report));
+    }
+
+    pub fn set_waker(&mut self, waker: Waker) {
+        self.waker = waker;
+    }
+
+    fn open_finder(&mut self) {
+        let root = std::env::current_dir().unwrap_or_else(|_| ".".into());
+        self.finder.open();
+        self.finder_task = Some(Task::spawn(&self.waker, move || finder::scan_files(&root)));
+        self.mode = Mode::Finder;
+    }
+
+    // `:Repl [cmd]`: starts an interpreter whose output shows in the output
+    // pane, the one for the file type if no command is given.
+    fn start_repl(&mut self, command: &str) {
+        let command = match command {
+            "" => match repl::default_command(&self.file_path) {
+                Some(command) => command,
+                None => {
+                    self.status_message =
+                        Some("No REPL for this file type; try :Repl CMD".to_string());
+                    return;
+                }
+            },
+            command => command,
+        };
+        self.repl = None;
+        match Repl::start(command, &self.waker) {
+            Ok(repl) => {
+                self.output.show(&format!("REPL {}", command), Vec::new());
+                self.repl = Some(repl);
+            }
+            Err(e) => self.status_message = Some(e),
+        }
+    }
+
+    // Sends lines to the REPL and moves past them, so sending line after
+    // line steps through the code.
+    fn send_to_repl(&mut self, start: usize, end: usize) {
+        let Some(repl) = &mut self.repl else {
+            self.status_message = Some("No REPL running; start one with :Repl".to_string());
+            return;
+        };
+        let text = self.content.join(start..end + 1, "\n") + "\n";
+        match repl.send(&text) {
+            Ok(()) => {
+                self.cursor = Position::clamped(&self.content, end + 1, 0);
+                self.output.visible = true;
+            }
+            Err(e) => self.status_message = Some(e),
+        }
+    }
+
+    fn check_repl(&mut self) {
+        let Some(repl) = &mut self.repl else {
+            return;
+        };
+        let lines = repl.poll();
+        if !lines.is_empty() {
+            self.output.append(lines);
+        } else if repl.exited() {
+            self.status_message = Some(format!("REPL '{}' exited", repl.command));
+            self.repl = None;
+        }
+    }
+
+    // Takes in the results of background tasks that have finished.
+    fn check_tasks(&mut self) {
+        if let Some(result) = self.git_task.as_ref().and_then(|(task, _)| task.poll()) {
+            let (_, report) = self.git_task.take().unwrap();
+            match result {
+                Ok(hunks) => {
+                    self.git_hunks = hunks;
+                    if report {
+                        self.status_message = Some(format!("{} hunk(s)", self.git_hunks.len()));
+                    }
+                }
+                Err(e) => self.status_message = Some(e),
+            }
+        }
+        if let Some(result) = self.finder_task.as_ref().and_then(|task| task.poll()) {
+            self.finder_task = None;
+            match result {
+                Ok(files) => self.finder.set_files(files),
+                Err(e) => {
+                    self.finder.set_files(Vec::new());
+                    self.status_message = Some(e);
+                }
+            }
+        }
+        if let Some(result) = self.print_task.as_ref().and_then(|(task, _)| task.poll()) {
+            let (_, path) = self.print_task.take().unwrap();
+            self.status_message = Some(match result.and_then(|printed| printed) {
+                Ok(()) => format!("Printed {}", path),
+                Err(e) => format!("Can't print {}: {}", path, e),
+            });
+        }
+    }
+
+    // The Ex commands that bring back the working directory, files, layout
+    // and cursor position.
+    fn session_commands(&self) -> Vec<String> {
+        let mut commands = Vec::new();
+        if let Ok(dir) = std::env::current_dir() {
+            commands.push(format!("cd {}", dir.display()));
+        }
+        // Opening the alternate file first makes it the alternate again.
+        if let Some(alternate) = &self.alternate_file {
+            commands.push(format!("edit {}", alternate));
+        }
+        commands.push(format!("edit {}", self.file_path));
+        commands.push(format!("set {}", self.options.summary()));
+        commands.push(format!("set {}", self.gutter.describe()));
+        if self.output.visible {
+            commands.push("copen".to_string());
+        }
+        commands.extend(self.keymap.commands());
+        commands.extend(self.operators.commands());
+        commands.extend(self.macros.commands());
+        commands.extend(self.completion_sources.commands());
+        commands.push(format!(
+            "cursor {} {}",
+            self.cursor.line() + 1,
+            self.cursor.grapheme() + 1
+        ));
+        commands
+    }
+
+    // `:nmap`, `:inoremap`, `:cunmap` and friends. Without arguments the
+    // mapping commands list that mode's mappings.
+    fn map_command(&mut self, name: &str, args: &str) {
+        let result = match keymap::command(name) {
+            Some(keymap::Command::Map(mode, _)) if args.is_empty() => {
+                self.output.show("Mappings", self.keymap.lines(mode));
+                Ok(())
+            }
+            Some(keymap::Command::Map(mode, remap)) => match args.split_once(' ') {
+                Some((lhs, rhs)) => self.keymap.map(mode, lhs, rhs.trim_start(), remap),
+                None => Err(format!("Missing keys to map {} to", args)),
+            },
+            Some(keymap::Command::Unmap(mode)) => self.keymap.unmap(mode, args),
+            None => Ok(()),
+        };
+        if let Err(e) = result {
+            self.status_message = Some(e);
+        }
+    }
+
+    fn show_cwd(&mut self) {
+        self.status_message = Some(match std::env::current_dir() {
+            Ok(dir) => dir.display().to_string(),
+            Err(e) => format!("Can't get current directory: {}", e),
+        });
+    }
+
+    // Runs the user's startup file. `None` is a clean start.
+    pub fn load_config(&mut self, path: Option<&str>) {
+        self.config = path.map(|p| p.to_string());
+        if let Some(path) = path {
+            self.source(path);
+        }
+    }
+
+    // `:ConfigShow`: everything startup files can change, as the commands
+    // that would set it up again.
+    fn show_config(&mut self) {
+        let lines = self.config_lines();
+        self.output.show("Config", lines);
+    }
+
+    // `:checkhealth`: the config, mappings, completion sources, external
+    // tools, terminal and state directory looked over for problems.
+    fn check_health(&mut self) {
+        let mut health = Health::new();
+        health::check_config(&mut health, self.config.as_deref(), |arg| {
+            self.parse_set_arg(arg).map(drop)
+        });
+        health.section("Keymaps");
+        let conflicts = self.keymap.conflicts(self.options.leader());
+        for conflict in &conflicts {
+            health.warn(conflict, "Map one of them to other keys, or unmap it");
+        }
+        if conflicts.is_empty() {
+            health.ok("No mappings get in each other's way");
+        }
+        health.section("Completion");
+        let mut unknown = 0;
+        for (filetype, sources) in self.completion_sources.filetypes() {
+            for source in sources {
+                if !self.completion_sources.has(source) {
+                    unknown += 1;
+                    health.error(
+                        &format!("No completion source {} for {}", source, filetype),
+                        "Use lsp, buffer, path, snippet, dictionary or one a plugin registers",
+                    );
+                }
+            }
+        }
+        let dictionary = self
+            .completion_sources
+            .filetypes()
+            .any(|(_, s)| s.iter().any(|s| s == "dictionary"));
+        if dictionary && !Path::new(&self.options.dictionary).is_file() {
+            unknown += 1;
+            health.warn(
+                &format!("dictionary={} doesn't exist", self.options.dictionary),
+                "Install a word list or set dictionary to one",
+            );
+        }
+        if unknown == 0 {
+            health.ok("Every completion source is there");
+        }
+        let mut tools = vec![Tool {
+            purpose: "the gutter's change signs".to_string(),
+            command: "git".to_string(),
+            fix: "Install git, or :set gutter without git".to_string(),
+        }];
+        tools.extend(self.servers.iter().map(|(key, command)| Tool {
+            purpose: format!("{} files' language server", key),
+            command: command.to_string(),
+            fix: format!("Install it, or use another with :lsp {} CMD", key),
+        }));
+        tools.extend(self.linters.iter().map(|(key, linter)| Tool {
+            purpose: format!("linting {} files", key),
+            command: linter.command.clone(),
+            fix: format!("Install it, or use another with :linter {} CMD", key),
+        }));
+        tools.extend(self.write_filters.iter().map(|(key, command)| Tool {
+            purpose: format!("writing {} files", key),
+            command: command.to_string(),
+            fix: format!(
+                "Install it, or remove the filter with :writefilter! {}",
+                key
+            ),
+        }));
+        tools.extend(self.prose_checker.iter().map(|command| Tool {
+            purpose: ":ProseCheck".to_string(),
+            command: command.clone(),
+            fix: "Install it, or use another with :ProseChecker CMD".to_string(),
+        }));
+        tools.push(Tool {
+            purpose: ":hardcopy".to_string(),
+            command: self.options.printprg.clone(),
+            fix: "Install it, or :set printprg to another".to_string(),
+        });
+        health::check_tools(&mut health, &tools);
+        health::check_terminal(&mut health, self.screen_size, &self.options);
+        health::check_state(&mut health, &dirs::state_dir(&self.options.statedir));
+        let lines = health.lines();
+        self.status_message = Some(match health.problems() {
+            0 => lines[0].clone(),
+            _ => format!("{}; see the Health pane", lines[0]),
+        });
+        self.output.show("Health", lines);
+    }
+
+    fn config_lines(&self) -> Vec<String> {
+        let mut lines = vec![match &self.config {
+            Some(path) => format!("\" Read {}", path),
+            None => "\" No startup file read".to_string(),
+        }];
+        lines.push(format!("set {}", self.options.summary()));
+        lines.push(format!("set {}", self.gutter.describe()));
+        lines.extend(self.keymap.commands());
+        lines.extend(self.operators.commands());
+        lines.extend(self.completion_sources.commands());
+        lines.extend(
+            self.prose_checker
+                .iter()
+                .map(|c| format!("ProseChecker {}", c)),
+        );
+        for (title, listing) in [
+            ("Abbreviations", self.aliases.abbreviation_lines()),
+            ("User commands", self.aliases.command_lines()),
+            ("Linters", self.linters.lines()),
+        ] {
+            if !listing.is_empty() {
+                lines.push(format!("\" {}", title));
+                lines.extend(listing);
+            }
+        }
+        lines
+    }
+
+    // Every line of the buffer drawn as the screen shows it, gutter and
+    // all, but without the cursor, selections or panes. Lines are drawn a
+    // chunk at a time so long files don't need one huge grid.
+    fn render_document(&mut self) -> Vec<Vec<Cell>> {
+        self.content.finish_loading();
+        let saved = (
+            self.screen_size,
+            self.row_offset,
+            self.mode,
+            self.output.visible,
+        );
+        let merge = self.merge.take();
+        let cursors = std::mem::take(&mut self.cursors);
+        // No mode that marks the cursor's bracket or a selection.
+        self.mode = Mode::Prompt;
+        self.output.visible = false;
+        let gutter_width = self.gutter.width(self.content.len());
+        let cols = if self.options.wrap {
+            saved.0 .1
+        } else {
+            let longest = self.content.iter().map(width::width).max().unwrap_or(0);
+            saved.0 .1.max(gutter_width + longest)
+        };
+        self.screen_size.1 = cols;
+        let mut rows = Vec::new();
+        let mut grid = GridRenderer::new();
+        for start in (0..self.content.len()).step_by(EXPORT_CHUNK) {
+            let end = (start + EXPORT_CHUNK).min(self.content.len());
+            let height: usize = (start..end).map(|r| self.line_breaks(r).len()).sum();
+            self.screen_size.0 = height + 1;
+            self.row_offset = start;
+            grid.begin(height + 1, cols);
+            draw_content(self, &mut grid);
+            rows.extend(grid.cells.chunks(cols).take(height).map(|row| row.to_vec()));
+        }
+        (
+            self.screen_size,
+            self.row_offset,
+            self.mode,
+            self.output.visible,
+        ) = saved;
+        self.merge = merge;
+        self.cursors = cursors;
+        rows
+    }
+
+    // `:TOhtml`, `:TOansi` and `:hardcopy`: the buffer as it looks, as a web
+    // page, colored text, or a PDF made from the page by `printprg`.
+    fn export(&mut self, format: &str, path: &str, force: bool) {
+        let path = match path {
+            "" if self.file_path.is_empty() => format!("rvex.{}", format),
+            "" => format!("{}.{}", self.file_path, format),
+            path => path.to_string(),
+        };
+        if !force && Path::new(&path).exists() {
+            self.status_message = Some(format!("{} exists (add ! to override)", path));
+            return;
+        }
+        let rows = self.render_document();
+        let title = if self.file_path.is_empty() {
+            "[No Name]"
+        } else {
+            &self.file_path
+        };
+        let written = match format {
+            "ansi" => fs::write(&path, export::ansi(&rows)).map_err(|e| e.to_string()),
+            "html" => fs::write(&path, export::html(&rows, title)).map_err(|e| e.to_string()),
+            _ => {
+                let page = export::html(&rows, title);
+                self.status_message = Some(match self.print_pdf(&page, &path) {
+                    Ok(()) => format!("Printing {} lines to {}", self.content.len(), path),
+                    Err(e) => format!("Can't print {}: {}", path, e),
+                });
+                return;
+            }
+        };
+        self.status_message = Some(match written {
+            Ok(()) => format!("Exported {} lines to {}", self.content.len(), path),
+            Err(e) => format!("Can't write {}: {}", path, e),
+        });
+    }
+
+    // In `printprg`, `%` stands for the page; the PDF's path goes last. The
+    // program runs on another thread; `check_tasks` reports how it went.
+    fn print_pdf(&mut self, page: &str, path: &str) -> Result<(), String> {
+        if self.options.printprg.is_empty() {
+            return Err("'printprg' is empty".to_string());
+        }
+        if self.print_task.is_some() {
+            return Err("still printing the last one".to_string());
+        }
+        let html = std::env::temp_dir().join(format!("rvex-hardcopy-{}.html", std::process::id()));
+        fs::write(&html, page).map_err(|e| e.to_string())?;
+        let cmd = format!(
+            "{} {}",
+            self.options
+                .printprg
+                .replace('%', &lint::quote(&html.display().to_string())),
+            lint::quote(path)
+        );
+        let task = Task::spawn(&self.waker, move || {
+            let result = output::shell(&cmd).output();
+            let _ = fs::remove_file(&html);
+            match result {
+                Ok(out) if out.status.success() => Ok(()),
+                Ok(out) => Err(match String::from_utf8_lossy(&out.stderr).lines().next() {
+                    Some(line) => format!("'{}' failed: {}", cmd, line),
+                    None => format!("'{}' exited with {}", cmd, out.status.code().unwrap_or(-1)),
+                }),
+                Err(e) => Err(format!("Can't run '{}': {}", cmd, e)),
+            }
+        });
+        self.print_task = Some((task, path.to_string()));
+        Ok(())
+    }
+
+    // `:bugreport [file]`: writes a report to attach to an issue. The file
+    // name is left out, as is the text; only its kind and size go in.
+    fn write_bug_report(&mut self, path: &str, force: bool) {
+        let mut report = Report::new();
+        let extension = Path::new(&self.file_path)
+            .extension()
+            .map_or(String::new(), |e| e.to_string_lossy().into_owned());
+        report.section(
+            "Editor",
+            vec![
+                format!(
+                    "file type: {}",
+                    if extension.is_empty() {
+                        "none"
+                    } else {
+                        &extension
+                    }
+                ),
+                format!("lines: {}", self.content.len()),
+                format!(
+                    "encoding: {} {}",
+                    self.encoding.name(),
+                    self.line_ending.name()
+                ),
+                format!("screen: {}x{}", self.screen_size.1, self.screen_size.0),
+                format!(
+                    "memory: {}",
+                    human_size(self.memory_usage().iter().map(|(_, n)| n).sum())
+                ),
+            ],
+        );
+        report.private_section("Config", self.config_lines());
+        report.section("Plugins", self.scripts.lines());
+        let tail = self.output.lines.len().saturating_sub(BUG_REPORT_LOG_LINES);
+        report.private_section(
+            "Output",
+            self.output.lines[tail..]
+                .iter()
+                .map(|l| output::strip_ansi(l))
+                .collect(),
+        );
+        let keys: Vec<Key> = self.key_trace.iter().map(|&k| Key::Press(k)).collect();
+        report.section(
+            "Keys",
+            if self.options.keytrace {
+                vec![keys::format(&keys)]
+            } else {
+                vec!["Not recorded; :set keytrace to keep the last keys typed".to_string()]
+            },
+        );
+        self.status_message = Some(match report.write(path, force) {
+            Ok(()) => format!("Bug report written to {}; nothing was sent", path),
+            Err(e) => e,
+        });
+    }
+
+    // Loads the plugins in `dir` and lets them see the file already open.
+    pub fn load_plugins(&mut self, dir: &Path) {
+        let errors = self.with_scripts(|scripts| Ok(scripts.load_dir(dir)));
+        if let Some(errors) = errors.filter(|e| !e.is_empty()) {
+            self.output.show("Plugin errors", errors);
+        }
+        self.register_plugin_sources();
+        self.run_hook("on_open");
+    }
+
+    // Adds the completion sources plugins registered, for `:completesources`
+    // to name. The built-in sources keep their names.
+    fn register_plugin_sources(&mut self) {
+        for name in self.scripts.completions() {
+            if !self.completion_sources.has(&name) {
+                self.completion_sources
+                    .register(Box::new(completion::Plugin::new(&name)));
+            }
+        }
+    }
+
+    fn run_hook(&mut self, event: &str) {
+        if self.scripts.has_hook(event) {
+            let path = self.file_path.clone();
+            self.with_scripts(|scripts| scripts.hook(event, &path));
+        }
+    }
+
+    // Lends the buffer and cursor to scripts for the length of `call`, then
+    // runs the commands they asked for. Errors end up in the message line.
+    fn with_scripts<T>(
+        &mut self,
+        call: impl FnOnce(&mut Scripts) -> Result<T, String>,
+    ) -> Option<T> {
+        let (readonly, cursor) = (self.readonly, self.cursor);
+        {
+            let mut host = self.scripts.host();
+            host.lines = std::mem::replace(&mut self.content, Buffer::from_lines(Vec::new()));
+            host.cursor = cursor;
+            host.path = self.file_path.clone();
+            host.readonly = readonly;
+            host.edits.clear();
+        }
+        let result = call(&mut self.scripts);
+        let (cursor, edits, commands, message) = {
+            let mut host = self.scripts.host();
+            self.content = std::mem::replace(&mut host.lines, Buffer::from_lines(Vec::new()));
+            let edits = std::mem::take(&mut host.edits);
+            (
+                host.cursor,
+                edits,
+                std::mem::take(&mut host.commands),
+                host.message.take(),
+            )
+        };
+        for edit in &edits {
+            match *edit {
+                scripting::Edit::Changed(row) => self.index.changed(row),
+                scripting::Edit::Inserted(row) => self.index.inserted(row, 1),
+                scripting::Edit::Removed(row) => self.index.removed(row, 1),
+            }
+        }
+        if !edits.is_empty() {
+            if self.content.is_empty() {
+                self.content.push(String::new());
+                self.index.inserted(0, 1);
+            }
+            self.modified = true;
+        }
+        self.cursor = Position::clamped(&self.content, cursor.line(), cursor.grapheme());
+        match &result {
+            Err(e) => self.status_message = Some(e.clone()),
+            Ok(_) => {
+                if let Some(message) = message {
+                    self.status_message = Some(message);
+                }
+            }
+        }
+        for command in commands {
+            self.command_buffer = command;
+            self.mode = Mode::Command;
+            handle_command_mode(self);
+        }
+        result.ok()
+    }
+
+    pub fn source(&mut self, path: &str) {
+        if path.ends_with(".rhai") {
+            let path = Path::new(path).to_path_buf();
+            if self.with_scripts(|scripts| scripts.load(&path)).is_some() {
+                self.register_plugin_sources();
+                self.run_hook("on_open");
+            }
+            return;
+        }
+        let commands = match session::load(path) {
+            Ok(commands) => commands,
+            Err(e) => {
+                self.status_message = Some(e);
+                return;
+            }
+        };
+        for command in commands {
+            self.command_buffer = command;
+            self.mode = Mode::Command;
+            handle_command_mode(self);
+        }
+    }
+
+    // Shows where a line range being typed on the command line points, by
+    // moving the cursor there until the command is run or abandoned.
+    fn preview_range(&mut self) {
+        let (cursor, row_offset) = *self
+            .preview_origin
+            .get_or_insert((self.cursor, self.row_offset));
+        self.cursor = cursor;
+        self.row_offset = row_offset;
+        let Ok((Some((start, end)), rest)) = range::parse(
+            &self.command_buffer,
+            cursor.line(),
+            self.content.len(),
+            self.visual_rows(),
+        ) else {
+            return;
+        };
+        // A bare range jumps to its last line; a command starts at its first.
+        let target = if rest.is_empty() { end } else { start };
+        self.cursor = Position::clamped(&self.content, target, 0);
+        self.scroll();
+    }
+
+    // Ctrl-N: adds a cursor at the next whole-word match of the word under
+    // the cursor, after the last cursor added.
+    fn add_cursor(&mut self) {
+        let line = &self.content[self.cursor.line()];
+        let Some((start, end)) =
+            expand::span_at(line, self.cursor_col(), search_index::is_word_char)
+        else {
+            self.status_message = Some("No string under cursor".to_string());
+            return;
+        };
+        let word: String = line.chars().skip(start).take(end - start).collect();
+        if self.cursors.is_empty() {
+            self.cursor = self.at_char(self.cursor.line(), start);
+        }
+        let from = *self.cursors.last().unwrap_or(&self.cursor);
+        self.content.finish_loading();
+        let found = self.index.find(&self.content, &word, true, from, true);
+        match found {
+            Some(at) if at != self.cursor && !self.cursors.contains(&at) => {
+                self.cursors.push(at);
+            }
+            _ => self.status_message = Some(format!("No more matches for {}", word)),
+        }
+    }
+
+    // Drops cursors that edits have run together, and any on the primary.
+    fn merge_cursors(&mut self) {
+        let primary = self.cursor;
+        self.cursors.sort();
+        self.cursors.dedup();
+        self.cursors.retain(|&c| c != primary);
+    }
+
+    // `I` and `A` on a block: a cursor at the start or end of the block on
+    // each of its lines. Lines too short for `I` are skipped; for `A` they
+    // are padded out.
+    fn insert_in_block(&mut self, selection: &Selection, append: bool) {
+        if !self.editable() {
+            return;
+        }
+        let (first, last) = selection.rows();
+        let columns = selection.columns();
+        let col = if append { columns.end } else { columns.start };
+        let mut cursors = Vec::new();
+        for row in first..=last {
+            let len = position::graphemes(&self.content[row]);
+            if len < col && append {
+                self.content.line_mut(row).push_str(&" ".repeat(col - len));
+                self.index.changed(row);
+                self.modified = true;
+            } else if len < col {
+                continue;
+            }
+            cursors.push(Position::clamped(&self.content, row, col));
+        }
+        self.cursor = Position::clamped(&self.content, first, columns.start);
+        if let Some(&primary) = cursors.first() {
+            self.cursor = primary;
+            self.cursors = cursors[1..].to_vec();
+        }
+        self.mode = Mode::Insert;
+    }
+
+    // `y` in visual mode: adds the selection to the yank history.
+    fn yank_selection(&mut self, selection: &Selection) {
+        let (first, last) = selection.rows();
+        let text: Vec<String> = (first..=last)
+            .map(|row| {
+                let line = &self.content[row];
+                let len = position::graphemes(line);
+                let columns = selection.columns_on(row, len);
+                line.graphemes(true)
+                    .skip(columns.start)
+                    .take(columns.end.min(len).saturating_sub(columns.start))
+                    .collect()
+            })
+            .collect();
+        let kind = match selection.kind {
+            VisualKind::Char => YankKind::Chars,
+            VisualKind::Line => YankKind::Lines,
+            VisualKind::Block => YankKind::Block,
+        };
+        let count = text.len();
+        self.yanks.push(text, kind);
+        self.cursor = match selection.kind {
+            VisualKind::Line => self.cursor.to_line(&self.content, first),
+            VisualKind::Block => Position::clamped(&self.content, first, selection.columns().start),
+            VisualKind::Char => selection.anchor.min(selection.cursor),
+        };
+        self.adjust_column();
+        if count > 2 {
+            self.status_message = Some(format!("{} lines yanked", count));
+        }
+    }
+
+    // `p` and `P`: puts the newest yank after or before the cursor, below
+    // or above it for whole lines.
+    fn put(&mut self, after: bool) {
+        if self.yanks.is_empty() {
+            self.status_message = Some("Nothing yanked".to_string());
+            return;
+        }
+        if !self.editable() {
+            return;
+        }
+        let yank = self.yanks.current().expect("history is not empty");
+        let (row, col) = (self.cursor.line(), self.cursor.grapheme());
+        let len = position::graphemes(&self.content[row]);
+        let (row, col, existing) = match yank.kind {
+            YankKind::Lines => (row + usize::from(after), 0, 0),
+            YankKind::Chars => (row, if after { (col + 1).min(len) } else { col }, 1),
+            YankKind::Block => (
+                row,
+                if after { (col + 1).min(len) } else { col },
+                yank.text.len(),
+            ),
+        };
+        let end = (row + existing).min(self.content.len());
+        let existing: Vec<String> = self
+            .content
+            .range(row..end)
+            .map(|l| l.to_string())
+            .collect();
+        let (lines, replaced, cursor) = yanks::put(&existing, col, yank);
+        let (kind, count) = (yank.kind, lines.len());
+        self.replace_lines(row..row + replaced, lines);
+        if kind == YankKind::Lines && self.indenter.language != indent::Language::Plain {
+            self.reindent_put(row, count);
+        }
+        self.cursor = Position::clamped(&self.content, row, cursor);
+    }
+
+    // Moves whole lines put in code to where the language indents the first
+    // of them, keeping how the others are indented relative to it.
+    fn reindent_put(&mut self, row: usize, count: usize) {
+        let Some(first) = (row..row + count).find(|&r| !self.content[r].trim().is_empty()) else {
+            return;
+        };
+        let from = self
+            .indenter
+            .width(indent::leading_ws(&self.content[first]));
+        let to = self
+            .indenter
+            .width(&self.indenter.indent_for(&self.content, first));
+        if from == to {
+            return;
+        }
+        for r in first..row + count {
+            let line = &self.content[r];
+            if line.trim().is_empty() {
+                continue;
+            }
+            let ws = indent::leading_ws(line);
+            let width = (self.indenter.width(ws) + to).saturating_sub(from);
+            let line = format!("{}{}", self.indenter.render(width), &line[ws.len()..]);
+            self.content.set(r, line);
+            self.index.changed(r);
+        }
+    }
+
+    fn open_yank_picker(&mut self) {
+        if self.yanks.is_empty() {
+            self.status_message = Some("Nothing yanked".to_string());
+        } else {
+            self.yank_picker = Some(0);
+        }
+    }
+
+    fn start_operator(&mut self, keys: &str, command: String) {
+        if command.is_empty() {
+            self.status_message = Some("operatorfunc is not set".to_string());
+            return;
+        }
+        self.operator = Some(PendingOperator {
+            keys: keys.to_string(),
+            command,
+            object: None,
+        });
+    }
+
+    // Runs an operator's command on `range`, as if typed with the range on
+    // the command line.
+    fn run_operator(&mut self, command: &str, (start, end): LineRange) {
+        self.cursor = Position::clamped(&self.content, start, 0);
+        self.command_buffer = format!(
+            "{},{}{}",
+            start + 1,
+            end + 1,
+            command.trim_start_matches(':')
+        );
+        self.mode = Mode::Command;
+        handle_command_mode(self);
+    }
+
+    fn start_visual(&mut self, kind: VisualKind) {
+        self.visual = Some(Selection {
+            kind,
+            anchor: self.cursor,
+            cursor: self.cursor,
+        });
+        self.mode = Mode::Visual;
+    }
+
+    // The lines of the last visual selection, for `'<` and `'>`.
+    fn visual_rows(&self) -> Option<LineRange> {
+        self.visual.map(|v| v.rows())
+    }
+
+    // The columns a command run on `'<,'>` is limited to, when the last
+    // visual selection was a block.
+    fn block_columns(&self, command: &str) -> Option<Range<usize>> {
+        let visual = self.visual.filter(|v| v.kind == VisualKind::Block)?;
+        command.starts_with("'<,'>").then(|| visual.columns())
+    }
+
+    // The substitution being typed on the command line, for `inccommand`.
+    // Until the replacement is started it just marks the matches.
+    fn typed_substitute(&self) -> Option<(LineRange, Substitute)> {
+        if self.mode != Mode::Command || self.options.inccommand == "off" {
+            return None;
+        }
+        let row = self
+            .preview_origin
+            .map_or(self.cursor, |(cursor, _)| cursor)
+            .line();
+        let last = self.content.len() - 1;
+        let (range, rest) = range::parse(
+            &self.command_buffer,
+            row,
+            self.content.len(),
+            self.visual_rows(),
+        )
+        .ok()?;
+        let args = substitute::strip_name(rest.trim_start())?;
+        let mut substitute = substitute::parse(args).ok()?;
+        if substitute.pattern.is_empty() {
+            return None;
+        }
+        if !substitute::has_replacement(args) {
+            substitute.replacement = substitute.pattern.clone();
+        }
+        substitute.columns = self.block_columns(&self.command_buffer);
+        let (start, end) = range.unwrap_or((row, row));
+        Some(((start.min(last), end.min(last)), substitute))
+    }
+
+    fn split_preview(&self) -> bool {
+        self.options.inccommand == "split" && self.typed_substitute().is_some()
+    }
+
+    fn end_preview(&mut self) {
+        if let Some((cursor, row_offset)) = self.preview_origin.take() {
+            self.cursor = cursor;
+            self.row_offset = row_offset;
+        }
+    }
+
+    fn jump_to_hunk(&mut self, forward: bool) {
+        let row = self.cursor.line();
+        let target = if forward {
+            self.git_hunks.iter().map(|h| h.row()).find(|&r| r > row)
+        } else {
+            self.git_hunks
+                .iter()
+                .rev()
+                .map(|h| h.row())
+                .find(|&r| r < row)
+        };
+        match target {
+            Some(target) => {
+                self.cursor = self.cursor.to_line(&self.content, target);
+            }
+            None => self.status_message = Some("No more hunks".to_string()),
+        }
+    }
+
+    fn reload(&mut self, force: bool) {
+        if self.modified && !force {
+            self.status_message =
+                Some("No write since last change (add ! to override)".to_string());
+            return;
+        }
+        let discarded = self.discard_changes();
+        self.load_from_disk();
+        swap::remove(&self.swap_dir(), &self.file_path);
+        self.adjust_column();
+        let mut message = format!("\"{}\" {}L reloaded", self.file_path, self.content.len());
+        if let Some(why) = discarded {
+            message.push_str(&format!(" (changes discarded, {})", why));
+        }
+        self.status_message = Some(message);
+    }
+
+    // Keeps a copy of unsaved edits in the trash before they are thrown away.
+    // This is best-effort: the edits are discarded either way, and mapped or
+    // huge buffers are not copied at all. Returns why no copy was kept, if
+    // one should have been.
+    fn discard_changes(&mut self) -> Option<String> {
+        if !self.modified {
+            return None;
+        }
+        if self.content.mapped_bytes().is_some() || self.content.memory() > MAX_TRASH_BYTES {
+            return Some("too large to keep in the trash".to_string());
+        }
+        let text = self.content.join(0..self.content.len(), "\n");
+        trash::trash_contents(Path::new(&self.file_path), &text)
+            .err()
+            .map(|e| format!("could not keep them in the trash: {}", e))
+    }
+
+    fn quit(&mut self, force: bool) {
+        if self.modified && !force {
+            self.status_message =
+                Some("No write since last change (add ! to override)".to_string());
+            return;
+        }
+        if let Some(why) = self.discard_changes() {
+            self.status_message = Some(format!("Changes discarded, {}", why));
+        }
+        self.should_exit = true;
+        self.remember_pin();
+        swap::remove(&self.swap_dir(), &self.file_path);
+        if self.last_autosession.is_some() {
+            autosession::unlock(&self.sessions_dir());
+        }
+    }
+
+    // `:cq`: quits without writing and reports failure to whoever started us,
+    // e.g. to abort a git merge.
+    fn cquit(&mut self, args: &str) {
+        let code = if args.is_empty() {
+            EXIT_CQUIT
+        } else {
+            match args.parse::<i32>() {
+                Ok(code) => code,
+                Err(_) => {
+                    self.status_message = Some(format!("Invalid exit code: {}", args));
+                    return;
+                }
+            }
+        };
+        self.quit(true);
+        if self.should_exit {
+            self.exit_code = code;
+        }
+    }
+
+    fn delete_file(&mut self) {
+        match trash::move_to_trash(Path::new(&self.file_path)) {
+            Ok(_) => {
+                self.disk_stamp = None;
+                self.modified = true;
+                self.status_message = Some(format!(
+                    "\"{}\" moved to trash (:undelete to restore)",
+                    self.file_path
+                ));
+            }
+            Err(e) => self.status_message = Some(format!("Delete failed: {}", e)),
+        }
+    }
+
+    fn undelete(&mut self, args: &str) {
+        let entries = trash::list();
+        if args.is_empty() {
+            let lines = entries
+                .iter()
+                .enumerate()
+                .map(|(i, e)| {
+                    let kind = if e.name.ends_with(".unsaved") {
+                        "  [unsaved changes]"
+                    } else {
+                        ""
+                    };
+                    format!(
+                        "{:3}  {}  {}{}",
+                        i + 1,
+                        e.deleted_at,
+                        e.original.display(),
+                        kind
+                    )
+                })
+                .collect();
+            self.output.show(":undelete [N]", lines);
+            return;
+        }
+        let Some(entry) = args
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| entries.get(n.wrapping_sub(1)))
+        else {
+            self.status_message = Some(format!("No trash entry {}", args));
+            return;
+        };
+        let original = entry.original.to_string_lossy().to_string();
+        // Discarded edits of a file that still exists go back into the buffer
+        // instead of overwriting what's on disk.
+        if entry.original.exists() && paths::same_file(&original, &self.file_path) {
+            if self.modified {
+                self.status_message = Some("No write since last change".to_string());
+                return;
+            }
+            match trash::read(entry) {
+                Ok(text) => {
+                    self.content =
+                        Buffer::from_lines(text.lines().map(|l| l.to_string()).collect());
+                    if self.content.is_empty() {
+                        self.content.push(String::new());
+                    }
+                    self.index.reset(self.content.len());
+                    self.modified = true;
+                    self.adjust_column();
+                    let _ = trash::forget(entry);
+                    self.status_message = Some(format!("Restored unsaved changes to {}", original));
+                }
+                Err(e) => self.status_message = Some(format!("Undelete failed: {}", e)),
+            }
+            return;
+        }
+        match trash::restore(entry) {
+            Ok(()) => {
+                if paths::same_file(&original, &self.file_path) {
+                    self.disk_stamp = file_stamp(&self.file_path);
+                }
+                self.status_message = Some(format!("Restored {}", original));
+            }
+            Err(e) => self.status_message = Some(format!("Undelete failed: {}", e)),
+        }
+    }
+
+    fn changed_on_disk(&self) -> bool {
+        let current = file_stamp(&self.file_path);
+        current.is_some() && current != self.disk_stamp
+    }
+
+    fn check_disk_change(&mut self) {
+        if self.last_disk_check.elapsed().as_secs() < 1 {
+            return;
+        }
+        self.last_disk_check = Instant::now();
+        let changed = self.changed_on_disk();
+        // Someone else writing the mapped file could cut pages we have yet
+        // to read off from under us.
+        if changed && self.content.mapped_bytes().is_some() {
+            let len = file_stamp(&self.file_path).map_or(0, |(_, len)| len);
+            self.content.detach(len);
+        }
+        if !self.disk_change_reported && changed {
+            self.disk_change_reported = true;
+            self.status_message = Some(if self.modified {
+                "W12: File changed on disk and the buffer was changed too; :e! to reload"
+                    .to_string()
+            } else {
+                "W11: File changed on disk since editing started; :e! to reload".to_string()
+            });
+        }
+    }
+
+    fn adjust_column(&mut self) {
+        self.cursor = Position::clamped(&self.content, self.cursor.line(), self.cursor.grapheme());
+    }
+
+    fn editable(&mut self) -> bool {
+        if self.readonly {
+            self.status_message = Some("Cannot make changes, 'readonly' is set".to_string());
+        }
+        !self.readonly
+    }
+
+    fn open_line(&mut self, row: usize) {
+        self.content.insert(row, String::new());
+        self.index.inserted(row, 1);
+        let indent = self.indenter.indent_for(&self.content, row);
+        self.content.set(row, indent);
+        self.cursor = Position::clamped(&self.content, row, usize::MAX);
+        self.modified = true;
+        self.mode = Mode::Insert;
+    }
+
+    fn reindent_line(&mut self, row: usize) {
+        let line = &self.content[row];
+        let old_indent = position::graphemes(indent::leading_ws(line));
+        let indent = if line.trim().is_empty() {
+            String::new()
+        } else {
+            self.indenter.indent_for(&self.content, row)
+        };
+        let new_line = format!("{}{}", indent, line.trim_start());
+        if new_line != self.content[row] {
+            self.content.set(row, new_line);
+            self.index.changed(row);
+            self.modified = true;
+        }
+        if self.cursor.line() == row {
+            let new_indent = position::graphemes(&indent);
+            let grapheme = (self.cursor.grapheme() + new_indent).saturating_sub(old_indent);
+            self.cursor = self.cursor.to_grapheme(&self.content, grapheme);
+        }
+    }
+
+    fn search(&mut self, forward: bool) {
+        let Some((pattern, whole_word)) = self.last_search.clone() else {
+            self.status_message = Some("No previous search pattern".to_string());
+            return;
+        };
+        let forward = forward == self.search_forward;
+        self.highlight_cleared = false;
+        self.content.finish_loading();
+        match self
+            .index
+            .find(&self.content, &pattern, whole_word, self.cursor, forward)
+        {
+            Some(pos) => {
+                let wrapped = if forward {
+                    pos <= self.cursor
+                } else {
+                    pos >= self.cursor
+                };
+                if wrapped {
+                    self.status_message = Some(if forward {
+                        "search hit BOTTOM, continuing at TOP".to_string()
+                    } else {
+                        "search hit TOP, continuing at BOTTOM".to_string()
+                    });
+                }
+                self.cursor = pos;
+            }
+            None => {
+                // Only replayed keys are a script; interactive misses are not errors.
+                if self.replaying {
+                    self.exit_code = EXIT_NOT_FOUND;
+                }
+                self.status_message = Some(format!("Pattern not found: {}", pattern));
+            }
+        }
+    }
+
+    // With `incsearch`, shows the first match of the search being typed by
+    // moving the cursor there until the search is run or abandoned.
+    fn preview_search(&mut self) {
+        if !self.options.incsearch {
+            return;
+        }
+        let (cursor, row_offset) = *self
+            .preview_origin
+            .get_or_insert((self.cursor, self.row_offset));
+        self.cursor = cursor;
+        self.row_offset = row_offset;
+        self.content.finish_loading();
+        let found = self.index.find(
+            &self.content,
+            &self.command_buffer,
+            false,
+            cursor,
+            self.search_forward,
+        );
+        if let Some(pos) = found {
+            self.cursor = pos;
+            self.scroll();
+        }
+    }
+
+    // The pattern whose matches are highlighted: the one being typed, or
+    // else the last search unless `:nohlsearch` hid it.
+    fn highlighted_search(&self) -> Option<(&str, bool)> {
+        if self.mode == Mode::Search {
+            let typed = self.options.incsearch && !self.command_buffer.is_empty();
+            return typed.then_some((self.command_buffer.as_str(), false));
+        }
+        if !self.options.hlsearch || self.highlight_cleared {
+            return None;
+        }
+        self.last_search
+            .as_ref()
+            .map(|(pattern, whole_word)| (pattern.as_str(), *whole_word))
+    }
+
+    fn search_word_under_cursor(&mut self, forward: bool) {
+        let line = &self.content[self.cursor.line()];
+        let Some((start, end)) =
+            expand::span_at(line, self.cursor_col(), search_index::is_word_char)
+        else {
+            self.status_message = Some("No string under cursor".to_string());
+            return;
+        };
+        self.cursor = self.at_char(self.cursor.line(), start);
+        self.last_search = Some((line.chars().skip(start).take(end - start).collect(), true));
+        self.search_forward = forward;
+        self.search(true);
+    }
+
+    fn move_to_line_start(&mut self) {
+        self.cursor = self.cursor.line_start();
+    }
+
+    fn move_to_line_end(&mut self) {
+        self.cursor = self.cursor.line_end(&self.content);
+    }
+
+    // Whether `j` and `k` move by screen rows here.
+    fn moves_by_display_lines(&self) -> bool {
+        self.options.displaylines && prose::is_prose(&self.file_path, &self.options.prosetypes)
+    }
+
+    // `gj` and `gk`: to the same cell of the next or previous screen row,
+    // which without `wrap` is the next or previous line.
+    fn move_display_line(&mut self, down: bool) {
+        let (row, parts) = (
+            self.cursor.line(),
+            self.line_breaks(self.cursor.line()).len(),
+        );
+        let (part, cell) = self.wrapped_position(self.cursor);
+        let part = part.min(parts - 1);
+        let (row, part) = if down && part + 1 < parts {
+            (row, part + 1)
+        } else if down && row + 1 < self.content.len() {
+            (row + 1, 0)
+        } else if !down && part > 0 {
+            (row, part - 1)
+        } else if !down && row > 0 {
+            (row - 1, self.line_breaks(row - 1).len() - 1)
+        } else {
+            return;
+        };
+        let (start, end) = self.display_line_span(row, part);
+        let line = &self.content[row];
+        let base = width::column(line, start);
+        let col = (start..end)
+            .find(|&col| width::column(line, col + 1) - base > cell)
+            .unwrap_or(end);
+        self.cursor = self.at_char(row, col);
+    }
+
+    // `g0` and `g$`: to the first or last character of the screen row.
+    fn move_to_display_line_edge(&mut self, end: bool) {
+        let parts = self.line_breaks(self.cursor.line()).len();
+        let part = self.wrapped_position(self.cursor).0.min(parts - 1);
+        let (start, last) = self.display_line_span(self.cursor.line(), part);
+        self.cursor = self.at_char(self.cursor.line(), if end { last } else { start });
+    }
+
+    // The char columns a screen row of `row` starts at and the last one the
+    // cursor can take on it, which for the last row is the end of the line.
+    fn display_line_span(&self, row: usize, part: usize) -> (usize, usize) {
+        let breaks = self.line_breaks(row);
+        match breaks.get(part + 1) {
+            Some(&next) => (breaks[part], next - 1),
+            None => (breaks[part], self.content[row].chars().count()),
+        }
+    }
+
+    fn jump_to_match(&mut self) {
+        if let Some((_, to)) = brackets::matching(
+            &self.content,
+            self.cursor,
+            self.indenter.language,
+            true,
+            usize::MAX,
+        ) {
+            self.cursor = to;
+        }
+    }
+
+    fn report_encode_errors(&mut self, errors: &[encoding::EncodeError]) {
+        let lines = errors
+            .iter()
+            .map(|e| {
+                format!(
+                    "{}:{}:{}: cannot encode {:?} (U+{:04X}) as {}",
+                    self.file_path,
+                    e.line + 1,
+                    e.col + 1,
+                    e.ch,
+                    u32::from(e.ch),
+                    self.encoding.name()
+                )
+            })
+            .collect();
+        self.output.show("encoding errors", lines);
+        let start = self.output.lines.len() - errors.len();
+        self.quickfix.set_from_output(&self.output.lines, start);
+    }
+
+    // Works out what `:set` does with `arg` without doing it, so that
+    // `:checkhealth` reads the startup file's options the way `:set` does.
+    fn parse_set_arg(&self, arg: &str) -> Result<SetArg, String> {
+        match arg {
+            "readonly" | "ro" | "noreadonly" | "noro" => {
+                return Ok(SetArg::Readonly(!arg.starts_with("no")))
+            }
+            "readonly?" | "ro?" => {
+                let prefix = if self.readonly { "" } else { "no" };
+                return Ok(SetArg::Show(format!("{}readonly", prefix)));
+            }
+            "bomb" | "nobomb" => return Ok(SetArg::Bomb(arg == "bomb")),
+            "bomb?" => {
+                let bom = self.encoding.writes_bom(self.bom);
+                return Ok(SetArg::Show(format!("{}bomb", if bom { "" } else { "no" })));
+            }
+            _ => {}
+        }
+        let (name, value) = arg
+            .split_once('=')
+            .unwrap_or((arg.trim_end_matches('?'), ""));
+        let query = value.is_empty();
+        match name {
+            "fileencoding" | "fenc" if query => Ok(SetArg::Show(format!(
+                "fileencoding={}",
+                self.encoding.name()
+            ))),
+            "fileencoding" | "fenc" => Encoding::parse(value)
+                .map(SetArg::Encoding)
+                .ok_or_else(|| format!("Unknown encoding: {}", value)),
+            "fileformat" | "ff" if query => Ok(SetArg::Show(format!(
+                "fileformat={}",
+                self.line_ending.name()
+            ))),
+            "fileformat" | "ff" => LineEnding::parse(value)
+                .map(SetArg::LineEnding)
+                .ok_or_else(|| format!("Unknown fileformat: {}", value)),
+            _ => {
+                let mut gutter = self.gutter.clone();
+                if let Some(result) = gutter.set(arg) {
+                    return result.map(|message| SetArg::Gutter(gutter, message));
+                }
+                let mut options = self.options.clone();
+                options
+                    .set(arg)
+                    .map(|message| SetArg::Options(Box::new(options), message))
+            }
+        }
+    }
+
+    fn apply_set_arg(&mut self, arg: SetArg) -> Result<Option<String>, String> {
+        match arg {
+            SetArg::Show(message) => return Ok(Some(message)),
+            SetArg::Readonly(on) => self.readonly = on,
+            SetArg::Bomb(bom) => {
+                if self.encoding.writes_bom(bom) != bom {
+                    return Err(format!(
+                        "{} files can't change 'bomb'",
+                        self.encoding.name()
+                    ));
+                }
+                self.modified |= bom != self.bom;
+                self.bom = bom;
+            }
+            SetArg::Encoding(encoding) => {
+                self.encoding = encoding;
+                self.modified = true;
+            }
+            SetArg::LineEnding(line_ending) => {
+                self.line_ending = line_ending;
+                self.modified = true;
+            }
+            SetArg::Gutter(gutter, message) => {
+                self.gutter = gutter;
+                return Ok(message);
+            }
+            SetArg::Options(options, message) => {
+                self.options = *options;
+                return Ok(message);
+            }
+        }
+        Ok(None)
+    }
+
+    fn convert_encoding(&mut self, name: &str) {
+        if !self.editable() {
+            return;
+        }
+        let Some(target) = Encoding::parse(name) else {
+            self.status_message = Some(format!("Unknown encoding: {}", name));
+            return;
+        };
+        match encoding::encode(
+            &self.content,
+            target,
+            self.line_ending,
+            self.final_newline,
+            self.bom,
+        ) {
+            Ok(_) => {
+                let from = self.encoding;
+                self.encoding = target;
+                self.modified = from != target || self.modified;
+                self.status_message = Some(format!(
+                    "Converted {} -> {}; :w to write",
+                    from.name(),
+                    target.name()
+                ));
+            }
+            Err(errors) => {
+                let previous = std::mem::replace(&mut self.encoding, target);
+                self.report_encode_errors(&errors);
+                self.encoding = previous;
+                self.status_message = Some(format!(
+                    "{} character(s) can't be represented in {}",
+                    errors.len(),
+                    target.name()
+                ));
+            }
+        }
+    }
+
+    fn file_info(&self) -> String {
+        let indent = if self.indenter.unit == "\t" {
+            "tabs".to_string()
+        } else {
+            format!("spaces:{}", self.indenter.unit.len())
+        };
+        format!(
+            "{}{} {} {}",
+            self.encoding.name(),
+            if self.encoding.writes_bom(self.bom) {
+                " [BOM]"
+            } else {
+                ""
+            },
+            self.line_ending.name(),
+            indent
+        )
+    }
+
+    fn save_file(&mut self, force: bool) -> bool {
+        if self.options.stripwhitespace && !self.readonly {
+            self.content.finish_loading();
+            self.strip_whitespace(0, self.content.len() - 1);
+        }
+        let saved = self.write_file(force);
+        if !saved {
+            self.exit_code = EXIT_WRITE_FAILED;
+        } else {
+            if self.exit_code == EXIT_WRITE_FAILED {
+                self.exit_code = 0;
+            }
+            self.refresh_git();
+            self.send_lsp_changes();
+            if let Some(client) = &mut self.lsp {
+                client.saved();
+            }
+            if self.options.lint != "off" {
+                self.lint(false);
+            }
+            self.check_prose(false);
+            self.run_hook("on_save");
+        }
+        saved
+    }
+
+    // Starts the linter for this file in the background, on a copy of the
+    // text if it has unsaved changes. `verbose` also reports a clean run.
+    fn lint(&mut self, verbose: bool) {
+        let Some(linter) = self.linters.for_path(&self.file_path) else {
+            if verbose {
+                self.status_message = Some("No linter for this file".to_string());
+            }
+            return;
+        };
+        let unsaved = self.modified.then(|| self.text());
+        match lint::Run::start(
+            linter,
+            &self.file_path,
+            unsaved.as_deref(),
+            verbose,
+            &self.waker,
+        ) {
+            Ok(run) => self.lint_run = Some(run),
+            Err(e) => self.status_message = Some(e),
+        }
+    }
+
+    fn check_lint(&mut self) {
+        if self.lint_after.is_some_and(|t| t.elapsed().as_secs() >= 1) {
+            self.lint_after = None;
+            self.lint(false);
+        }
+        let Some(result) = self.lint_run.as_ref().and_then(|run| run.poll()) else {
+            return;
+        };
+        let run = self.lint_run.take().expect("polled a running linter");
+        let text = match result {
+            Ok(text) => text,
+            Err(e) => {
+                // Linters that aren't installed only matter when asked for.
+                if run.verbose {
+                    self.status_message = Some(e);
+                }
+                return;
+            }
+        };
+        let lines: Vec<String> = text.lines().map(|l| l.to_string()).collect();
+        let start = self.output.log(&run.command, lines.clone());
+        self.quickfix.entries.clear();
+        self.quickfix.current = 0;
+        for (i, line) in lines.iter().enumerate() {
+            if let Some(d) = run.parse(&output::strip_ansi(line)) {
+                self.quickfix.entries.push(QuickfixEntry {
+                    path: d.path,
+                    line: d.line,
+                    col: d.col,
+                    kind: d.kind,
+                    message: d.message,
+                    output_line: start + i,
+                });
+            }
+        }
+        let count = |kind| {
+            self.quickfix
+                .entries
+                .iter()
+                .filter(|e| e.kind == kind)
+                .count()
+        };
+        let (errors, warnings) = (count('E'), count('W'));
+        if errors + warnings > 0 || run.verbose {
+            self.status_message = Some(format!(
+                "Lint: {} error(s), {} warning(s)",
+                errors, warnings
+            ));
+        }
+    }
+
+    // Starts the prose checker in the background when this is a prose
+    // buffer. `verbose` also reports a clean run.
+    pub fn check_prose(&mut self, verbose: bool) {
+        self.prose_after = None;
+        let command = match &self.prose_checker {
+            Some(command) if prose::is_prose(&self.file_path, &self.options.prosetypes) => {
+                command.clone()
+            }
+            _ => {
+                if verbose {
+                    self.status_message = Some("No prose checker for this file".to_string());
+                }
+                return;
+            }
+        };
+        match prose::Run::start(&command, &self.file_path, self.text(), verbose, &self.waker) {
+            Ok(run) => self.prose_run = Some(run),
+            Err(e) => self.status_message = Some(e),
+        }
+    }
+
+    fn poll_prose(&mut self) {
+        if self.prose_after.is_some_and(|t| t.elapsed().as_secs() >= 1) && self.prose_run.is_none()
+        {
+            self.check_prose(false);
+        }
+        let Some(result) = self.prose_run.as_ref().and_then(|run| run.poll()) else {
+            return;
+        };
+        let run = self.prose_run.take().expect("polled a running checker");
+        match result {
+            Ok(issues) => {
+                if !issues.is_empty() || run.verbose {
+                    self.status_message = Some(format!("Prose: {} issue(s)", issues.len()));
+                }
+                self.prose_issues = issues;
+            }
+            // Checkers that aren't running only matter when asked for.
+            Err(e) if run.verbose => self.status_message = Some(format!("{}: {}", run.command, e)),
+            Err(_) => {}
+        }
+    }
+
+    // `]s` and `[s`.
+    fn jump_to_issue(&mut self, forward: bool) {
+        let at = |issue: &prose::Issue| {
+            let row = issue.row.min(self.content.len() - 1);
+            Position::from_char(&self.content[row], row, issue.start)
+        };
+        let target = if forward {
+            self.prose_issues.iter().find(|&i| at(i) > self.cursor)
+        } else {
+            self.prose_issues
+                .iter()
+                .rev()
+                .find(|&i| at(i) < self.cursor)
+        };
+        match target {
+            Some(issue) => {
+                let position = at(issue);
+                self.status_message = Some(issue.message.clone());
+                self.cursor =
+                    Position::clamped(&self.content, position.line(), position.grapheme());
+            }
+            None => self.status_message = Some("No more prose issues".to_string()),
+        }
+    }
+
+    // `z=` and `:CodeAction`: the fixes for what is under the cursor.
+    fn open_code_actions(&mut self) {
+        let (cursor, text) = (self.cursor, &self.content[self.cursor.line()]);
+        let Some(issue) = self.prose_issues.iter().find(|i| i.covers(cursor, text)) else {
+            self.status_message = Some("No code actions here".to_string());
+            return;
+        };
+        self.status_message = Some(issue.message.clone());
+        let fixes = prose::fixes(&self.prose_issues, cursor, text);
+        if fixes.is_empty() || !self.editable() {
+            return;
+        }
+        self.code_actions = Some((fixes, 0));
+    }
+
+    fn apply_fix(&mut self, fix: prose::Fix) {
+        if fix.row >= self.content.len() {
+            return;
+        }
+        let end = fix.end.min(self.content[fix.row].chars().count());
+        let start = fix.start.min(end);
+        let line = self.content.line_mut(fix.row);
+        let range = byte_index(line, start)..byte_index(line, end);
+        line.replace_range(range, &fix.replacement);
+        self.index.changed(fix.row);
+        self.modified = true;
+        self.cursor = self.at_char(fix.row, start);
+        // Later issues on the line move with the text until the checker runs
+        // again.
+        let shift = fix.replacement.chars().count() as isize - (end - start) as isize;
+        self.prose_issues
+            .retain(|i| !(i.row == fix.row && i.start < end && start < i.end.max(i.start + 1)));
+        for issue in self
+            .prose_issues
+            .iter_mut()
+            .filter(|i| i.row == fix.row && i.start >= end)
+        {
+            issue.start = issue.start.saturating_add_signed(shift);
+            issue.end = issue.end.saturating_add_signed(shift);
+        }
+    }
+
+    fn write_file(&mut self, force: bool) -> bool {
+        self.content.finish_loading();
+        if self.readonly && !force {
+            self.status_message = Some("'readonly' option is set (add ! to override)".to_string());
+            return false;
+        }
+        if let Some(name) = paths::reserved_name(&self.file_path, paths::Style::current()) {
+            self.status_message = Some(format!("Can't write {}: reserved device name", name));
+            return false;
+        }
+        if !force && self.changed_on_disk() {
+            self.status_message = Some(
+                "WARNING: The file has been changed since reading it! Use :w! to overwrite"
+                    .to_string(),
+            );
+            return false;
+        }
+        let bytes = match encoding::encode(
+            &self.content,
+            self.encoding,
+            self.line_ending,
+            self.final_newline,
+            self.bom,
+        ) {
+            Ok(bytes) => bytes,
+            Err(errors) => {
+                self.report_encode_errors(&errors);
+                self.status_message = Some(format!(
+                    "Conversion to {} failed for {} character(s); not written",
+                    self.encoding.name(),
+                    errors.len()
+                ));
+                return false;
+            }
+        };
+        if let Some(cmd) = self.write_filters.for_path(&self.file_path) {
+            let cmd = cmd.to_string();
+            return match output::filter(&cmd, bytes) {
+                Ok(_) => {
+                    self.modified = false;
+                    self.last_edit = None;
+                    swap::remove(&self.swap_dir(), &self.file_path);
+                    self.status_message = Some(format!("Written through '{}'", cmd));
+                    true
+                }
+                Err(e) => {
+                    self.status_message = Some(format!("Save error: {}", e));
+                    false
+                }
+            };
+        }
+        // A mapped file must not be truncated while we still read from it, so
+        // its new contents go to a new file that replaces the old one.
+        let written = if self.content.mapped_bytes().is_some() {
+            write_replacing(&self.file_path, &bytes)
+        } else {
+            fs::write(&self.file_path, bytes)
+        };
+        match written {
+            Ok(_) => {
+                self.modified = false;
+                self.disk_stamp = file_stamp(&self.file_path);
+                self.disk_change_reported = false;
+                self.last_edit = None;
+                swap::remove(&self.swap_dir(), &self.file_path);
+                self.status_message = Some("File saved".to_string());
+                true
+            }
+            Err(e) => {
+                self.status_message = Some(format!("Save error: {}", e));
+                false
+            }
+        }
+    }
+}
+
+// True for files we can read but not write. Opening for append checks the
+// permissions the OS actually applies without touching the file.
+fn read_only_on_disk(path: &str) -> bool {
+    Path::new(path).exists() && fs::OpenOptions::new().append(true).open(path).is_err()
+}
+
+fn read_file(path: &str) -> Decoded {
+    // Reading a device like `CON` would wait on the console, not a file.
+    let reserved = paths::reserved_name(path, paths::Style::current()).is_some();
+    let bytes = if reserved {
+        None
+    } else if let Some(decoded) = buffer::open_large(path) {
+        return decoded;
+    } else {
+        fs::read(path).ok()
+    };
+    match bytes {
+        Some(bytes) => encoding::decode(&bytes),
+        None => Decoded {
+            lines: Buffer::from_lines(vec![String::new()]),
+            encoding: Encoding::Utf8,
+            line_ending: LineEnding::Unix,
+            final_newline: true,
+            bom: false,
+        },
+    }
+}
+
+fn write_replacing(path: &str, bytes: &[u8]) -> io::Result<()> {
+    let tmp = format!("{}.rvex-tmp", path);
+    fs::write(&tmp, bytes)?;
+    if let Ok(metadata) = fs::metadata(path) {
+        let _ = fs::set_permissions(&tmp, metadata.permissions());
+    }
+    fs::rename(&tmp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+fn file_stamp(path: &str) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+fn kind_color(kind: char) -> Color {
+    match kind {
+        'E' => render::RED,
+        'W' => render::YELLOW,
+        _ => render::BLUE,
+    }
+}
+
+fn byte_index(line: &str, char_index: usize) -> usize {
+    line.char_indices()
+        .nth(char_index)
+        .map_or(line.len(), |(i, _)| i)
+}
+
+fn draw_content(state: &EditorState, renderer: &mut dyn Renderer) {
+    let cols = state.screen_size.1;
+    let visible_lines = state.content_height();
+    let marks = state.gutter_marks();
+    let gutter_width = state.gutter.width(state.content.len());
+    let top = state.merge_height();
+
+    let view = state.view();
+    let end = view.last().map_or(state.row_offset, |(row, _, _)| row + 1);
+    // Draws `text` over buffer row `row` from column `col` on, following the
+    // row onto the screen rows it wraps to.
+    let put = |renderer: &mut dyn Renderer, row: usize, col: usize, text: &str, style: Style| {
+        let Some((_, breaks, first)) = view.iter().find(|(r, _, _)| *r == row) else {
+            return;
+        };
+        let line = &state.content[row];
+        let mut chars = text.chars();
+        let mut at = col;
+        for (part, &start) in breaks.iter().enumerate() {
+            let part_end = breaks.get(part + 1).copied().unwrap_or(usize::MAX);
+            if at >= part_end {
+                continue;
+            }
+            let piece: String = chars.by_ref().take(part_end - at).collect();
+            if piece.is_empty() || first + part >= visible_lines {
+                break;
+            }
+            let cell = gutter_width + width::column(line, at) - width::column(line, start);
+            renderer.put(top + first + part, cell, &piece, style);
+            at = part_end;
+        }
+    };
+    // Only pairs that could end up on screen are worth looking for.
+    let pair = match state.mode {
+        Mode::Normal | Mode::Insert => brackets::matching(
+            &state.content,
+            state.cursor,
+            state.indenter.language,
+            false,
+            visible_lines,
+        ),
+        _ => None,
+    };
+    let preview = state.typed_substitute();
+    let list = state.options.list.then(|| {
+        let chars = whitespace::ListChars::parse(&state.options.listchars).unwrap_or_default();
+        let color = render::parse_color(&state.options.listcolor).unwrap_or(render::BLUE);
+        (chars, Style::fg(color))
+    });
+    for (row, _, first) in &view {
+        let (row, line) = (*row, &state.content[*row]);
+        let screen_row = top + first;
+        state.gutter.render(
+            renderer,
+            screen_row,
+            row,
+            state.cursor.line(),
+            state.content.len(),
+            &marks,
+        );
+        if gutter_width >= cols {
+            continue;
+        }
+        match &preview {
+            Some(((start, end), substitute)) if (*start..=*end).contains(&row) => {
+                let (line, changes) = substitute.apply_marked(line);
+                draw_marked(renderer, screen_row, gutter_width, &line, &changes);
+            }
+            _ => {
+                put(renderer, row, 0, line, Style::default());
+                if let Some((chars, style)) = &list {
+                    for (col, symbol) in chars.marks(line) {
+                        put(renderer, row, col, &symbol.to_string(), *style);
+                    }
+                }
+            }
+        }
+    }
+    for diagnostic in &state.diagnostics {
+        let (first, last) = (diagnostic.start.line, diagnostic.end.line);
+        for row in first.max(state.row_offset)..=last.min(end.saturating_sub(1)) {
+            let line = &state.content[row];
+            let from = if row == first {
+                lsp::char_col(line, diagnostic.start.character)
+            } else {
+                0
+            };
+            let to = if row == last {
+                lsp::char_col(line, diagnostic.end.character)
+            } else {
+                line.chars().count()
+            };
+            // Empty ranges still mark the character they start at.
+            let text: String = line
+                .chars()
+                .skip(from)
+                .take(to.max(from + 1) - from)
+                .collect();
+            let style = Style {
+                fg: Some(kind_color(diagnostic.kind)),
+                underline: true,
+                ..Style::default()
+            };
+            put(renderer, row, from, &text, style);
+        }
+    }
+    let visible = state.row_offset..end;
+    for issue in state
+        .prose_issues
+        .iter()
+        .filter(|i| visible.contains(&i.row))
+    {
+        let line = &state.content[issue.row];
+        let len = issue.end.max(issue.start + 1) - issue.start;
+        let text: String = line.chars().skip(issue.start).take(len).collect();
+        let style = Style {
+            fg: Some(kind_color(issue.kind)),
+            underline: true,
+            ..Style::default()
+        };
+        put(renderer, issue.row, issue.start, &text, style);
+    }
+    if let Some((pattern, whole_word)) = state.highlighted_search().filter(|_| gutter_width < cols)
+    {
+        let style = Style {
+            bg: Some(render::YELLOW),
+            ..Style::default()
+        };
+        let len = pattern.chars().count();
+        for row in state.row_offset..end {
+            let line = &state.content[row];
+            for col in search_index::matches(line, pattern, whole_word) {
+                let text: String = line.chars().skip(col).take(len).collect();
+                put(renderer, row, col, &text, style);
+            }
+        }
+    }
+    if let Some(selection) = state.visual.filter(|_| state.mode == Mode::Visual) {
+        let (first, last) = selection.rows();
+        for row in first.max(state.row_offset)..=last.min(end.saturating_sub(1)) {
+            let line = &state.content[row];
+            let columns = selection.columns_on(row, position::graphemes(line));
+            // Past the end of the line the selection shows as blanks.
+            let text: String = line
+                .graphemes(true)
+                .chain(std::iter::repeat(" "))
+                .skip(columns.start)
+                .take(columns.len())
+                .collect();
+            let col = position::char_col(line, columns.start);
+            put(renderer, row, col, &text, Style::reverse());
+        }
+    }
+    for &cursor in &state.cursors {
+        if (state.row_offset..end).contains(&cursor.line()) {
+            let line = &state.content[cursor.line()];
+            let text = line.graphemes(true).nth(cursor.grapheme()).unwrap_or(" ");
+            put(
+                renderer,
+                cursor.line(),
+                cursor.char_col(line),
+                text,
+                Style::reverse(),
+            );
+        }
+    }
+    if let Some(Prompt::Substitute(confirm)) = &state.prompt {
+        let (row, byte) = confirm.at;
+        if (state.row_offset..end).contains(&row) {
+            let line = &state.content[row];
+            let text = &line[byte..byte + confirm.substitute.pattern.len()];
+            put(
+                renderer,
+                row,
+                line[..byte].chars().count(),
+                text,
+                Style::reverse(),
+            );
+        }
+    }
+    if let Some((_, to)) = pair.filter(|(_, to)| (state.row_offset..end).contains(&to.line())) {
+        let (row, line) = (to.line(), &state.content[to.line()]);
+        let col = to.char_col(line);
+        let c = line.chars().nth(col).unwrap_or(' ');
+        let style = Style {
+            bg: Some(render::CYAN),
+            ..Style::default()
+        };
+        put(renderer, row, col, &c.to_string(), style);
+    }
+}
+
+// Shows LOCAL, BASE and REMOTE side by side, each scrolled to its version of
+// the current conflict when it can be found there.
+fn draw_merge(state: &EditorState, renderer: &mut dyn Renderer) {
+    let height = state.merge_height();
+    let Some(merge) = &state.merge else {
+        return;
+    };
+    if height < 2 {
+        return;
+    }
+    let cols = state.screen_size.1;
+    let width = cols.saturating_sub(2) / 3;
+    let conflicts = merge::conflicts(&state.content);
+    let conflict = merge::current(&conflicts, state.cursor.line()).map(|i| &conflicts[i]);
+    for (n, pane) in merge.panes.iter().enumerate() {
+        let left = n * (width + 1);
+        if n > 0 {
+            for row in 0..height {
+                renderer.put(row, left - 1, "│", Style::default());
+            }
+        }
+        let title = format!(" {} {}", pane.title, pane.path);
+        renderer.put(0, left, &width::fit(&title, width), Style::reverse());
+
+        let section = conflict.and_then(|c| match n {
+            0 => Some(c.local()),
+            1 => c.base(),
+            _ => Some(c.remote()),
+        });
+        let lines: Vec<&str> = section.map_or(Vec::new(), |r| state.content.range(r).collect());
+        let found = pane.find(&lines);
+        let offset = match (found, conflict) {
+            (Some(start), _) => start,
+            // Fall back to the same relative position as in the result.
+            (None, Some(c)) => c.start * pane.lines.len() / state.content.len().max(1),
+            (None, None) => 0,
+        }
+        .saturating_sub((height - 1) / 3);
+        for row in 1..height {
+            let index = offset + row - 1;
+            let Some(line) = pane.lines.get(index) else {
+                break;
+            };
+            let text = width::truncate(line, width);
+            let in_section = found.is_some_and(|s| (s..s + lines.len()).contains(&index));
+            let style = if in_section {
+                Style::bold()
+            } else {
+                Style::default()
+            };
+            renderer.put(row, left, text, style);
+        }
+    }
+}
+
+// Draws a line with the given char ranges picked out.
+fn draw_marked(
+    renderer: &mut dyn Renderer,
+    row: usize,
+    col: usize,
+    line: &str,
+    marks: &[Range<usize>],
+) {
+    renderer.put(row, col, line, Style::default());
+    for mark in marks {
+        let text: String = line.chars().skip(mark.start).take(mark.len()).collect();
+        renderer.put(
+            row,
+            col + width::column(line, mark.start),
+            &text,
+            Style::reverse(),
+        );
+    }
+}
+
+// With `inccommand=split`, the output pane lists the lines a substitution
+// being typed would change.
+fn draw_substitute_preview(
+    state: &EditorState,
+    renderer: &mut dyn Renderer,
+    top: usize,
+    height: usize,
+) {
+    let Some(((start, end), substitute)) = state.typed_substitute() else {
+        return;
+    };
+    let cols = state.screen_size.1;
+    // One line more than fits tells whether there are more.
+    let changed: Vec<_> = (start..=end)
+        .filter_map(|row| {
+            let (line, marks) = substitute.apply_marked(&state.content[row]);
+            (!marks.is_empty()).then_some((row, line, marks))
+        })
+        .take(height)
+        .collect();
+    for (i, (row, line, marks)) in changed.iter().take(height - 1).enumerate() {
+        let number = format!("{:>5}| ", row + 1);
+        renderer.put(top + 1 + i, 0, &number, Style::fg(render::YELLOW));
+        draw_marked(renderer, top + 1 + i, number.len(), line, marks);
+    }
+    let count = if changed.len() < height {
+        format!("{} line(s)", changed.len())
+    } else {
+        format!("{}+ lines", height - 1)
+    };
+    let title = format!(" [Preview] {} ", count);
+    renderer.put(top, 0, &width::fit(&title, cols), Style::reverse());
+}
+
+fn draw_output(state: &EditorState, renderer: &mut dyn Renderer) {
+    let height = state.output_height();
+    if height == 0 {
+        return;
+    }
+    let cols = state.screen_size.1;
+    let top = state.merge_height() + state.content_height();
+    if state.split_preview() {
+        draw_substitute_preview(state, renderer, top, height);
+        return;
+    }
+    let title = format!(" [Output] {} ", state.output.title);
+    renderer.put(top, 0, &width::fit(&title, cols), Style::reverse());
+    for i in 0..height - 1 {
+        let index = state.output.scroll + i;
+        let Some(line) = state.output.lines.get(index) else {
+            break;
+        };
+        let used = renderer.put_ansi(top + 1 + i, 0, line, cols);
+        // Quickfix entries are marked across the whole output line.
+        let mark = match state.quickfix.is_entry_line(index) {
+            Some(qf) if qf == state.quickfix.current => Style::reverse(),
+            Some(_) => Style::underline(),
+            None => continue,
+        };
+        let text = output::strip_ansi(line);
+        renderer.put(top + 1 + i, 0, width::truncate(&text, used), mark);
+    }
+}
+
+fn draw_finder(state: &EditorState, renderer: &mut dyn Renderer) {
+    let lines = state.finder.visible_lines();
+    let title = if state.finder.scanning {
+        " Files (scanning…) ".to_string()
+    } else {
+        format!(
+            " Files ({}/{}) ",
+            state.finder.matches.len(),
+            state.finder.files.len()
+        )
+    };
+    Popup {
+        title: &title,
+        prompt: Some(&state.finder.query),
+        lines: &lines,
+        selected: (!lines.is_empty()).then_some(state.finder.selected),
+    }
+    .draw(renderer);
+}
+
+fn status_line(state: &EditorState) -> String {
+    if state.mode == Mode::Command {
+        format!(":{}", state.command_buffer)
+    } else if state.mode == Mode::Search {
+        let prefix = if state.search_forward { '/' } else { '?' };
+        format!("{}{}", prefix, state.command_buffer)
+    } else if state.mode == Mode::Prompt {
+        state.prompt_text()
+    } else {
+        format!(
+            " {} | {}{}{}{}{}{}{} | {} | {}:{} {}",
+            match state.mode {
+                Mode::Normal => "NORMAL",
+                Mode::Insert => "INSERT",
+                Mode::Visual => match state.visual.map(|v| v.kind) {
+                    Some(VisualKind::Line) => "V-LINE",
+                    Some(VisualKind::Block) => "V-BLOCK",
+                    _ => "VISUAL",
+                },
+                Mode::Command => "COMMAND",
+                Mode::Finder => "FINDER",
+                Mode::Prompt => "PROMPT",
+                Mode::Search => "SEARCH",
+            },
+            state.file_path,
+            if state.modified { " [+]" } else { "" },
+            if state.readonly { " [RO]" } else { "" },
+            if state.content.loading() {
+                format!(" [loading {}%]", state.content.progress())
+            } else {
+                String::new()
+            },
+            match &state.merge {
+                Some(_) => format!(" [{} conflicts]", merge::conflicts(&state.content).len()),
+                None => String::new(),
+            },
+            match state.cursors.len() {
+                0 => String::new(),
+                n => format!(" [{} cursors]", n + 1),
+            },
+            match state.macros.recording() {
+                Some(register) => format!(" [recording @{}]", register),
+                None => String::new(),
+            },
+            state.file_info(),
+            state.cursor.line() + 1,
+            state.cursor.grapheme() + 1,
+            state.status_message.as_deref().unwrap_or("")
+        )
+    }
+}
+
+fn handle_normal_mode(event: &KeyEvent, state: &mut EditorState) {
+    if handle_code_action_key(event, state) || handle_yank_picker_key(event, state) {
+        return;
+    }
+    if !state.cursors.is_empty() && state.pending.is_none() && handle_cursors_key(event, state) {
+        return;
+    }
+    if let Some(operator) = state.operator.take() {
+        handle_operator_key(event, state, operator);
+        return;
+    }
+    let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
+    if let Some(pending) = state.pending.take() {
+        let keys = match event.code {
+            KeyCode::Char(c) if !ctrl => format!("{}{}", pending, c),
+            _ => String::new(),
+        };
+        if let Some(command) = state.operators.get(&keys) {
+            let command = command.to_string();
+            state.start_operator(&keys, command);
+            return;
+        }
+        match (pending, event.code) {
+            ('g', KeyCode::Char('@')) => {
+                state.start_operator("g@", state.options.operatorfunc.clone())
+            }
+            (']', KeyCode::Char('x')) => state.jump_to_conflict(true),
+            (']', KeyCode::Char('c')) => state.jump_to_hunk(true),
+            ('[', KeyCode::Char('c')) => state.jump_to_hunk(false),
+            ('[', KeyCode::Char('x')) => state.jump_to_conflict(false),
+            ('g', KeyCode::Char('d')) => state.ask_lsp(false),
+            ('z', KeyCode::Char('=')) => state.open_code_actions(),
+            ('g', KeyCode::Char('j')) => state.move_display_line(true),
+            ('g', KeyCode::Char('k')) => state.move_display_line(false),
+            ('g', KeyCode::Char('0')) => state.move_to_display_line_edge(false),
+            ('g', KeyCode::Char('$')) => state.move_to_display_line_edge(true),
+            (']', KeyCode::Char('s')) => state.jump_to_issue(true),
+            ('[', KeyCode::Char('s')) => state.jump_to_issue(false),
+            ('q', KeyCode::Char(r)) if macros::is_register(r) => state.macros.start(r),
+            ('@', KeyCode::Char(r)) if macros::is_register(r) || r == '@' => state.play_register(r),
+            _ => {}
+        }
+        return;
+    }
+    if let KeyCode::Char(c) = event.code {
+        if let Some(command) = state.operators.get(&c.to_string()).filter(|_| !ctrl) {
+            let command = command.to_string();
+            state.start_operator(&c.to_string(), command);
+            return;
+        }
+    }
+    match event.code {
+        KeyCode::Char('h') | KeyCode::Left => {
+            state.cursor = state
+                .cursor
+                .to_grapheme(&state.content, state.cursor.grapheme().saturating_sub(1))
+        }
+        KeyCode::Char('j') | KeyCode::Down if state.moves_by_display_lines() => {
+            state.move_display_line(true)
+        }
+        KeyCode::Char('k') | KeyCode::Up if state.moves_by_display_lines() => {
+            state.move_display_line(false)
+        }
+        KeyCode::Char('j') | KeyCode::Down
+            if state.cursor.line() < state.content.len().saturating_sub(1) =>
+        {
+            state.cursor = state
+                .cursor
+                .to_line(&state.content, state.cursor.line() + 1);
+        }
+        KeyCode::Char('k') | KeyCode::Up => {
+            state.cursor = state
+                .cursor
+                .to_line(&state.content, state.cursor.line().saturating_sub(1));
+        }
+        KeyCode::Char('l') | KeyCode::Right => {
+            state.cursor = state
+                .cursor
+                .to_grapheme(&state.content, state.cursor.grapheme() + 1);
+        }
+        KeyCode::Char('i') if state.editable() => state.mode = Mode::Insert,
+        KeyCode::Char(':') => state.mode = Mode::Command,
+        KeyCode::Char('/') | KeyCode::Char('?') => {
+            state.search_forward = event.code == KeyCode::Char('/');
+            state.mode = Mode::Search;
+        }
+        KeyCode::Char('n') if event.modifiers.contains(KeyModifiers::CONTROL) => state.add_cursor(),
+        KeyCode::Char('n') => state.search(true),
+        KeyCode::Char('N') => state.search(false),
+        KeyCode::Char('*') => state.search_word_under_cursor(true),
+        KeyCode::Char('#') => state.search_word_under_cursor(false),
+        KeyCode::Char(c @ '1'..='4') if event.modifiers.contains(KeyModifiers::ALT) => {
+            state.jump_to_pin(c as usize - '0' as usize)
+        }
+        KeyCode::Char('0') => state.move_to_line_start(),
+        KeyCode::Char('$') => state.move_to_line_end(),
+        KeyCode::Char('%') => state.jump_to_match(),
+        KeyCode::Char('w') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+            state.save_file(false);
+        }
+        KeyCode::F(5) => state.rerun_command(),
+        KeyCode::Char('p') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+            state.open_finder()
+        }
+        KeyCode::Char('q') if event.modifiers.contains(KeyModifiers::CONTROL) => state.quit(false),
+        KeyCode::Char('z') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+            state.suspend_requested = true
+        }
+        KeyCode::Char('o') if state.editable() => state.open_line(state.cursor.line() + 1),
+        KeyCode::Char('O') if state.editable() => state.open_line(state.cursor.line()),
+        KeyCode::Char(c @ ('>' | '<' | '=')) => {
+            state.start_operator(&c.to_string(), builtin_operator(c).to_string())
+        }
+        KeyCode::Char('q') if state.macros.recording().is_some() => state.stop_recording(),
+        KeyCode::Char(c @ (']' | '[' | 'g' | 'z' | 'q' | '@')) => state.pending = Some(c),
+        KeyCode::Char('K') => state.ask_lsp(true),
+        KeyCode::Char(c @ ('p' | 'P')) if !ctrl => state.put(c == 'p'),
+        KeyCode::Char('v') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+            state.start_visual(VisualKind::Block)
+        }
+        KeyCode::Char('v') => state.start_visual(VisualKind::Char),
+        KeyCode::Char('V') => state.start_visual(VisualKind::Line),
+        KeyCode::Char('d')
+            if event.modifiers.contains(KeyModifiers::CONTROL)
+                && !state.content.is_empty()
+                && state.editable() =>
+        {
+            state.content.remove(state.cursor.line());
+            state.index.removed(state.cursor.line(), 1);
+            state.modified = true;
+            if state.content.is_empty() {
+                state.content.push(String::new());
+                state.index.inserted(0, 1);
+            }
+            state.adjust_column();
+        }
+        _ => {}
+    }
+}
+
+// The key after an operator: a motion, whose lines the operator then works
+// on, a text object (`ip`, `ap`), or the operator's last key again for the
+// current line. Anything else cancels it.
+fn handle_operator_key(event: &KeyEvent, state: &mut EditorState, mut operator: PendingOperator) {
+    let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
+    let row = state.cursor.line();
+    let range = match (operator.object, event.code) {
+        (Some(object), KeyCode::Char('p')) => {
+            operators::paragraph(&state.content, row, object == 'a')
+        }
+        (Some(_), _) => return,
+        (None, KeyCode::Char(c @ ('i' | 'a'))) => {
+            operator.object = Some(c);
+            state.operator = Some(operator);
+            return;
+        }
+        (None, KeyCode::Char(c)) if !ctrl && operator.keys.ends_with(c) => (row, row),
+        // Operators work on whole lines, so these go by lines even when `j`
+        // and `k` move by screen rows.
+        (None, KeyCode::Char('j') | KeyCode::Down) if !ctrl => {
+            (row, (row + 1).min(state.content.len() - 1))
+        }
+        (None, KeyCode::Char('k') | KeyCode::Up) if !ctrl => (row.saturating_sub(1), row),
+        (
+            None,
+            KeyCode::Char('h' | 'l' | '0' | '$' | '%' | 'n' | 'N' | '*' | '#')
+            | KeyCode::Left
+            | KeyCode::Right,
+        ) if !ctrl => {
+            let start = state.cursor;
+            handle_normal_mode(event, state);
+            let target = state.cursor.line();
+            state.cursor = start;
+            (row.min(target), row.max(target))
+        }
+        _ => return,
+    };
+    state.run_operator(&operator.command, range);
+}
+
+// Normal-mode keys while there are extra cursors: motions move every
+// cursor, `i` inserts at all of them and Esc drops the extras. Anything
+// else drops them too before it is handled as usual.
+fn handle_cursors_key(event: &KeyEvent, state: &mut EditorState) -> bool {
+    let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
+    match event.code {
+        KeyCode::Char('n') if ctrl => state.add_cursor(),
+        KeyCode::Esc => state.cursors.clear(),
+        KeyCode::Char('i') => return false,
+        KeyCode::Char('h' | 'j' | 'k' | 'l' | '0' | '$')
+        | KeyCode::Left
+        | KeyCode::Right
+        | KeyCode::Up
+        | KeyCode::Down
+            if !ctrl =>
+        {
+            let primary = state.cursor;
+            let mut cursors = std::mem::take(&mut state.cursors);
+            for cursor in &mut cursors {
+                state.cursor = *cursor;
+                handle_normal_mode(event, state);
+                *cursor = state.cursor;
+            }
+            state.cursor = primary;
+            handle_normal_mode(event, state);
+            state.cursors = cursors;
+            state.merge_cursors();
+        }
+        _ => {
+            state.cursors.clear();
+            return false;
+        }
+    }
+    true
+}
+
+// Repeats an insert-mode edit at every cursor, from the last one back so
+// that each edit only moves the cursors already done, which are then
+// fixed up: those on the edited line keep their distance from its end and
+// those below follow the change in line count.
+fn insert_at_cursors(event: &KeyEvent, state: &mut EditorState) {
+    let mut all: Vec<(Position, bool)> = state.cursors.drain(..).map(|c| (c, false)).collect();
+    all.push((state.cursor, true));
+    all.sort();
+    let mut done: Vec<(Position, bool)> = Vec::new();
+    for (at, primary) in all.into_iter().rev() {
+        let line_len = position::graphemes(&state.content[at.line()]);
+        let line_count = state.content.len();
+        state.cursor = at;
+        handle_insert_mode(event, state);
+        let to = state.cursor;
+        let new_len = position::graphemes(&state.content[to.line()]);
+        for (cursor, _) in &mut done {
+            if cursor.line() == at.line() {
+                let grapheme = (new_len + cursor.grapheme())
+                    .saturating_sub(line_len)
+                    .max(to.grapheme());
+                *cursor = Position::clamped(&state.content, to.line(), grapheme);
+            } else {
+                let line = (cursor.line() + state.content.len()).saturating_sub(line_count);
+                *cursor = cursor.to_line(&state.content, line);
+            }
+        }
+        done.push((to, primary));
+    }
+    for (cursor, primary) in done {
+        if primary {
+            state.cursor = cursor;
+        } else {
+            state.cursors.push(cursor);
+        }
+    }
+    state.merge_cursors();
+}
+
+// Visual mode moves the cursor like normal mode, with the selection
+// following it. `:` starts a command on the selected lines.
+fn handle_visual_mode(event: &KeyEvent, state: &mut EditorState) {
+    let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
+    let kind = match event.code {
+        KeyCode::Char('v') if ctrl => Some(VisualKind::Block),
+        KeyCode::Char('v') => Some(VisualKind::Char),
+        KeyCode::Char('V') => Some(VisualKind::Line),
+        _ => None,
+    };
+    let Some(selection) = &mut state.visual else {
+        state.mode = Mode::Normal;
+        return;
+    };
+    match (kind, event.code) {
+        (Some(kind), _) if kind == selection.kind => state.mode = Mode::Normal,
+        (Some(kind), _) => selection.kind = kind,
+        (_, KeyCode::Esc) => state.mode = Mode::Normal,
+        (_, KeyCode::Char('c')) if ctrl => state.mode = Mode::Normal,
+        (_, KeyCode::Char('o')) => {
+            std::mem::swap(&mut selection.anchor, &mut state.cursor);
+            state.adjust_column();
+        }
+        (_, KeyCode::Char(':')) => {
+            state.command_buffer = "'<,'>".to_string();
+            state.mode = Mode::Command;
+        }
+        (_, KeyCode::Char(c @ ('>' | '<' | '='))) => {
+            let rows = selection.rows();
+            state.mode = Mode::Normal;
+            state.run_operator(builtin_operator(c), rows);
+            return;
+        }
+        (_, KeyCode::Char('y')) => {
+            let selection = *selection;
+            state.mode = Mode::Normal;
+            state.yank_selection(&selection);
+            return;
+        }
+        (_, KeyCode::Char(c @ ('I' | 'A'))) if selection.kind == VisualKind::Block => {
+            let selection = *selection;
+            state.insert_in_block(&selection, c == 'A');
+            return;
+        }
+        (
+            _,
+            KeyCode::Char('h' | 'j' | 'k' | 'l' | '0' | '$' | '%' | 'n' | 'N' | '*' | '#')
+            | KeyCode::Left
+            | KeyCode::Right
+            | KeyCode::Up
+            | KeyCode::Down,
+        ) if !ctrl => handle_normal_mode(event, state),
+        _ => {}
+    }
+    if let Some(selection) = &mut state.visual {
+        selection.cursor = state.cursor;
+    }
+}
+
+// Keys for the completion menu while it is open. Anything it doesn't use
+// closes it, except typing, which narrows it down.
+fn handle_completion_key(event: &KeyEvent, state: &mut EditorState) -> bool {
+    let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
+    let Some(completion) = &mut state.completion else {
+        return false;
+    };
+    match event.code {
+        KeyCode::Char('n') if ctrl => completion.select(true),
+        KeyCode::Char('p') if ctrl => completion.select(false),
+        KeyCode::Down => completion.select(true),
+        KeyCode::Up => completion.select(false),
+        KeyCode::Enter | KeyCode::Tab => {
+            state.modified = true;
+            state.accept_completion();
+        }
+        KeyCode::Char(_) | KeyCode::Backspace if !ctrl => return false,
+        _ => {
+            state.completion = None;
+            return false;
+        }
+    }
+    true
+}
+
+// Keys for the code-action menu while it is open. Anything else closes it.
+fn handle_code_action_key(event: &KeyEvent, state: &mut EditorState) -> bool {
+    let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
+    let Some((fixes, selected)) = &mut state.code_actions else {
+        return false;
+    };
+    let next = match event.code {
+        KeyCode::Char('n') if ctrl => Some(true),
+        KeyCode::Char('p') if ctrl => Some(false),
+        KeyCode::Char('j') | KeyCode::Down => Some(true),
+        KeyCode::Char('k') | KeyCode::Up => Some(false),
+        _ => None,
+    };
+    match event.code {
+        _ if next == Some(true) => *selected = (*selected + 1) % fixes.len(),
+        _ if next == Some(false) => *selected = (*selected + fixes.len() - 1) % fixes.len(),
+        KeyCode::Enter => {
+            let (mut fixes, selected) = state.code_actions.take().expect("menu is open");
+            state.apply_fix(fixes.swap_remove(selected));
+        }
+        _ => state.code_actions = None,
+    }
+    true
+}
+
+// Keys for the `:Yanks` picker: Enter yanks the entry again, `p` and `P`
+// also put it, `d` drops it from the history and anything else closes it.
+fn handle_yank_picker_key(event: &KeyEvent, state: &mut EditorState) -> bool {
+    let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
+    let Some(selected) = state.yank_picker else {
+        return false;
+    };
+    let count = state.yanks.len();
+    match event.code {
+        KeyCode::Char('n') if ctrl => state.yank_picker = Some((selected + 1) % count),
+        KeyCode::Char('p') if ctrl => state.yank_picker = Some((selected + count - 1) % count),
+        KeyCode::Char('j') | KeyCode::Down => state.yank_picker = Some((selected + 1) % count),
+        KeyCode::Char('k') | KeyCode::Up => {
+            state.yank_picker = Some((selected + count - 1) % count)
+        }
+        KeyCode::Char('d') => {
+            state.yanks.remove(selected);
+            state.yank_picker = match state.yanks.len() {
+                0 => None,
+                n => Some(selected.min(n - 1)),
+            };
+        }
+        KeyCode::Enter | KeyCode::Char('p' | 'P') => {
+            state.yank_picker = None;
+            state.yanks.promote(selected);
+            match event.code {
+                KeyCode::Char(c) => state.put(c == 'p'),
+                _ => state.status_message = Some("Yanked again".to_string()),
+            }
+        }
+        _ => state.yank_picker = None,
+    }
+    true
+}
+
+fn handle_insert_mode(event: &KeyEvent, state: &mut EditorState) {
+    if !state.cursors.is_empty() {
+        let typing = matches!(event.code, KeyCode::Char(_))
+            && !event
+                .modifiers
+                .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT);
+        if typing
+            || matches!(
+                event.code,
+                KeyCode::Backspace | KeyCode::Delete | KeyCode::Enter
+            )
+        {
+            insert_at_cursors(event, state);
+        } else if event.code == KeyCode::Esc {
+            state.mode = Mode::Normal;
+        }
+        return;
+    }
+    if handle_completion_key(event, state) {
+        return;
+    }
+    let completing = state.completion.is_some();
+    if matches!(
+        event.code,
+        KeyCode::Backspace | KeyCode::Delete | KeyCode::Enter | KeyCode::Char(_)
+    ) {
+        state.modified = true;
+        state.index.changed(state.cursor.line());
+    }
+    match event.code {
+        KeyCode::Esc => state.mode = Mode::Normal,
+        KeyCode::Backspace => {
+            if state.cursor.grapheme() > 0 {
+                let before = state
+                    .cursor
+                    .to_grapheme(&state.content, state.cursor.grapheme() - 1);
+                let line = state.content.line_mut(state.cursor.line());
+                line.replace_range(before.byte(line)..state.cursor.byte(line), "");
+                state.cursor = before;
+            } else if state.cursor.line() > 0 {
+                let current_line = state.content.remove(state.cursor.line());
+                state.index.removed(state.cursor.line(), 1);
+                state.cursor =
+                    Position::clamped(&state.content, state.cursor.line() - 1, usize::MAX);
+                state.index.changed(state.cursor.line());
+                state
+                    .content
+                    .line_mut(state.cursor.line())
+                    .push_str(&current_line);
+            }
+        }
+        KeyCode::Delete => {
+            let after = state
+                .cursor
+                .to_grapheme(&state.content, state.cursor.grapheme() + 1);
+            let line = state.content.line_mut(state.cursor.line());
+            line.replace_range(state.cursor.byte(line)..after.byte(line), "");
+        }
+        KeyCode::Enter => {
+            let current_line = state.content[state.cursor.line()].to_string();
+            let (left, right) = current_line.split_at(state.cursor.byte(&current_line));
+            state.content.set(state.cursor.line(), left.to_string());
+            state
+                .content
+                .insert(state.cursor.line() + 1, right.trim_start().to_string());
+            state.index.inserted(state.cursor.line() + 1, 1);
+            let row = state.cursor.line() + 1;
+            let indent = state.indenter.indent_for(&state.content, row);
+            state.content.line_mut(row).insert_str(0, &indent);
+            state.cursor = Position::clamped(&state.content, row, position::graphemes(&indent));
+        }
+        KeyCode::Char(c @ ('n' | 'p')) if event.modifiers.contains(KeyModifiers::CONTROL) => {
+            state.start_completion(c == 'n');
+        }
+        KeyCode::Char(c) => {
+            if c.is_control()
+                || event
+                    .modifiers
+                    .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+            {
+                return;
+            }
+            // A combining mark joins the grapheme before it, so the cursor
+            // goes after the typed char rather than one grapheme on.
+            let col = state.cursor_col();
+            let line = state.content.line_mut(state.cursor.line());
+            let at = state.cursor.byte(line);
+            line.insert(at, c);
+            state.cursor = state.at_char(state.cursor.line(), col + 1);
+            if matches!(c, '}' | ')' | ']')
+                && state.content[state.cursor.line()].trim() == c.to_string()
+            {
+                state.reindent_line(state.cursor.line());
+            }
+        }
+        _ => {}
+    }
+    if completing {
+        state.filter_completion();
+    }
+}
+
+fn handle_finder_mode(event: &KeyEvent, state: &mut EditorState) {
+    let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
+    match event.code {
+        KeyCode::Esc => state.mode = Mode::Normal,
+        KeyCode::Enter => {
+            if let Some(path) = state.finder.selection().map(|p| p.to_string()) {
+                if state.open_file(&path) {
+                    state.status_message = Some(format!("\"{}\"", path));
+                }
+            }
+            state.mode = Mode::Normal;
+        }
+        KeyCode::Down | KeyCode::Tab => state.finder.select_next(),
+        KeyCode::Char('n') | KeyCode::Char('j') if ctrl => state.finder.select_next(),
+        KeyCode::Up | KeyCode::BackTab => state.finder.select_prev(),
+        KeyCode::Char('p') | KeyCode::Char('k') if ctrl => state.finder.select_prev(),
+        KeyCode::Backspace => {
+            state.finder.query.pop();
+            state.finder.update();
+        }
+        KeyCode::Char(c) if !ctrl => {
+            state.finder.query.push(c);
+            state.finder.update();
+        }
+        _ => {}
+    }
+}
+
+fn handle_prompt_mode(event: &KeyEvent, state: &mut EditorState) {
+    let Some(prompt) = state.prompt.take() else {
+        state.mode = Mode::Normal;
+        return;
+    };
+    match (prompt, event.code) {
+        (Prompt::RecoverSwap(info), KeyCode::Char('r')) => {
+            state.content = Buffer::from_lines(info.content);
+            state.index.reset(state.content.len());
+            state.modified = true;
+            state.adjust_column();
+            state.status_message =
+                Some("Recovered from swap file; :w to keep the changes".to_string());
+        }
+        (Prompt::RecoverSwap(_), KeyCode::Char('d')) => {
+            swap::remove(&state.swap_dir(), &state.file_path)
+        }
+        (Prompt::RecoverSwap(_), KeyCode::Char('e')) => {}
+        (Prompt::RecoverSwap(_), KeyCode::Char('q')) => state.should_exit = true,
+        (Prompt::RestoreSession(crashed, _), KeyCode::Char('r')) => state.restore_session(crashed),
+        (Prompt::RestoreSession(crashed, pending), KeyCode::Char('p')) => {
+            state.preview_recovery(&crashed);
+            state.prompt = Some(Prompt::RestoreSession(crashed, pending));
+            return;
+        }
+        (Prompt::RestoreSession(crashed, _), KeyCode::Char(c @ ('d' | 'e'))) => {
+            if c == 'd' {
+                autosession::discard(&state.sessions_dir(), crashed.pid);
+            }
+            // The file opened now may have a swap file of its own.
+            state.mode = Mode::Normal;
+            state.check_swap();
+            return;
+        }
+        (Prompt::Substitute(confirm), code) => {
+            state.answer_substitute(confirm, code);
+            return;
+        }
+        (prompt, _) => {
+            state.prompt = Some(prompt);
+            return;
+        }
+    }
+    state.mode = Mode::Normal;
+}
+
+fn handle_cmdline_key(event: &KeyEvent, state: &mut EditorState) {
+    match event.code {
+        KeyCode::Enter if state.mode == Mode::Search => {
+            state.end_preview();
+            if !state.command_buffer.is_empty() {
+                state.last_search = Some((state.command_buffer.clone(), false));
+            }
+            state.command_buffer.clear();
+            state.mode = Mode::Normal;
+            state.search(true);
+        }
+        KeyCode::Enter => {
+            state.end_preview();
+            handle_command_mode(state);
+        }
+        KeyCode::Char(c) => {
+            state.command_buffer.push(c);
+            if state.mode == Mode::Command {
+                state.preview_range();
+            } else {
+                state.preview_search();
+            }
+        }
+        KeyCode::Backspace => {
+            state.command_buffer.pop();
+            if state.mode == Mode::Command {
+                state.preview_range();
+            } else {
+                state.preview_search();
+            }
+        }
+        KeyCode::Esc => {
+            state.end_preview();
+            state.mode = Mode::Normal;
+            state.command_buffer.clear();
+        }
+        _ => {}
+    }
+}
+
+// The Ex commands run by the built-in operators, as for `:operator`.
+fn builtin_operator(key: char) -> &'static str {
+    match key {
+        '>' => ">",
+        '<' => "<",
+        _ => "Reindent",
+    }
+}
+
+// Whether `command` is `:>` (true) or `:<` (false), shifting as many times
+// as it has characters.
+fn shift_command(command: &str) -> Option<bool> {
+    let right = command.starts_with('>');
+    let c = if right { '>' } else { '<' };
+    (!command.is_empty() && command.chars().all(|x| x == c)).then_some(right)
+}
+
+// The command of `:w !cmd`. `:w!` without a space is a forced write instead.
+fn write_command(command: &str) -> Option<&str> {
+    let rest = command
+        .strip_prefix("write ")
+        .or_else(|| command.strip_prefix("w "))?;
+    Some(rest.trim_start().strip_prefix('!')?.trim())
+}
+
+// Splits off the line range and expands abbreviations and user commands in
+// what follows.
+fn parse_command(state: &EditorState, text: &str) -> Result<(Option<LineRange>, String), String> {
+    let (cursor, len, visual) = (
+        state.cursor.line(),
+        state.content.len(),
+        state.visual_rows(),
+    );
+    let (range, rest) = range::parse(text, cursor, len, visual)?;
+    let expanded = state.aliases.expand(rest.trim_start());
+    if range.is_some() {
+        return Ok((range, expand_command(state, &expanded)?));
+    }
+    // A user command may bring its own range, like `:command Sort %!sort`.
+    let (range, rest) = range::parse(&expanded, cursor, len, visual)?;
+    Ok((range, expand_command(state, rest)?))
+}
+
+fn expand_command(state: &EditorState, command: &str) -> Result<String, String> {
+    if !expand::takes_files(command) {
+        return Ok(command.to_string());
+    }
+    let context = expand::Context {
+        file: &state.file_path,
+        alternate: state.alternate_file.as_deref(),
+        line: &state.content[state.cursor.line()],
+        col: state.cursor_col(),
+    };
+    expand::expand(command, &context)
+}
+
+fn handle_command_mode(state: &mut EditorState) {
+    state.content.finish_loading();
+    let (range, command) = match parse_command(state, &state.command_buffer) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            state.status_message = Some(e);
+            state.command_buffer.clear();
+            state.mode = Mode::Normal;
+            return;
+        }
+    };
+    if let Some(range) = range {
+        let columns = state.block_columns(&state.command_buffer);
+        state.run_ranged(range, command.trim(), columns);
+        state.command_buffer.clear();
+        if state.mode == Mode::Command {
+            state.mode = Mode::Normal;
+        }
+        return;
+    }
+    let command = command.as_str();
+    let (name, args) = match command.split_once(' ') {
+        Some((name, args)) => (name, args.trim()),
+        None => (command, ""),
+    };
+    match name {
+        _ if write_command(command).is_some() => {
+            state.run_ranged((0, state.content.len() - 1), command, None);
+        }
+        "sort" | "sor" | "sort!" | "sor!" | "StripWhitespace" | "Reindent" => {
+            state.run_ranged((0, state.content.len() - 1), command, None);
+        }
+        _ if shift_command(command).is_some() => {
+            state.run_ranged((state.cursor.line(), state.cursor.line()), command, None);
+        }
+        "w" | "w!" => {
+            state.save_file(name == "w!");
+        }
+        "mksession" | "mks" | "mksession!" | "mks!" => {
+            let path = if args.is_empty() {
+                session::DEFAULT_FILE
+            } else {
+                args
+            };
+            let commands = state.session_commands();
+            state.status_message =
+                Some(match session::save(path, &commands, name.ends_with('!')) {
+                    Ok(()) => format!("Session saved to {}", path),
+                    Err(e) => e,
+                });
+        }
+        "source" | "so" if !args.is_empty() => state.source(args),
+        "cd" if args.is_empty() => state.show_cwd(),
+        "pwd" => state.show_cwd(),
+        "cd" => match std::env::set_current_dir(args) {
+            Ok(()) => state.show_cwd(),
+            Err(e) => {
+                state.status_message = Some(format!("Can't change directory to {}: {}", args, e))
+            }
+        },
+        "cursor" => {
+            let mut numbers = args.split_whitespace().map(|n| n.parse::<usize>());
+            match (numbers.next(), numbers.next().unwrap_or(Ok(1))) {
+                (Some(Ok(line)), Ok(col)) => {
+                    state.cursor = Position::clamped(
+                        &state.content,
+                        line.saturating_sub(1),
+                        col.saturating_sub(1),
+                    );
+                }
+                _ => state.status_message = Some("Usage: :cursor LINE [COLUMN]".to_string()),
+            }
+        }
+        "GitRefresh" => state.start_git_diff(true),
+        "writefilter" | "wf" if args.is_empty() => {
+            state
+                .output
+                .show("Write filters", state.write_filters.lines());
+        }
+        "writefilter" | "wf" => match args.split_once(' ') {
+            Some((key, cmd)) => state.write_filters.set(key, cmd.trim()),
+            None => match state.write_filters.get(args) {
+                Some(cmd) => state.status_message = Some(format!("{}  {}", args, cmd)),
+                None => state.status_message = Some(format!("No write filter for {}", args)),
+            },
+        },
+        "completesources" if args.is_empty() => {
+            state
+                .output
+                .show("Completion sources", state.completion_sources.lines());
+        }
+        "completesources" => match args.split_once(' ') {
+            Some((filetype, sources)) => {
+                let sources = sources.split_whitespace().map(String::from).collect();
+                state.completion_sources.set(filetype, sources);
+            }
+            None => {
+                let sources = state.completion_sources.get_filetype(args).join(" ");
+                state.status_message = Some(format!("{}  {}", args, sources));
+            }
+        },
+        "snippet" if args.is_empty() => {
+            state
+                .output
+                .show("Snippets", state.completion_sources.snippet_lines());
+        }
+        "snippet" => match args.split_once(' ') {
+            Some((name, text)) => state.completion_sources.define_snippet(name, text.trim()),
+            None => state.status_message = Some(format!("Missing text for snippet {}", args)),
+        },
+        "ProseCheck" => state.check_prose(true),
+        "ProseChecker" if args.is_empty() => {
+            state.status_message = Some(
+                state
+                    .prose_checker
+                    .clone()
+                    .unwrap_or_else(|| "No prose checker".to_string()),
+            )
+        }
+        "ProseChecker" => state.prose_checker = Some(args.to_string()),
+        "ProseChecker!" => {
+            state.prose_checker = None;
+            state.prose_issues.clear();
+        }
+        "CodeAction" => state.open_code_actions(),
+        "linter" if args.is_empty() => state.output.show("Linters", state.linters.lines()),
+        "linter" => match args.split_once(' ') {
+            Some((key, cmd)) => state.linters.set(key, cmd.trim()),
+            None => match state.linters.get(args) {
+                Some(linter) => {
+                    state.status_message = Some(format!("{}  {}", args, linter.command))
+                }
+                None => state.status_message = Some(format!("No linter for {}", args)),
+            },
+        },
+        "linter!" => {
+            if !state.linters.remove(args) {
+                state.status_message = Some(format!("No linter for {}", args));
+            }
+        }
+        "lintformat" => match args.split_once(' ') {
+            Some((key, format)) => {
+                if let Err(e) = state.linters.set_format(key, format.trim()) {
+                    state.status_message = Some(e);
+                }
+            }
+            None => state.status_message = Some("Usage: :lintformat KEY FORMAT".to_string()),
+        },
+        "Lint" => state.lint(true),
+        "Repl" => state.start_repl(args),
+        "Repl!" => state.repl = None,
+        "ReplSend" => state.send_to_repl(state.cursor.line(), state.cursor.line()),
+        "operator" | "op" if args.is_empty() => {
+            state.output.show("Operators", state.operators.lines())
+        }
+        "operator" | "op" => match args.split_once(' ') {
+            Some((keys, command)) => {
+                if let Err(e) = state.operators.define(keys, command.trim()) {
+                    state.status_message = Some(e);
+                }
+            }
+            None => state.status_message = Some(format!("Missing command for operator {}", args)),
+        },
+        "operator!" | "op!" => {
+            if !state.operators.remove(args) {
+                state.status_message = Some(format!("No such operator: {}", args));
+            }
+        }
+        "memory" | "mem" => state.show_memory(),
+        "snapshot" => state.snapshot(args),
+        "trigger" => state.trigger(args),
+        "trigger!" => {
+            if !state.macros.remove(args) {
+                state.status_message = Some(format!("No trigger {}", args));
+            }
+        }
+        "Yanks" => state.open_yank_picker(),
+        "Pin" => state.pin(),
+        "Pins" if args.is_empty() => {
+            let pins = state.pins();
+            if pins.is_empty() {
+                state.status_message = Some("No pins".to_string());
+            } else {
+                let lines = pins.lines();
+                state.output.show("Pins", lines);
+            }
+        }
+        "Pins" => match args.parse() {
+            Ok(slot) => state.jump_to_pin(slot),
+            Err(_) => state.status_message = Some("Usage: :Pins [N]".to_string()),
+        },
+        "Unpin" => state.unpin(args),
+        "TOhtml" | "TOhtml!" => state.export("html", args, name.ends_with('!')),
+        "TOansi" | "TOansi!" => state.export("ansi", args, name.ends_with('!')),
+        "hardcopy" | "ha" | "hardcopy!" | "ha!" => state.export("pdf", args, name.ends_with('!')),
+        "bugreport" | "bugreport!" => {
+            let path = if args.is_empty() {
+                bugreport::DEFAULT_FILE
+            } else {
+                args
+            };
+            state.write_bug_report(path, name.ends_with('!'));
+        }
+        "Plugins" => state.output.show("Plugins", state.scripts.lines()),
+        "checkhealth" => state.check_health(),
+        _ if state.scripts.has_command(name) => {
+            let name = name.to_string();
+            let args = args.to_string();
+            state.with_scripts(|scripts| scripts.run_command(&name, &args));
+        }
+        "ConfigShow" => state.show_config(),
+        "nohlsearch" | "noh" => state.highlight_cleared = true,
+        "lsp" if args.is_empty() => state.output.show("Language servers", state.servers.lines()),
+        "lsp" => match args.split_once(' ') {
+            Some((key, cmd)) => state.servers.set(key, cmd.trim()),
+            None => match state.servers.get(args) {
+                Some(cmd) => state.status_message = Some(format!("{}  {}", args, cmd)),
+                None => state.status_message = Some(format!("No language server for {}", args)),
+            },
+        },
+        "lsp!" => {
+            if !state.servers.remove(args) {
+                state.status_message = Some(format!("No language server for {}", args));
+            }
+        }
+        "LspRestart" => state.lsp_started = false,
+        "LspStop" => {
+            state.lsp = None;
+            state.diagnostics.clear();
+        }
+        "writefilter!" | "wf!" => {
+            if !state.write_filters.remove(args) {
+                state.status_message = Some(format!("No write filter for {}", args));
+            }
+        }
+        "completesources!" => {
+            if !state.completion_sources.remove(args) {
+                state.status_message = Some(format!("No completion sources set for {}", args));
+            }
+        }
+        "snippet!" => {
+            if !state.completion_sources.remove_snippet(args) {
+                state.status_message = Some(format!("No snippet {}", args));
+            }
+        }
+        "q" | "q!" => state.quit(name == "q!"),
+        "cq" | "cq!" | "cquit" | "cquit!" => state.cquit(args),
+        "Delete" => state.delete_file(),
+        "set" | "se" if args.is_empty() => state.status_message = Some(state.options.summary()),
+        "set" | "se" => {
+            let statedir = dirs::state_dir(&state.options.statedir);
+            for arg in options::split_args(args) {
+                let arg = arg.as_str();
+                match state
+                    .parse_set_arg(arg)
+                    .and_then(|arg| state.apply_set_arg(arg))
+                {
+                    Ok(message) => state.status_message = message,
+                    Err(e) => {
+                        state.status_message = Some(e);
+                        break;
+                    }
+                }
+            }
+            if dirs::state_dir(&state.options.statedir) != statedir {
+                state.migrate_state(&statedir);
+            }
+        }
+        "undelete" => state.undelete(args),
+        _ if keymap::command(name).is_some() => state.map_command(name, args),
+        "cabbrev" | "ca" | "command" | "com" if args.is_empty() => {
+            let (title, lines) = if name.starts_with("ca") {
+                ("Abbreviations", state.aliases.abbreviation_lines())
+            } else {
+                ("User commands", state.aliases.command_lines())
+            };
+            state.output.show(title, lines);
+        }
+        "cabbrev" | "ca" | "command" | "com" => match args.split_once(' ') {
+            Some((lhs, rhs)) if name.starts_with("ca") => {
+                state.aliases.abbreviate(lhs, rhs.trim());
+            }
+            Some((lhs, rhs)) => {
+                if let Err(e) = state.aliases.define(lhs, rhs.trim()) {
+                    state.status_message = Some(e);
+                }
+            }
+            None => state.status_message = Some(format!("Missing replacement for {}", args)),
+        },
+        "cunabbrev" | "cuna" => {
+            if !state.aliases.unabbreviate(args) {
+                state.status_message = Some(format!("No such abbreviation: {}", args));
+            }
+        }
+        "delcommand" | "delc" => {
+            if !state.aliases.undefine(args) {
+                state.status_message = Some(format!("No such user-defined command: {}", args));
+            }
+        }
+        "ConvertEncoding" => state.convert_encoding(args),
+        "wq" | "wq!" => {
+            let left = match &state.merge {
+                Some(_) => merge::conflicts(&state.content).len(),
+                None => 0,
+            };
+            if left > 0 && name == "wq" {
+                state.status_message =
+                    Some(format!("{} conflict(s) left (add ! to override)", left));
+            } else {
+                state.should_exit = state.save_file(name == "wq!");
+            }
+        }
+        "diffget" | "dg" if state.merge.is_some() => state.pick_conflict(args),
+        "e" | "edit" if args.is_empty() => state.reload(false),
+        "e!" | "edit!" if args.is_empty() => state.reload(true),
+        "e" | "edit" => {
+            if state.open_file(args) {
+                state.status_message = Some(format!("\"{}\"", args));
+            }
+        }
+        _ if substitute::strip_name(command).is_some() => {
+            state.run_ranged((state.cursor.line(), state.cursor.line()), command, None);
+        }
+        _ if command.starts_with('!') => state.run_shell(command[1..].trim()),
+        "r" | "read" if args.starts_with('!') => state.read_command(args[1..].trim()),
+        "r" | "read" if !args.is_empty() => state.read_file_below(args),
+        "make" => {
+            let cmd = if args.is_empty() {
+                "make".to_string()
+            } else {
+                format!("make {}", args)
+            };
+            state.run_command(&cmd);
+        }
+        "copen" => state.output.visible = true,
+        "cclose" => state.output.visible = false,
+        "cn" | "cnext" => state.jump_to_quickfix(state.quickfix.current + 1),
+        "cp" | "cprev" => match state.quickfix.current.checked_sub(1) {
+            Some(index) => state.jump_to_quickfix(index),
+            None => state.status_message = Some("No more items".to_string()),
+        },
+        "cc" => {
+            let index = args.parse::<usize>().unwrap_or(state.quickfix.current + 1);
+            state.jump_to_quickfix(index.saturating_sub(1));
+        }
+        _ => state.status_message = Some(format!("Unknown command: {}", state.command_buffer)),
+    }
+    state.command_buffer.clear();
+    // Commands like `:s///c` leave a prompt up.
+    if state.mode == Mode::Command {
+        state.mode = Mode::Normal;
+    }
+}
+
+fn draw_frame(state: &EditorState, renderer: &mut dyn Renderer) {
+    let (rows, cols) = state.screen_size;
+    renderer.begin(rows, cols);
+    // A terminal can briefly report no size at all while it is resized.
+    if rows == 0 || cols == 0 {
+        renderer.set_cursor(0, 0);
+        return;
+    }
+    draw_merge(state, renderer);
+    draw_content(state, renderer);
+    draw_output(state, renderer);
+    let status = Style {
+        fg: Some(render::WHITE),
+        bg: Some(render::BLUE),
+        ..Style::default()
+    };
+    renderer.put(
+        rows - 1,
+        0,
+        &width::fit(&status_line(state), cols - 1),
+        status,
+    );
+    if state.mode == Mode::Finder {
+        draw_finder(state, renderer);
+    }
+    if let Some(completion) = state
+        .completion
+        .as_ref()
+        .filter(|_| state.mode == Mode::Insert)
+    {
+        let (row, col) = state.screen_position(state.at_char(
+            completion.row.min(state.content.len() - 1),
+            completion.start,
+        ));
+        let (row, col) = (
+            state.merge_height() + row,
+            col + state.gutter.width(state.content.len()),
+        );
+        Menu {
+            lines: &completion.labels,
+            selected: completion.selected,
+        }
+        .draw(renderer, row, col);
+    }
+    if let Some((fixes, selected)) = state
+        .code_actions
+        .as_ref()
+        .filter(|_| state.mode == Mode::Normal)
+    {
+        let lines: Vec<String> = fixes.iter().map(|f| f.label.clone()).collect();
+        let (row, col) = state.screen_position(state.at_char(fixes[0].row, fixes[0].start));
+        let (row, col) = (
+            state.merge_height() + row,
+            col + state.gutter.width(state.content.len()),
+        );
+        Menu {
+            lines: &lines,
+            selected: *selected,
+        }
+        .draw(renderer, row, col);
+    }
+    if let Some(selected) = state.yank_picker.filter(|_| state.mode == Mode::Normal) {
+        Popup {
+            title: " Yanks ",
+            prompt: None,
+            lines: &state.yanks.lines(),
+            selected: Some(selected),
+        }
+        .draw(renderer);
+    }
+    let (row, col) = state.screen_position(state.cursor);
+    renderer.set_cursor(
+        (state.merge_height() + row).min(rows - 1),
+        (col + state.gutter.width(state.content.len())).min(cols - 1),
+    );
+}
+
+fn handle_key(state: &mut EditorState, key_event: KeyEvent) {
+    if !matches!(state.mode, Mode::Command | Mode::Search) {
+        state.status_message = None;
+    }
+    match state.mode {
+        Mode::Normal => handle_normal_mode(&key_event, state),
+        Mode::Insert => handle_insert_mode(&key_event, state),
+        Mode::Visual => handle_visual_mode(&key_event, state),
+        Mode::Finder => handle_finder_mode(&key_event, state),
+        Mode::Prompt => handle_prompt_mode(&key_event, state),
+        Mode::Command | Mode::Search => handle_cmdline_key(&key_event, state),
+    }
+    if state.modified {
+        state.last_edit = Some(Instant::now());
+        if state.options.lint == "idle" {
+            state.lint_after = state.last_edit;
+        }
+        if state.prose_checker.is_some() {
+            state.prose_after = state.last_edit;
+        }
+    }
+}
+
+fn human_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
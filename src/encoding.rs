@@ -0,0 +1,221 @@
+use crate::buffer::Buffer;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Encoding {
+    Utf8,
+    Latin1,
+    Cp1252,
+    Utf16Le,
+    Utf16Be,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LineEnding {
+    Unix,
+    Dos,
+}
+
+pub struct Decoded {
+    pub lines: Buffer,
+    pub encoding: Encoding,
+    pub line_ending: LineEnding,
+    pub final_newline: bool,
+    // Whether the file started with a byte order mark, which isn't part of
+    // the text. UTF-16 files always do.
+    pub bom: bool,
+}
+
+pub struct EncodeError {
+    pub line: usize,
+    pub col: usize,
+    pub ch: char,
+}
+
+// Windows-1252 code points for bytes 0x80..=0x9F; the rest match Latin-1.
+// Undefined slots map to the C1 control with the same value.
+const CP1252_HIGH: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž', '\u{8F}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+];
+
+impl Encoding {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "latin1" | "latin-1" | "iso-8859-1" | "iso8859-1" => Some(Encoding::Latin1),
+            "cp1252" | "windows-1252" => Some(Encoding::Cp1252),
+            "utf-16le" | "utf16le" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" | "utf-16" | "utf16" => Some(Encoding::Utf16Be),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Latin1 => "latin1",
+            Encoding::Cp1252 => "cp1252",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+        }
+    }
+
+    // Whether a byte order mark is written: always for UTF-16, for UTF-8
+    // if asked for, never for the single-byte encodings.
+    pub fn writes_bom(self, bom: bool) -> bool {
+        match self {
+            Encoding::Utf16Le | Encoding::Utf16Be => true,
+            Encoding::Utf8 => bom,
+            Encoding::Latin1 | Encoding::Cp1252 => false,
+        }
+    }
+
+    fn encode_char(self, c: char, out: &mut Vec<u8>) -> bool {
+        match self {
+            Encoding::Utf8 => {
+                let mut buf = [0; 4];
+                out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+            Encoding::Latin1 => match u8::try_from(u32::from(c)) {
+                Ok(b) => out.push(b),
+                Err(_) => return false,
+            },
+            Encoding::Cp1252 => {
+                if let Some(i) = CP1252_HIGH.iter().position(|&h| h == c) {
+                    out.push(0x80 + i as u8);
+                } else {
+                    match u8::try_from(u32::from(c)) {
+                        Ok(b) if !(0x80..0xA0).contains(&b) => out.push(b),
+                        _ => return false,
+                    }
+                }
+            }
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let mut buf = [0; 2];
+                for unit in c.encode_utf16(&mut buf) {
+                    let bytes = if self == Encoding::Utf16Le {
+                        unit.to_le_bytes()
+                    } else {
+                        unit.to_be_bytes()
+                    };
+                    out.extend_from_slice(&bytes);
+                }
+            }
+        }
+        true
+    }
+}
+
+impl LineEnding {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "unix" => Some(LineEnding::Unix),
+            "dos" => Some(LineEnding::Dos),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Unix => "unix",
+            LineEnding::Dos => "dos",
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Unix => "\n",
+            LineEnding::Dos => "\r\n",
+        }
+    }
+}
+
+pub const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+
+pub fn decode(bytes: &[u8]) -> Decoded {
+    let (text, encoding) = if let Some(rest) = bytes.strip_prefix(&[0xFF, 0xFE]) {
+        (decode_utf16(rest, u16::from_le_bytes), Encoding::Utf16Le)
+    } else if let Some(rest) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        (decode_utf16(rest, u16::from_be_bytes), Encoding::Utf16Be)
+    } else if let Some(rest) = bytes.strip_prefix(UTF8_BOM) {
+        (String::from_utf8_lossy(rest).to_string(), Encoding::Utf8)
+    } else {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => (text.to_string(), Encoding::Utf8),
+            Err(_) => (decode_bytes(bytes, Encoding::Latin1), Encoding::Latin1),
+        }
+    };
+    let line_ending = if text.contains("\r\n") {
+        LineEnding::Dos
+    } else {
+        LineEnding::Unix
+    };
+    let final_newline = text.ends_with('\n');
+    let mut lines: Vec<String> = text.lines().map(|l| l.to_string()).collect();
+    if lines.is_empty() {
+        lines.push(String::new());
+    }
+    Decoded {
+        lines: Buffer::from_lines(lines),
+        encoding,
+        line_ending,
+        final_newline,
+        bom: encoding.writes_bom(bytes.starts_with(UTF8_BOM)),
+    }
+}
+
+pub fn decode_bytes(bytes: &[u8], encoding: Encoding) -> String {
+    match encoding {
+        Encoding::Utf8 => String::from_utf8_lossy(bytes).to_string(),
+        Encoding::Latin1 => bytes.iter().map(|&b| char::from(b)).collect(),
+        Encoding::Cp1252 => bytes
+            .iter()
+            .map(|&b| match b {
+                0x80..=0x9F => CP1252_HIGH[usize::from(b - 0x80)],
+                _ => char::from(b),
+            })
+            .collect(),
+        Encoding::Utf16Le => decode_utf16(bytes, u16::from_le_bytes),
+        Encoding::Utf16Be => decode_utf16(bytes, u16::from_be_bytes),
+    }
+}
+
+fn decode_utf16(bytes: &[u8], read: fn([u8; 2]) -> u16) -> String {
+    let units = bytes.chunks_exact(2).map(|c| read([c[0], c[1]]));
+    char::decode_utf16(units)
+        .map(|r| r.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+// Encodes the buffer, collecting every character the target encoding can't
+// represent instead of stopping at the first one.
+pub fn encode(
+    lines: &Buffer,
+    encoding: Encoding,
+    line_ending: LineEnding,
+    final_newline: bool,
+    bom: bool,
+) -> Result<Vec<u8>, Vec<EncodeError>> {
+    let mut out = Vec::new();
+    let mut errors = Vec::new();
+    if encoding.writes_bom(bom) {
+        encoding.encode_char('\u{FEFF}', &mut out);
+    }
+    for (i, line) in lines.iter().enumerate() {
+        for (col, ch) in line.chars().enumerate() {
+            if !encoding.encode_char(ch, &mut out) {
+                errors.push(EncodeError { line: i, col, ch });
+            }
+        }
+        if i + 1 < lines.len() || final_newline {
+            for ch in line_ending.as_str().chars() {
+                encoding.encode_char(ch, &mut out);
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(out)
+    } else {
+        Err(errors)
+    }
+}
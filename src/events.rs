@@ -0,0 +1,99 @@
+use crate::keys::KeyEvent;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+// What the main loop waits on: keys and resizes from the terminal, a timer
+// for housekeeping, and background tasks saying they have finished.
+pub enum Event {
+    Key(KeyEvent),
+    Resize(usize, usize),
+    Tick,
+    Wake,
+}
+
+pub struct EventLoop {
+    sender: Sender<Event>,
+    receiver: Receiver<Event>,
+}
+
+impl Default for EventLoop {
+    fn default() -> Self {
+        EventLoop::new()
+    }
+}
+
+impl EventLoop {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        EventLoop { sender, receiver }
+    }
+
+    pub fn sender(&self) -> Sender<Event> {
+        self.sender.clone()
+    }
+
+    pub fn waker(&self) -> Waker {
+        Waker(Some(self.sender.clone()))
+    }
+
+    // Sends a tick every `period` until the loop goes away.
+    pub fn start_timer(&self, period: Duration) {
+        let sender = self.sender.clone();
+        thread::spawn(move || loop {
+            thread::sleep(period);
+            if sender.send(Event::Tick).is_err() {
+                break;
+            }
+        });
+    }
+
+    // The next event, waiting at most `timeout` for one.
+    pub fn next(&self, timeout: Duration) -> Option<Event> {
+        if timeout.is_zero() {
+            self.receiver.try_recv().ok()
+        } else {
+            self.receiver.recv_timeout(timeout).ok()
+        }
+    }
+}
+
+// Lets work on another thread get the main loop to look at its results
+// straight away. Without a loop, as when running headless, waking does
+// nothing and results are picked up on the next update.
+#[derive(Clone, Default)]
+pub struct Waker(Option<Sender<Event>>);
+
+impl Waker {
+    pub fn wake(&self) {
+        if let Some(sender) = &self.0 {
+            let _ = sender.send(Event::Wake);
+        }
+    }
+}
+
+// The result of a job running on its own thread.
+pub struct Task<T> {
+    receiver: Receiver<T>,
+}
+
+impl<T: Send + 'static> Task<T> {
+    pub fn spawn(waker: &Waker, job: impl FnOnce() -> T + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let waker = waker.clone();
+        thread::spawn(move || {
+            let _ = sender.send(job());
+            waker.wake();
+        });
+        Task { receiver }
+    }
+
+    // The result once the job is done. A job that panicked gives `Err`.
+    pub fn poll(&self) -> Option<Result<T, String>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(Ok(result)),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err("Background task died".to_string())),
+        }
+    }
+}
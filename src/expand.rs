@@ -0,0 +1,85 @@
+use crate::search_index::is_word_char;
+
+// What `%`, `#`, `<cword>` and `<cfile>` stand for in a command line.
+pub struct Context<'a> {
+    pub file: &'a str,
+    pub alternate: Option<&'a str>,
+    pub line: &'a str,
+    pub col: usize,
+}
+
+fn is_file_char(c: char) -> bool {
+    c.is_alphanumeric() || "/\\.-_~+:@".contains(c)
+}
+
+// The run of `is_part` characters under the cursor, or else the first one
+// after it on the line, as a char range.
+pub fn span_at(line: &str, col: usize, is_part: impl Fn(char) -> bool) -> Option<(usize, usize)> {
+    let chars: Vec<char> = line.chars().collect();
+    let is_part = |i: usize| chars.get(i).is_some_and(|&c| is_part(c));
+    let start = (col..chars.len()).find(|&i| is_part(i))?;
+    let start = (0..=start)
+        .rev()
+        .take_while(|&i| is_part(i))
+        .last()
+        .unwrap_or(start);
+    let end = (start..chars.len())
+        .find(|&i| !is_part(i))
+        .unwrap_or(chars.len());
+    Some((start, end))
+}
+
+fn text_at(line: &str, col: usize, is_part: impl Fn(char) -> bool) -> Option<String> {
+    let (start, end) = span_at(line, col, is_part)?;
+    Some(line.chars().skip(start).take(end - start).collect())
+}
+
+// Only commands that take file names or run shell commands get their
+// arguments expanded, so patterns and mappings can use `%` and `#` freely.
+pub fn takes_files(command: &str) -> bool {
+    let end = command
+        .find(|c: char| !c.is_alphanumeric())
+        .unwrap_or(command.len());
+    command.starts_with('!')
+        || matches!(
+            &command[..end],
+            "r" | "read" | "w" | "write" | "wq" | "e" | "edit" | "make"
+        )
+}
+
+// Replaces `%` with the current file name, `#` with the alternate one and
+// `<cword>`/`<cfile>` with the word or file name under the cursor. A
+// backslash keeps `%` and `#` literal.
+pub fn expand(text: &str, context: &Context) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let mut skip = c.len_utf8();
+        match c {
+            '\\' if rest[1..].starts_with(['%', '#']) => {
+                out.push_str(&rest[1..2]);
+                skip = 2;
+            }
+            '%' => out.push_str(context.file),
+            '#' => match context.alternate {
+                Some(alternate) => out.push_str(alternate),
+                None => return Err("No alternate file name to substitute for '#'".to_string()),
+            },
+            '<' if rest.starts_with("<cword>") => {
+                let word = text_at(context.line, context.col, is_word_char)
+                    .ok_or("No word under cursor for <cword>")?;
+                out.push_str(&word);
+                skip = "<cword>".len();
+            }
+            '<' if rest.starts_with("<cfile>") => {
+                let file = text_at(context.line, context.col, is_file_char)
+                    .ok_or("No file name under cursor for <cfile>")?;
+                out.push_str(&file);
+                skip = "<cfile>".len();
+            }
+            c => out.push(c),
+        }
+        rest = &rest[skip..];
+    }
+    Ok(out)
+}
@@ -0,0 +1,120 @@
+use crate::render::{Cell, Color, Style};
+
+// The colors exported text is shown in where the editor leaves them to the
+// terminal: light on dark, like most terminal themes.
+const FOREGROUND: &str = "#e5e5e5";
+const BACKGROUND: &str = "#000000";
+
+// xterm's first sixteen colors.
+const PALETTE: [&str; 16] = [
+    "#000000", "#cd0000", "#00cd00", "#cdcd00", "#0000ee", "#cd00cd", "#00cdcd", "#e5e5e5",
+    "#7f7f7f", "#ff0000", "#00ff00", "#ffff00", "#5c5cff", "#ff00ff", "#00ffff", "#ffffff",
+];
+
+// The runs of one style in a row of cells, without the blanks at the end.
+// The second halves of double width clusters hold nothing and drop out.
+fn runs(row: &[Cell]) -> Vec<(Style, String)> {
+    let used = row
+        .iter()
+        .rposition(|c| c.symbol != " " || c.style != Style::default())
+        .map_or(0, |i| i + 1);
+    let mut runs: Vec<(Style, String)> = Vec::new();
+    for cell in &row[..used] {
+        match runs.last_mut() {
+            Some((style, text)) if *style == cell.style => text.push_str(&cell.symbol),
+            _ => runs.push((cell.style, cell.symbol.clone())),
+        }
+    }
+    runs
+}
+
+// Text with the escape sequences that color it in a terminal, for `less -R`
+// or `cat`.
+pub fn ansi(rows: &[Vec<Cell>]) -> String {
+    let mut out = String::new();
+    for row in rows {
+        for (style, text) in runs(row) {
+            if style == Style::default() {
+                out.push_str(&text);
+            } else {
+                out.push_str(&format!("{}{}\x1b[0m", style.sgr(), text));
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// A standalone page showing the text as the screen does.
+pub fn html(rows: &[Vec<Cell>], title: &str) -> String {
+    let mut body = String::new();
+    for row in rows {
+        for (style, text) in runs(row) {
+            let css = css(style);
+            if css.is_empty() {
+                body.push_str(&escape(&text));
+            } else {
+                body.push_str(&format!("<span style=\"{}\">{}</span>", css, escape(&text)));
+            }
+        }
+        body.push('\n');
+    }
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n\
+         <body style=\"margin: 0; color: {}; background: {}\">\n\
+         <pre style=\"font-family: monospace; margin: 0; padding: 1em\">\n{}</pre>\n</body>\n</html>\n",
+        escape(title),
+        FOREGROUND,
+        BACKGROUND,
+        body
+    )
+}
+
+fn css(style: Style) -> String {
+    let (mut fg, mut bg) = (style.fg.map(hex), style.bg.map(hex));
+    if style.reverse {
+        (fg, bg) = (
+            Some(bg.unwrap_or_else(|| BACKGROUND.to_string())),
+            Some(fg.unwrap_or_else(|| FOREGROUND.to_string())),
+        );
+    }
+    let mut rules = Vec::new();
+    if let Some(fg) = fg {
+        rules.push(format!("color: {}", fg));
+    }
+    if let Some(bg) = bg {
+        rules.push(format!("background: {}", bg));
+    }
+    if style.bold {
+        rules.push("font-weight: bold".to_string());
+    }
+    if style.underline {
+        rules.push("text-decoration: underline".to_string());
+    }
+    rules.join("; ")
+}
+
+// The 256-color palette is the sixteen above, a 6x6x6 cube and 24 grays.
+fn hex(color: Color) -> String {
+    let (r, g, b) = match color {
+        Color::Rgb(r, g, b) => (r, g, b),
+        Color::Indexed(n) if n < 16 => return PALETTE[n as usize].to_string(),
+        Color::Indexed(n) if n < 232 => {
+            let level = |i: u8| if i == 0 { 0 } else { 55 + 40 * i };
+            let n = n - 16;
+            (level(n / 36), level(n / 6 % 6), level(n % 6))
+        }
+        Color::Indexed(n) => {
+            let gray = 8 + 10 * (n - 232);
+            (gray, gray, gray)
+        }
+    };
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
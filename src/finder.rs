@@ -0,0 +1,247 @@
+use std::fs;
+use std::path::Path;
+
+const MAX_FILES: usize = 50_000;
+
+pub struct FileFinder {
+    pub query: String,
+    pub files: Vec<String>,
+    pub matches: Vec<usize>,
+    pub selected: usize,
+    // Set while the files are being listed in the background.
+    pub scanning: bool,
+}
+
+impl Default for FileFinder {
+    fn default() -> Self {
+        FileFinder::new()
+    }
+}
+
+impl FileFinder {
+    pub fn new() -> Self {
+        FileFinder {
+            query: String::new(),
+            files: Vec::new(),
+            matches: Vec::new(),
+            selected: 0,
+            scanning: false,
+        }
+    }
+
+    // Starts over with no files until `set_files` brings the scan results.
+    pub fn open(&mut self) {
+        self.query.clear();
+        self.files.clear();
+        self.scanning = true;
+        self.update();
+    }
+
+    pub fn set_files(&mut self, files: Vec<String>) {
+        self.files = files;
+        self.scanning = false;
+        self.update();
+    }
+
+    pub fn update(&mut self) {
+        let mut scored: Vec<(i64, usize)> = self
+            .files
+            .iter()
+            .enumerate()
+            .filter_map(|(i, f)| fuzzy_score(&self.query, f).map(|s| (s, i)))
+            .collect();
+        scored.sort_by(|a, b| {
+            b.0.cmp(&a.0)
+                .then(self.files[a.1].len().cmp(&self.files[b.1].len()))
+        });
+        self.matches = scored.into_iter().map(|(_, i)| i).collect();
+        self.selected = 0;
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.matches.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn selection(&self) -> Option<&str> {
+        self.matches
+            .get(self.selected)
+            .map(|&i| self.files[i].as_str())
+    }
+
+    pub fn visible_lines(&self) -> Vec<String> {
+        self.matches
+            .iter()
+            .map(|&i| self.files[i].clone())
+            .collect()
+    }
+}
+
+// The files under `root` that aren't ignored, sorted.
+pub fn scan_files(root: &Path) -> Vec<String> {
+    let mut files = Vec::new();
+    let mut ignores = Vec::new();
+    scan(root, root, &mut ignores, &mut files);
+    files.sort();
+    files
+}
+
+// Scores `candidate` as a subsequence match of `query`. Consecutive
+// characters and matches at the start of a path segment or word score
+// higher; `None` means not every query character was found in order.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let mut score = 0;
+    let mut prev_match: Option<usize> = None;
+    let cand: Vec<char> = candidate.chars().collect();
+    let mut pos = 0;
+    for q in query.chars() {
+        let q_lower = q.to_ascii_lowercase();
+        let found = (pos..cand.len()).find(|&i| cand[i].to_ascii_lowercase() == q_lower)?;
+        score += 1;
+        if prev_match.is_some_and(|p| p + 1 == found) {
+            score += 5;
+        }
+        if found == 0 || matches!(cand[found - 1], '/' | '\\' | '_' | '-' | '.' | ' ') {
+            score += 8;
+        }
+        if cand[found] == q {
+            score += 1;
+        }
+        score -= (found - pos) as i64 / 4;
+        prev_match = Some(found);
+        pos = found + 1;
+    }
+    Some(score)
+}
+
+struct IgnoreRule {
+    base: String,
+    pattern: String,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+fn scan(root: &Path, dir: &Path, ignores: &mut Vec<IgnoreRule>, files: &mut Vec<String>) {
+    let rel_dir = relative(root, dir);
+    let rules_before = ignores.len();
+    if let Ok(text) = fs::read_to_string(dir.join(".gitignore")) {
+        ignores.extend(text.lines().filter_map(|l| parse_rule(&rel_dir, l)));
+    }
+    let Ok(entries) = fs::read_dir(dir) else {
+        ignores.truncate(rules_before);
+        return;
+    };
+    let mut entries: Vec<_> = entries.flatten().collect();
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        if files.len() >= MAX_FILES {
+            break;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name == ".git" {
+            continue;
+        }
+        let path = entry.path();
+        let rel = relative(root, &path);
+        let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+        if is_ignored(ignores, &rel, is_dir) {
+            continue;
+        }
+        if is_dir {
+            scan(root, &path, ignores, files);
+        } else {
+            files.push(rel);
+        }
+    }
+    ignores.truncate(rules_before);
+}
+
+fn relative(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+fn parse_rule(base: &str, line: &str) -> Option<IgnoreRule> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negated, line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let (dir_only, line) = match line.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let anchored = line.contains('/');
+    Some(IgnoreRule {
+        base: base.to_string(),
+        pattern: line.trim_start_matches('/').to_string(),
+        negated,
+        dir_only,
+        anchored,
+    })
+}
+
+fn is_ignored(rules: &[IgnoreRule], rel: &str, is_dir: bool) -> bool {
+    let mut ignored = false;
+    for rule in rules {
+        if rule.dir_only && !is_dir {
+            continue;
+        }
+        let local = if rule.base.is_empty() {
+            rel
+        } else {
+            match rel
+                .strip_prefix(&rule.base)
+                .and_then(|r| r.strip_prefix('/'))
+            {
+                Some(local) => local,
+                None => continue,
+            }
+        };
+        let matched = if rule.anchored {
+            glob_match(&rule.pattern, local)
+        } else {
+            glob_match(&rule.pattern, local.rsplit('/').next().unwrap_or(local))
+        };
+        if matched {
+            ignored = !rule.negated;
+        }
+    }
+    ignored
+}
+
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    glob_match_from(&p, &t)
+}
+
+fn glob_match_from(p: &[char], t: &[char]) -> bool {
+    match p.first() {
+        None => t.is_empty(),
+        Some('*') if p.get(1) == Some(&'*') => {
+            let rest = if p.get(2) == Some(&'/') {
+                &p[3..]
+            } else {
+                &p[2..]
+            };
+            (0..=t.len()).any(|i| glob_match_from(rest, &t[i..]))
+        }
+        Some('*') => (0..=t.len())
+            .take_while(|&i| i == 0 || t[i - 1] != '/')
+            .any(|i| glob_match_from(&p[1..], &t[i..])),
+        Some('?') => !t.is_empty() && t[0] != '/' && glob_match_from(&p[1..], &t[1..]),
+        Some(&c) => t.first() == Some(&c) && glob_match_from(&p[1..], &t[1..]),
+    }
+}
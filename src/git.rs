@@ -0,0 +1,81 @@
+use similar::{capture_diff_slices, Algorithm, DiffOp};
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Change {
+    Added,
+    Modified,
+    Deleted,
+}
+
+// A run of buffer lines that differs from HEAD. Deleted hunks have no lines
+// left in the buffer; `start` is the line the deleted text used to precede.
+pub struct Hunk {
+    pub start: usize,
+    pub len: usize,
+    pub change: Change,
+}
+
+impl Hunk {
+    // The line its gutter marker goes on: deletions are marked on the line
+    // above the gap.
+    pub fn row(&self) -> usize {
+        match self.change {
+            Change::Deleted => self.start.saturating_sub(1),
+            _ => self.start,
+        }
+    }
+}
+
+// The file's contents as of the last commit, or None if it isn't tracked.
+fn head_contents(path: &str) -> Option<String> {
+    let path = Path::new(path);
+    let dir = path.parent().filter(|d| !d.as_os_str().is_empty());
+    let name = path.file_name()?.to_string_lossy();
+    let out = Command::new("git")
+        .arg("-C")
+        .arg(dir.unwrap_or(Path::new(".")))
+        .arg("show")
+        .arg(format!("HEAD:./{}", name))
+        .output()
+        .ok()?;
+    out.status
+        .success()
+        .then(|| String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+// Diffs the buffer against HEAD. Files outside a repository or not yet
+// committed have no hunks.
+pub fn hunks(path: &str, lines: &[String]) -> Vec<Hunk> {
+    let Some(head) = head_contents(path) else {
+        return Vec::new();
+    };
+    let old: Vec<&str> = head.lines().collect();
+    let new: Vec<&str> = lines.iter().map(|l| l.as_str()).collect();
+    capture_diff_slices(Algorithm::Myers, &old, &new)
+        .into_iter()
+        .filter_map(|op| match op {
+            DiffOp::Equal { .. } => None,
+            DiffOp::Insert {
+                new_index, new_len, ..
+            } => Some(Hunk {
+                start: new_index,
+                len: new_len,
+                change: Change::Added,
+            }),
+            DiffOp::Delete { new_index, .. } => Some(Hunk {
+                start: new_index,
+                len: 0,
+                change: Change::Deleted,
+            }),
+            DiffOp::Replace {
+                new_index, new_len, ..
+            } => Some(Hunk {
+                start: new_index,
+                len: new_len,
+                change: Change::Modified,
+            }),
+        })
+        .collect()
+}
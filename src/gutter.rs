@@ -0,0 +1,182 @@
+use crate::render::{Color, Renderer, Style, BLUE};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Component {
+    Number,
+    RelativeNumber,
+    Sign,
+    Fold,
+    Git,
+}
+
+pub struct Sign {
+    pub text: char,
+    pub color: Color,
+}
+
+// Per-line marks supplied by the editor for the current frame.
+#[derive(Default)]
+pub struct Marks {
+    pub signs: HashMap<usize, Sign>,
+    pub git: HashMap<usize, Sign>,
+}
+
+// The columns drawn left of the text, in display order. Each window owns one
+// so the layout can differ between windows.
+#[derive(Clone)]
+pub struct Gutter {
+    pub components: Vec<Component>,
+}
+
+impl Component {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "number" | "nu" => Some(Component::Number),
+            "relativenumber" | "rnu" => Some(Component::RelativeNumber),
+            "sign" | "signcolumn" | "scl" => Some(Component::Sign),
+            "fold" | "foldcolumn" | "fdc" => Some(Component::Fold),
+            "git" => Some(Component::Git),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Component::Number => "number",
+            Component::RelativeNumber => "relativenumber",
+            Component::Sign => "sign",
+            Component::Fold => "fold",
+            Component::Git => "git",
+        }
+    }
+
+    fn width(self, line_count: usize) -> usize {
+        match self {
+            Component::Number | Component::RelativeNumber => {
+                line_count.to_string().len().max(4) + 1
+            }
+            Component::Sign => 2,
+            Component::Fold | Component::Git => 1,
+        }
+    }
+}
+
+impl Default for Gutter {
+    fn default() -> Self {
+        Gutter::new()
+    }
+}
+
+impl Gutter {
+    pub fn new() -> Self {
+        Gutter {
+            components: vec![Component::Number],
+        }
+    }
+
+    pub fn width(&self, line_count: usize) -> usize {
+        self.components.iter().map(|c| c.width(line_count)).sum()
+    }
+
+    // Draws the gutter for buffer line `row` at the start of `screen_row`.
+    pub fn render(
+        &self,
+        renderer: &mut dyn Renderer,
+        screen_row: usize,
+        row: usize,
+        cursor_row: usize,
+        line_count: usize,
+        marks: &Marks,
+    ) {
+        let mut col = 0;
+        for &component in &self.components {
+            let width = component.width(line_count);
+            let number = |n: usize| format!("{:>w$} ", n, w = width - 1);
+            match component {
+                Component::Number => {
+                    renderer.put(screen_row, col, &number(row + 1), Style::fg(BLUE))
+                }
+                Component::RelativeNumber => renderer.put(
+                    screen_row,
+                    col,
+                    &number(row.abs_diff(cursor_row)),
+                    Style::fg(BLUE),
+                ),
+                Component::Sign => {
+                    if let Some(sign) = marks.signs.get(&row) {
+                        renderer.put(
+                            screen_row,
+                            col,
+                            &sign.text.to_string(),
+                            Style::fg(sign.color),
+                        )
+                    }
+                }
+                Component::Git => {
+                    if let Some(sign) = marks.git.get(&row) {
+                        renderer.put(
+                            screen_row,
+                            col,
+                            &sign.text.to_string(),
+                            Style::fg(sign.color),
+                        )
+                    }
+                }
+                // Nothing folds yet; the column keeps its place in the layout.
+                Component::Fold => {}
+            }
+            col += width;
+        }
+    }
+
+    fn toggle(&mut self, component: Component, on: bool) {
+        let present = self.components.contains(&component);
+        if on && !present {
+            self.components.insert(0, component);
+        } else if !on {
+            self.components.retain(|&c| c != component);
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let names: Vec<&str> = self.components.iter().map(|c| c.name()).collect();
+        format!("gutter={}", names.join(","))
+    }
+
+    // Handles the window-local gutter options; `None` means the option is
+    // not one of ours.
+    pub fn set(&mut self, arg: &str) -> Option<Result<Option<String>, String>> {
+        if let Some(value) = arg.strip_prefix("gutter=") {
+            let mut components = Vec::new();
+            for name in value.split(',').filter(|n| !n.is_empty()) {
+                match Component::parse(name) {
+                    Some(c) if !components.contains(&c) => components.push(c),
+                    Some(_) => {}
+                    None => return Some(Err(format!("Unknown gutter component: {}", name))),
+                }
+            }
+            self.components = components;
+            return Some(Ok(None));
+        }
+        if arg == "gutter" || arg == "gutter?" {
+            return Some(Ok(Some(self.describe())));
+        }
+        if let Some(name) = arg.strip_suffix('?') {
+            let component = Component::parse(name).filter(|&c| c != Component::Git)?;
+            let on = self.components.contains(&component);
+            return Some(Ok(Some(format!(
+                "{}{}",
+                if on { "" } else { "no" },
+                component.name()
+            ))));
+        }
+        let (name, on) = match arg.strip_prefix("no") {
+            Some(name) => (name, false),
+            None => (arg, true),
+        };
+        let component = Component::parse(name).filter(|&c| c != Component::Git)?;
+        self.toggle(component, on);
+        Some(Ok(None))
+    }
+}
@@ -0,0 +1,94 @@
+use crate::editor::{EditorState, Mode};
+use crate::keys;
+use crate::output;
+use crate::render::{Cell, GridRenderer};
+use std::thread;
+use std::time::Duration;
+
+// Drives the editor without a terminal. Keys are fed in Vim notation and
+// handled exactly like `--replay` keys, with the same housekeeping between
+// them as the terminal loop does. Frames are drawn into a cell grid that
+// tests can read back.
+pub struct Headless {
+    pub editor: EditorState,
+    grid: GridRenderer,
+}
+
+impl Headless {
+    pub fn open(path: &str) -> Self {
+        Headless::with_size(path, 24, 80)
+    }
+
+    pub fn with_size(path: &str, rows: usize, cols: usize) -> Self {
+        let mut editor = EditorState::new(path.to_string());
+        editor.resize(rows, cols);
+        Headless {
+            editor,
+            grid: GridRenderer::new(),
+        }
+    }
+
+    // Handles every key in `keys`, stopping early if the editor quits.
+    pub fn feed(&mut self, keys: &str) -> Result<(), String> {
+        self.editor.replay(keys::parse(keys)?);
+        while !self.editor.should_exit() && self.editor.handle_pending_key() {
+            // There is no screen to leave, so `:!cmd` just runs.
+            if let Some(cmd) = self.editor.take_shell_request() {
+                let status = output::shell(&cmd).status().map(|s| s.code());
+                self.editor.shell_finished(&cmd, status);
+            }
+            self.editor.update();
+            while self.editor.background_work() {}
+        }
+        Ok(())
+    }
+
+    // Waits for background jobs to finish and picks up their results.
+    pub fn wait(&mut self) {
+        while self.editor.busy() {
+            thread::sleep(Duration::from_millis(5));
+            self.editor.update();
+        }
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.editor.lines().map(|l| l.to_string()).collect()
+    }
+
+    pub fn text(&self) -> String {
+        self.lines().join("\n")
+    }
+
+    // The line and grapheme of the cursor.
+    pub fn cursor(&self) -> (usize, usize) {
+        let cursor = self.editor.cursor();
+        (cursor.line(), cursor.grapheme())
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.editor.mode()
+    }
+
+    pub fn status(&self) -> Option<&str> {
+        self.editor.status_message()
+    }
+
+    // Draws a frame and returns its rows, with trailing blanks trimmed.
+    pub fn screen(&mut self) -> Vec<String> {
+        self.editor.update();
+        self.editor.draw(&mut self.grid);
+        self.grid
+            .cells
+            .chunks(self.grid.cols.max(1))
+            .map(|row| {
+                let text: String = row.iter().map(|cell| cell.symbol.as_str()).collect();
+                text.trim_end().to_string()
+            })
+            .collect()
+    }
+
+    // A cell of the frame `screen` drew last.
+    pub fn cell(&self, row: usize, col: usize) -> &Cell {
+        &self.grid.cells[row * self.grid.cols + col]
+    }
+}
@@ -0,0 +1,243 @@
+use crate::keymap;
+use crate::keys;
+use crate::options::{self, Options};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// `:checkhealth`: what in the setup gets in the way, found before it does.
+// Each check adds findings to its section, either fine or a problem that
+// comes with what to do about it.
+pub struct Health {
+    sections: Vec<(String, Vec<String>)>,
+    problems: usize,
+}
+
+// An external program the setup runs: what for, the command, and how to
+// set another.
+pub struct Tool {
+    pub purpose: String,
+    pub command: String,
+    pub fix: String,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health::new()
+    }
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Health {
+            sections: Vec::new(),
+            problems: 0,
+        }
+    }
+
+    pub fn section(&mut self, title: &str) {
+        self.sections.push((title.to_string(), Vec::new()));
+    }
+
+    fn add(&mut self, line: String) {
+        match self.sections.last_mut() {
+            Some((_, lines)) => lines.push(line),
+            None => self.sections.push((String::new(), vec![line])),
+        }
+    }
+
+    pub fn ok(&mut self, text: &str) {
+        self.add(format!("  OK       {}", text));
+    }
+
+    pub fn warn(&mut self, text: &str, fix: &str) {
+        self.problem("WARNING", text, fix);
+    }
+
+    pub fn error(&mut self, text: &str, fix: &str) {
+        self.problem("ERROR", text, fix);
+    }
+
+    fn problem(&mut self, level: &str, text: &str, fix: &str) {
+        self.problems += 1;
+        self.add(format!("  {:<8} {}", level, text));
+        self.add(format!("           {}", fix));
+    }
+
+    pub fn problems(&self) -> usize {
+        self.problems
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![match self.problems {
+            0 => "No problems found".to_string(),
+            1 => "1 problem found".to_string(),
+            n => format!("{} problems found", n),
+        }];
+        for (title, findings) in &self.sections {
+            lines.push(title.clone());
+            lines.extend(findings.iter().cloned());
+        }
+        lines
+    }
+}
+
+// Reads the startup file again and tries its options, with `check_option`,
+// and mappings, so mistakes whose messages were lost while it ran show up
+// with their line.
+pub fn check_config(
+    health: &mut Health,
+    path: Option<&str>,
+    check_option: impl Fn(&str) -> Result<(), String>,
+) {
+    health.section("Config");
+    let Some(path) = path else {
+        health.ok("No startup file read");
+        return;
+    };
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            health.error(
+                &format!("Can't read {}: {}", path, e),
+                "Create it, or start with --config and another file",
+            );
+            return;
+        }
+    };
+    let before = health.problems;
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('"') {
+            continue;
+        }
+        let (name, args) = line
+            .split_once(' ')
+            .map_or((line, ""), |(name, args)| (name, args.trim()));
+        let errors: Vec<String> = match (name, keymap::command(name)) {
+            ("set" | "se", _) => options::split_args(args)
+                .iter()
+                .filter_map(|arg| check_option(arg).err())
+                .collect(),
+            (_, Some(keymap::Command::Map(..))) if !args.is_empty() => match args.split_once(' ') {
+                Some((lhs, rhs)) => [lhs, rhs.trim_start()]
+                    .iter()
+                    .filter_map(|keys| keys::parse(keys).err())
+                    .collect(),
+                None => vec![format!("Missing keys to map {} to", args)],
+            },
+            _ => Vec::new(),
+        };
+        for e in errors {
+            health.error(
+                &format!("{} line {}: {}", path, n + 1, e),
+                "Fix the line or remove it",
+            );
+        }
+    }
+    if health.problems == before {
+        health.ok(&format!("{} has no mistakes in options or mappings", path));
+    }
+}
+
+pub fn check_tools(health: &mut Health, tools: &[Tool]) {
+    health.section("Tools");
+    for tool in tools {
+        let Some(program) = tool.command.split_whitespace().next() else {
+            continue;
+        };
+        match find_program(program) {
+            Some(path) => health.ok(&format!(
+                "{} for {}: {}",
+                program,
+                tool.purpose,
+                path.display()
+            )),
+            None => health.warn(
+                &format!(
+                    "{} for {} isn't installed or isn't on PATH",
+                    program, tool.purpose
+                ),
+                &tool.fix,
+            ),
+        }
+    }
+}
+
+// The file `program` runs: itself if it is a path, or else the first one
+// by that name in a directory on PATH.
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    if program.contains('/') {
+        return Some(PathBuf::from(program)).filter(|p| p.is_file());
+    }
+    let names: Vec<String> = if cfg!(windows) {
+        ["exe", "cmd", "bat"]
+            .iter()
+            .map(|ext| format!("{}.{}", program, ext))
+            .collect()
+    } else {
+        vec![program.to_string()]
+    };
+    env::split_paths(&env::var_os("PATH")?)
+        .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+        .find(|path| path.is_file())
+}
+
+// What the environment says about the terminal, and whether the window
+// has room for menus and panes.
+pub fn check_terminal(health: &mut Health, size: (usize, usize), options: &Options) {
+    health.section("Terminal");
+    let term = env::var("TERM").unwrap_or_default();
+    match term.as_str() {
+        "" => health.error(
+            "TERM isn't set",
+            "Set it to the terminal's type, like xterm-256color",
+        ),
+        "dumb" => health.error(
+            "TERM=dumb can't move the cursor or draw colors",
+            "Set it to the terminal's type, like xterm-256color",
+        ),
+        _ => health.ok(&format!("TERM={}", term)),
+    }
+    if env::var_os("TMUX").is_some() && !term.starts_with("tmux") && !term.starts_with("screen") {
+        health.warn(
+            &format!("TERM={} inside tmux", term),
+            "Set default-terminal to tmux-256color in tmux.conf",
+        );
+    }
+    let truecolor = env::var("COLORTERM").is_ok_and(|c| c == "truecolor" || c == "24bit");
+    if options.listcolor.starts_with('#') && !truecolor {
+        health.warn(
+            &format!(
+                "listcolor={} needs true color, which COLORTERM doesn't say the terminal has",
+                options.listcolor
+            ),
+            "Use a named color, or set COLORTERM=truecolor if the terminal has it",
+        );
+    }
+    let (rows, cols) = size;
+    if rows < 10 || cols < 40 {
+        health.warn(
+            &format!("The window is {}x{}", cols, rows),
+            "Menus and panes need at least 40x10; make it larger",
+        );
+    } else {
+        health.ok(&format!("The window is {}x{}", cols, rows));
+    }
+}
+
+// Swap files, pins and sessions all go in the state directory.
+pub fn check_state(health: &mut Health, dir: &Path) {
+    health.section("State");
+    let probe = dir.join(".checkhealth");
+    match fs::create_dir_all(dir).and_then(|_| fs::write(&probe, "")) {
+        Ok(()) => {
+            let _ = fs::remove_file(probe);
+            health.ok(&format!("{} is writable", dir.display()));
+        }
+        Err(e) => health.error(
+            &format!("Can't write to {}: {}", dir.display(), e),
+            "Swap files and sessions aren't saved; set statedir to a directory you can write to",
+        ),
+    }
+}
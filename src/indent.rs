@@ -0,0 +1,291 @@
+use crate::buffer::Buffer;
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Language {
+    Rust,
+    Python,
+    CLike,
+    Yaml,
+    Plain,
+}
+
+impl Language {
+    pub fn detect(path: &str) -> Self {
+        let ext = Path::new(path)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match ext.as_str() {
+            "rs" => Language::Rust,
+            "py" | "pyw" | "pyi" => Language::Python,
+            "c" | "h" | "cc" | "cpp" | "cxx" | "hpp" | "hh" | "java" | "js" | "jsx" | "ts"
+            | "tsx" | "cs" | "go" | "kt" | "swift" | "css" | "scss" | "json" | "php" => {
+                Language::CLike
+            }
+            "yml" | "yaml" => Language::Yaml,
+            _ => Language::Plain,
+        }
+    }
+}
+
+pub struct Indenter {
+    pub language: Language,
+    pub unit: String,
+    pub tab_width: usize,
+}
+
+impl Indenter {
+    pub fn new(path: &str, lines: &Buffer) -> Self {
+        let language = Language::detect(path);
+        let default_width = if language == Language::Yaml { 2 } else { 4 };
+        Indenter {
+            language,
+            unit: detect_unit(lines).unwrap_or_else(|| " ".repeat(default_width)),
+            tab_width: 4,
+        }
+    }
+
+    pub fn unit_width(&self) -> usize {
+        self.width(&self.unit).max(1)
+    }
+
+    pub fn width(&self, indent: &str) -> usize {
+        indent
+            .chars()
+            .map(|c| if c == '\t' { self.tab_width } else { 1 })
+            .sum()
+    }
+
+    pub fn render(&self, width: usize) -> String {
+        if self.unit == "\t" {
+            "\t".repeat(width / self.tab_width) + " ".repeat(width % self.tab_width).as_str()
+        } else {
+            " ".repeat(width)
+        }
+    }
+
+    // Computes the indentation `lines[row]` should have, based on the lines
+    // above it and on the line's own leading text (closers, `else:` ...).
+    pub fn indent_for(&self, lines: &Buffer, row: usize) -> String {
+        let width = match self.language {
+            Language::Rust | Language::CLike => self.bracket_indent(lines, row),
+            Language::Python => self.python_indent(lines, row),
+            Language::Yaml => self.yaml_indent(lines, row),
+            Language::Plain => prev_nonblank(lines, row)
+                .map(|p| self.width(leading_ws(&lines[p])))
+                .unwrap_or(0),
+        };
+        self.render(width)
+    }
+
+    fn bracket_indent(&self, lines: &Buffer, row: usize) -> usize {
+        // Start from the closest line at column zero so the cost stays bounded
+        // in long files; top-level items reset the bracket depth to zero.
+        let anchor = (0..row)
+            .rev()
+            .find(|&r| {
+                let l = &lines[r];
+                !l.is_empty() && leading_ws(l).is_empty() && !starts_with_closer(l)
+            })
+            .unwrap_or(0);
+        let mut depth: usize = 0;
+        let mut in_block_comment = false;
+        for line in lines.range(anchor..row) {
+            let (opened, closed) = bracket_balance(line, self.language, &mut in_block_comment);
+            depth = (depth + opened).saturating_sub(closed);
+        }
+        if starts_with_closer(&lines[row]) {
+            depth = depth.saturating_sub(1);
+        }
+        let anchor_width = if anchor < row {
+            self.width(leading_ws(&lines[anchor]))
+        } else {
+            0
+        };
+        anchor_width + depth * self.unit_width()
+    }
+
+    fn python_indent(&self, lines: &Buffer, row: usize) -> usize {
+        let Some(prev) = prev_nonblank(lines, row) else {
+            return 0;
+        };
+        let prev_line = strip_comment(&lines[prev], '#');
+        let mut width = self.width(leading_ws(&lines[prev]));
+        let trimmed = prev_line.trim();
+        let first_word = trimmed.split([' ', '(', ':']).next().unwrap_or("");
+        let mut in_comment = false;
+        let (opened, closed) = bracket_balance(prev_line, Language::Python, &mut in_comment);
+        if trimmed.ends_with(':') || opened > closed {
+            width += self.unit_width();
+        } else if matches!(
+            first_word,
+            "return" | "pass" | "break" | "continue" | "raise"
+        ) {
+            width = width.saturating_sub(self.unit_width());
+        }
+        let current = lines[row].trim_start();
+        let current_word = current.split([' ', ':']).next().unwrap_or("");
+        let openers: &[&str] = match current_word {
+            "elif" => &["if", "elif"],
+            "else" => &["if", "elif", "for", "while", "try", "except"],
+            "except" | "finally" => &["try", "except"],
+            _ if starts_with_closer(current) => return width.saturating_sub(self.unit_width()),
+            _ => return width,
+        };
+        // Align with the block this clause continues, e.g. `else:` under its `if`.
+        (0..row)
+            .rev()
+            .map(|r| (self.width(leading_ws(&lines[r])), lines[r].trim_start()))
+            .find(|(w, l)| {
+                *w <= width && openers.contains(&l.split([' ', ':']).next().unwrap_or(""))
+            })
+            .map_or(width.saturating_sub(self.unit_width()), |(w, _)| w)
+    }
+
+    fn yaml_indent(&self, lines: &Buffer, row: usize) -> usize {
+        let Some(prev) = prev_nonblank(lines, row) else {
+            return 0;
+        };
+        let prev_line = strip_comment(&lines[prev], '#');
+        let base = self.width(leading_ws(prev_line));
+        let trimmed = prev_line.trim();
+        let item_offset = if trimmed.starts_with("- ") { 2 } else { 0 };
+        if trimmed.ends_with(':') || trimmed.ends_with('|') || trimmed.ends_with('>') {
+            base + item_offset + self.unit_width()
+        } else if lines[row].trim_start().starts_with("- ") || item_offset == 0 {
+            base
+        } else {
+            base + item_offset
+        }
+    }
+}
+
+fn detect_unit(lines: &Buffer) -> Option<String> {
+    let mut smallest: Option<usize> = None;
+    for line in lines.iter().take(1000) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let ws = leading_ws(line);
+        if ws.starts_with('\t') {
+            return Some("\t".to_string());
+        }
+        if !ws.is_empty() {
+            smallest = Some(smallest.map_or(ws.len(), |s| s.min(ws.len())));
+        }
+    }
+    smallest.filter(|&s| s <= 8).map(|s| " ".repeat(s))
+}
+
+pub fn leading_ws(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+fn prev_nonblank(lines: &Buffer, row: usize) -> Option<usize> {
+    (0..row).rev().find(|&r| !lines[r].trim().is_empty())
+}
+
+fn starts_with_closer(line: &str) -> bool {
+    matches!(line.trim_start().chars().next(), Some('}' | ')' | ']'))
+}
+
+fn strip_comment(line: &str, marker: char) -> &str {
+    let mut in_string: Option<char> = None;
+    for (i, c) in line.char_indices() {
+        match in_string {
+            Some(q) if c == q => in_string = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => in_string = Some(c),
+            None if c == marker => return &line[..i],
+            None => {}
+        }
+    }
+    line
+}
+
+// Counts brackets opened and closed on a line, ignoring string and character
+// literals and comments.
+fn bracket_balance(line: &str, language: Language, in_block_comment: &mut bool) -> (usize, usize) {
+    let mask = code_mask(line, language, in_block_comment);
+    let (mut opened, mut closed) = (0usize, 0usize);
+    for (c, code) in line.chars().zip(mask) {
+        match c {
+            _ if !code => {}
+            '{' | '(' | '[' => opened += 1,
+            '}' | ')' | ']' => {
+                if opened > 0 {
+                    opened -= 1;
+                } else {
+                    closed += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    (opened, closed)
+}
+
+// Marks which characters of `line` are code rather than part of a string,
+// character literal or comment. Languages we know nothing about are all code.
+pub fn code_mask(line: &str, language: Language, in_block_comment: &mut bool) -> Vec<bool> {
+    let chars: Vec<char> = line.chars().collect();
+    if matches!(language, Language::Plain | Language::Yaml) {
+        return vec![true; chars.len()];
+    }
+    let mut code = vec![false; chars.len()];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if *in_block_comment {
+            if c == '*' && next == Some('/') {
+                *in_block_comment = false;
+                i += 1;
+            }
+            i += 1;
+            continue;
+        }
+        match c {
+            '/' if next == Some('/') && language != Language::Python => break,
+            '#' if language == Language::Python => break,
+            '/' if next == Some('*') && language != Language::Python => {
+                *in_block_comment = true;
+                i += 1;
+            }
+            '"' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    if chars[i] == '\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            '\'' if language == Language::Rust => {
+                // A lifetime like 'a has no closing quote; only skip char literals.
+                if next == Some('\\') {
+                    if let Some(end) = chars[i + 2..].iter().position(|&c| c == '\'') {
+                        i += end + 2;
+                    }
+                } else if chars.get(i + 2) == Some(&'\'') {
+                    i += 2;
+                } else {
+                    code[i] = true;
+                }
+            }
+            '\'' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '\'' {
+                    if chars[i] == '\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            _ => code[i] = true,
+        }
+        i += 1;
+    }
+    code
+}
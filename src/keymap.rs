@@ -0,0 +1,254 @@
+use crate::keys::{self, Key, KeyEvent};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+// How long a key that starts a longer mapping waits for the rest of it.
+const TIMEOUT: Duration = Duration::from_millis(1000);
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum MapMode {
+    Normal,
+    Insert,
+    Command,
+}
+
+// The mapping commands: `:nmap`, `:inoremap`, `:cunmap` and so on.
+pub enum Command {
+    Map(MapMode, bool),
+    Unmap(MapMode),
+}
+
+pub fn command(name: &str) -> Option<Command> {
+    Some(match name {
+        "map" | "nmap" | "nm" => Command::Map(MapMode::Normal, true),
+        "noremap" | "no" | "nnoremap" | "nn" => Command::Map(MapMode::Normal, false),
+        "imap" | "im" => Command::Map(MapMode::Insert, true),
+        "inoremap" | "ino" => Command::Map(MapMode::Insert, false),
+        "cmap" | "cm" => Command::Map(MapMode::Command, true),
+        "cnoremap" | "cno" => Command::Map(MapMode::Command, false),
+        "unmap" | "unm" | "nunmap" | "nun" => Command::Unmap(MapMode::Normal),
+        "iunmap" | "iu" => Command::Unmap(MapMode::Insert),
+        "cunmap" | "cu" => Command::Unmap(MapMode::Command),
+        _ => return None,
+    })
+}
+
+impl MapMode {
+    fn prefix(self) -> char {
+        match self {
+            MapMode::Normal => 'n',
+            MapMode::Insert => 'i',
+            MapMode::Command => 'c',
+        }
+    }
+}
+
+pub struct Mapping {
+    pub rhs: Vec<Key>,
+    // Whether the keys it produces are looked up in the keymap again.
+    pub remap: bool,
+}
+
+// What to do with the keys typed so far. Keys in `rest` were typed after
+// the ones used up and have to be looked up again.
+pub enum Step {
+    // They start a longer mapping; wait for more.
+    Wait,
+    // Keys from `remap_from` on are looked up again.
+    Mapped {
+        keys: Vec<KeyEvent>,
+        remap_from: usize,
+        rest: Vec<KeyEvent>,
+    },
+    // Nothing matched, so the first key goes to the mode handler as typed.
+    Unmapped {
+        key: KeyEvent,
+        rest: Vec<KeyEvent>,
+    },
+}
+
+// User key mappings (`:nmap <leader>w :w<CR>`, `:inoremap jk <Esc>`), one
+// table per mode. Keys typed in a mapped mode pass through here before the
+// mode handlers see them.
+pub struct Keymap {
+    maps: BTreeMap<(MapMode, String), (Vec<Key>, Mapping)>,
+    pending: Vec<KeyEvent>,
+    pending_since: Instant,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::new()
+    }
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        Keymap {
+            maps: BTreeMap::new(),
+            pending: Vec::new(),
+            pending_since: Instant::now(),
+        }
+    }
+
+    pub fn map(&mut self, mode: MapMode, lhs: &str, rhs: &str, remap: bool) -> Result<(), String> {
+        let lhs = keys::parse(lhs)?;
+        if lhs.is_empty() {
+            return Err("Missing keys to map".to_string());
+        }
+        let rhs = keys::parse(rhs)?;
+        self.maps
+            .insert((mode, keys::format(&lhs)), (lhs, Mapping { rhs, remap }));
+        Ok(())
+    }
+
+    pub fn unmap(&mut self, mode: MapMode, lhs: &str) -> Result<(), String> {
+        let lhs = keys::format(&keys::parse(lhs)?);
+        match self.maps.remove(&(mode, lhs)) {
+            Some(_) => Ok(()),
+            None => Err("No such mapping".to_string()),
+        }
+    }
+
+    pub fn lines(&self, mode: MapMode) -> Vec<String> {
+        self.maps
+            .iter()
+            .filter(|((m, _), _)| *m == mode)
+            .map(|((m, lhs), (_, mapping))| {
+                format!(
+                    "{}  {:<12} {}{}",
+                    m.prefix(),
+                    lhs,
+                    if mapping.remap { " " } else { "*" },
+                    keys::format(&mapping.rhs)
+                )
+            })
+            .collect()
+    }
+
+    // The commands that recreate every mapping, for sessions.
+    pub fn commands(&self) -> Vec<String> {
+        self.maps
+            .iter()
+            .map(|((m, lhs), (_, mapping))| {
+                format!(
+                    "{}{}map {} {}",
+                    m.prefix(),
+                    if mapping.remap { "" } else { "nore" },
+                    lhs,
+                    keys::format(&mapping.rhs)
+                )
+            })
+            .collect()
+    }
+
+    // Mappings that get in each other's way: two that come to the same keys
+    // once `<leader>` is resolved, of which only one ever runs, and one that
+    // starts a longer one, which waits for the rest before it runs.
+    pub fn conflicts(&self, leader: KeyEvent) -> Vec<String> {
+        let mut conflicts = Vec::new();
+        let maps: Vec<_> = self.maps.iter().collect();
+        for (i, ((mode, lhs), (keys, _))) in maps.iter().enumerate() {
+            let resolved: Vec<KeyEvent> = keys.iter().map(|&k| resolve(k, leader)).collect();
+            for ((other_mode, other), (other_keys, _)) in &maps[i + 1..] {
+                if other_mode != mode {
+                    continue;
+                }
+                let shorter = keys.len().min(other_keys.len());
+                if !starts_with(&other_keys[..shorter], &resolved[..shorter], leader) {
+                    continue;
+                }
+                let (short, long) = if keys.len() <= other_keys.len() {
+                    (lhs, other)
+                } else {
+                    (other, lhs)
+                };
+                conflicts.push(if keys.len() == other_keys.len() {
+                    format!(
+                        "{}  {} and {} are the same keys; only one of them runs",
+                        mode.prefix(),
+                        short,
+                        long
+                    )
+                } else {
+                    format!(
+                        "{}  {} waits a second for {} before it runs",
+                        mode.prefix(),
+                        short,
+                        long
+                    )
+                });
+            }
+        }
+        conflicts
+    }
+
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    pub fn timed_out(&self) -> bool {
+        self.is_pending() && self.pending_since.elapsed() >= TIMEOUT
+    }
+
+    pub fn feed(&mut self, mode: MapMode, key: KeyEvent, leader: KeyEvent) -> Step {
+        if self.pending.is_empty() {
+            self.pending_since = Instant::now();
+        }
+        self.pending.push(key);
+        let typed = &self.pending;
+        let longer = self.maps.iter().any(|((m, _), (lhs, _))| {
+            *m == mode && lhs.len() > typed.len() && starts_with(lhs, typed, leader)
+        });
+        if longer {
+            return Step::Wait;
+        }
+        self.flush(mode, leader)
+    }
+
+    // Ends the pending sequence with the longest mapping it starts with, or
+    // with its first key unmapped.
+    pub fn flush(&mut self, mode: MapMode, leader: KeyEvent) -> Step {
+        let mut typed = std::mem::take(&mut self.pending);
+        for n in (1..=typed.len()).rev() {
+            let exact = self.maps.iter().find(|((m, _), (lhs, _))| {
+                *m == mode && lhs.len() == n && starts_with(lhs, &typed, leader)
+            });
+            if let Some((_, (lhs, mapping))) = exact {
+                let keys: Vec<KeyEvent> = mapping.rhs.iter().map(|&k| resolve(k, leader)).collect();
+                // Like Vim, `nmap n nzz` doesn't map its own `n` again.
+                let remap_from = if !mapping.remap {
+                    keys.len()
+                } else if keys.starts_with(&typed[..n]) {
+                    lhs.len()
+                } else {
+                    0
+                };
+                return Step::Mapped {
+                    keys,
+                    remap_from,
+                    rest: typed.split_off(n),
+                };
+            }
+        }
+        let rest = typed.split_off(1);
+        Step::Unmapped {
+            key: typed[0],
+            rest,
+        }
+    }
+}
+
+// Whether `lhs` and `typed` agree as far as both go.
+fn starts_with(lhs: &[Key], typed: &[KeyEvent], leader: KeyEvent) -> bool {
+    lhs.iter()
+        .zip(typed)
+        .all(|(&l, t)| resolve(l, leader) == *t)
+}
+
+fn resolve(key: Key, leader: KeyEvent) -> KeyEvent {
+    match key {
+        Key::Press(event) => event,
+        Key::Leader => leader,
+    }
+}
@@ -0,0 +1,314 @@
+use std::ops::{BitOr, BitOrAssign, Sub, SubAssign};
+
+// Key events as the editor sees them. Front ends translate their own events
+// into these, so the engine doesn't depend on any terminal library.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum KeyCode {
+    Char(char),
+    F(u8),
+    Esc,
+    Enter,
+    Tab,
+    BackTab,
+    Backspace,
+    Delete,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct KeyModifiers(u8);
+
+impl KeyModifiers {
+    pub const NONE: Self = KeyModifiers(0);
+    pub const SHIFT: Self = KeyModifiers(1);
+    pub const CONTROL: Self = KeyModifiers(2);
+    pub const ALT: Self = KeyModifiers(4);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl BitOr for KeyModifiers {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        KeyModifiers(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for KeyModifiers {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+impl Sub for KeyModifiers {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        KeyModifiers(self.0 & !other.0)
+    }
+}
+
+impl SubAssign for KeyModifiers {
+    fn sub_assign(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl KeyEvent {
+    pub const fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        KeyEvent { code, modifiers }
+    }
+}
+
+// One element of a key sequence. `<leader>` is kept symbolic so mappings can
+// be written before the leader key is known and still print back unchanged.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Key {
+    Press(KeyEvent),
+    Leader,
+}
+
+const NAMED: &[(&str, KeyCode)] = &[
+    ("Esc", KeyCode::Esc),
+    ("CR", KeyCode::Enter),
+    ("Enter", KeyCode::Enter),
+    ("Return", KeyCode::Enter),
+    ("Tab", KeyCode::Tab),
+    ("BS", KeyCode::Backspace),
+    ("Del", KeyCode::Delete),
+    ("Space", KeyCode::Char(' ')),
+    ("lt", KeyCode::Char('<')),
+    ("Bslash", KeyCode::Char('\\')),
+    ("Bar", KeyCode::Char('|')),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("Insert", KeyCode::Insert),
+];
+
+// Parses Vim-style key notation such as `dd`, `<C-w>`, `<leader>f` or
+// `:w<CR>`. A `<` without a matching `>` is taken literally.
+pub fn parse(text: &str) -> Result<Vec<Key>, String> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest.find('>').filter(|&end| end > 1) {
+                let name = &rest[1..end];
+                if !name.contains('<') && !name.contains(' ') {
+                    keys.push(parse_name(name)?);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+        keys.push(Key::Press(KeyEvent::new(
+            KeyCode::Char(c),
+            KeyModifiers::NONE,
+        )));
+        rest = &rest[c.len_utf8()..];
+    }
+    Ok(keys)
+}
+
+fn parse_name(name: &str) -> Result<Key, String> {
+    if name.eq_ignore_ascii_case("leader") {
+        return Ok(Key::Leader);
+    }
+    let mut modifiers = KeyModifiers::NONE;
+    let mut base = name;
+    while base.len() > 2 && base.as_bytes()[1] == b'-' {
+        modifiers |= match base.as_bytes()[0].to_ascii_uppercase() {
+            b'C' => KeyModifiers::CONTROL,
+            b'S' => KeyModifiers::SHIFT,
+            b'A' | b'M' => KeyModifiers::ALT,
+            _ => return Err(format!("Unknown modifier in <{}>", name)),
+        };
+        base = &base[2..];
+    }
+    let mut chars = base.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) if modifiers != KeyModifiers::NONE => KeyCode::Char(c),
+        _ => match NAMED.iter().find(|(n, _)| n.eq_ignore_ascii_case(base)) {
+            Some(&(_, code)) => code,
+            None => match base
+                .strip_prefix(['F', 'f'])
+                .and_then(|n| n.parse::<u8>().ok())
+            {
+                Some(n) if (1..=24).contains(&n) => KeyCode::F(n),
+                _ => return Err(format!("Unknown key: <{}>", name)),
+            },
+        },
+    };
+    // Terminals report Shift-Tab as its own key.
+    if code == KeyCode::Tab && modifiers.contains(KeyModifiers::SHIFT) {
+        return Ok(Key::Press(KeyEvent::new(
+            KeyCode::BackTab,
+            modifiers - KeyModifiers::SHIFT,
+        )));
+    }
+    Ok(Key::Press(KeyEvent::new(code, modifiers)))
+}
+
+// Writes keys back in canonical notation; `parse(&format(keys))` returns the
+// same keys.
+pub fn format(keys: &[Key]) -> String {
+    keys.iter().map(|&key| format_key(key)).collect()
+}
+
+fn format_key(key: Key) -> String {
+    let event = match key {
+        Key::Leader => return "<leader>".to_string(),
+        Key::Press(event) => event,
+    };
+    let mut modifiers = event.modifiers;
+    let base = match event.code {
+        KeyCode::BackTab => {
+            modifiers |= KeyModifiers::SHIFT;
+            "Tab".to_string()
+        }
+        KeyCode::Char(c) => {
+            // Shift is already part of the character itself.
+            modifiers -= KeyModifiers::SHIFT;
+            if modifiers == KeyModifiers::NONE {
+                return if c == '<' {
+                    "<lt>".to_string()
+                } else {
+                    c.to_string()
+                };
+            }
+            match NAMED.iter().find(|(_, code)| *code == event.code) {
+                Some((name, _)) => name.to_string(),
+                None => c.to_string(),
+            }
+        }
+        KeyCode::F(n) => format!("F{}", n),
+        code => match NAMED.iter().find(|(_, c)| *c == code) {
+            Some((name, _)) => name.to_string(),
+            None => format!("{:?}", code),
+        },
+    };
+    let mut prefix = String::new();
+    if modifiers.contains(KeyModifiers::CONTROL) {
+        prefix.push_str("C-");
+    }
+    if modifiers.contains(KeyModifiers::SHIFT) {
+        prefix.push_str("S-");
+    }
+    if modifiers.contains(KeyModifiers::ALT) {
+        prefix.push_str("A-");
+    }
+    format!("<{}{}>", prefix, base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(code: KeyCode, modifiers: KeyModifiers) -> Key {
+        Key::Press(KeyEvent::new(code, modifiers))
+    }
+
+    #[test]
+    fn parses_special_keys() {
+        assert_eq!(
+            parse("<C-w>j<Esc><F5><leader>").unwrap(),
+            vec![
+                press(KeyCode::Char('w'), KeyModifiers::CONTROL),
+                press(KeyCode::Char('j'), KeyModifiers::NONE),
+                press(KeyCode::Esc, KeyModifiers::NONE),
+                press(KeyCode::F(5), KeyModifiers::NONE),
+                Key::Leader,
+            ]
+        );
+    }
+
+    #[test]
+    fn names_are_case_insensitive() {
+        assert_eq!(parse("<esc>").unwrap(), parse("<ESC>").unwrap());
+        assert_eq!(
+            parse("<c-W>").unwrap(),
+            vec![press(KeyCode::Char('W'), KeyModifiers::CONTROL)]
+        );
+        assert_eq!(parse("<Leader>").unwrap(), vec![Key::Leader]);
+    }
+
+    #[test]
+    fn lone_angle_brackets_are_literal() {
+        let keys = parse("a<b <> x>").unwrap();
+        assert_eq!(format(&keys), "a<lt>b <lt>> x>");
+        assert_eq!(keys.len(), 9);
+    }
+
+    #[test]
+    fn unknown_names_are_errors() {
+        assert!(parse("<Nope>").is_err());
+        assert!(parse("<X-a>").is_err());
+        assert!(parse("<F99>").is_err());
+    }
+
+    #[test]
+    fn round_trips_canonical_notation() {
+        for text in [
+            "dd",
+            ":w<CR>",
+            "<C-w>v",
+            "<leader>ff",
+            "<Esc><Tab><S-Tab><BS><Del>",
+            "<Up><Down><Left><Right><Home><End><PageUp><PageDown><Insert>",
+            "<F1><F12>",
+            "<A-x><C-S-Up>",
+            "a b",
+            "<C-Space>",
+            "<lt>tag>",
+        ] {
+            assert_eq!(format(&parse(text).unwrap()), text);
+        }
+    }
+
+    #[test]
+    fn round_trips_key_events() {
+        let keys = vec![
+            press(KeyCode::Char('x'), KeyModifiers::ALT),
+            press(KeyCode::Char('<'), KeyModifiers::NONE),
+            press(KeyCode::BackTab, KeyModifiers::NONE),
+            press(KeyCode::F(3), KeyModifiers::CONTROL),
+            press(KeyCode::Enter, KeyModifiers::NONE),
+            Key::Leader,
+        ];
+        assert_eq!(parse(&format(&keys)).unwrap(), keys);
+    }
+
+    #[test]
+    fn aliases_format_canonically() {
+        assert_eq!(format(&parse("<Enter><Return>").unwrap()), "<CR><CR>");
+        assert_eq!(format(&parse("<M-a>").unwrap()), "<A-a>");
+        assert_eq!(format(&parse("<Space>").unwrap()), " ");
+    }
+}
@@ -0,0 +1,48 @@
+pub mod aliases;
+pub mod autosession;
+pub mod brackets;
+pub mod buffer;
+pub mod bugreport;
+pub mod completion;
+pub mod config;
+pub mod dirs;
+pub mod editor;
+pub mod encoding;
+pub mod events;
+pub mod expand;
+pub mod export;
+pub mod finder;
+pub mod git;
+pub mod gutter;
+pub mod headless;
+pub mod health;
+pub mod indent;
+pub mod keymap;
+pub mod keys;
+pub mod lint;
+pub mod lsp;
+pub mod macros;
+pub mod merge;
+pub mod operators;
+pub mod options;
+pub mod output;
+pub mod paths;
+pub mod pins;
+pub mod popup;
+pub mod position;
+pub mod prose;
+pub mod quickfix;
+pub mod range;
+pub mod render;
+pub mod repl;
+pub mod scripting;
+pub mod search_index;
+pub mod session;
+pub mod snapshot;
+pub mod substitute;
+pub mod swap;
+pub mod trash;
+pub mod whitespace;
+pub mod width;
+pub mod write_filters;
+pub mod yanks;
@@ -0,0 +1,329 @@
+use crate::events::{Task, Waker};
+use crate::output;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Tried in order on every line of output when a linter has no format of its
+// own.
+pub const DEFAULT_FORMAT: &str = "%f:%l:%c: %t: %m,%f:%l:%c: %m,%f:%l: %m";
+
+pub struct Linter {
+    pub command: String,
+    pub format: String,
+}
+
+// Linters keyed by extension or file name, like write filters. In the
+// command `%` stands for the file being checked. Formats are Vim-style
+// errorformats: comma-separated patterns in which `%f` is the file, `%l`
+// the line, `%c` the column, `%m` the message, `%t` a word whose first
+// letter gives the kind (`error`, `warning`, `W291`), `%.%#` anything and
+// `%%` a percent sign.
+pub struct Linters {
+    linters: BTreeMap<String, Linter>,
+}
+
+impl Default for Linters {
+    fn default() -> Self {
+        Linters::new()
+    }
+}
+
+impl Linters {
+    pub fn new() -> Self {
+        let mut linters = Linters {
+            linters: BTreeMap::new(),
+        };
+        linters.set("sh", "shellcheck -f gcc %");
+        linters.set("py", "flake8 %");
+        linters.set("rs", "cargo clippy --quiet --message-format short");
+        let _ = linters.set_format("sh", "%f:%l:%c: %t: %m");
+        let _ = linters.set_format("py", "%f:%l:%c: %t %m");
+        linters
+    }
+
+    // Keeps the format of a linter that is already set up.
+    pub fn set(&mut self, key: &str, command: &str) {
+        let linter = self.linters.entry(key.to_string()).or_insert(Linter {
+            command: String::new(),
+            format: DEFAULT_FORMAT.to_string(),
+        });
+        linter.command = command.to_string();
+    }
+
+    pub fn set_format(&mut self, key: &str, format: &str) -> Result<(), String> {
+        let linter = self
+            .linters
+            .get_mut(key)
+            .ok_or_else(|| format!("No linter for {}", key))?;
+        linter.format = format.to_string();
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Linter> {
+        self.linters.get(key)
+    }
+
+    pub fn remove(&mut self, key: &str) -> bool {
+        self.linters.remove(key).is_some()
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let width = self
+            .linters
+            .keys()
+            .map(|k| k.chars().count())
+            .max()
+            .unwrap_or(0);
+        self.linters
+            .iter()
+            .map(|(key, linter)| {
+                format!(
+                    "{:<width$}  {}  [{}]",
+                    key,
+                    linter.command,
+                    linter.format,
+                    width = width
+                )
+            })
+            .collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Linter)> {
+        self.linters.iter().map(|(k, linter)| (k.as_str(), linter))
+    }
+
+    // A linter for the file's name wins over one for its extension.
+    pub fn for_path(&self, path: &str) -> Option<&Linter> {
+        let path = Path::new(path);
+        let name = path.file_name()?.to_string_lossy();
+        let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+        self.linters
+            .get(name.as_ref())
+            .or_else(|| self.linters.get(ext.as_deref()?))
+    }
+}
+
+pub struct Diagnostic {
+    pub path: String,
+    pub line: usize,
+    pub col: usize,
+    // 'E', 'W' or 'I'.
+    pub kind: char,
+    pub message: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Token {
+    Char(char),
+    File,
+    Line,
+    Col,
+    Kind,
+    Message,
+    Any,
+}
+
+fn tokens(pattern: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut rest = pattern;
+    while let Some(c) = rest.chars().next() {
+        let (token, len) = match rest.get(..4) {
+            Some("%.%#") => (Token::Any, 4),
+            _ => match (c, rest[1..].chars().next()) {
+                ('%', Some('f')) => (Token::File, 2),
+                ('%', Some('l')) => (Token::Line, 2),
+                ('%', Some('c')) => (Token::Col, 2),
+                ('%', Some('t')) => (Token::Kind, 2),
+                ('%', Some('m')) => (Token::Message, 2),
+                ('%', Some('%')) => (Token::Char('%'), 2),
+                _ => (Token::Char(c), c.len_utf8()),
+            },
+        };
+        tokens.push(token);
+        rest = &rest[len..];
+    }
+    tokens
+}
+
+// Splits a format at the commas that aren't escaped with a backslash.
+fn patterns(format: &str) -> Vec<String> {
+    let mut patterns = vec![String::new()];
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(',') => patterns.last_mut().unwrap().push(','),
+                Some(next) => {
+                    patterns.last_mut().unwrap().push(c);
+                    patterns.last_mut().unwrap().push(next);
+                }
+                None => patterns.last_mut().unwrap().push(c),
+            },
+            ',' => patterns.push(String::new()),
+            c => patterns.last_mut().unwrap().push(c),
+        }
+    }
+    patterns
+}
+
+// Matches `tokens` against all of `text`, filling `captures` with the text
+// each capturing token took. Shorter captures are tried first.
+fn matches(tokens: &[Token], text: &[char], captures: &mut Vec<(Token, String)>) -> bool {
+    let Some((&token, rest)) = tokens.split_first() else {
+        return text.is_empty();
+    };
+    if let Token::Char(c) = token {
+        return text.first() == Some(&c) && matches(rest, &text[1..], captures);
+    }
+    let allowed = |c: char| match token {
+        Token::Line | Token::Col => c.is_ascii_digit(),
+        Token::Kind => c.is_alphanumeric(),
+        _ => true,
+    };
+    let min = if matches!(token, Token::Any | Token::Message) {
+        0
+    } else {
+        1
+    };
+    let max = text.iter().position(|&c| !allowed(c)).unwrap_or(text.len());
+    for len in min..=max {
+        let mark = captures.len();
+        captures.push((token, text[..len].iter().collect()));
+        if matches(rest, &text[len..], captures) {
+            return true;
+        }
+        captures.truncate(mark);
+    }
+    false
+}
+
+// Parses one line of linter output with the first pattern of `format` that
+// matches it and names a line.
+pub fn parse_line(format: &str, line: &str) -> Option<Diagnostic> {
+    let text: Vec<char> = line.chars().collect();
+    patterns(format).iter().find_map(|pattern| {
+        let mut captures = Vec::new();
+        if !matches(&tokens(pattern), &text, &mut captures) {
+            return None;
+        }
+        let mut diagnostic = Diagnostic {
+            path: String::new(),
+            line: 0,
+            col: 1,
+            kind: 'E',
+            message: String::new(),
+        };
+        for (token, value) in captures {
+            match token {
+                Token::File => diagnostic.path = value,
+                Token::Line => diagnostic.line = value.parse().ok()?,
+                Token::Col => diagnostic.col = value.parse().ok()?,
+                Token::Kind => diagnostic.kind = kind(&value),
+                Token::Message => diagnostic.message = value.trim().to_string(),
+                _ => {}
+            }
+        }
+        (diagnostic.line > 0).then_some(diagnostic)
+    })
+}
+
+fn kind(word: &str) -> char {
+    match word.chars().next().map(|c| c.to_ascii_uppercase()) {
+        Some('W') => 'W',
+        Some('I' | 'N') => 'I',
+        _ => 'E',
+    }
+}
+
+pub fn quote(path: &str) -> String {
+    if cfg!(windows) {
+        format!("\"{}\"", path)
+    } else {
+        format!("'{}'", path.replace('\'', "'\\''"))
+    }
+}
+
+// A linter running in the background. Unsaved text is checked through a
+// copy in the temporary directory, which reports map back to the file.
+pub struct Run {
+    pub command: String,
+    // Whether a clean result is worth a message.
+    pub verbose: bool,
+    path: String,
+    format: String,
+    copy: Option<PathBuf>,
+    task: Task<Result<String, String>>,
+}
+
+impl Run {
+    pub fn start(
+        linter: &Linter,
+        path: &str,
+        unsaved: Option<&str>,
+        verbose: bool,
+        waker: &Waker,
+    ) -> Result<Run, String> {
+        let copy = match unsaved {
+            Some(text) => {
+                let dir = std::env::temp_dir().join(format!("rvex-lint-{}", std::process::id()));
+                let name = Path::new(path).file_name().ok_or("No file name")?;
+                let copy = dir.join(name);
+                fs::create_dir_all(&dir)
+                    .and_then(|_| fs::write(&copy, text))
+                    .map_err(|e| format!("Can't write {}: {}", copy.display(), e))?;
+                Some(copy)
+            }
+            None => None,
+        };
+        let checked = copy
+            .as_ref()
+            .map_or(path.to_string(), |c| c.display().to_string());
+        let command = linter.command.replace('%', &quote(&checked));
+        let cmd = command.clone();
+        let task = Task::spawn(waker, move || match output::shell(&cmd).output() {
+            Ok(out) => Ok(String::from_utf8_lossy(&out.stdout).to_string()
+                + String::from_utf8_lossy(&out.stderr).as_ref()),
+            Err(e) => Err(format!("Can't run '{}': {}", cmd, e)),
+        });
+        Ok(Run {
+            command,
+            verbose,
+            path: path.to_string(),
+            format: linter.format.clone(),
+            copy,
+            task,
+        })
+    }
+
+    // The linter's combined output once it has finished.
+    pub fn poll(&self) -> Option<Result<String, String>> {
+        self.task
+            .poll()
+            .map(|result| result.unwrap_or_else(|_| Err(format!("'{}' died", self.command))))
+    }
+
+    pub fn parse(&self, line: &str) -> Option<Diagnostic> {
+        let mut diagnostic = parse_line(&self.format, line)?;
+        if self
+            .copy
+            .as_ref()
+            .is_some_and(|c| Path::new(&diagnostic.path) == c)
+        {
+            diagnostic.path = self.path.clone();
+        }
+        Some(diagnostic)
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        if let Some(copy) = &self.copy {
+            let _ = fs::remove_file(copy);
+            // Fails harmlessly while another copy is still in there.
+            if let Some(dir) = copy.parent() {
+                let _ = fs::remove_dir(dir);
+            }
+        }
+    }
+}
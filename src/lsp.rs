@@ -0,0 +1,512 @@
+use crate::aliases;
+use crate::events::Waker;
+use crate::output;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+// Language server commands keyed by extension or file name, like write
+// filters.
+pub struct Servers {
+    servers: BTreeMap<String, String>,
+}
+
+impl Default for Servers {
+    fn default() -> Self {
+        Servers::new()
+    }
+}
+
+impl Servers {
+    pub fn new() -> Self {
+        let mut servers = Servers {
+            servers: BTreeMap::new(),
+        };
+        servers.set("rs", "rust-analyzer");
+        servers
+    }
+
+    pub fn set(&mut self, key: &str, cmd: &str) {
+        self.servers.insert(key.to_string(), cmd.to_string());
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.servers.get(key).map(|cmd| cmd.as_str())
+    }
+
+    pub fn remove(&mut self, key: &str) -> bool {
+        self.servers.remove(key).is_some()
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        aliases::listing(&self.servers)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.servers
+            .iter()
+            .map(|(k, cmd)| (k.as_str(), cmd.as_str()))
+    }
+
+    pub fn for_path(&self, path: &str) -> Option<&str> {
+        let path = Path::new(path);
+        let name = path.file_name()?.to_string_lossy();
+        let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+        self.servers
+            .get(name.as_ref())
+            .or_else(|| self.servers.get(ext.as_deref()?))
+            .map(|cmd| cmd.as_str())
+    }
+}
+
+fn language_id(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()).unwrap_or("") {
+        "rs" => "rust",
+        "py" => "python",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "hpp" => "cpp",
+        "go" => "go",
+        "js" => "javascript",
+        "ts" => "typescript",
+        "sh" => "shellscript",
+        _ => "plaintext",
+    }
+}
+
+// The project a file belongs to: the nearest directory above it that looks
+// like the top of one, or else its own directory.
+pub fn root(path: &Path) -> PathBuf {
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    let markers = [
+        ".git",
+        "Cargo.toml",
+        "package.json",
+        "go.mod",
+        "pyproject.toml",
+    ];
+    dir.ancestors()
+        .find(|d| markers.iter().any(|m| d.join(m).exists()))
+        .unwrap_or(dir)
+        .to_path_buf()
+}
+
+pub fn uri(path: &Path) -> String {
+    let text = path.to_string_lossy().replace('\\', "/");
+    let mut uri = String::from("file://");
+    if !text.starts_with('/') {
+        uri.push('/');
+    }
+    for b in text.bytes() {
+        if b.is_ascii_alphanumeric() || b"/-._~:".contains(&b) {
+            uri.push(b as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", b));
+        }
+    }
+    uri
+}
+
+fn path_from_uri(uri: &str) -> Option<String> {
+    let text = uri.strip_prefix("file://")?;
+    let mut bytes = Vec::new();
+    let mut rest = text.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        match (b, tail.get(..2)) {
+            (b'%', Some(hex)) => {
+                let hex = std::str::from_utf8(hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+    let path = String::from_utf8(bytes).ok()?;
+    // `/C:/x` on Windows.
+    Some(match path.get(2..3) {
+        Some(":") if cfg!(windows) => path[1..].to_string(),
+        _ => path,
+    })
+}
+
+// Positions on the wire count UTF-16 code units; the editor counts chars.
+pub fn utf16_col(line: &str, col: usize) -> usize {
+    line.chars().take(col).map(char::len_utf16).sum()
+}
+
+pub fn char_col(line: &str, utf16: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in line.chars().enumerate() {
+        if units >= utf16 {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    line.chars().count()
+}
+
+// A place as the server counts it: a line and a UTF-16 column, in the text
+// it was last sent, which edits since may have moved.
+#[derive(Clone, Copy)]
+pub struct WirePosition {
+    pub line: usize,
+    pub character: usize,
+}
+
+pub struct Diagnostic {
+    pub start: WirePosition,
+    pub end: WirePosition,
+    // 'E', 'W' or 'I'.
+    pub kind: char,
+    pub message: String,
+}
+
+pub struct Location {
+    pub path: String,
+    pub line: usize,
+    pub character: usize,
+}
+
+pub enum Event {
+    Diagnostics(Vec<Diagnostic>),
+    Definition(Option<Location>),
+    Hover(Option<String>),
+    Completion(Vec<String>),
+    Error(String),
+    Exited,
+}
+
+#[derive(Clone, Copy)]
+enum Request {
+    Initialize,
+    Definition,
+    Hover,
+    Completion,
+    Shutdown,
+}
+
+// One language server, talking JSON-RPC over its stdin and stdout. A thread
+// reads its messages so the main loop only ever polls. The file is synced
+// by sending its whole text after each change.
+pub struct Client {
+    pub command: String,
+    child: Option<Child>,
+    stdin: Option<ChildStdin>,
+    receiver: Receiver<Value>,
+    next_id: u64,
+    requests: HashMap<u64, Request>,
+    ready: bool,
+    // Messages held back until the server has answered `initialize`.
+    queued: Vec<Value>,
+    uri: String,
+    version: i64,
+    exited: bool,
+}
+
+impl Client {
+    pub fn start(command: &str, path: &str, text: String, waker: &Waker) -> Result<Client, String> {
+        let path = std::path::absolute(path).map_err(|e| e.to_string())?;
+        let mut child = output::shell(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Can't start '{}': {}", command, e))?;
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let (sender, receiver) = mpsc::channel();
+        let waker = waker.clone();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(stdout);
+            while let Some(message) = read_message(&mut reader) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+                waker.wake();
+            }
+        });
+        let mut client = Client {
+            command: command.to_string(),
+            child: Some(child),
+            stdin,
+            receiver,
+            next_id: 1,
+            requests: HashMap::new(),
+            ready: false,
+            queued: Vec::new(),
+            uri: uri(&path),
+            version: 0,
+            exited: false,
+        };
+        let capabilities = json!({
+            "textDocument": {
+                "synchronization": { "didSave": true },
+                "publishDiagnostics": {},
+                "definition": {},
+                "completion": { "completionItem": { "snippetSupport": false } },
+                "hover": { "contentFormat": ["plaintext", "markdown"] },
+            }
+        });
+        client.request(
+            Request::Initialize,
+            "initialize",
+            json!({
+                "processId": std::process::id(),
+                "rootUri": uri(&root(&path)),
+                "capabilities": capabilities,
+            }),
+        );
+        client.notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": {
+                    "uri": client.uri,
+                    "languageId": language_id(&path),
+                    "version": 0,
+                    "text": text,
+                }
+            }),
+        );
+        Ok(client)
+    }
+
+    fn send(&mut self, message: Value) {
+        if !self.ready && message["method"] != "initialize" {
+            self.queued.push(message);
+            return;
+        }
+        let body = message.to_string();
+        if let Some(stdin) = &mut self.stdin {
+            let _ = write!(stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+            let _ = stdin.flush();
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    fn request(&mut self, kind: Request, method: &str, params: Value) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.requests.insert(id, kind);
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+    }
+
+    fn position(&self, line: usize, character: usize) -> Value {
+        json!({
+            "textDocument": { "uri": self.uri },
+            "position": { "line": line, "character": character },
+        })
+    }
+
+    pub fn change(&mut self, text: String) {
+        self.version += 1;
+        let params = json!({
+            "textDocument": { "uri": self.uri, "version": self.version },
+            "contentChanges": [{ "text": text }],
+        });
+        self.notify("textDocument/didChange", params);
+    }
+
+    pub fn saved(&mut self) {
+        let params = json!({ "textDocument": { "uri": self.uri } });
+        self.notify("textDocument/didSave", params);
+    }
+
+    pub fn definition(&mut self, line: usize, character: usize) {
+        let params = self.position(line, character);
+        self.request(Request::Definition, "textDocument/definition", params);
+    }
+
+    pub fn hover(&mut self, line: usize, character: usize) {
+        let params = self.position(line, character);
+        self.request(Request::Hover, "textDocument/hover", params);
+    }
+
+    pub fn completion(&mut self, line: usize, character: usize) {
+        let params = self.position(line, character);
+        self.request(Request::Completion, "textDocument/completion", params);
+    }
+
+    // Handles whatever the server has sent since the last call.
+    pub fn poll(&mut self) -> Vec<Event> {
+        let mut events = Vec::new();
+        loop {
+            match self.receiver.try_recv() {
+                Ok(message) => events.extend(self.handle(message)),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    if !self.exited {
+                        self.exited = true;
+                        events.push(Event::Exited);
+                    }
+                    break;
+                }
+            }
+        }
+        events
+    }
+
+    fn handle(&mut self, message: Value) -> Option<Event> {
+        let method = message["method"].as_str();
+        let id = &message["id"];
+        if let (Some(method), false) = (method, id.is_null()) {
+            // We don't act on server requests, but they all want an answer.
+            let result = match method {
+                "workspace/configuration" => {
+                    let items = message["params"]["items"].as_array().map_or(0, |i| i.len());
+                    Value::Array(vec![Value::Null; items])
+                }
+                _ => Value::Null,
+            };
+            self.send(json!({ "jsonrpc": "2.0", "id": id, "result": result }));
+            return None;
+        }
+        if method == Some("textDocument/publishDiagnostics") {
+            let params = &message["params"];
+            if params["uri"] != self.uri.as_str() {
+                return None;
+            }
+            let list = params["diagnostics"].as_array()?;
+            return Some(Event::Diagnostics(
+                list.iter().filter_map(diagnostic).collect(),
+            ));
+        }
+        let kind = self.requests.remove(&id.as_u64()?)?;
+        if let Some(error) = message["error"]["message"].as_str() {
+            return Some(Event::Error(error.to_string()));
+        }
+        let result = &message["result"];
+        match kind {
+            Request::Initialize => {
+                self.ready = true;
+                self.notify("initialized", json!({}));
+                for message in std::mem::take(&mut self.queued) {
+                    self.send(message);
+                }
+                None
+            }
+            Request::Definition => Some(Event::Definition(location(result))),
+            Request::Hover => Some(Event::Hover(hover_text(&result["contents"]))),
+            Request::Completion => Some(Event::Completion(completions(result))),
+            Request::Shutdown => None,
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.ready = true;
+        self.request(Request::Shutdown, "shutdown", Value::Null);
+        self.notify("exit", Value::Null);
+        // Closing stdin tells servers that missed `exit` to go away too.
+        self.stdin = None;
+        if let Some(mut child) = self.child.take() {
+            thread::spawn(move || child.wait());
+        }
+    }
+}
+
+fn read_message(reader: &mut impl BufRead) -> Option<Value> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let mut body = vec![0; length?];
+    reader.read_exact(&mut body).ok()?;
+    // A message we can't parse is skipped rather than ending the session.
+    Some(serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+fn wire_position(value: &Value) -> Option<WirePosition> {
+    Some(WirePosition {
+        line: value["line"].as_u64()? as usize,
+        character: value["character"].as_u64()? as usize,
+    })
+}
+
+fn diagnostic(value: &Value) -> Option<Diagnostic> {
+    let range = &value["range"];
+    Some(Diagnostic {
+        start: wire_position(&range["start"])?,
+        end: wire_position(&range["end"])?,
+        kind: match value["severity"].as_u64() {
+            Some(2) => 'W',
+            Some(3 | 4) => 'I',
+            _ => 'E',
+        },
+        message: value["message"].as_str().unwrap_or("").to_string(),
+    })
+}
+
+// The first of a Location, Location[] or LocationLink[].
+fn location(result: &Value) -> Option<Location> {
+    let first = match result {
+        Value::Array(list) => list.first()?,
+        other => other,
+    };
+    let uri = first["uri"].as_str().or(first["targetUri"].as_str())?;
+    let range = if first["targetSelectionRange"].is_object() {
+        &first["targetSelectionRange"]
+    } else {
+        &first["range"]
+    };
+    let WirePosition { line, character } = wire_position(&range["start"])?;
+    Some(Location {
+        path: path_from_uri(uri)?,
+        line,
+        character,
+    })
+}
+
+// The text each item of a CompletionItem[] or CompletionList inserts.
+fn completions(result: &Value) -> Vec<String> {
+    let items = result.as_array().or(result["items"].as_array());
+    items
+        .into_iter()
+        .flatten()
+        .filter_map(|item| {
+            item["textEdit"]["newText"]
+                .as_str()
+                .or(item["insertText"].as_str())
+                .or(item["label"].as_str())
+                .map(|text| text.to_string())
+        })
+        .collect()
+}
+
+// Hover contents come as a string, a `{language, value}` pair, markup, or a
+// list of those.
+fn hover_text(contents: &Value) -> Option<String> {
+    let text = match contents {
+        Value::String(s) => s.clone(),
+        Value::Array(list) => list
+            .iter()
+            .filter_map(hover_text)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Value::Object(_) => contents["value"].as_str()?.to_string(),
+        _ => return None,
+    };
+    let text = text.trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
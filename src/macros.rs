@@ -0,0 +1,122 @@
+use crate::keys::{self, Key, KeyEvent};
+use std::collections::BTreeMap;
+
+// Keys recorded with `q{a-z}` and played with `@{a-z}`, and triggers: named
+// key sequences run with `:trigger NAME`, which leader mappings and user
+// commands can call like small functions that need no script. Defining a
+// trigger as `@a` keeps what register `a` holds now, so a recorded macro
+// outlives the session through `:mksession` or the config.
+pub struct Macros {
+    registers: BTreeMap<char, Vec<Key>>,
+    recording: Option<(char, Vec<KeyEvent>)>,
+    // The register `@@` plays.
+    pub last: Option<char>,
+    triggers: BTreeMap<String, Vec<Key>>,
+}
+
+impl Default for Macros {
+    fn default() -> Self {
+        Macros::new()
+    }
+}
+
+pub fn is_register(c: char) -> bool {
+    c.is_ascii_lowercase()
+}
+
+impl Macros {
+    pub fn new() -> Self {
+        Macros {
+            registers: BTreeMap::new(),
+            recording: None,
+            last: None,
+            triggers: BTreeMap::new(),
+        }
+    }
+
+    pub fn start(&mut self, register: char) {
+        self.recording = Some((register, Vec::new()));
+    }
+
+    pub fn recording(&self) -> Option<char> {
+        self.recording.as_ref().map(|(register, _)| *register)
+    }
+
+    pub fn record(&mut self, key: KeyEvent) {
+        if let Some((_, keys)) = &mut self.recording {
+            keys.push(key);
+        }
+    }
+
+    // Stores what was recorded, without the `q` that stopped it.
+    pub fn stop(&mut self) -> Option<char> {
+        let (register, mut keys) = self.recording.take()?;
+        keys.pop();
+        self.registers
+            .insert(register, keys.into_iter().map(Key::Press).collect());
+        Some(register)
+    }
+
+    pub fn register(&self, register: char) -> Option<&[Key]> {
+        self.registers.get(&register).map(|keys| keys.as_slice())
+    }
+
+    pub fn define(&mut self, name: &str, keys: &str) -> Result<(), String> {
+        let valid = name.starts_with(|c: char| c.is_alphabetic())
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+        if !valid {
+            return Err(format!("Invalid trigger name: {}", name));
+        }
+        let keys = match keys.strip_prefix('@') {
+            Some(r) if r.len() == 1 && r.chars().all(is_register) => {
+                let register = r.chars().next().unwrap();
+                self.register(register)
+                    .ok_or_else(|| format!("Nothing recorded in @{}", register))?
+                    .to_vec()
+            }
+            _ => keys::parse(keys)?,
+        };
+        if keys.is_empty() {
+            return Err(format!("Missing keys for trigger {}", name));
+        }
+        self.triggers.insert(name.to_string(), keys);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.triggers.remove(name).is_some()
+    }
+
+    pub fn trigger(&self, name: &str) -> Option<&[Key]> {
+        self.triggers.get(name).map(|keys| keys.as_slice())
+    }
+
+    // Registers first, then triggers.
+    pub fn lines(&self) -> Vec<String> {
+        let registers = self
+            .registers
+            .iter()
+            .map(|(register, keys)| format!("@{}  {}", register, keys::format(keys)));
+        let width = self
+            .triggers
+            .keys()
+            .map(|n| n.chars().count())
+            .max()
+            .unwrap_or(0);
+        let triggers = self
+            .triggers
+            .iter()
+            .map(|(name, keys)| format!("{:<width$}  {}", name, keys::format(keys), width = width));
+        registers.chain(triggers).collect()
+    }
+
+    // The commands that define the triggers again, for sessions.
+    pub fn commands(&self) -> Vec<String> {
+        self.triggers
+            .iter()
+            .map(|(name, keys)| format!("trigger {} {}", name, keys::format(keys)))
+            .collect()
+    }
+}
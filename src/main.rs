@@ -1,282 +1,205 @@
-use crossterm::terminal::{enable_raw_mode, disable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
-use crossterm::ExecutableCommand;
-use crossterm::cursor::{Hide, Show};
-use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+mod terminal;
+
 use std::{
-    fs,
     io::{self, stdin, stdout, Write},
     path::Path,
+    time::Duration,
 };
+use terminal::{Input, Signals};
+use text_editor::editor::{EditorState, EXIT_MISSING_FILE, EXIT_USAGE};
+use text_editor::events::{Event, EventLoop};
+use text_editor::keys;
+use text_editor::merge::Merge;
+use text_editor::{config, dirs, render, session};
 
-struct EditorState {
-    mode: Mode,
-    cursor: (usize, usize),
-    content: Vec<String>,
-    file_path: String,
-    status_message: Option<String>,
-    screen_size: (usize, usize),
-    should_exit: bool,
-    command_buffer: String,
-}
-
-#[derive(PartialEq)]
-enum Mode {
-    Normal,
-    Insert,
-    Command,
-}
-
-impl EditorState {
-    fn new(file_path: String) -> Self {
-        let mut content = Vec::new();
-        if Path::new(&file_path).exists() {
-            content = fs::read_to_string(&file_path)
-                .unwrap_or_default()
-                .lines()
-                .map(|line| line.to_string())
-                .collect();
-        }
-        if content.is_empty() {
-            content.push(String::new());
-        }
-        let (rows, cols) = crossterm::terminal::size().unwrap_or((24, 80));
-        EditorState {
-            mode: Mode::Normal,
-            cursor: (0, 0),
-            content,
-            file_path,
-            status_message: None,
-            screen_size: (rows as usize, cols as usize),
-            should_exit: false,
-            command_buffer: String::new(),
+fn main() -> io::Result<()> {
+    let mut view_mode = false;
+    let mut strict = false;
+    let mut merge_paths = None;
+    let mut session = None;
+    let mut clean = false;
+    let mut config_path = None;
+    let mut file_path = None;
+    let mut replay = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-R" => view_mode = true,
+            "--strict" => strict = true,
+            "--clean" => clean = true,
+            "--config" => match args.next() {
+                Some(path) => config_path = Some(path),
+                None => {
+                    eprintln!("--config needs a file");
+                    std::process::exit(EXIT_USAGE);
+                }
+            },
+            "--session" => session = Some(args.next().unwrap_or(session::DEFAULT_FILE.to_string())),
+            "--merge" => {
+                let paths: Vec<String> = args.by_ref().take(4).collect();
+                if paths.len() < 4 {
+                    eprintln!("--merge needs LOCAL BASE REMOTE MERGED");
+                    std::process::exit(EXIT_USAGE);
+                }
+                file_path = Some(paths[3].clone());
+                merge_paths = Some(paths);
+            }
+            "--replay" => match keys::parse(&args.next().unwrap_or_default()) {
+                Ok(keys) => replay = keys,
+                Err(e) => {
+                    eprintln!("--replay: {}", e);
+                    std::process::exit(EXIT_USAGE);
+                }
+            },
+            _ if arg.starts_with('-') => {
+                eprintln!("Unknown option: {}", arg);
+                eprintln!(
+                    "Usage: text_editor [-R] [--strict] [--clean] [--config FILE] [--replay KEYS] [--session FILE] [file]"
+                );
+                eprintln!("       text_editor --merge LOCAL BASE REMOTE MERGED");
+                std::process::exit(EXIT_USAGE);
+            }
+            _ => file_path = Some(arg),
         }
     }
-
-    fn adjust_column(&mut self) {
-        if self.cursor.0 >= self.content.len() {
-            self.cursor.0 = self.content.len().saturating_sub(1);
-        }
-        let line_len = self.content[self.cursor.0].chars().count();
-        if self.cursor.1 > line_len {
-            self.cursor.1 = line_len;
-        }
+    let file_path = match file_path {
+        Some(path) => path,
+        // The session says which file to open.
+        None if session.is_some() => String::new(),
+        None => {
+            let mut file_path = String::new();
+            print!("Enter file path: ");
+            stdout().flush()?;
+            stdin().read_line(&mut file_path)?;
+            file_path.trim().to_string()
+        }
+    };
+    if strict && !file_path.is_empty() && !Path::new(&file_path).exists() {
+        eprintln!("{}: No such file", file_path);
+        std::process::exit(EXIT_MISSING_FILE);
     }
+    // `--clean` wins, so a broken `--config` in an alias can be switched off.
+    // It also leaves out plugins.
+    let config_path = match config_path {
+        _ if clean => None,
+        Some(path) if !Path::new(&path).exists() => {
+            eprintln!("--config: {}: No such file", path);
+            std::process::exit(EXIT_MISSING_FILE);
+        }
+        Some(path) => Some(path),
+        None => config::default_path()
+            .filter(|p| p.exists())
+            .map(|p| p.to_string_lossy().into_owned()),
+    };
+    let merge = match merge_paths {
+        Some(paths) => match Merge::open(&paths[0], &paths[1], &paths[2]) {
+            Ok(merge) => Some(merge),
+            Err(e) => {
+                eprintln!("--merge: {}", e);
+                std::process::exit(EXIT_MISSING_FILE);
+            }
+        },
+        None => None,
+    };
 
-    fn move_to_line_start(&mut self) {
-        self.cursor.1 = 0;
-    }
+    terminal::install_panic_hook();
+    let signals = Signals::register()?;
+    terminal::enter()?;
 
-    fn move_to_line_end(&mut self) {
-        self.cursor.1 = self.content[self.cursor.0].chars().count();
+    let mut state = EditorState::new(file_path);
+    if let Ok((cols, rows)) = crossterm::terminal::size() {
+        state.resize(rows as usize, cols as usize);
     }
-
-    fn save_file(&mut self) {
-        match fs::write(&self.file_path, self.content.join("\n")) {
-            Ok(_) => self.status_message = Some("File saved".to_string()),
-            Err(e) => self.status_message = Some(format!("Save error: {}", e)),
-        }
+    state.load_config(config_path.as_deref());
+    state.migrate_state(&dirs::legacy_state_dir());
+    if let Some(dir) = config::plugins_dir().filter(|_| !clean) {
+        state.load_plugins(&dir);
     }
-}
-
-fn draw_content(state: &EditorState, frame: &mut String) -> io::Result<()> {
-    let (_, cols) = crossterm::terminal::size()?;
-    let visible_lines = state.screen_size.0 - 1;
-
-    for (row, line) in state.content.iter().enumerate().take(visible_lines) {
-        frame.push_str(&format!("\x1b[{};1H\x1b[34m{:4} \x1b[0m", row + 1, row + 1));
-        
-        let line = line.chars().take(cols as usize - 5).collect::<String>();
-        frame.push_str(&format!("\x1b[{};6H{}", row + 1, line));
+    if view_mode {
+        state.set_view_mode();
     }
-    Ok(())
-}
-
-fn handle_normal_mode(event: &KeyEvent, state: &mut EditorState) {
-    match event.code {
-        KeyCode::Char('h') | KeyCode::Left => state.cursor.1 = state.cursor.1.saturating_sub(1),
-        KeyCode::Char('j') | KeyCode::Down => {
-            if state.cursor.0 < state.content.len().saturating_sub(1) {
-                state.cursor.0 += 1;
-                state.adjust_column();
-            }
-        }
-        KeyCode::Char('k') | KeyCode::Up => {
-            state.cursor.0 = state.cursor.0.saturating_sub(1);
-            state.adjust_column();
-        }
-        KeyCode::Char('l') | KeyCode::Right => {
-            let line_len = state.content[state.cursor.0].chars().count();
-            if state.cursor.1 < line_len {
-                state.cursor.1 += 1;
-            }
-        }
-        KeyCode::Char('i') => state.mode = Mode::Insert,
-        KeyCode::Char(':') => state.mode = Mode::Command,
-        KeyCode::Char('0') => state.move_to_line_start(),
-        KeyCode::Char('$') => state.move_to_line_end(),
-        KeyCode::Char('w') if event.modifiers.contains(KeyModifiers::CONTROL) => state.save_file(),
-        KeyCode::Char('q') if event.modifiers.contains(KeyModifiers::CONTROL) => {
-            state.should_exit = true
-        }
-        KeyCode::Char('o') => {
-            state.content.insert(state.cursor.0 + 1, String::new());
-            state.cursor.0 += 1;
-            state.cursor.1 = 0;
-            state.mode = Mode::Insert;
-        }
-        KeyCode::Char('d') if event.modifiers.contains(KeyModifiers::CONTROL) => {
-            if !state.content.is_empty() {
-                state.content.remove(state.cursor.0);
-                if state.cursor.0 >= state.content.len() && !state.content.is_empty() {
-                    state.cursor.0 = state.content.len() - 1;
-                }
-            }
-        }
-        _ => {}
+    if let Some(merge) = merge {
+        state.start_merge(merge);
     }
-}
-
-fn handle_insert_mode(event: &KeyEvent, state: &mut EditorState) {
-    match event.code {
-        KeyCode::Esc => state.mode = Mode::Normal,
-        KeyCode::Backspace => {
-            if state.cursor.1 > 0 {
-                let line = &mut state.content[state.cursor.0];
-                let mut chars: Vec<char> = line.chars().collect();
-                chars.remove(state.cursor.1 - 1);
-                *line = chars.into_iter().collect();
-                state.cursor.1 -= 1;
-            } else if state.cursor.0 > 0 {
-                let current_line = state.content.remove(state.cursor.0);
-                state.cursor.0 -= 1;
-                let prev_line = &mut state.content[state.cursor.0];
-                state.cursor.1 = prev_line.chars().count();
-                prev_line.push_str(&current_line);
-            }
-        }
-        KeyCode::Delete => {
-            let line = &mut state.content[state.cursor.0];
-            let chars_len = line.chars().count();
-            if state.cursor.1 < chars_len {
-                let mut chars: Vec<char> = line.chars().collect();
-                chars.remove(state.cursor.1);
-                *line = chars.into_iter().collect();
-            }
-        }
-        KeyCode::Enter => {
-            let current_line = state.content[state.cursor.0].clone();
-            let (left, right) = current_line.split_at(state.cursor.1);
-            state.content[state.cursor.0] = left.to_string();
-            state.content.insert(state.cursor.0 + 1, right.to_string());
-            state.cursor.0 += 1;
-            state.cursor.1 = 0;
-        }
-        KeyCode::Char(c) => {
-            if c.is_control() || event.modifiers != KeyModifiers::NONE {
-                return;
-            }
-            let line = &mut state.content[state.cursor.0];
-            let mut chars: Vec<char> = line.chars().collect();
-            chars.insert(state.cursor.1, c);
-            *line = chars.into_iter().collect();
-            state.cursor.1 += 1;
-        }
-        _ => {}
+    state.refresh_git();
+    if let Some(path) = session {
+        state.source(&path);
     }
-}
-
-fn handle_command_mode(state: &mut EditorState) {
-    match state.command_buffer.as_str() {
-        "w" => state.save_file(),
-        "q" => state.should_exit = true,
-        "wq" => {
-            state.save_file();
-            state.should_exit = true;
-        }
-        _ => state.status_message = Some(format!("Unknown command: {}", state.command_buffer)),
+    // Replayed keys would answer the prompt meant for whoever comes back
+    // after a crash.
+    if replay.is_empty() {
+        state.check_crash();
+    }
+    state.replay(replay);
+    state.check_swap();
+    state.start_autosession();
+    let result = run(&mut state, &signals);
+    terminal::leave();
+    result?;
+    if state.exit_code() != 0 {
+        std::process::exit(state.exit_code());
     }
-    state.command_buffer.clear();
+    Ok(())
 }
 
-fn main() -> io::Result<()> {
-    let mut file_path = String::new();
-    print!("Enter file path: ");
-    stdout().flush()?;
-    stdin().read_line(&mut file_path)?;
-    let file_path = file_path.trim().to_string();
+// How often the loop wakes up for housekeeping like autosave when nothing
+// else happens.
+const TICK: Duration = Duration::from_millis(100);
 
-    enable_raw_mode()?;
+fn run(state: &mut EditorState, signals: &Signals) -> io::Result<()> {
     let mut stdout = stdout();
-    stdout.execute(EnterAlternateScreen)?;
-    stdout.execute(Hide)?;
-
-    let mut state = EditorState::new(file_path);
-
-    while !state.should_exit {
-        let (rows, cols) = crossterm::terminal::size()?;
-        state.screen_size = (rows as usize, cols as usize);
-
-        let mut frame = String::new();
-        
-        frame.push_str("\x1b[2J");
-        frame.push_str("\x1b[1;1H");
-        
-        draw_content(&state, &mut frame)?;
-        
-        frame.push_str(&format!(
-            "\x1b[{};1H\x1b[44m\x1b[37m{:<width$}\x1b[0m",
-            rows,
-            format!(" {} | {} | {}:{} ", 
-                match state.mode {
-                    Mode::Normal => "NORMAL",
-                    Mode::Insert => "INSERT",
-                    Mode::Command => "COMMAND",
-                },
-                state.file_path, 
-                state.cursor.0 + 1, 
-                state.cursor.1 + 1),
-            width = cols as usize - 1
-        ));
-
-        frame.push_str(&format!(
-            "\x1b[{};{}H",
-            (state.cursor.0 + 1).min(rows as usize),
-            (state.cursor.1 + 6).min(cols as usize)
-        ));
-
-        print!("{}", frame);
-        stdout.flush()?;
-
-        if event::poll(std::time::Duration::from_millis(100))? {
-            match event::read()? {
-                Event::Key(KeyEvent { code, modifiers, kind, .. }) 
-                    if kind == event::KeyEventKind::Press => 
-                {
-                    let key_event = KeyEvent::new(code, modifiers);
-                    match state.mode {
-                        Mode::Normal => handle_normal_mode(&key_event, &mut state),
-                        Mode::Insert => handle_insert_mode(&key_event, &mut state),
-                        Mode::Command => match key_event.code {
-                            KeyCode::Enter => handle_command_mode(&mut state),
-                            KeyCode::Char(c) => state.command_buffer.push(c),
-                            KeyCode::Backspace => {
-                                state.command_buffer.pop();
-                            }
-                            KeyCode::Esc => {
-                                state.mode = Mode::Normal;
-                                state.command_buffer.clear();
-                            }
-                            _ => {}
-                        },
-                    }
-                }
-                _ => {}
-            }
-        }
+    let mut renderer_name = state.renderer_name().to_string();
+    let mut renderer = render::backend(&renderer_name);
+    let events = EventLoop::new();
+    let input = Input::start(events.sender());
+    events.start_timer(TICK);
+    state.set_waker(events.waker());
+    let mut redraw = true;
+    while !state.should_exit() {
+        if signals.terminate_requested() {
+            state.save_swap();
+            break;
+        }
+        if signals.take_suspend() || state.take_suspend_request() {
+            input.pause();
+            terminal::suspend()?;
+            input.resume();
+            redraw = true;
+        }
+        if let Some(cmd) = state.take_shell_request() {
+            input.pause();
+            let result = terminal::run_shell(&cmd);
+            input.resume();
+            state.shell_finished(&cmd, result);
+            redraw = true;
+        }
+        state.update();
+
+        if renderer_name != state.renderer_name() {
+            renderer_name = state.renderer_name().to_string();
+            renderer = render::backend(&renderer_name);
+        }
+        if redraw {
+            state.draw(renderer.as_mut());
+            renderer.present(&mut stdout)?;
+            redraw = false;
+        }
+
+        // Keys waiting from a replay or a mapping go first. While there is
+        // background work left, events are only checked for in between.
+        if state.handle_pending_key() {
+            redraw = true;
+            continue;
+        }
+        let busy = state.background_work();
+        let timeout = if busy { Duration::ZERO } else { TICK * 2 };
+        match events.next(timeout) {
+            Some(Event::Key(key)) => state.handle_key(key),
+            Some(Event::Resize(rows, cols)) => state.resize(rows, cols),
+            Some(Event::Tick | Event::Wake) => {}
+            None => continue,
+        }
+        redraw = true;
     }
-
-    stdout.execute(Show)?;
-    stdout.execute(LeaveAlternateScreen)?;
-    disable_raw_mode()?;
     Ok(())
-}
\ No newline at end of file
+}
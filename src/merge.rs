@@ -0,0 +1,163 @@
+use crate::buffer::Buffer;
+use std::fs;
+use std::io;
+use std::ops::Range;
+
+// Which version of a conflict to keep.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Pick {
+    Local,
+    Base,
+    Remote,
+    Both,
+}
+
+impl Pick {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "local" | "lo" | "ours" => Some(Pick::Local),
+            "base" | "ba" => Some(Pick::Base),
+            "remote" | "re" | "theirs" => Some(Pick::Remote),
+            "both" => Some(Pick::Both),
+            _ => None,
+        }
+    }
+}
+
+// One of the read-only versions shown above the merge result.
+pub struct Pane {
+    pub title: &'static str,
+    pub path: String,
+    pub lines: Vec<String>,
+}
+
+impl Pane {
+    fn open(title: &'static str, path: &str) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        Ok(Pane {
+            title,
+            path: path.to_string(),
+            lines: String::from_utf8_lossy(&bytes)
+                .lines()
+                .map(|l| l.to_string())
+                .collect(),
+        })
+    }
+
+    // Where `section` starts in this version, if it appears verbatim.
+    pub fn find(&self, section: &[&str]) -> Option<usize> {
+        if section.is_empty() || section.len() > self.lines.len() {
+            return None;
+        }
+        (0..=self.lines.len() - section.len()).find(|&start| {
+            section
+                .iter()
+                .zip(&self.lines[start..])
+                .all(|(a, b)| *a == b.as_str())
+        })
+    }
+}
+
+// The files git hands a mergetool. The result file (MERGED) is the buffer
+// being edited; the other three are only shown.
+pub struct Merge {
+    pub panes: [Pane; 3],
+}
+
+impl Merge {
+    pub fn open(local: &str, base: &str, remote: &str) -> io::Result<Self> {
+        Ok(Merge {
+            panes: [
+                Pane::open("LOCAL", local)?,
+                Pane::open("BASE", base)?,
+                Pane::open("REMOTE", remote)?,
+            ],
+        })
+    }
+}
+
+// A block of conflict markers in the result, by line number. `base` is only
+// there when git writes diff3-style conflicts.
+pub struct Conflict {
+    pub start: usize,
+    pub base: Option<usize>,
+    pub middle: usize,
+    pub end: usize,
+}
+
+impl Conflict {
+    pub fn lines(&self) -> Range<usize> {
+        self.start..self.end + 1
+    }
+
+    pub fn local(&self) -> Range<usize> {
+        self.start + 1..self.base.unwrap_or(self.middle)
+    }
+
+    pub fn base(&self) -> Option<Range<usize>> {
+        self.base.map(|base| base + 1..self.middle)
+    }
+
+    pub fn remote(&self) -> Range<usize> {
+        self.middle + 1..self.end
+    }
+
+    // The lines that replace the whole block when `pick` is chosen.
+    pub fn resolve(&self, lines: &Buffer, pick: Pick) -> Result<Vec<String>, String> {
+        let ranges = match pick {
+            Pick::Local => vec![self.local()],
+            Pick::Remote => vec![self.remote()],
+            Pick::Both => vec![self.local(), self.remote()],
+            Pick::Base => match self.base() {
+                Some(base) => vec![base],
+                None => {
+                    return Err(
+                        "Conflict has no base section (set merge.conflictStyle=diff3)".to_string(),
+                    )
+                }
+            },
+        };
+        Ok(ranges
+            .into_iter()
+            .flat_map(|range| lines.range(range).map(|l| l.to_string()))
+            .collect())
+    }
+}
+
+fn is_marker(line: &str, marker: &str) -> bool {
+    line.strip_prefix(marker)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(' '))
+}
+
+// Finds every complete conflict block. Unbalanced markers are left alone.
+pub fn conflicts(lines: &Buffer) -> Vec<Conflict> {
+    let mut found = Vec::new();
+    let mut start = None;
+    let mut base = None;
+    let mut middle = None;
+    for (row, line) in lines.iter().enumerate() {
+        if is_marker(line, "<<<<<<<") {
+            (start, base, middle) = (Some(row), None, None);
+        } else if start.is_some() && middle.is_none() && is_marker(line, "|||||||") {
+            base = Some(row);
+        } else if start.is_some() && middle.is_none() && is_marker(line, "=======") {
+            middle = Some(row);
+        } else if is_marker(line, ">>>>>>>") {
+            if let (Some(start), Some(middle)) = (start, middle) {
+                found.push(Conflict {
+                    start,
+                    base,
+                    middle,
+                    end: row,
+                });
+            }
+            (start, base, middle) = (None, None, None);
+        }
+    }
+    found
+}
+
+// The conflict the cursor is in, or else the next one below it.
+pub fn current(conflicts: &[Conflict], row: usize) -> Option<usize> {
+    conflicts.iter().position(|c| c.end >= row)
+}
@@ -0,0 +1,89 @@
+use crate::aliases;
+use crate::buffer::Buffer;
+use crate::range::LineRange;
+use std::collections::BTreeMap;
+
+// User-defined operators: `:operator gr !tr a-z n-za-m` makes `gr` followed
+// by a motion or text object run the Ex command on the lines it covers, and
+// `grr` run it on the current line. The command can be anything that takes
+// a range, a user command included. Keys are one character, or `g` and one
+// more; `g@` is built in and runs the user command named by `operatorfunc`.
+pub struct Operators {
+    operators: BTreeMap<String, String>,
+}
+
+impl Default for Operators {
+    fn default() -> Self {
+        Operators::new()
+    }
+}
+
+impl Operators {
+    pub fn new() -> Self {
+        Operators {
+            operators: BTreeMap::new(),
+        }
+    }
+
+    pub fn define(&mut self, keys: &str, command: &str) -> Result<(), String> {
+        let valid = match keys.chars().collect::<Vec<_>>().as_slice() {
+            [c] => !c.is_whitespace(),
+            ['g', c] => !c.is_whitespace() && *c != '@',
+            _ => false,
+        };
+        if !valid {
+            return Err(format!("Operators are one key or g and one key: {}", keys));
+        }
+        self.operators.insert(keys.to_string(), command.to_string());
+        Ok(())
+    }
+
+    pub fn remove(&mut self, keys: &str) -> bool {
+        self.operators.remove(keys).is_some()
+    }
+
+    pub fn get(&self, keys: &str) -> Option<&str> {
+        self.operators.get(keys).map(|c| c.as_str())
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        aliases::listing(&self.operators)
+    }
+
+    // The commands that define them again, for sessions.
+    pub fn commands(&self) -> Vec<String> {
+        self.operators
+            .iter()
+            .map(|(keys, command)| format!("operator {} {}", keys, command))
+            .collect()
+    }
+}
+
+// The paragraph around `row`: the run of non-blank lines it is in, or of
+// blank lines if it is blank. `around` also takes the blank lines after it,
+// or before it when there are none after.
+pub fn paragraph(lines: &Buffer, row: usize, around: bool) -> LineRange {
+    let blank = |r: usize| lines[r].trim().is_empty();
+    let kind = blank(row);
+    let mut start = row;
+    while start > 0 && blank(start - 1) == kind {
+        start -= 1;
+    }
+    let mut end = row;
+    while end + 1 < lines.len() && blank(end + 1) == kind {
+        end += 1;
+    }
+    if around {
+        if end + 1 < lines.len() {
+            end += 1;
+            while end + 1 < lines.len() && blank(end + 1) != kind {
+                end += 1;
+            }
+        } else {
+            while start > 0 && blank(start - 1) != kind {
+                start -= 1;
+            }
+        }
+    }
+    (start, end)
+}
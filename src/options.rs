@@ -0,0 +1,251 @@
+use crate::keys::{self, Key, KeyCode, KeyEvent, KeyModifiers};
+use crate::render;
+use crate::whitespace::ListChars;
+
+#[derive(Clone)]
+pub struct Options {
+    pub autosave: u64,
+    // Seconds between saves of the session to restore after a crash; 0 to
+    // save it only when the editor is killed.
+    pub autosession: u64,
+    pub swapfile: bool,
+    pub renderer: String,
+    // What `<leader>` stands for in mappings, in key notation.
+    pub leader: String,
+    // How `:s` previews while it is typed: `nosplit` in the text, `split`
+    // also in a pane listing every affected line, or `off`.
+    pub inccommand: String,
+    // When linters run: `save`, `idle` (also a second after edits stop) or
+    // `off`.
+    pub lint: String,
+    // Memory use in megabytes past which the search index is dropped; 0
+    // for no limit.
+    pub maxmemory: u64,
+    // The user command `g@` runs on the lines its motion covers.
+    pub operatorfunc: String,
+    // Columns `>` and `<` shift by; 0 for the file's own indent unit.
+    pub shiftwidth: u64,
+    // Whether every match of the last search is highlighted.
+    pub hlsearch: bool,
+    // Whether typing a search moves the view to its first match.
+    pub incsearch: bool,
+    // Whether tabs, trailing spaces and non-breaking spaces are shown with
+    // the symbols in `listchars`, drawn in `listcolor`.
+    pub list: bool,
+    pub listchars: String,
+    pub listcolor: String,
+    // Whether trailing whitespace is stripped from every line on saving.
+    pub stripwhitespace: bool,
+    // Whether the last keys typed are kept for `:bugreport`.
+    pub keytrace: bool,
+    // Where swap files and other state are kept; empty for the platform's
+    // usual place. Files already there move along when it changes.
+    pub statedir: String,
+    // The extensions of files `:ProseChecker` checks.
+    pub prosetypes: String,
+    // Whether lines too long for the window continue on the next screen
+    // row instead of being cut off.
+    pub wrap: bool,
+    // Whether `j` and `k` move by screen rows, like `gj` and `gk`, in
+    // buffers of the `prosetypes`.
+    pub displaylines: bool,
+    // What `:hardcopy` turns the HTML page `%` into a PDF with; the PDF's
+    // path is added at the end.
+    pub printprg: String,
+    // The word list, one word per line, the `dictionary` completion source
+    // reads.
+    pub dictionary: String,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options::new()
+    }
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Options {
+            autosave: 0,
+            autosession: 30,
+            swapfile: true,
+            renderer: "ansi".to_string(),
+            leader: "\\".to_string(),
+            inccommand: "nosplit".to_string(),
+            lint: "save".to_string(),
+            maxmemory: 1024,
+            operatorfunc: String::new(),
+            shiftwidth: 0,
+            hlsearch: true,
+            incsearch: true,
+            list: false,
+            listchars: "tab:>,trail:-,nbsp:+".to_string(),
+            listcolor: "blue".to_string(),
+            stripwhitespace: false,
+            keytrace: false,
+            statedir: String::new(),
+            prosetypes: "md,markdown,txt,rst,adoc,org,tex".to_string(),
+            wrap: false,
+            displaylines: false,
+            printprg: "wkhtmltopdf --quiet %".to_string(),
+            dictionary: "/usr/share/dict/words".to_string(),
+        }
+    }
+
+    // Applies one `:set` argument: `name`, `noname`, `name=value` or `name?`.
+    // Returns text to show in the message line, if any.
+    pub fn set(&mut self, arg: &str) -> Result<Option<String>, String> {
+        if let Some(name) = arg.strip_suffix('?') {
+            return self.get(name).map(|v| Some(format!("{}={}", name, v)));
+        }
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg, None),
+        };
+        match (name, value) {
+            ("autosave" | "as", Some(v)) => self.autosave = parse_number(name, v)?,
+            ("autosession" | "ass", Some(v)) => self.autosession = parse_number(name, v)?,
+            ("maxmemory" | "mm", Some(v)) => self.maxmemory = parse_number(name, v)?,
+            ("shiftwidth" | "sw", Some(v)) => self.shiftwidth = parse_number(name, v)?,
+            ("swapfile" | "swf", None) => self.swapfile = true,
+            ("noswapfile" | "noswf", None) => self.swapfile = false,
+            ("hlsearch" | "hls", None) => self.hlsearch = true,
+            ("nohlsearch" | "nohls", None) => self.hlsearch = false,
+            ("incsearch" | "is", None) => self.incsearch = true,
+            ("noincsearch" | "nois", None) => self.incsearch = false,
+            ("list", None) => self.list = true,
+            ("nolist", None) => self.list = false,
+            ("listchars" | "lcs", Some(v)) => {
+                ListChars::parse(v)?;
+                self.listchars = v.to_string()
+            }
+            ("listcolor", Some(v)) => match render::parse_color(v) {
+                Some(_) => self.listcolor = v.to_string(),
+                None => return Err(format!("Unknown color: {}", v)),
+            },
+            ("stripwhitespace" | "sws", None) => self.stripwhitespace = true,
+            ("nostripwhitespace" | "nosws", None) => self.stripwhitespace = false,
+            ("keytrace", None) => self.keytrace = true,
+            ("nokeytrace", None) => self.keytrace = false,
+            ("statedir" | "sdir", Some(v)) => self.statedir = v.to_string(),
+            ("prosetypes", Some(v)) => self.prosetypes = v.to_string(),
+            ("wrap", None) => self.wrap = true,
+            ("nowrap", None) => self.wrap = false,
+            ("displaylines" | "dl", None) => self.displaylines = true,
+            ("nodisplaylines" | "nodl", None) => self.displaylines = false,
+            ("renderer", Some(v @ ("ansi" | "grid"))) => self.renderer = v.to_string(),
+            ("renderer", Some(v)) => return Err(format!("Unknown renderer: {}", v)),
+            ("inccommand" | "icm", Some(v @ ("nosplit" | "split" | "off"))) => {
+                self.inccommand = v.to_string()
+            }
+            ("inccommand" | "icm", Some(v)) => return Err(format!("Unknown inccommand: {}", v)),
+            ("lint", Some(v @ ("save" | "idle" | "off"))) => self.lint = v.to_string(),
+            ("lint", Some(v)) => return Err(format!("Unknown lint setting: {}", v)),
+            ("operatorfunc" | "opfunc", Some(v)) => self.operatorfunc = v.to_string(),
+            ("printprg" | "pprg", Some(v)) => self.printprg = v.to_string(),
+            ("dictionary" | "dict", Some(v)) => self.dictionary = v.to_string(),
+            ("leader", Some(v)) => match keys::parse(v)?.as_slice() {
+                [Key::Press(_)] => self.leader = v.to_string(),
+                _ => return Err(format!("Leader must be a single key: {}", v)),
+            },
+            _ => match self.get(name) {
+                Ok(v) if value.is_none() => return Ok(Some(format!("{}={}", name, v))),
+                _ => return Err(format!("Unknown option: {}", arg)),
+            },
+        }
+        Ok(None)
+    }
+
+    pub fn leader(&self) -> KeyEvent {
+        match keys::parse(&self.leader).ok().as_deref() {
+            Some([Key::Press(event)]) => *event,
+            _ => KeyEvent::new(KeyCode::Char('\\'), KeyModifiers::NONE),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<String, String> {
+        Ok(match name {
+            "autosave" | "as" => self.autosave.to_string(),
+            "autosession" | "ass" => self.autosession.to_string(),
+            "swapfile" | "swf" => self.swapfile.to_string(),
+            "renderer" => self.renderer.clone(),
+            "leader" => self.leader.clone(),
+            "inccommand" | "icm" => self.inccommand.clone(),
+            "lint" => self.lint.clone(),
+            "maxmemory" | "mm" => self.maxmemory.to_string(),
+            "operatorfunc" | "opfunc" => self.operatorfunc.clone(),
+            "printprg" | "pprg" => self.printprg.clone(),
+            "dictionary" | "dict" => self.dictionary.clone(),
+            "shiftwidth" | "sw" => self.shiftwidth.to_string(),
+            "hlsearch" | "hls" => self.hlsearch.to_string(),
+            "incsearch" | "is" => self.incsearch.to_string(),
+            "list" => self.list.to_string(),
+            "listchars" | "lcs" => self.listchars.clone(),
+            "listcolor" => self.listcolor.clone(),
+            "stripwhitespace" | "sws" => self.stripwhitespace.to_string(),
+            "keytrace" => self.keytrace.to_string(),
+            "statedir" | "sdir" => self.statedir.clone(),
+            "prosetypes" => self.prosetypes.clone(),
+            "wrap" => self.wrap.to_string(),
+            "displaylines" | "dl" => self.displaylines.to_string(),
+            _ => return Err(format!("Unknown option: {}", name)),
+        })
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "autosave={} autosession={} {}swapfile renderer={} leader={} inccommand={} lint={} maxmemory={} operatorfunc={} shiftwidth={} {}hlsearch {}incsearch {}list listchars={} listcolor={} {}stripwhitespace {}keytrace statedir={} prosetypes={} {}wrap {}displaylines printprg={} dictionary={}",
+            self.autosave,
+            self.autosession,
+            if self.swapfile { "" } else { "no" },
+            self.renderer,
+            self.leader,
+            self.inccommand,
+            self.lint,
+            self.maxmemory,
+            self.operatorfunc,
+            self.shiftwidth,
+            if self.hlsearch { "" } else { "no" },
+            if self.incsearch { "" } else { "no" },
+            if self.list { "" } else { "no" },
+            self.listchars,
+            self.listcolor,
+            if self.stripwhitespace { "" } else { "no" },
+            if self.keytrace { "" } else { "no" },
+            self.statedir,
+            self.prosetypes,
+            if self.wrap { "" } else { "no" },
+            if self.displaylines { "" } else { "no" },
+            self.printprg.replace(' ', "\\ "),
+            self.dictionary.replace(' ', "\\ ")
+        )
+    }
+}
+
+// Splits `:set` arguments at whitespace that isn't escaped with a
+// backslash, so values can hold spaces as in `printprg=a\ b`.
+pub fn split_args(args: &str) -> Vec<String> {
+    let mut words = vec![String::new()];
+    let mut chars = args.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek().is_some_and(|c| c.is_whitespace()) => {
+                words.last_mut().unwrap().push(chars.next().unwrap());
+            }
+            c if c.is_whitespace() => {
+                if !words.last().unwrap().is_empty() {
+                    words.push(String::new());
+                }
+            }
+            c => words.last_mut().unwrap().push(c),
+        }
+    }
+    words.retain(|w| !w.is_empty());
+    words
+}
+
+fn parse_number(name: &str, value: &str) -> Result<u64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", name, value))
+}
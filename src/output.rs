@@ -0,0 +1,169 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const PANE_HEIGHT: usize = 10;
+
+pub struct OutputBuffer {
+    pub lines: Vec<String>,
+    pub visible: bool,
+    pub scroll: usize,
+    pub title: String,
+    last_command: Option<String>,
+}
+
+impl Default for OutputBuffer {
+    fn default() -> Self {
+        OutputBuffer::new()
+    }
+}
+
+impl OutputBuffer {
+    pub fn new() -> Self {
+        OutputBuffer {
+            lines: Vec::new(),
+            visible: false,
+            scroll: 0,
+            title: String::new(),
+            last_command: None,
+        }
+    }
+
+    pub fn memory(&self) -> usize {
+        self.lines.iter().map(|l| l.capacity()).sum::<usize>()
+            + self.lines.capacity() * std::mem::size_of::<String>()
+    }
+
+    pub fn last_command(&self) -> Option<&str> {
+        self.last_command.as_deref()
+    }
+
+    // Runs `cmd` through the shell and appends a timestamped section with its
+    // combined stdout/stderr. Returns the index of the first output line and
+    // the exit status so callers can parse the section into the quickfix list.
+    pub fn run(&mut self, cmd: &str) -> (usize, Option<i32>) {
+        self.last_command = Some(cmd.to_string());
+        self.title = cmd.to_string();
+        self.visible = true;
+        self.lines
+            .push(format!("\x1b[1m[{}] $ {}\x1b[0m", timestamp(), cmd));
+        let start = self.lines.len();
+
+        let status = match shell(cmd).output() {
+            Ok(out) => {
+                let text = String::from_utf8_lossy(&out.stdout).to_string()
+                    + String::from_utf8_lossy(&out.stderr).as_ref();
+                self.lines.extend(text.lines().map(|l| l.to_string()));
+                out.status.code()
+            }
+            Err(e) => {
+                self.lines
+                    .push(format!("\x1b[31mfailed to run command: {}\x1b[0m", e));
+                None
+            }
+        };
+        match status {
+            Some(0) => self.lines.push("\x1b[32m[exit 0]\x1b[0m".to_string()),
+            Some(code) => self.lines.push(format!("\x1b[31m[exit {}]\x1b[0m", code)),
+            None => {}
+        }
+        self.scroll = start.saturating_sub(1);
+        (start, status)
+    }
+
+    pub fn show(&mut self, title: &str, lines: Vec<String>) {
+        self.title = title.to_string();
+        self.visible = true;
+        self.scroll = self.lines.len();
+        self.log(title, lines);
+    }
+
+    // Adds lines to the end of the last section, scrolled into view.
+    pub fn append(&mut self, lines: Vec<String>) {
+        self.lines.extend(lines);
+        self.visible = true;
+        self.scroll = self.lines.len().saturating_sub(PANE_HEIGHT - 1);
+    }
+
+    // Appends a section without bringing up the pane. Returns the index of
+    // its first line.
+    pub fn log(&mut self, title: &str, lines: Vec<String>) -> usize {
+        self.lines
+            .push(format!("\x1b[1m[{}] {}\x1b[0m", timestamp(), title));
+        let start = self.lines.len();
+        self.lines.extend(lines);
+        start
+    }
+}
+
+pub fn shell(cmd: &str) -> Command {
+    if cfg!(windows) {
+        let mut c = Command::new("cmd");
+        c.args(["/C", cmd]);
+        c
+    } else {
+        let mut c = Command::new("sh");
+        c.args(["-c", cmd]);
+        c
+    }
+}
+
+// Pipes `input` through `cmd` and returns its stdout. Input is written from
+// a separate thread so a filter that emits output before reading all of its
+// input can't deadlock against us.
+pub fn filter(cmd: &str, input: Vec<u8>) -> Result<String, String> {
+    let mut child = shell(cmd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run command: {}", e))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let writer = thread::spawn(move || stdin.write_all(&input));
+    let out = child
+        .wait_with_output()
+        .map_err(|e| format!("failed to run command: {}", e))?;
+    let _ = writer.join();
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        return Err(match stderr.lines().next() {
+            Some(line) => format!("'{}' failed: {}", cmd, line),
+            None => format!("'{}' exited with {}", cmd, out.status.code().unwrap_or(-1)),
+        });
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).to_string())
+}
+
+pub fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+pub fn strip_ansi(line: &str) -> String {
+    let mut out = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            if chars.peek() == Some(&'[') {
+                chars.next();
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+        } else if !c.is_control() || c == '\t' {
+            out.push(c);
+        }
+    }
+    out
+}
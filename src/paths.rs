@@ -0,0 +1,212 @@
+use std::borrow::Cow;
+use std::env;
+use std::fs;
+
+// How paths are spelled. Only Windows knows drive letters, UNC shares and
+// backslash separators, and only it matches names without regard to case.
+// Both styles work on every platform so they can be tested anywhere.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Style {
+    Unix,
+    Windows,
+}
+
+impl Style {
+    pub fn current() -> Self {
+        if cfg!(windows) {
+            Style::Windows
+        } else {
+            Style::Unix
+        }
+    }
+
+    fn is_separator(self, c: char) -> bool {
+        c == '/' || (self == Style::Windows && c == '\\')
+    }
+}
+
+// What a path starts with before its first ordinary component.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Prefix {
+    // `C:`, followed by a separator when the path is absolute.
+    Drive(char),
+    // `\\server\share`.
+    Unc(String, String),
+}
+
+// Windows' verbatim prefixes, as `canonicalize` returns them there.
+const VERBATIM_UNC: &str = r"\\?\UNC\";
+const VERBATIM: &str = r"\\?\";
+
+// Device names Windows reserves in every directory, with any extension.
+const RESERVED: [&str; 4] = ["CON", "PRN", "AUX", "NUL"];
+
+pub fn prefix(path: &str, style: Style) -> Option<Prefix> {
+    if style == Style::Unix {
+        return None;
+    }
+    let path = &*strip_verbatim(path);
+    let mut chars = path.chars();
+    match (chars.next(), chars.next()) {
+        (Some(letter), Some(':')) if letter.is_ascii_alphabetic() => {
+            Some(Prefix::Drive(letter.to_ascii_uppercase()))
+        }
+        (Some(a), Some(b)) if style.is_separator(a) && style.is_separator(b) => {
+            let mut parts = path[2..].split(|c| style.is_separator(c));
+            let server = parts.next().filter(|s| !s.is_empty())?;
+            let share = parts.next().filter(|s| !s.is_empty())?;
+            Some(Prefix::Unc(server.to_string(), share.to_string()))
+        }
+        _ => None,
+    }
+}
+
+pub fn is_absolute(path: &str, style: Style) -> bool {
+    let path = &*strip_verbatim(path);
+    match prefix(path, style) {
+        Some(Prefix::Unc(..)) => true,
+        Some(Prefix::Drive(_)) => path[2..].starts_with(|c| style.is_separator(c)),
+        None => path.starts_with(|c| style.is_separator(c)),
+    }
+}
+
+// `\\?\C:\x` is `C:\x` and `\\?\UNC\server\share` is `\\server\share`.
+pub fn strip_verbatim(path: &str) -> Cow<'_, str> {
+    if let Some(rest) = path.strip_prefix(VERBATIM_UNC) {
+        Cow::Owned(format!(r"\\{}", rest))
+    } else {
+        Cow::Borrowed(path.strip_prefix(VERBATIM).unwrap_or(path))
+    }
+}
+
+// The spelling two paths to the same file share: separators made `/`, `.`
+// and `..` resolved, and on Windows the case folded. Symbolic links are
+// not followed; `same_file` looks at the disk for that.
+pub fn key(path: &str, style: Style) -> String {
+    let path = &*strip_verbatim(path);
+    let (head, rest) = match prefix(path, style) {
+        Some(Prefix::Drive(letter)) => (format!("{}:", letter), &path[2..]),
+        Some(Prefix::Unc(server, share)) => {
+            let len = 2 + server.len() + 1 + share.len();
+            (
+                format!("//{}/{}", server, share),
+                &path[len.min(path.len())..],
+            )
+        }
+        None => (String::new(), path),
+    };
+    let absolute = rest.starts_with(|c| style.is_separator(c));
+    let mut parts: Vec<&str> = Vec::new();
+    for part in rest.split(|c| style.is_separator(c)) {
+        match part {
+            "" | "." => {}
+            ".." if parts.last().is_some_and(|p| *p != "..") => {
+                parts.pop();
+            }
+            ".." if absolute => {}
+            _ => parts.push(part),
+        }
+    }
+    let mut key = head;
+    if absolute || matches!(prefix(path, style), Some(Prefix::Unc(..))) {
+        key.push('/');
+    }
+    key.push_str(&parts.join("/"));
+    if style == Style::Windows {
+        key.to_lowercase()
+    } else {
+        key
+    }
+}
+
+// Whether two names refer to one file: by what the disk says when both
+// exist, else by spelling after making them absolute.
+pub fn same_file(a: &str, b: &str) -> bool {
+    let style = Style::current();
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => key(&a.to_string_lossy(), style) == key(&b.to_string_lossy(), style),
+        _ => key(&absolute(a, style), style) == key(&absolute(b, style), style),
+    }
+}
+
+fn absolute(path: &str, style: Style) -> String {
+    if is_absolute(path, style) {
+        return path.to_string();
+    }
+    match env::current_dir() {
+        Ok(dir) => format!("{}/{}", dir.display(), path),
+        Err(_) => path.to_string(),
+    }
+}
+
+// The reserved device name `path` ends in, like `CON` or `nul.txt`, which
+// Windows won't open as a file.
+pub fn reserved_name(path: &str, style: Style) -> Option<String> {
+    if style == Style::Unix {
+        return None;
+    }
+    let name = path.rsplit(|c| style.is_separator(c)).next()?;
+    let stem = name
+        .split('.')
+        .next()?
+        .trim_end_matches(' ')
+        .to_ascii_uppercase();
+    let numbered = (stem.starts_with("COM") || stem.starts_with("LPT"))
+        && stem.len() == 4
+        && stem.as_bytes()[3].is_ascii_digit()
+        && stem.as_bytes()[3] != b'0';
+    (RESERVED.contains(&stem.as_str()) || numbered).then(|| name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_windows_prefixes() {
+        let w = Style::Windows;
+        assert_eq!(prefix(r"c:\Users\me", w), Some(Prefix::Drive('C')));
+        assert_eq!(
+            prefix(r"\\server\share\notes.txt", w),
+            Some(Prefix::Unc("server".to_string(), "share".to_string()))
+        );
+        assert_eq!(
+            prefix(r"\\?\UNC\server\share\x", w),
+            prefix(r"\\server\share\x", w)
+        );
+        assert_eq!(prefix(r"C:\x", Style::Unix), None);
+        assert!(is_absolute(r"C:\x", w));
+        assert!(!is_absolute("C:x", w));
+        assert!(is_absolute("//server/share", w));
+        assert!(!is_absolute(r"\x", Style::Unix));
+    }
+
+    #[test]
+    fn spellings_of_one_path_share_a_key() {
+        let w = Style::Windows;
+        assert_eq!(
+            key(r"C:\Users\Me\notes.txt", w),
+            key("c:/users/me/./NOTES.txt", w)
+        );
+        assert_eq!(key(r"\\?\C:\a\b\..\c", w), "c:/a/c");
+        assert_eq!(
+            key(r"\\Server\Share\x", w),
+            key(r"\\?\UNC\server\share\x", w)
+        );
+        assert_eq!(key("/a/./b/../c", Style::Unix), "/a/c");
+        assert_eq!(key("../a", Style::Unix), "../a");
+        assert_ne!(key("/a/B", Style::Unix), key("/a/b", Style::Unix));
+        assert_ne!(key(r"a\b", Style::Unix), key("a/b", Style::Unix));
+    }
+
+    #[test]
+    fn knows_reserved_names() {
+        let w = Style::Windows;
+        assert_eq!(reserved_name(r"C:\tmp\con", w).as_deref(), Some("con"));
+        assert_eq!(reserved_name("nul.txt", w).as_deref(), Some("nul.txt"));
+        assert_eq!(reserved_name("COM1", w).as_deref(), Some("COM1"));
+        assert_eq!(reserved_name("COM0", w), None);
+        assert_eq!(reserved_name("console.txt", w), None);
+        assert_eq!(reserved_name("con", Style::Unix), None);
+    }
+}
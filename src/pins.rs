@@ -0,0 +1,160 @@
+use crate::paths;
+use crate::position::Position;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const HEADER: &str = "RVEX-PINS 1";
+
+// A pinned file and the line and grapheme the cursor was last on in it.
+// The file may have changed since, so they are moved back onto it when it
+// is opened.
+pub struct Pin {
+    pub path: PathBuf,
+    pub cursor: (usize, usize),
+}
+
+// The files of one project pinned for jumping to without the finder, in the
+// order they were pinned. Kept in the state directory, one file per project,
+// with paths inside the project stored relative to its root.
+pub struct Pins {
+    pub root: PathBuf,
+    pins: Vec<Pin>,
+}
+
+// Named after the project's root with the separators replaced, like swap
+// files.
+fn pins_path(dir: &Path, root: &Path) -> PathBuf {
+    let name: String = paths::key(&root.to_string_lossy(), paths::Style::current())
+        .chars()
+        .map(|c| {
+            if matches!(c, '/' | '\\' | ':') {
+                '%'
+            } else {
+                c
+            }
+        })
+        .collect();
+    dir.join(name)
+}
+
+impl Pins {
+    // The project's pins, or none if it has no file or an unreadable one.
+    pub fn load(dir: &Path, root: &Path) -> Pins {
+        let mut pins = Pins {
+            root: root.to_path_buf(),
+            pins: Vec::new(),
+        };
+        let Ok(text) = fs::read_to_string(pins_path(dir, root)) else {
+            return pins;
+        };
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return pins;
+        }
+        for line in lines {
+            let mut fields = line.splitn(3, ':');
+            let (Some(row), Some(col), Some(path)) = (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            if let (Ok(row), Ok(col)) = (row.parse(), col.parse()) {
+                pins.pins.push(Pin {
+                    path: root.join(path),
+                    cursor: (row, col),
+                });
+            }
+        }
+        pins
+    }
+
+    // Removes the file once nothing is pinned.
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        let path = pins_path(dir, &self.root);
+        if self.pins.is_empty() {
+            return match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        let mut text = format!("{}\n", HEADER);
+        for pin in &self.pins {
+            let (line, grapheme) = pin.cursor;
+            text.push_str(&format!("{}:{}:{}\n", line, grapheme, self.display(pin)));
+        }
+        fs::create_dir_all(dir)?;
+        fs::write(path, text)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pins.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty()
+    }
+
+    // The one-based slot of `path`, if it is pinned.
+    pub fn slot(&self, path: &Path) -> Option<usize> {
+        let path = path.to_string_lossy();
+        self.pins
+            .iter()
+            .position(|pin| paths::same_file(&pin.path.to_string_lossy(), &path))
+            .map(|i| i + 1)
+    }
+
+    pub fn get(&self, slot: usize) -> Option<&Pin> {
+        self.pins.get(slot.checked_sub(1)?)
+    }
+
+    // Pins `path` after the others, or moves its pin to `cursor` if it
+    // already has one. Returns its slot.
+    pub fn add(&mut self, path: &Path, cursor: Position) -> usize {
+        if let Some(slot) = self.remember(path, cursor) {
+            return slot;
+        }
+        self.pins.push(Pin {
+            path: path.to_path_buf(),
+            cursor: (cursor.line(), cursor.grapheme()),
+        });
+        self.pins.len()
+    }
+
+    // Moves the pin of `path`, if it has one, to `cursor`.
+    pub fn remember(&mut self, path: &Path, cursor: Position) -> Option<usize> {
+        let slot = self.slot(path)?;
+        self.pins[slot - 1].cursor = (cursor.line(), cursor.grapheme());
+        Some(slot)
+    }
+
+    pub fn remove(&mut self, slot: usize) -> Option<Pin> {
+        (1..=self.pins.len())
+            .contains(&slot)
+            .then(|| self.pins.remove(slot - 1))
+    }
+
+    // The path relative to the project when it is inside it.
+    pub fn display(&self, pin: &Pin) -> String {
+        pin.path
+            .strip_prefix(&self.root)
+            .unwrap_or(&pin.path)
+            .display()
+            .to_string()
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.pins
+            .iter()
+            .enumerate()
+            .map(|(i, pin)| {
+                format!(
+                    "{:>2}  {}:{}:{}",
+                    i + 1,
+                    self.display(pin),
+                    pin.cursor.0 + 1,
+                    pin.cursor.1 + 1
+                )
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,145 @@
+use crate::render::{self, Renderer, Style};
+use crate::width::{self, fit};
+
+pub struct Popup<'a> {
+    pub title: &'a str,
+    pub prompt: Option<&'a str>,
+    pub lines: &'a [String],
+    pub selected: Option<usize>,
+}
+
+impl Popup<'_> {
+    // Draws a bordered box centered on the screen, on top of whatever has
+    // already been drawn. When the box wouldn't have room for a single
+    // line of the list, it shrinks to one line over the status line.
+    pub fn draw(&self, renderer: &mut dyn Renderer) {
+        let (rows, cols) = renderer.size();
+        let width = (cols * 3 / 4).max(20).min(cols);
+        let prompt_rows = usize::from(self.prompt.is_some());
+        let height = (rows * 2 / 3)
+            .max(3 + prompt_rows)
+            .min(rows.saturating_sub(1));
+        if width < 4 || height < 3 + prompt_rows {
+            self.draw_line(renderer);
+            return;
+        }
+        let top = (rows.saturating_sub(1) - height) / 2;
+        let left = (cols - width) / 2;
+        let inner = width - 2;
+        let plain = Style::default();
+
+        let title = width::truncate(self.title, inner);
+        let border = "─".repeat(inner - width::width(title));
+        renderer.put(top, left, &format!("┌{}{}┐", title, border), plain);
+        let mut row = top + 1;
+        if let Some(prompt) = self.prompt {
+            self.framed(
+                renderer,
+                row,
+                left,
+                &fit(&format!("> {}", prompt), inner),
+                Style::bold(),
+            );
+            row += 1;
+        }
+
+        let list_height = height - 2 - prompt_rows;
+        let first = match self.selected {
+            Some(sel) if sel >= list_height => sel + 1 - list_height,
+            _ => 0,
+        };
+        for i in 0..list_height {
+            let index = first + i;
+            let text = fit(self.lines.get(index).map_or("", |l| l.as_str()), inner);
+            let style = if self.selected == Some(index) {
+                Style::reverse()
+            } else {
+                plain
+            };
+            self.framed(renderer, row, left, &text, style);
+            row += 1;
+        }
+        renderer.put(row, left, &format!("└{}┘", "─".repeat(inner)), plain);
+    }
+
+    fn draw_line(&self, renderer: &mut dyn Renderer) {
+        let (rows, cols) = renderer.size();
+        if rows == 0 || cols == 0 {
+            return;
+        }
+        let mut text = self.title.trim().to_string();
+        if let Some(prompt) = self.prompt {
+            text.push_str(&format!(" > {}", prompt));
+        }
+        let head = width::width(text.trim_end()) + 1;
+        renderer.put(rows - 1, 0, &fit(&text, cols), Style::bold());
+        if let Some(line) = self.selected.and_then(|i| self.lines.get(i)) {
+            renderer.put(rows - 1, head, line, Style::reverse());
+        }
+    }
+
+    fn framed(
+        &self,
+        renderer: &mut dyn Renderer,
+        row: usize,
+        left: usize,
+        text: &str,
+        style: Style,
+    ) {
+        let inner = width::width(text);
+        renderer.put(row, left, "│", Style::default());
+        renderer.put(row, left + 1, text, style);
+        renderer.put(row, left + 1 + inner, "│", Style::default());
+    }
+}
+
+// A borderless list anchored at a screen position, like the insert-mode
+// completion menu. It opens below the anchor row, or above it when there
+// is no room underneath.
+pub struct Menu<'a> {
+    pub lines: &'a [String],
+    pub selected: usize,
+}
+
+impl Menu<'_> {
+    pub const HEIGHT: usize = 10;
+
+    pub fn draw(&self, renderer: &mut dyn Renderer, row: usize, col: usize) {
+        let (rows, cols) = renderer.size();
+        // The bottom row belongs to the status line.
+        let below = rows.saturating_sub(row + 2);
+        let height = self.lines.len().min(Menu::HEIGHT);
+        let (top, height) = if height <= below || below >= row {
+            (row + 1, height.min(below))
+        } else {
+            (row - height.min(row), height.min(row))
+        };
+        let width = self
+            .lines
+            .iter()
+            .map(|l| width::width(l) + 2)
+            .max()
+            .unwrap_or(0)
+            .min(cols);
+        if height == 0 || width == 0 {
+            return;
+        }
+        let left = col.min(cols - width);
+        let first = (self.selected + 1).saturating_sub(height);
+        for i in 0..height {
+            let index = first + i;
+            let style = if index == self.selected {
+                Style {
+                    fg: Some(render::WHITE),
+                    bg: Some(render::BLUE),
+                    bold: true,
+                    ..Style::default()
+                }
+            } else {
+                Style::reverse()
+            };
+            let text = fit(&format!(" {}", self.lines[index]), width);
+            renderer.put(top + i, left, &text, style);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use crate::buffer::Buffer;
+use crate::width;
+use unicode_segmentation::UnicodeSegmentation;
+
+// A place in the buffer: a line and a grapheme cluster on it. What shows up
+// as one character, like `é` typed as `e` and a combining accent, or a flag,
+// is one grapheme, so a position can't land inside one. The column in other
+// units depends on the line's text: chars for the helpers that count them,
+// bytes for slicing and cells for the screen. A grapheme past the end of the
+// line counts as one of each, like the blanks after it on the screen.
+// Moving one onto the buffer goes through `clamped` and the methods built on
+// it, which keep it there.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    line: usize,
+    grapheme: usize,
+}
+
+impl Position {
+    const fn new(line: usize, grapheme: usize) -> Self {
+        Position { line, grapheme }
+    }
+
+    // `line` and `grapheme` moved onto the buffer: no further than its last
+    // line, or than just past the end of that line.
+    pub fn clamped(buffer: &Buffer, line: usize, grapheme: usize) -> Self {
+        let line = line.min(buffer.len().saturating_sub(1));
+        let len = if buffer.is_empty() {
+            0
+        } else {
+            graphemes(&buffer[line])
+        };
+        Position::new(line, grapheme.min(len))
+    }
+
+    pub const fn line(self) -> usize {
+        self.line
+    }
+
+    pub const fn grapheme(self) -> usize {
+        self.grapheme
+    }
+
+    // The same grapheme on `line`, or the end of that line if it is
+    // shorter.
+    pub fn to_line(self, buffer: &Buffer, line: usize) -> Self {
+        Position::clamped(buffer, line, self.grapheme)
+    }
+
+    // Grapheme `grapheme` of the same line, or just past its end.
+    pub fn to_grapheme(self, buffer: &Buffer, grapheme: usize) -> Self {
+        Position::clamped(buffer, self.line, grapheme)
+    }
+
+    pub fn line_start(self) -> Self {
+        Position::new(self.line, 0)
+    }
+
+    // Just past the last grapheme of the line.
+    pub fn line_end(self, buffer: &Buffer) -> Self {
+        self.to_grapheme(buffer, usize::MAX)
+    }
+
+    // The grapheme holding char `col` of `text`, the text of `line`.
+    pub fn from_char(text: &str, line: usize, col: usize) -> Self {
+        let mut chars = 0;
+        for (i, cluster) in text.graphemes(true).enumerate() {
+            chars += cluster.chars().count();
+            if chars > col {
+                return Position::new(line, i);
+            }
+        }
+        Position::new(line, graphemes(text) + (col - chars))
+    }
+
+    // The grapheme holding byte `byte` of `text`.
+    pub fn from_byte(text: &str, line: usize, byte: usize) -> Self {
+        if byte >= text.len() {
+            return Position::new(line, graphemes(text) + (byte - text.len()));
+        }
+        let grapheme = text
+            .grapheme_indices(true)
+            .take_while(|&(i, g)| i + g.len() <= byte)
+            .count();
+        Position::new(line, grapheme)
+    }
+
+    pub fn char_col(self, text: &str) -> usize {
+        char_col(text, self.grapheme)
+    }
+
+    // Where the grapheme starts in `text`, or its length past the end.
+    pub fn byte(self, text: &str) -> usize {
+        byte(text, self.grapheme)
+    }
+
+    // The screen column, relative to the start of the line.
+    pub fn cell(self, text: &str) -> usize {
+        width::column(text, self.char_col(text))
+    }
+}
+
+pub fn graphemes(text: &str) -> usize {
+    text.graphemes(true).count()
+}
+
+// The chars before grapheme `grapheme` of `text`, for columns that aren't
+// on a line of the buffer.
+pub fn char_col(text: &str, grapheme: usize) -> usize {
+    let chars: usize = text
+        .graphemes(true)
+        .take(grapheme)
+        .map(|g| g.chars().count())
+        .sum();
+    chars + grapheme.saturating_sub(graphemes(text))
+}
+
+pub fn byte(text: &str, grapheme: usize) -> usize {
+    text.grapheme_indices(true)
+        .nth(grapheme)
+        .map_or(text.len(), |(i, _)| i)
+}
@@ -0,0 +1,243 @@
+use crate::events::{Task, Waker};
+use crate::lint;
+use crate::output;
+use crate::position::Position;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Suggestions past this many per issue are left out of the menu.
+const MAX_REPLACEMENTS: usize = 5;
+
+// Something a prose checker found, in character columns of one line.
+pub struct Issue {
+    pub row: usize,
+    pub start: usize,
+    pub end: usize,
+    // 'E', 'W' or 'I'.
+    pub kind: char,
+    pub message: String,
+    pub replacements: Vec<String>,
+}
+
+impl Issue {
+    // Whether the issue takes in `cursor`, a place on `text`.
+    pub fn covers(&self, cursor: Position, text: &str) -> bool {
+        let col = cursor.char_col(text);
+        cursor.line() == self.row && (self.start..self.end.max(self.start + 1)).contains(&col)
+    }
+}
+
+// An entry of the code-action menu: `replacement` in place of columns
+// `start..end` of `row`.
+pub struct Fix {
+    pub label: String,
+    pub row: usize,
+    pub start: usize,
+    pub end: usize,
+    pub replacement: String,
+}
+
+// The fixes offered for the issues under the cursor.
+pub fn fixes(issues: &[Issue], cursor: Position, text: &str) -> Vec<Fix> {
+    issues
+        .iter()
+        .filter(|issue| issue.covers(cursor, text))
+        .flat_map(|issue| {
+            issue.replacements.iter().map(|replacement| Fix {
+                label: if replacement.is_empty() {
+                    "Delete".to_string()
+                } else {
+                    format!("Replace with \"{}\"", replacement)
+                },
+                row: issue.row,
+                start: issue.start,
+                end: issue.end,
+                replacement: replacement.clone(),
+            })
+        })
+        .collect()
+}
+
+// Reads what a checker printed: the JSON of LanguageTool's `/v2/check`,
+// which counts offsets into the whole text in UTF-16 units, or of
+// `vale --output=JSON`, which gives lines and one-based inclusive spans.
+pub fn parse(output: &str, text: &str) -> Result<Vec<Issue>, String> {
+    let json: Value =
+        serde_json::from_str(output).map_err(|e| format!("Unreadable checker output: {}", e))?;
+    let mut issues = match json.get("matches").and_then(|m| m.as_array()) {
+        Some(matches) => matches
+            .iter()
+            .filter_map(|m| language_tool_issue(m, text))
+            .collect(),
+        None => json
+            .as_object()
+            .ok_or("Unknown checker output")?
+            .values()
+            .filter_map(|alerts| alerts.as_array())
+            .flatten()
+            .filter_map(vale_issue)
+            .collect::<Vec<_>>(),
+    };
+    issues.sort_by_key(|issue| (issue.row, issue.start));
+    Ok(issues)
+}
+
+fn language_tool_issue(m: &Value, text: &str) -> Option<Issue> {
+    let offset = m.get("offset")?.as_u64()? as usize;
+    let length = m.get("length")?.as_u64()? as usize;
+    let (row, start) = position(text, offset);
+    let (end_row, end) = position(text, offset + length);
+    let rule = m.get("rule");
+    let kind = match rule
+        .and_then(|r| r.get("issueType"))
+        .and_then(|t| t.as_str())
+    {
+        Some("misspelling") => 'E',
+        Some("style" | "typographical" | "whitespace") => 'I',
+        _ => 'W',
+    };
+    let replacements = m
+        .get("replacements")
+        .and_then(|r| r.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|r| r.get("value")?.as_str().map(|v| v.to_string()))
+        .take(MAX_REPLACEMENTS)
+        .collect();
+    Some(Issue {
+        row,
+        start,
+        end: if end_row == row { end } else { usize::MAX },
+        kind,
+        message: with_rule(m.get("message")?.as_str()?, rule.and_then(|r| r.get("id"))),
+        replacements,
+    })
+}
+
+fn vale_issue(alert: &Value) -> Option<Issue> {
+    let line = alert.get("Line")?.as_u64()? as usize;
+    let span = alert.get("Span")?.as_array()?;
+    let first = span.first()?.as_u64()? as usize;
+    let last = span.get(1)?.as_u64()? as usize;
+    let kind = match alert.get("Severity").and_then(|s| s.as_str()) {
+        Some("error") => 'E',
+        Some("warning") => 'W',
+        _ => 'I',
+    };
+    let action = alert.get("Action");
+    let params = action
+        .and_then(|a| a.get("Params"))
+        .and_then(|p| p.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|p| p.as_str().map(|s| s.to_string()));
+    let replacements = match action.and_then(|a| a.get("Name")).and_then(|n| n.as_str()) {
+        Some("replace") => params.take(MAX_REPLACEMENTS).collect(),
+        Some("remove") => vec![String::new()],
+        _ => Vec::new(),
+    };
+    Some(Issue {
+        row: line.checked_sub(1)?,
+        start: first.saturating_sub(1),
+        end: last,
+        kind,
+        message: with_rule(alert.get("Message")?.as_str()?, alert.get("Check")),
+        replacements,
+    })
+}
+
+fn with_rule(message: &str, rule: Option<&Value>) -> String {
+    match rule.and_then(|r| r.as_str()) {
+        Some(rule) => format!("{} [{}]", message, rule),
+        None => message.to_string(),
+    }
+}
+
+// The line and character column of a UTF-16 offset into `text`.
+fn position(text: &str, offset: usize) -> (usize, usize) {
+    let (mut row, mut col, mut units) = (0, 0, 0);
+    for c in text.chars() {
+        if units >= offset {
+            break;
+        }
+        units += c.len_utf16();
+        if c == '\n' {
+            row += 1;
+            col = 0;
+        } else {
+            col += 1;
+        }
+    }
+    (row, col)
+}
+
+// A prose checker running in the background on a copy of the text, so it
+// sees unsaved changes and its positions match the buffer.
+pub struct Run {
+    pub command: String,
+    // Whether a clean result is worth a message.
+    pub verbose: bool,
+    text: String,
+    copy: PathBuf,
+    task: Task<Result<String, String>>,
+}
+
+impl Run {
+    // In `command`, `%` stands for the copy. It keeps the file's name, so
+    // checkers can tell Markdown from reStructuredText by the extension.
+    pub fn start(
+        command: &str,
+        path: &str,
+        text: String,
+        verbose: bool,
+        waker: &Waker,
+    ) -> Result<Run, String> {
+        let dir = std::env::temp_dir().join(format!("rvex-prose-{}", std::process::id()));
+        let name = Path::new(path).file_name().ok_or("No file name")?;
+        let copy = dir.join(name);
+        fs::create_dir_all(&dir)
+            .and_then(|_| fs::write(&copy, &text))
+            .map_err(|e| format!("Can't write {}: {}", copy.display(), e))?;
+        let command = command.replace('%', &lint::quote(&copy.display().to_string()));
+        let cmd = command.clone();
+        let task = Task::spawn(waker, move || match output::shell(&cmd).output() {
+            Ok(out) => Ok(String::from_utf8_lossy(&out.stdout).to_string()),
+            Err(e) => Err(format!("Can't run '{}': {}", cmd, e)),
+        });
+        Ok(Run {
+            command,
+            verbose,
+            text,
+            copy,
+            task,
+        })
+    }
+
+    // The issues found, once the checker has finished.
+    pub fn poll(&self) -> Option<Result<Vec<Issue>, String>> {
+        let result = self.task.poll()?;
+        Some(
+            result
+                .unwrap_or_else(|_| Err(format!("'{}' died", self.command)))
+                .and_then(|output| parse(&output, &self.text)),
+        )
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.copy);
+        if let Some(dir) = self.copy.parent() {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+}
+
+// Whether `path` is one of `types`, a comma-separated list of extensions.
+pub fn is_prose(path: &str, types: &str) -> bool {
+    let ext = Path::new(path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase());
+    ext.is_some_and(|ext| types.split(',').any(|t| t.trim() == ext))
+}
@@ -0,0 +1,96 @@
+use crate::output::strip_ansi;
+
+pub struct QuickfixEntry {
+    pub path: String,
+    pub line: usize,
+    pub col: usize,
+    // 'E', 'W' or 'I', for the sign column.
+    pub kind: char,
+    pub message: String,
+    pub output_line: usize,
+}
+
+pub struct QuickfixList {
+    pub entries: Vec<QuickfixEntry>,
+    pub current: usize,
+}
+
+impl Default for QuickfixList {
+    fn default() -> Self {
+        QuickfixList::new()
+    }
+}
+
+impl QuickfixList {
+    pub fn new() -> Self {
+        QuickfixList {
+            entries: Vec::new(),
+            current: 0,
+        }
+    }
+
+    pub fn set_from_output(&mut self, lines: &[String], start: usize) {
+        self.entries.clear();
+        self.current = 0;
+        let mut last_message = String::new();
+        for (i, raw) in lines.iter().enumerate().skip(start) {
+            let line = strip_ansi(raw);
+            let trimmed = line.trim_start();
+            if trimmed.starts_with("error") || trimmed.starts_with("warning") {
+                last_message = trimmed.to_string();
+            }
+            if let Some(loc) = trimmed.strip_prefix("--> ") {
+                if let Some((path, lnum, col, _)) = parse_location(loc) {
+                    self.entries.push(QuickfixEntry {
+                        path,
+                        line: lnum,
+                        col,
+                        kind: kind(&last_message),
+                        message: last_message.clone(),
+                        output_line: i,
+                    });
+                }
+            } else if let Some((path, lnum, col, message)) = parse_location(&line) {
+                self.entries.push(QuickfixEntry {
+                    path,
+                    line: lnum,
+                    col,
+                    kind: kind(&message),
+                    message,
+                    output_line: i,
+                });
+            }
+        }
+    }
+
+    pub fn is_entry_line(&self, output_line: usize) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|e| e.output_line == output_line)
+    }
+}
+
+fn kind(message: &str) -> char {
+    if message.starts_with("warning") {
+        'W'
+    } else {
+        'E'
+    }
+}
+
+// Parses `path:line[:col][: message]`, the format shared by rustc, gcc, grep -n
+// and most linters.
+fn parse_location(text: &str) -> Option<(String, usize, usize, String)> {
+    let mut parts = text.splitn(4, ':');
+    let path = parts.next()?.to_string();
+    let rest: Vec<&str> = parts.collect();
+    if path.is_empty() || path.contains(' ') {
+        return None;
+    }
+    let line = rest.first()?.trim().parse::<usize>().ok()?;
+    let (col, message) = match rest.get(1).map(|s| s.trim().parse::<usize>()) {
+        Some(Ok(col)) => (col, rest.get(2).unwrap_or(&"").trim().to_string()),
+        _ => (1, rest[1..].join(":").trim().to_string()),
+    };
+    Some((path, line, col, message))
+}
@@ -0,0 +1,75 @@
+// Zero-based, inclusive first and last line.
+pub type LineRange = (usize, usize);
+
+// Parses the line range at the start of an Ex command: `%`, `N`, `.`, `$`,
+// `'<` and `'>` (the first and last line of the last visual selection,
+// `visual`), optionally followed by `+N`/`-N` offsets, and `a,b` pairs.
+// Returns the zero-based inclusive range, if one was given, and the rest of
+// the command.
+pub fn parse(
+    cmd: &str,
+    cursor_row: usize,
+    line_count: usize,
+    visual: Option<LineRange>,
+) -> Result<(Option<LineRange>, &str), String> {
+    if let Some(rest) = cmd.strip_prefix('%') {
+        return Ok((Some((0, line_count.saturating_sub(1))), rest));
+    }
+    let Some((first, rest)) = address(cmd, cursor_row, line_count, visual)? else {
+        return Ok((None, cmd));
+    };
+    let (last, rest) = match rest.strip_prefix(',') {
+        Some(after) => match address(after, cursor_row, line_count, visual)? {
+            Some(found) => found,
+            None => return Err(format!("Invalid range: {}", cmd)),
+        },
+        None => (first, rest),
+    };
+    Ok((Some((first.min(last), first.max(last))), rest))
+}
+
+fn address(
+    text: &str,
+    cursor_row: usize,
+    line_count: usize,
+    visual: Option<LineRange>,
+) -> Result<Option<(usize, &str)>, String> {
+    let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (mut line, mut rest) = if digits > 0 {
+        (
+            text[..digits].parse::<i64>().unwrap_or(i64::MAX),
+            &text[digits..],
+        )
+    } else if let Some(rest) = text.strip_prefix('.') {
+        (cursor_row as i64 + 1, rest)
+    } else if let Some(rest) = text.strip_prefix('$') {
+        (line_count as i64, rest)
+    } else if let Some(rest) = text.strip_prefix("'<").or(text.strip_prefix("'>")) {
+        let (first, last) = visual.ok_or("Mark not set")?;
+        let row = if text.starts_with("'<") { first } else { last };
+        (row as i64 + 1, rest)
+    } else if text.starts_with(['+', '-']) {
+        (cursor_row as i64 + 1, text)
+    } else {
+        return Ok(None);
+    };
+    while let Some(sign) = rest.chars().next().filter(|c| matches!(c, '+' | '-')) {
+        rest = &rest[1..];
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let offset = if digits > 0 {
+            rest[..digits].parse::<i64>().unwrap_or(i64::MAX)
+        } else {
+            1
+        };
+        rest = &rest[digits..];
+        line = if sign == '+' {
+            line.saturating_add(offset)
+        } else {
+            line.saturating_sub(offset)
+        };
+    }
+    if line < 0 || line > line_count as i64 {
+        return Err(format!("Invalid range: line {} does not exist", line));
+    }
+    Ok(Some(((line as usize).saturating_sub(1), rest)))
+}
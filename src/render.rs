@@ -0,0 +1,434 @@
+use crate::width;
+use std::io::{self, Write};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Color {
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+pub const BLUE: Color = Color::Indexed(4);
+pub const CYAN: Color = Color::Indexed(6);
+pub const RED: Color = Color::Indexed(1);
+pub const GREEN: Color = Color::Indexed(2);
+pub const YELLOW: Color = Color::Indexed(3);
+pub const WHITE: Color = Color::Indexed(7);
+
+const NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+// A color as written in options: one of the eight names, a palette index,
+// or `#rrggbb`.
+pub fn parse_color(name: &str) -> Option<Color> {
+    if let Some(n) = NAMES.iter().position(|&c| c == name) {
+        return Some(Color::Indexed(n as u8));
+    }
+    if let Some(hex) = name.strip_prefix('#').filter(|h| h.len() == 6) {
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        return Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?));
+    }
+    name.parse().ok().map(Color::Indexed)
+}
+
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub underline: bool,
+    pub reverse: bool,
+}
+
+impl Style {
+    pub fn fg(color: Color) -> Self {
+        Style {
+            fg: Some(color),
+            ..Style::default()
+        }
+    }
+
+    pub fn bold() -> Self {
+        Style {
+            bold: true,
+            ..Style::default()
+        }
+    }
+
+    pub fn underline() -> Self {
+        Style {
+            underline: true,
+            ..Style::default()
+        }
+    }
+
+    pub fn reverse() -> Self {
+        Style {
+            reverse: true,
+            ..Style::default()
+        }
+    }
+
+    // The SGR sequence selecting this style from any previous one.
+    pub fn sgr(&self) -> String {
+        let mut params = vec!["0".to_string()];
+        if self.bold {
+            params.push("1".to_string());
+        }
+        if self.underline {
+            params.push("4".to_string());
+        }
+        if self.reverse {
+            params.push("7".to_string());
+        }
+        if let Some(fg) = self.fg {
+            params.push(color_param(fg, 30, 90, 38));
+        }
+        if let Some(bg) = self.bg {
+            params.push(color_param(bg, 40, 100, 48));
+        }
+        format!("\x1b[{}m", params.join(";"))
+    }
+
+    // Applies the parameters of an SGR sequence (the part between `ESC [`
+    // and `m`) as written by programs in the output pane.
+    fn apply_sgr(&mut self, params: &str) {
+        let codes: Vec<u16> = params.split(';').map(|p| p.parse().unwrap_or(0)).collect();
+        let mut i = 0;
+        while i < codes.len() {
+            match codes[i] {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                4 => self.underline = true,
+                7 => self.reverse = true,
+                22 => self.bold = false,
+                24 => self.underline = false,
+                27 => self.reverse = false,
+                c @ 30..=37 => self.fg = Some(Color::Indexed(c as u8 - 30)),
+                c @ 90..=97 => self.fg = Some(Color::Indexed(c as u8 - 90 + 8)),
+                39 => self.fg = None,
+                c @ 40..=47 => self.bg = Some(Color::Indexed(c as u8 - 40)),
+                c @ 100..=107 => self.bg = Some(Color::Indexed(c as u8 - 100 + 8)),
+                49 => self.bg = None,
+                c @ (38 | 48) => {
+                    let color = match codes.get(i + 1) {
+                        Some(5) => {
+                            let color = codes.get(i + 2).map(|&n| Color::Indexed(n as u8));
+                            i += 2;
+                            color
+                        }
+                        Some(2) => {
+                            let channel = |k: usize| codes.get(i + k).copied().unwrap_or(0) as u8;
+                            let color = Color::Rgb(channel(2), channel(3), channel(4));
+                            i += 4;
+                            Some(color)
+                        }
+                        _ => None,
+                    };
+                    if c == 38 {
+                        self.fg = color;
+                    } else {
+                        self.bg = color;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+}
+
+fn color_param(color: Color, base: u8, bright: u8, extended: u8) -> String {
+    match color {
+        Color::Indexed(n) if n < 8 => (base + n).to_string(),
+        Color::Indexed(n) if n < 16 => (bright + n - 8).to_string(),
+        Color::Indexed(n) => format!("{};5;{}", extended, n),
+        Color::Rgb(r, g, b) => format!("{};2;{};{};{}", extended, r, g, b),
+    }
+}
+
+// Control characters would move the terminal cursor behind our back, so every
+// backend draws them as blanks.
+fn printable(cluster: &str) -> &str {
+    if cluster.starts_with(char::is_control) {
+        " "
+    } else {
+        cluster
+    }
+}
+
+// The clusters of `text` that fit in `cells` cells, with their widths. One
+// that is double width but only has room for half is drawn as a blank.
+fn layout(text: &str, cells: usize) -> Vec<(&str, usize)> {
+    let mut used = 0;
+    let mut out = Vec::new();
+    for (cluster, w) in width::clusters(text) {
+        if w == 0 {
+            continue;
+        }
+        if used + w > cells {
+            if used < cells {
+                out.push((" ", 1));
+            }
+            break;
+        }
+        out.push((printable(cluster), w));
+        used += w;
+    }
+    out
+}
+
+// Something a frame can be drawn onto. Positions are zero-based screen rows
+// and columns, in cells; text is laid out by grapheme cluster, double width
+// ones taking two cells, and clipped at the right edge.
+pub trait Renderer {
+    fn begin(&mut self, rows: usize, cols: usize);
+    fn size(&self) -> (usize, usize);
+    fn put(&mut self, row: usize, col: usize, text: &str, style: Style);
+    fn set_cursor(&mut self, row: usize, col: usize);
+    fn present(&mut self, out: &mut dyn Write) -> io::Result<()>;
+
+    // Draws text containing SGR color sequences, dropping every other escape
+    // sequence, and returns the number of columns used.
+    fn put_ansi(&mut self, row: usize, col: usize, text: &str, width: usize) -> usize {
+        let mut style = Style::default();
+        let mut run = String::new();
+        let mut start = col;
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                if chars.peek() != Some(&'[') {
+                    continue;
+                }
+                chars.next();
+                let mut seq = String::new();
+                for c in chars.by_ref() {
+                    seq.push(c);
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+                if let Some(params) = seq.strip_suffix('m') {
+                    self.put(row, start, &run, style);
+                    start += width::width(&run);
+                    run.clear();
+                    style.apply_sgr(params);
+                }
+            } else if c == '\t' || !c.is_control() {
+                run.push(c);
+                if start - col + width::width(&run) > width {
+                    run.pop();
+                    break;
+                }
+            }
+        }
+        self.put(row, start, &run, style);
+        start - col + width::width(&run)
+    }
+}
+
+// Builds the frame as a string of escape sequences, clearing and redrawing
+// the whole screen every time.
+pub struct AnsiRenderer {
+    frame: String,
+    rows: usize,
+    cols: usize,
+    cursor: (usize, usize),
+}
+
+impl Default for AnsiRenderer {
+    fn default() -> Self {
+        AnsiRenderer::new()
+    }
+}
+
+impl AnsiRenderer {
+    pub fn new() -> Self {
+        AnsiRenderer {
+            frame: String::new(),
+            rows: 0,
+            cols: 0,
+            cursor: (0, 0),
+        }
+    }
+}
+
+impl Renderer for AnsiRenderer {
+    fn begin(&mut self, rows: usize, cols: usize) {
+        self.rows = rows;
+        self.cols = cols;
+        self.frame = "\x1b[2J\x1b[1;1H".to_string();
+    }
+
+    fn size(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    fn put(&mut self, row: usize, col: usize, text: &str, style: Style) {
+        if row >= self.rows || col >= self.cols {
+            return;
+        }
+        let text: String = layout(text, self.cols - col)
+            .into_iter()
+            .map(|(c, _)| c)
+            .collect();
+        self.frame.push_str(&format!(
+            "\x1b[{};{}H{}{}\x1b[0m",
+            row + 1,
+            col + 1,
+            style.sgr(),
+            text
+        ));
+    }
+
+    fn set_cursor(&mut self, row: usize, col: usize) {
+        self.cursor = (row, col);
+    }
+
+    fn present(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        self.frame.push_str(&format!(
+            "\x1b[{};{}H",
+            self.cursor.0 + 1,
+            self.cursor.1 + 1
+        ));
+        out.write_all(self.frame.as_bytes())?;
+        out.flush()
+    }
+}
+
+// What one screen cell shows. A double width cluster sits in the first of
+// its two cells and leaves the second one empty.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Cell {
+    pub symbol: String,
+    pub style: Style,
+}
+
+impl Cell {
+    fn blank() -> Self {
+        Cell {
+            symbol: " ".to_string(),
+            style: Style::default(),
+        }
+    }
+}
+
+// Draws into a grid of cells and only sends the cells that changed since the
+// previous frame. The grid can also be inspected directly, which is what
+// makes it useful without a terminal.
+pub struct GridRenderer {
+    pub rows: usize,
+    pub cols: usize,
+    pub cells: Vec<Cell>,
+    pub cursor: (usize, usize),
+    previous: Vec<Cell>,
+}
+
+impl Default for GridRenderer {
+    fn default() -> Self {
+        GridRenderer::new()
+    }
+}
+
+impl GridRenderer {
+    pub fn new() -> Self {
+        GridRenderer {
+            rows: 0,
+            cols: 0,
+            cells: Vec::new(),
+            cursor: (0, 0),
+            previous: Vec::new(),
+        }
+    }
+}
+
+impl Renderer for GridRenderer {
+    fn begin(&mut self, rows: usize, cols: usize) {
+        if (rows, cols) != (self.rows, self.cols) {
+            self.previous.clear();
+        }
+        self.rows = rows;
+        self.cols = cols;
+        self.cells = vec![Cell::blank(); rows * cols];
+    }
+
+    fn size(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    fn put(&mut self, row: usize, col: usize, text: &str, style: Style) {
+        if row >= self.rows || col >= self.cols {
+            return;
+        }
+        let start = row * self.cols;
+        let mut at = col;
+        for (cluster, w) in layout(text, self.cols - col) {
+            // Overwriting half of a double width cluster blanks the other half.
+            if self.cells[start + at].symbol.is_empty() && at > 0 {
+                self.cells[start + at - 1].symbol = " ".to_string();
+            }
+            let end = at + w;
+            if end < self.cols && self.cells[start + end].symbol.is_empty() {
+                self.cells[start + end].symbol = " ".to_string();
+            }
+            self.cells[start + at] = Cell {
+                symbol: cluster.to_string(),
+                style,
+            };
+            for i in at + 1..end {
+                self.cells[start + i] = Cell {
+                    symbol: String::new(),
+                    style,
+                };
+            }
+            at = end;
+        }
+    }
+
+    fn set_cursor(&mut self, row: usize, col: usize) {
+        self.cursor = (row, col);
+    }
+
+    fn present(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        let mut buf = String::new();
+        let full = self.previous.len() != self.cells.len();
+        if full {
+            buf.push_str("\x1b[0m\x1b[2J");
+        }
+        let mut style = None;
+        let mut next = None;
+        for (i, cell) in self.cells.iter().enumerate() {
+            if !full && self.previous[i] == *cell {
+                continue;
+            }
+            if cell.symbol.is_empty() || (full && *cell == Cell::blank()) {
+                continue;
+            }
+            let at = (i / self.cols, i % self.cols);
+            if next != Some(at) {
+                buf.push_str(&format!("\x1b[{};{}H", at.0 + 1, at.1 + 1));
+            }
+            if style != Some(cell.style) {
+                buf.push_str(&cell.style.sgr());
+                style = Some(cell.style);
+            }
+            buf.push_str(&cell.symbol);
+            next = Some((at.0, at.1 + width::width(&cell.symbol).max(1)));
+        }
+        buf.push_str(&format!(
+            "\x1b[0m\x1b[{};{}H",
+            self.cursor.0 + 1,
+            self.cursor.1 + 1
+        ));
+        self.previous = self.cells.clone();
+        out.write_all(buf.as_bytes())?;
+        out.flush()
+    }
+}
+
+pub fn backend(name: &str) -> Box<dyn Renderer> {
+    match name {
+        "grid" => Box::new(GridRenderer::new()),
+        _ => Box::new(AnsiRenderer::new()),
+    }
+}
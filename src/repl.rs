@@ -0,0 +1,135 @@
+use crate::events::Waker;
+use crate::output;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+// What `:Repl` starts for a file when no command is given, by extension.
+// Python's prompts would end up in the output between results, so they are
+// turned off.
+const DEFAULTS: [(&str, &str); 5] = [
+    (
+        "py",
+        "python3 -qiu -c 'import sys; sys.ps1 = sys.ps2 = \"\"'",
+    ),
+    ("jl", "julia --banner=no"),
+    ("lisp", "sbcl --noinform"),
+    ("rb", "irb --noprompt"),
+    ("js", "node -i"),
+];
+
+pub fn default_command(path: &str) -> Option<&'static str> {
+    let ext = Path::new(path)
+        .extension()?
+        .to_string_lossy()
+        .to_lowercase();
+    DEFAULTS
+        .iter()
+        .find(|(e, _)| *e == ext)
+        .map(|(_, cmd)| *cmd)
+}
+
+// An interpreter running in the background with its input fed from the
+// editor. Its stdout and stderr are read on threads of their own and come
+// back through `poll` a line at a time.
+pub struct Repl {
+    pub command: String,
+    child: Child,
+    stdin: Option<ChildStdin>,
+    receiver: Receiver<String>,
+    // Output after the last newline, until the rest of its line arrives.
+    partial: String,
+    // Set once both pipes have been read to the end.
+    closed: bool,
+}
+
+impl Repl {
+    pub fn start(command: &str, waker: &Waker) -> Result<Repl, String> {
+        let mut child = output::shell(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Can't start '{}': {}", command, e))?;
+        let stdin = child.stdin.take();
+        let (sender, receiver) = mpsc::channel();
+        let stdout = child
+            .stdout
+            .take()
+            .map(|s| Box::new(s) as Box<dyn Read + Send>);
+        let stderr = child
+            .stderr
+            .take()
+            .map(|s| Box::new(s) as Box<dyn Read + Send>);
+        for mut pipe in [stdout, stderr].into_iter().flatten() {
+            let sender = sender.clone();
+            let waker = waker.clone();
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                while let Ok(n @ 1..) = pipe.read(&mut buf) {
+                    if sender
+                        .send(String::from_utf8_lossy(&buf[..n]).into_owned())
+                        .is_err()
+                    {
+                        break;
+                    }
+                    waker.wake();
+                }
+            });
+        }
+        Ok(Repl {
+            command: command.to_string(),
+            child,
+            stdin,
+            receiver,
+            partial: String::new(),
+            closed: false,
+        })
+    }
+
+    pub fn send(&mut self, text: &str) -> Result<(), String> {
+        let stdin = self.stdin.as_mut().ok_or("The REPL has exited")?;
+        stdin
+            .write_all(text.as_bytes())
+            .and_then(|_| stdin.flush())
+            .map_err(|e| format!("Can't send to the REPL: {}", e))
+    }
+
+    // Complete lines of output that arrived since the last call, and the
+    // rest too once the interpreter has gone.
+    pub fn poll(&mut self) -> Vec<String> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(text) => self.partial.push_str(&text),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.closed = true;
+                    if !self.partial.is_empty() {
+                        self.partial.push('\n');
+                    }
+                    break;
+                }
+            }
+        }
+        let Some(end) = self.partial.rfind('\n') else {
+            return Vec::new();
+        };
+        let lines = self.partial[..end].lines().map(|l| l.to_string()).collect();
+        self.partial.drain(..=end);
+        lines
+    }
+
+    pub fn exited(&self) -> bool {
+        self.closed
+    }
+}
+
+impl Drop for Repl {
+    fn drop(&mut self) {
+        self.stdin = None;
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
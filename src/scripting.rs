@@ -0,0 +1,357 @@
+use crate::buffer::Buffer;
+use crate::position::Position;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Scope, AST};
+use std::cell::{RefCell, RefMut};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+// A script gets through this many steps per call before it is stopped, so a
+// plugin stuck in a loop can't hang the editor.
+const MAX_OPERATIONS: u64 = 50_000_000;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+// A change a script made to the lent buffer, for the editor to update its
+// search index with.
+pub enum Edit {
+    Changed(usize),
+    Inserted(usize),
+    Removed(usize),
+}
+
+// What scripts see of the editor while one of their functions runs. The
+// editor lends its buffer and cursor for the call and takes them back,
+// together with the commands the script asked for, when it returns. A
+// mapped file is only read to the end once a script looks at its lines.
+pub struct Host {
+    pub lines: Buffer,
+    pub cursor: Position,
+    pub path: String,
+    pub readonly: bool,
+    pub edits: Vec<Edit>,
+    // Ex commands to run once the call is over.
+    pub commands: Vec<String>,
+    pub message: Option<String>,
+    // Ex commands defined by scripts: the plugin and the function to call.
+    user_commands: BTreeMap<String, (usize, String)>,
+    // Completion sources defined by scripts, the same way.
+    completions: BTreeMap<String, (usize, String)>,
+    // The plugin whose code is running.
+    plugin: usize,
+}
+
+impl Host {
+    fn loaded(&mut self) -> &mut Buffer {
+        self.lines.finish_loading();
+        &mut self.lines
+    }
+
+    fn line(&mut self, row: i64) -> ScriptResult<usize> {
+        let len = self.loaded().len();
+        usize::try_from(row)
+            .ok()
+            .filter(|&r| r < len)
+            .ok_or_else(|| format!("No line {}", row).into())
+    }
+
+    fn edit(&mut self, edit: Edit) -> ScriptResult<()> {
+        if self.readonly {
+            return Err("The buffer is read-only".into());
+        }
+        self.edits.push(edit);
+        Ok(())
+    }
+}
+
+struct Plugin {
+    name: String,
+    ast: AST,
+}
+
+// Plugins written in Rhai. A plugin is one `.rhai` file; its top level runs
+// when it is loaded and can map keys, set options and define commands, and
+// functions named after events (`on_open`, `on_save`) run when those happen.
+pub struct Scripts {
+    engine: Engine,
+    host: Rc<RefCell<Host>>,
+    plugins: Vec<Plugin>,
+}
+
+impl Default for Scripts {
+    fn default() -> Self {
+        Scripts::new()
+    }
+}
+
+impl Scripts {
+    pub fn new() -> Self {
+        let host = Rc::new(RefCell::new(Host {
+            lines: Buffer::from_lines(Vec::new()),
+            cursor: Position::default(),
+            path: String::new(),
+            readonly: false,
+            edits: Vec::new(),
+            commands: Vec::new(),
+            message: None,
+            user_commands: BTreeMap::new(),
+            completions: BTreeMap::new(),
+            plugin: 0,
+        }));
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        register_api(&mut engine, &host);
+        Scripts {
+            engine,
+            host,
+            plugins: Vec::new(),
+        }
+    }
+
+    pub fn host(&self) -> RefMut<'_, Host> {
+        self.host.borrow_mut()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    pub fn load(&mut self, path: &Path) -> Result<(), String> {
+        let name = path.display().to_string();
+        let ast = self
+            .engine
+            .compile_file(path.to_path_buf())
+            .map_err(|e| format!("{}: {}", name, e))?;
+        // What a plugin that fails registers before failing is dropped, as
+        // there is no plugin left to call into; names it took over from
+        // another plugin go back to that one.
+        let registered = {
+            let mut host = self.host();
+            host.plugin = self.plugins.len();
+            (host.user_commands.clone(), host.completions.clone())
+        };
+        if let Err(e) = self.engine.run_ast_with_scope(&mut Scope::new(), &ast) {
+            let mut host = self.host();
+            (host.user_commands, host.completions) = registered;
+            return Err(format!("{}: {}", name, e));
+        }
+        self.plugins.push(Plugin { name, ast });
+        Ok(())
+    }
+
+    // Loads every `.rhai` file in `dir`, in name order. A plugin that fails
+    // doesn't stop the others; the errors come back together.
+    pub fn load_dir(&mut self, dir: &Path) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        let mut paths: Vec<_> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|e| e == "rhai"))
+            .collect();
+        paths.sort();
+        paths.iter().filter_map(|p| self.load(p).err()).collect()
+    }
+
+    pub fn has_command(&self, name: &str) -> bool {
+        self.host.borrow().user_commands.contains_key(name)
+    }
+
+    pub fn run_command(&mut self, name: &str, args: &str) -> Result<(), String> {
+        let Some((plugin, function)) = self.host.borrow().user_commands.get(name).cloned() else {
+            return Err(format!("Not an editor command: {}", name));
+        };
+        self.call(plugin, &function, args)
+    }
+
+    // The names of the completion sources plugins registered.
+    pub fn completions(&self) -> Vec<String> {
+        self.host.borrow().completions.keys().cloned().collect()
+    }
+
+    // Asks the completion source `name` for the words that complete `word`.
+    pub fn complete(&mut self, name: &str, word: &str) -> Result<Vec<String>, String> {
+        let Some((plugin, function)) = self.host.borrow().completions.get(name).cloned() else {
+            return Err(format!("No completion source {}", name));
+        };
+        self.host().plugin = plugin;
+        let Plugin { name, ast } = &self.plugins[plugin];
+        let words = self
+            .engine
+            .call_fn::<Array>(&mut Scope::new(), ast, &function, (word.to_string(),))
+            .map_err(|e| format!("{}: {}", name, e))?;
+        Ok(words.into_iter().map(|w| w.to_string()).collect())
+    }
+
+    fn defines(&self, plugin: usize, event: &str) -> bool {
+        self.plugins[plugin]
+            .ast
+            .iter_functions()
+            .any(|f| f.name == event && f.params.len() == 1)
+    }
+
+    // Whether any plugin handles `event`.
+    pub fn has_hook(&self, event: &str) -> bool {
+        (0..self.plugins.len()).any(|plugin| self.defines(plugin, event))
+    }
+
+    // Calls the function named `event` in every plugin that has one.
+    pub fn hook(&mut self, event: &str, path: &str) -> Result<(), String> {
+        for plugin in 0..self.plugins.len() {
+            if self.defines(plugin, event) {
+                self.call(plugin, event, path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, plugin: usize, function: &str, arg: &str) -> Result<(), String> {
+        self.host().plugin = plugin;
+        let Plugin { name, ast } = &self.plugins[plugin];
+        self.engine
+            .call_fn::<Dynamic>(&mut Scope::new(), ast, function, (arg.to_string(),))
+            .map(|_| ())
+            .map_err(|e| format!("{}: {}", name, e))
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let host = self.host.borrow();
+        let mut lines: Vec<String> = self.plugins.iter().map(|p| p.name.clone()).collect();
+        for (name, (plugin, function)) in &host.user_commands {
+            let file = Path::new(&self.plugins[*plugin].name)
+                .file_name()
+                .unwrap_or_default();
+            lines.push(format!(
+                ":{:<20}{}() in {}",
+                name,
+                function,
+                file.to_string_lossy()
+            ));
+        }
+        for (name, (plugin, function)) in &host.completions {
+            let file = Path::new(&self.plugins[*plugin].name)
+                .file_name()
+                .unwrap_or_default();
+            lines.push(format!(
+                "completion {:<9}{}() in {}",
+                name,
+                function,
+                file.to_string_lossy()
+            ));
+        }
+        lines
+    }
+}
+
+// Rows and columns are counted from zero, as in the editor, but columns in
+// chars, like the strings scripts get lines as.
+fn register_api(engine: &mut Engine, host: &Rc<RefCell<Host>>) {
+    let h = host.clone();
+    engine.register_fn("line_count", move || h.borrow_mut().loaded().len() as i64);
+    let h = host.clone();
+    engine.register_fn("get_line", move |row: i64| -> ScriptResult<String> {
+        let mut host = h.borrow_mut();
+        let row = host.line(row)?;
+        Ok(host.lines[row].to_string())
+    });
+    let h = host.clone();
+    engine.register_fn(
+        "set_line",
+        move |row: i64, text: &str| -> ScriptResult<()> {
+            let mut host = h.borrow_mut();
+            let row = host.line(row)?;
+            host.edit(Edit::Changed(row))?;
+            host.lines.set(row, text.to_string());
+            Ok(())
+        },
+    );
+    let h = host.clone();
+    engine.register_fn(
+        "insert_line",
+        move |row: i64, text: &str| -> ScriptResult<()> {
+            let mut host = h.borrow_mut();
+            let len = host.loaded().len() as i64;
+            if !(0..=len).contains(&row) {
+                return Err(format!("No line {}", row).into());
+            }
+            host.edit(Edit::Inserted(row as usize))?;
+            host.lines.insert(row as usize, text.to_string());
+            Ok(())
+        },
+    );
+    let h = host.clone();
+    engine.register_fn("delete_line", move |row: i64| -> ScriptResult<()> {
+        let mut host = h.borrow_mut();
+        let row = host.line(row)?;
+        host.edit(Edit::Removed(row))?;
+        host.lines.remove(row);
+        Ok(())
+    });
+    let h = host.clone();
+    engine.register_fn("cursor", move || -> Array {
+        let host = h.borrow();
+        let cursor = host.cursor;
+        // The script may have removed the cursor's line.
+        let text = if cursor.line() < host.lines.len() {
+            &host.lines[cursor.line()]
+        } else {
+            ""
+        };
+        let col = cursor.char_col(text);
+        vec![
+            Dynamic::from(cursor.line() as i64),
+            Dynamic::from(col as i64),
+        ]
+    });
+    let h = host.clone();
+    engine.register_fn(
+        "set_cursor",
+        move |row: i64, col: i64| -> ScriptResult<()> {
+            let mut host = h.borrow_mut();
+            let row = host.line(row)?;
+            host.cursor = Position::from_char(&host.lines[row], row, col.max(0) as usize);
+            Ok(())
+        },
+    );
+    let h = host.clone();
+    engine.register_fn("file_path", move || h.borrow().path.clone());
+    let h = host.clone();
+    engine.register_fn("command", move |command: &str| {
+        h.borrow_mut().commands.push(command.to_string());
+    });
+    let h = host.clone();
+    engine.register_fn("message", move |text: &str| {
+        h.borrow_mut().message = Some(text.to_string());
+    });
+    let h = host.clone();
+    engine.register_fn("map", move |mode: &str, lhs: &str, rhs: &str| {
+        let command = format!("{}noremap {} {}", mode, lhs, rhs);
+        h.borrow_mut().commands.push(command);
+    });
+    let h = host.clone();
+    engine.register_fn(
+        "register_command",
+        move |name: &str, function: &str| -> ScriptResult<()> {
+            if !name.starts_with(|c: char| c.is_ascii_uppercase()) {
+                return Err(
+                    format!("Commands must start with an uppercase letter: {}", name).into(),
+                );
+            }
+            let mut host = h.borrow_mut();
+            let plugin = host.plugin;
+            host.user_commands
+                .insert(name.to_string(), (plugin, function.to_string()));
+            Ok(())
+        },
+    );
+    // The function gets the word before the cursor and returns the words
+    // to offer for it.
+    let h = host.clone();
+    engine.register_fn("register_completion", move |name: &str, function: &str| {
+        let mut host = h.borrow_mut();
+        let plugin = host.plugin;
+        host.completions
+            .insert(name.to_string(), (plugin, function.to_string()));
+    });
+}
@@ -0,0 +1,345 @@
+use crate::buffer::Buffer;
+use crate::position::{self, Position};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+const BLOCK_LINES: usize = 256;
+
+// The buffer is split into blocks of lines. Each block keeps the set of words
+// and character trigrams it contains, so a search only scans the lines of
+// blocks that can possibly match. Edits mark their block dirty; dirty blocks
+// are scanned line by line until `refresh` gets to them while idle.
+struct Block {
+    len: usize,
+    dirty: bool,
+    words: HashMap<String, usize>,
+    trigrams: HashSet<u64>,
+}
+
+pub struct SearchIndex {
+    blocks: Vec<Block>,
+    words: BTreeMap<String, usize>,
+    // Set when memory runs short; every block then stays dirty and searches
+    // scan lines.
+    disabled: bool,
+}
+
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+impl Block {
+    fn new(len: usize) -> Self {
+        Block {
+            len,
+            dirty: true,
+            words: HashMap::new(),
+            trigrams: HashSet::new(),
+        }
+    }
+}
+
+impl SearchIndex {
+    pub fn new(line_count: usize) -> Self {
+        let mut index = SearchIndex {
+            blocks: Vec::new(),
+            words: BTreeMap::new(),
+            disabled: false,
+        };
+        index.reset(line_count);
+        index
+    }
+
+    pub fn reset(&mut self, line_count: usize) {
+        self.disabled = false;
+        self.words.clear();
+        self.blocks = (0..line_count.max(1))
+            .step_by(BLOCK_LINES)
+            .map(|start| Block::new(BLOCK_LINES.min(line_count.max(1) - start)))
+            .collect();
+    }
+
+    pub fn disable(&mut self) {
+        self.disabled = true;
+        self.words = BTreeMap::new();
+        for block in &mut self.blocks {
+            block.words = HashMap::new();
+            block.trigrams = HashSet::new();
+            block.dirty = true;
+        }
+    }
+
+    pub fn disabled(&self) -> bool {
+        self.disabled
+    }
+
+    // A rough count of the bytes the index holds: words with a map entry's
+    // overhead, and trigrams as the hashes they are stored as.
+    pub fn memory(&self) -> usize {
+        let entry = std::mem::size_of::<(String, usize)>() + 16;
+        let words: usize = self.words.keys().map(|w| w.len() + entry).sum();
+        let blocks: usize = self
+            .blocks
+            .iter()
+            .map(|b| {
+                let words: usize = b.words.keys().map(|w| w.len() + entry).sum();
+                words + b.trigrams.capacity() * (std::mem::size_of::<u64>() + 1)
+            })
+            .sum();
+        words + blocks
+    }
+
+    fn locate(&self, row: usize) -> (usize, usize) {
+        let mut start = 0;
+        for (i, block) in self.blocks.iter().enumerate() {
+            if row < start + block.len {
+                return (i, start);
+            }
+            start += block.len;
+        }
+        (
+            self.blocks.len() - 1,
+            start - self.blocks.last().map_or(0, |b| b.len),
+        )
+    }
+
+    pub fn changed(&mut self, row: usize) {
+        let (i, _) = self.locate(row);
+        self.blocks[i].dirty = true;
+    }
+
+    pub fn inserted(&mut self, row: usize, count: usize) {
+        let (i, _) = self.locate(row.saturating_sub(1));
+        let block = &mut self.blocks[i];
+        block.len += count;
+        block.dirty = true;
+        if block.len > BLOCK_LINES * 2 {
+            let half = block.len / 2;
+            block.len -= half;
+            self.blocks.insert(i + 1, Block::new(half));
+        }
+    }
+
+    pub fn removed(&mut self, row: usize, mut count: usize) {
+        while count > 0 {
+            let (i, start) = self.locate(row);
+            let block = &mut self.blocks[i];
+            let n = count.min((start + block.len).saturating_sub(row));
+            if n == 0 {
+                break;
+            }
+            block.len -= n;
+            block.dirty = true;
+            count -= n;
+            if block.len == 0 && self.blocks.len() > 1 {
+                self.forget_words(i);
+                self.blocks.remove(i);
+            }
+        }
+    }
+
+    fn forget_words(&mut self, i: usize) {
+        for (word, n) in self.blocks[i].words.drain() {
+            if let Some(total) = self.words.get_mut(&word) {
+                *total = total.saturating_sub(n);
+                if *total == 0 {
+                    self.words.remove(&word);
+                }
+            }
+        }
+    }
+
+    fn check_len(&mut self, lines: &Buffer) {
+        let total: usize = self.blocks.iter().map(|b| b.len).sum();
+        if total != lines.len() {
+            // Someone edited the buffer without telling us; start over.
+            self.reset(lines.len());
+        }
+    }
+
+    // Re-indexes up to `budget` dirty blocks. Returns true once nothing is
+    // left to do.
+    pub fn refresh(&mut self, lines: &Buffer, mut budget: usize) -> bool {
+        self.check_len(lines);
+        if self.disabled {
+            return true;
+        }
+        let mut start = 0;
+        for i in 0..self.blocks.len() {
+            let len = self.blocks[i].len;
+            if self.blocks[i].dirty {
+                if budget == 0 {
+                    return false;
+                }
+                budget -= 1;
+                self.forget_words(i);
+                let mut words = HashMap::new();
+                let mut trigrams = HashSet::new();
+                for line in lines.range(start..start + len) {
+                    for word in line.split(|c: char| !is_word_char(c)) {
+                        if !word.is_empty() {
+                            *words.entry(word.to_string()).or_insert(0) += 1;
+                        }
+                    }
+                    let chars: Vec<char> = line.chars().collect();
+                    for w in chars.windows(3) {
+                        trigrams.insert(trigram(w));
+                    }
+                }
+                for (word, n) in &words {
+                    *self.words.entry(word.clone()).or_insert(0) += n;
+                }
+                let block = &mut self.blocks[i];
+                block.words = words;
+                block.trigrams = trigrams;
+                block.dirty = false;
+            }
+            start += len;
+        }
+        true
+    }
+
+    // Indexed words starting with `prefix`, in order. Words in blocks that
+    // changed since the last refresh are missing until it catches up.
+    pub fn words_with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.words
+            .range(prefix.to_string()..)
+            .map(|(word, _)| word.as_str())
+            .take_while(move |word| word.starts_with(prefix))
+    }
+
+    pub fn find(
+        &mut self,
+        lines: &Buffer,
+        pattern: &str,
+        whole_word: bool,
+        from: Position,
+        forward: bool,
+    ) -> Option<Position> {
+        if pattern.is_empty() {
+            return None;
+        }
+        self.check_len(lines);
+        let complete = self.blocks.iter().all(|b| !b.dirty);
+        if complete && whole_word && !self.words.contains_key(pattern) {
+            return None;
+        }
+        let pattern_trigrams: Vec<u64> = pattern
+            .chars()
+            .collect::<Vec<_>>()
+            .windows(3)
+            .map(trigram)
+            .collect();
+        let candidate = |block: &Block| {
+            if block.dirty {
+                true
+            } else if whole_word {
+                block.words.contains_key(pattern)
+            } else {
+                pattern_trigrams.iter().all(|t| block.trigrams.contains(t))
+            }
+        };
+
+        let mut starts = Vec::with_capacity(self.blocks.len());
+        let mut start = 0;
+        for block in &self.blocks {
+            starts.push(start);
+            start += block.len;
+        }
+        // Matches are found in chars; the one under the cursor is skipped
+        // whole, even where it is several.
+        let line = &lines[from.line()];
+        let (at, after) = (
+            from.char_col(line),
+            position::char_col(line, from.grapheme() + 1),
+        );
+        let (first, _) = self.locate(from.line());
+        let n = self.blocks.len();
+        // Visit blocks starting at the cursor's block, wrapping around, and
+        // come back to the cursor's block once more for the part before it.
+        for step in 0..=n {
+            let i = if forward {
+                (first + step) % n
+            } else {
+                (first + n - step) % n
+            };
+            if !candidate(&self.blocks[i]) {
+                continue;
+            }
+            let rows: Vec<usize> = (starts[i]..starts[i] + self.blocks[i].len).collect();
+            let ordered: Box<dyn Iterator<Item = &usize>> = if forward {
+                Box::new(rows.iter())
+            } else {
+                Box::new(rows.iter().rev())
+            };
+            for &row in ordered {
+                let line = &lines[row];
+                let hit = if step == 0 && row == from.line() {
+                    if forward {
+                        find_in_line(line, pattern, whole_word, Some(after), None, true)
+                    } else {
+                        find_in_line(line, pattern, whole_word, None, Some(at), false)
+                    }
+                } else if step == n && row == from.line() {
+                    if forward {
+                        find_in_line(line, pattern, whole_word, None, Some(after), true)
+                    } else {
+                        find_in_line(line, pattern, whole_word, Some(at), None, false)
+                    }
+                } else if (step == 0 && forward == (row < from.line()))
+                    || (step == n && forward == (row > from.line()))
+                {
+                    continue;
+                } else {
+                    find_in_line(line, pattern, whole_word, None, None, forward)
+                };
+                if let Some(col) = hit {
+                    return Some(Position::from_char(line, row, col));
+                }
+            }
+        }
+        None
+    }
+}
+
+fn trigram(w: &[char]) -> u64 {
+    (u64::from(w[0]) << 42) | (u64::from(w[1]) << 21) | u64::from(w[2])
+}
+
+// The columns, in characters, where `pattern` starts in `line`.
+pub fn matches<'a>(
+    line: &'a str,
+    pattern: &'a str,
+    whole_word: bool,
+) -> impl Iterator<Item = usize> + 'a {
+    line.match_indices(pattern).filter_map(move |(byte, m)| {
+        if whole_word
+            && (line[..byte].chars().next_back().is_some_and(is_word_char)
+                || line[byte + m.len()..]
+                    .chars()
+                    .next()
+                    .is_some_and(is_word_char))
+        {
+            return None;
+        }
+        Some(line[..byte].chars().count())
+    })
+}
+
+// Finds the first (or last, when searching backwards) match whose start
+// column lies in `from..to`, in characters.
+fn find_in_line(
+    line: &str,
+    pattern: &str,
+    whole_word: bool,
+    from: Option<usize>,
+    to: Option<usize>,
+    forward: bool,
+) -> Option<usize> {
+    let mut hits = matches(line, pattern, whole_word)
+        .filter(|&col| from.is_none_or(|f| col >= f) && to.is_none_or(|t| col < t));
+    if forward {
+        hits.next()
+    } else {
+        hits.last()
+    }
+}
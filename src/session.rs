@@ -0,0 +1,28 @@
+use std::fs;
+use std::path::Path;
+
+pub const DEFAULT_FILE: &str = "Session.rvex";
+
+// A session file is a list of Ex commands, one per line, that `:source`
+// replays. Lines starting with `"` are comments.
+pub fn save(path: &str, commands: &[String], force: bool) -> Result<(), String> {
+    if !force && Path::new(path).exists() {
+        return Err(format!("{} exists (add ! to override)", path));
+    }
+    let mut text = String::from("\" RVex session; restore with :source or --session\n");
+    for command in commands {
+        text.push_str(command);
+        text.push('\n');
+    }
+    fs::write(path, text).map_err(|e| format!("Can't write {}: {}", path, e))
+}
+
+pub fn load(path: &str) -> Result<Vec<String>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+    Ok(text
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('"'))
+        .map(|l| l.to_string())
+        .collect())
+}
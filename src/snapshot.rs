@@ -0,0 +1,131 @@
+use similar::{ChangeTag, TextDiff};
+use std::collections::BTreeMap;
+
+// Lines of context around each change in a diff.
+const CONTEXT: usize = 3;
+
+struct Snapshot {
+    lines: Vec<String>,
+    taken: String,
+}
+
+// Named copies of buffers, kept in memory until the editor exits and apart
+// from undo, to go back to after a risky bulk edit. Each file has names of
+// its own; files are keyed by `paths::key`.
+pub struct Snapshots {
+    files: BTreeMap<String, BTreeMap<String, Snapshot>>,
+}
+
+impl Default for Snapshots {
+    fn default() -> Self {
+        Snapshots::new()
+    }
+}
+
+impl Snapshots {
+    pub fn new() -> Self {
+        Snapshots {
+            files: BTreeMap::new(),
+        }
+    }
+
+    // Replaces any snapshot of the file already going by `name`.
+    pub fn save(&mut self, file: &str, name: &str, lines: Vec<String>) {
+        self.files.entry(file.to_string()).or_default().insert(
+            name.to_string(),
+            Snapshot {
+                lines,
+                taken: crate::trash::now_iso8601(),
+            },
+        );
+    }
+
+    pub fn get(&self, file: &str, name: &str) -> Option<&[String]> {
+        Some(&self.files.get(file)?.get(name)?.lines)
+    }
+
+    pub fn remove(&mut self, file: &str, name: &str) -> bool {
+        let Some(names) = self.files.get_mut(file) else {
+            return false;
+        };
+        let removed = names.remove(name).is_some();
+        if names.is_empty() {
+            self.files.remove(file);
+        }
+        removed
+    }
+
+    // The file's snapshots with when they were taken and their sizes.
+    pub fn lines(&self, file: &str) -> Vec<String> {
+        let Some(names) = self.files.get(file) else {
+            return Vec::new();
+        };
+        let width = names.keys().map(|n| n.chars().count()).max().unwrap_or(0);
+        names
+            .iter()
+            .map(|(name, snapshot)| {
+                format!(
+                    "{:<width$}  {}  {} lines",
+                    name,
+                    snapshot.taken,
+                    snapshot.lines.len(),
+                    width = width
+                )
+            })
+            .collect()
+    }
+
+    pub fn memory(&self) -> usize {
+        self.files
+            .values()
+            .flat_map(|names| names.values())
+            .map(|s| {
+                s.lines.iter().map(|l| l.capacity()).sum::<usize>()
+                    + s.lines.capacity() * std::mem::size_of::<String>()
+            })
+            .sum()
+    }
+}
+
+// A unified diff from the snapshot `name` to `lines`, colored for the
+// output pane. Empty when they are the same.
+pub fn diff(name: &str, snapshot: &[String], lines: &[String]) -> Vec<String> {
+    unified(&format!("snapshot {}", name), "buffer", snapshot, lines)
+}
+
+// A unified diff from `old`, called `from` in the header, to `new`.
+pub fn unified(from: &str, to: &str, old: &[String], new: &[String]) -> Vec<String> {
+    let old: Vec<&str> = old.iter().map(|l| l.as_str()).collect();
+    let new: Vec<&str> = new.iter().map(|l| l.as_str()).collect();
+    let diff = TextDiff::from_slices(&old, &new);
+    let groups = diff.grouped_ops(CONTEXT);
+    if groups.is_empty() {
+        return Vec::new();
+    }
+    let mut out = vec![
+        format!("\x1b[1m--- {}\x1b[0m", from),
+        format!("\x1b[1m+++ {}\x1b[0m", to),
+    ];
+    for group in groups {
+        let (first, last) = (&group[0], &group[group.len() - 1]);
+        let old = first.old_range().start..last.old_range().end;
+        let new = first.new_range().start..last.new_range().end;
+        out.push(format!(
+            "\x1b[36m@@ -{},{} +{},{} @@\x1b[0m",
+            old.start + 1,
+            old.len(),
+            new.start + 1,
+            new.len()
+        ));
+        for op in &group {
+            for change in diff.iter_changes(op) {
+                out.push(match change.tag() {
+                    ChangeTag::Equal => format!(" {}", change.value()),
+                    ChangeTag::Delete => format!("\x1b[31m-{}\x1b[0m", change.value()),
+                    ChangeTag::Insert => format!("\x1b[32m+{}\x1b[0m", change.value()),
+                });
+            }
+        }
+    }
+    out
+}
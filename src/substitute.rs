@@ -0,0 +1,153 @@
+use crate::position;
+use std::ops::Range;
+
+// `:s/pattern/replacement/flags`. Like `/`, the pattern is plain text. Any
+// punctuation can stand in for `/`; a backslash escapes the delimiter.
+pub struct Substitute {
+    pub pattern: String,
+    pub replacement: String,
+    pub global: bool,
+    pub confirm: bool,
+    // The grapheme columns matches must lie in, for a block-wise visual
+    // range.
+    pub columns: Option<Range<usize>>,
+}
+
+// Strips the command name from `s/a/b/` or `substitute#a#b#`.
+pub fn strip_name(command: &str) -> Option<&str> {
+    let rest = command
+        .strip_prefix("substitute")
+        .or_else(|| command.strip_prefix('s'))?;
+    rest.starts_with(|c: char| c.is_ascii_punctuation() && c != '"' && c != '|')
+        .then_some(rest)
+}
+
+// Splits `/pattern/replacement/flags` at the unescaped delimiters.
+fn split(text: &str) -> Result<Vec<String>, String> {
+    let mut chars = text.chars();
+    let delimiter = chars.next().ok_or("Missing pattern")?;
+    let mut parts = vec![String::new()];
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some(next) if next == delimiter => parts.last_mut().unwrap().push(next),
+                Some(next) => {
+                    parts.last_mut().unwrap().push(c);
+                    parts.last_mut().unwrap().push(next);
+                }
+                None => parts.last_mut().unwrap().push(c),
+            }
+        } else if c == delimiter && parts.len() < 3 {
+            parts.push(String::new());
+        } else {
+            parts.last_mut().unwrap().push(c);
+        }
+    }
+    Ok(parts)
+}
+
+// Whether the delimiter after the pattern has been typed yet.
+pub fn has_replacement(text: &str) -> bool {
+    split(text).is_ok_and(|parts| parts.len() > 1)
+}
+
+pub fn parse(text: &str) -> Result<Substitute, String> {
+    let mut parts = split(text)?.into_iter();
+    let pattern = parts.next().unwrap_or_default();
+    let replacement = parts.next().unwrap_or_default();
+    let mut substitute = Substitute {
+        pattern,
+        replacement,
+        global: false,
+        confirm: false,
+        columns: None,
+    };
+    for flag in parts.next().unwrap_or_default().chars() {
+        match flag {
+            'g' => substitute.global = true,
+            'c' => substitute.confirm = true,
+            ' ' => {}
+            _ => return Err(format!("Invalid flag: {}", flag)),
+        }
+    }
+    Ok(substitute)
+}
+
+impl Substitute {
+    // The byte range of `line` that `columns` covers.
+    fn bounds(&self, line: &str) -> Range<usize> {
+        let Some(columns) = &self.columns else {
+            return 0..line.len();
+        };
+        let byte = |col: usize| position::byte(line, col);
+        byte(columns.start)..byte(columns.end)
+    }
+
+    // Byte offset of the first match in `line` at or after `from`.
+    pub fn find(&self, line: &str, from: usize) -> Option<usize> {
+        let bounds = self.bounds(line);
+        let from = from.max(bounds.start);
+        line.get(from..bounds.end)?
+            .find(&self.pattern)
+            .map(|i| from + i)
+    }
+
+    // Replaces the first match, or every match with `g`. Returns the new line
+    // and how many replacements were made.
+    pub fn apply(&self, line: &str) -> (String, usize) {
+        let bounds = self.bounds(line);
+        let text = &line[bounds.clone()];
+        let (text, count) = if self.global {
+            let count = text.matches(&self.pattern).count();
+            (text.replace(&self.pattern, &self.replacement), count)
+        } else if text.contains(&self.pattern) {
+            (text.replacen(&self.pattern, &self.replacement, 1), 1)
+        } else {
+            return (line.to_string(), 0);
+        };
+        (
+            line[..bounds.start].to_string() + text.as_str() + &line[bounds.end..],
+            count,
+        )
+    }
+
+    // Like `apply`, but also returns the char ranges of the new line that
+    // replacements landed on.
+    pub fn apply_marked(&self, line: &str) -> (String, Vec<Range<usize>>) {
+        let bounds = self.bounds(line);
+        let mut out = line[..bounds.start].to_string();
+        let mut marks = Vec::new();
+        let mut rest = &line[bounds.start..bounds.end];
+        let mut col = out.chars().count();
+        let width = self.replacement.chars().count();
+        while let Some(i) = rest
+            .find(&self.pattern)
+            .filter(|_| !self.pattern.is_empty())
+        {
+            out.push_str(&rest[..i]);
+            out.push_str(&self.replacement);
+            col += rest[..i].chars().count();
+            marks.push(col..col + width);
+            col += width;
+            rest = &rest[i + self.pattern.len()..];
+            if !self.global {
+                break;
+            }
+        }
+        out.push_str(rest);
+        out.push_str(&line[bounds.end..]);
+        (out, marks)
+    }
+}
+
+// The state of a `:s///c` while it waits for y/n/a/q/l. `at` is the line and
+// byte offset to look for the next match from, and the match being asked
+// about while the prompt is up.
+pub struct Confirm {
+    pub substitute: Substitute,
+    pub at: (usize, usize),
+    pub last: usize,
+    pub count: usize,
+    pub lines: usize,
+    pub last_changed: Option<usize>,
+}
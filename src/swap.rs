@@ -0,0 +1,90 @@
+use crate::buffer::Buffer;
+use crate::paths;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+
+const HEADER: &str = "RVEX-SWAP 1";
+
+pub struct SwapInfo {
+    pub pid: u32,
+    pub modified: Option<SystemTime>,
+    pub content: Vec<String>,
+}
+
+// Swap files are named after the full path of the edited file with the
+// separators replaced, like Vim's `directory` option with a trailing `//`.
+pub fn swap_path(dir: &Path, file: &str) -> PathBuf {
+    let absolute = fs::canonicalize(file).unwrap_or_else(|_| {
+        let path = Path::new(file);
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            env::current_dir().unwrap_or_default().join(path)
+        }
+    });
+    // One file spelled differently, or with the verbatim prefix Windows
+    // canonicalizes to, gets one swap file.
+    let name: String = paths::key(&absolute.to_string_lossy(), paths::Style::current())
+        .chars()
+        .map(|c| {
+            if matches!(c, '/' | '\\' | ':') {
+                '%'
+            } else {
+                c
+            }
+        })
+        .collect();
+    dir.join(format!("{}.swp", name))
+}
+
+pub fn write(dir: &Path, file: &str, lines: &Buffer) -> io::Result<()> {
+    let path = swap_path(dir, file);
+    fs::create_dir_all(dir)?;
+    let mut text = format!("{}\npid={}\npath={}\n\n", HEADER, process::id(), file);
+    text.push_str(&lines.join(0..lines.len(), "\n"));
+    let tmp = path.with_extension("swp.tmp");
+    fs::write(&tmp, text)?;
+    fs::rename(tmp, path)
+}
+
+pub fn read(dir: &Path, file: &str) -> Option<SwapInfo> {
+    let path = swap_path(dir, file);
+    let text = fs::read_to_string(&path).ok()?;
+    let (header, body) = text.split_once("\n\n")?;
+    let mut lines = header.lines();
+    if lines.next()? != HEADER {
+        return None;
+    }
+    let pid = lines
+        .find_map(|l| l.strip_prefix("pid="))
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(0);
+    let mut content: Vec<String> = body.lines().map(|l| l.to_string()).collect();
+    if content.is_empty() {
+        content.push(String::new());
+    }
+    Some(SwapInfo {
+        pid,
+        modified: fs::metadata(&path).and_then(|m| m.modified()).ok(),
+        content,
+    })
+}
+
+pub fn remove(dir: &Path, file: &str) {
+    let _ = fs::remove_file(swap_path(dir, file));
+}
+
+pub fn process_running(pid: u32) -> bool {
+    if pid == process::id() {
+        return true;
+    }
+    if cfg!(target_os = "linux") {
+        Path::new("/proc").join(pid.to_string()).exists()
+    } else {
+        false
+    }
+}
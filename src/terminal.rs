@@ -0,0 +1,200 @@
+use crossterm::cursor::{Hide, Show};
+use crossterm::event;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use crossterm::ExecutableCommand;
+use std::io::{self, stdin, stdout, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use text_editor::events::Event;
+use text_editor::keys::{KeyCode, KeyEvent, KeyModifiers};
+use text_editor::output;
+
+pub fn enter() -> io::Result<()> {
+    enable_raw_mode()?;
+    let mut stdout = stdout();
+    stdout.execute(EnterAlternateScreen)?;
+    stdout.execute(Hide)?;
+    Ok(())
+}
+
+// Best effort: this also runs from the panic hook, where there is nothing
+// useful to do with an error.
+pub fn leave() {
+    let mut stdout = stdout();
+    let _ = stdout.execute(Show);
+    let _ = stdout.execute(LeaveAlternateScreen);
+    let _ = disable_raw_mode();
+}
+
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        leave();
+        default_hook(info);
+    }));
+}
+
+// Hands the terminal back to the shell and stops the process like an
+// ordinary Ctrl-Z; execution continues here after `fg`.
+pub fn suspend() -> io::Result<()> {
+    leave();
+    #[cfg(unix)]
+    signal_hook::low_level::emulate_default_handler(signal_hook::consts::SIGTSTP)?;
+    enter()
+}
+
+// Runs `cmd` on the normal screen with the terminal in cooked mode, so
+// interactive programs work, and waits for Enter before returning.
+pub fn run_shell(cmd: &str) -> io::Result<Option<i32>> {
+    leave();
+    println!();
+    let status = output::shell(cmd).status();
+    print!("\r\nPress ENTER to continue");
+    stdout().flush()?;
+    let mut line = String::new();
+    stdin().read_line(&mut line)?;
+    enter()?;
+    Ok(status?.code())
+}
+
+pub struct Signals {
+    terminate: Arc<AtomicBool>,
+    suspend: Arc<AtomicBool>,
+}
+
+impl Signals {
+    pub fn register() -> io::Result<Self> {
+        let signals = Signals {
+            terminate: Arc::new(AtomicBool::new(false)),
+            suspend: Arc::new(AtomicBool::new(false)),
+        };
+        #[cfg(unix)]
+        {
+            use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGTSTP};
+            for signal in [SIGTERM, SIGHUP] {
+                signal_hook::flag::register(signal, Arc::clone(&signals.terminate))?;
+            }
+            // In raw mode Ctrl-C comes as a key. SIGINT only arrives while a
+            // `:!` command or its Enter prompt has the terminal, and is meant
+            // for the command, so the editor just survives it.
+            signal_hook::flag::register(SIGINT, Arc::new(AtomicBool::new(false)))?;
+            signal_hook::flag::register(SIGTSTP, Arc::clone(&signals.suspend))?;
+        }
+        Ok(signals)
+    }
+
+    pub fn terminate_requested(&self) -> bool {
+        self.terminate.load(Ordering::Relaxed)
+    }
+
+    pub fn take_suspend(&self) -> bool {
+        self.suspend.swap(false, Ordering::Relaxed)
+    }
+}
+
+// Reads the terminal on a thread of its own and sends keys and resizes to
+// the main loop. It stops reading while paused, so that a shell command or
+// the shell after Ctrl-Z gets the keys instead.
+pub struct Input {
+    paused: Arc<AtomicBool>,
+    // Set by the reader once it has stopped reading.
+    parked: Arc<AtomicBool>,
+}
+
+impl Input {
+    pub fn start(sender: Sender<Event>) -> Input {
+        let input = Input {
+            paused: Arc::new(AtomicBool::new(false)),
+            parked: Arc::new(AtomicBool::new(false)),
+        };
+        let paused = Arc::clone(&input.paused);
+        let parked = Arc::clone(&input.parked);
+        thread::spawn(move || {
+            loop {
+                if paused.load(Ordering::SeqCst) {
+                    parked.store(true, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                    continue;
+                }
+                parked.store(false, Ordering::SeqCst);
+                match event::poll(Duration::from_millis(50)) {
+                    Ok(true) if !paused.load(Ordering::SeqCst) => {}
+                    Ok(_) => continue,
+                    Err(_) => break,
+                }
+                let event = match event::read() {
+                    Ok(event::Event::Key(event::KeyEvent {
+                        code,
+                        modifiers,
+                        kind: event::KeyEventKind::Press,
+                        ..
+                    })) => match convert_key(code, modifiers) {
+                        Some(key) => Event::Key(key),
+                        None => continue,
+                    },
+                    Ok(event::Event::Resize(cols, rows)) => {
+                        Event::Resize(rows as usize, cols as usize)
+                    }
+                    Ok(_) => continue,
+                    Err(_) => break,
+                };
+                if sender.send(event).is_err() {
+                    break;
+                }
+            }
+            parked.store(true, Ordering::SeqCst);
+        });
+        input
+    }
+
+    // Returns once the reader has stopped reading.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+        while !self.parked.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+}
+
+fn convert_key(code: event::KeyCode, modifiers: event::KeyModifiers) -> Option<KeyEvent> {
+    let code = match code {
+        event::KeyCode::Char(c) => KeyCode::Char(c),
+        event::KeyCode::F(n) => KeyCode::F(n),
+        event::KeyCode::Esc => KeyCode::Esc,
+        event::KeyCode::Enter => KeyCode::Enter,
+        event::KeyCode::Tab => KeyCode::Tab,
+        event::KeyCode::BackTab => KeyCode::BackTab,
+        event::KeyCode::Backspace => KeyCode::Backspace,
+        event::KeyCode::Delete => KeyCode::Delete,
+        event::KeyCode::Up => KeyCode::Up,
+        event::KeyCode::Down => KeyCode::Down,
+        event::KeyCode::Left => KeyCode::Left,
+        event::KeyCode::Right => KeyCode::Right,
+        event::KeyCode::Home => KeyCode::Home,
+        event::KeyCode::End => KeyCode::End,
+        event::KeyCode::PageUp => KeyCode::PageUp,
+        event::KeyCode::PageDown => KeyCode::PageDown,
+        event::KeyCode::Insert => KeyCode::Insert,
+        _ => return None,
+    };
+    let mut converted = KeyModifiers::NONE;
+    for (from, to) in [
+        (event::KeyModifiers::SHIFT, KeyModifiers::SHIFT),
+        (event::KeyModifiers::CONTROL, KeyModifiers::CONTROL),
+        (event::KeyModifiers::ALT, KeyModifiers::ALT),
+    ] {
+        if modifiers.contains(from) {
+            converted |= to;
+        }
+    }
+    Some(KeyEvent::new(code, converted))
+}
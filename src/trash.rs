@@ -0,0 +1,224 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Files and discarded buffers are kept in a freedesktop.org-style trash:
+// `files/` holds the data and `info/<name>.trashinfo` records where it came
+// from, so other tools (file managers, `gio trash`) can see and restore them.
+pub struct TrashEntry {
+    pub name: String,
+    pub original: PathBuf,
+    pub deleted_at: String,
+}
+
+pub fn trash_dir() -> PathBuf {
+    if cfg!(target_os = "linux") || cfg!(target_os = "freebsd") {
+        if let Some(data) = env::var_os("XDG_DATA_HOME").filter(|d| !d.is_empty()) {
+            return PathBuf::from(data).join("Trash");
+        }
+        if let Some(home) = env::var_os("HOME") {
+            return PathBuf::from(home).join(".local/share/Trash");
+        }
+    }
+    let base = env::var_os("LOCALAPPDATA")
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME").map(|h| PathBuf::from(h).join("Library/Application Support"))
+        })
+        .unwrap_or_else(env::temp_dir);
+    base.join("rvex").join("trash")
+}
+
+pub fn move_to_trash(path: &Path) -> io::Result<String> {
+    let original = absolute(path);
+    let name = reserve_name(&original, "")?;
+    let target = trash_dir().join("files").join(&name);
+    if fs::rename(&original, &target).is_err() {
+        // Cross-device moves can't be renamed; fall back to copy + remove.
+        fs::copy(&original, &target)?;
+        fs::remove_file(&original)?;
+    }
+    Ok(name)
+}
+
+// Stores unsaved buffer contents that are about to be thrown away.
+pub fn trash_contents(path: &Path, contents: &str) -> io::Result<String> {
+    let original = absolute(path);
+    let name = reserve_name(&original, ".unsaved")?;
+    fs::write(trash_dir().join("files").join(&name), contents)?;
+    Ok(name)
+}
+
+pub fn list() -> Vec<TrashEntry> {
+    let Ok(dir) = fs::read_dir(trash_dir().join("info")) else {
+        return Vec::new();
+    };
+    let mut entries: Vec<TrashEntry> = dir
+        .flatten()
+        .filter_map(|e| {
+            let file_name = e.file_name().to_string_lossy().to_string();
+            let name = file_name.strip_suffix(".trashinfo")?.to_string();
+            let info = fs::read_to_string(e.path()).ok()?;
+            let mut original = None;
+            let mut deleted_at = String::new();
+            for line in info.lines() {
+                if let Some(p) = line.strip_prefix("Path=") {
+                    original = Some(PathBuf::from(percent_decode(p)));
+                } else if let Some(d) = line.strip_prefix("DeletionDate=") {
+                    deleted_at = d.to_string();
+                }
+            }
+            Some(TrashEntry {
+                name,
+                original: original?,
+                deleted_at,
+            })
+        })
+        .filter(|e| trash_dir().join("files").join(&e.name).exists())
+        .collect();
+    entries.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at));
+    entries
+}
+
+pub fn read(entry: &TrashEntry) -> io::Result<String> {
+    fs::read_to_string(trash_dir().join("files").join(&entry.name))
+}
+
+pub fn restore(entry: &TrashEntry) -> io::Result<()> {
+    if entry.original.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", entry.original.display()),
+        ));
+    }
+    if let Some(parent) = entry.original.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let source = trash_dir().join("files").join(&entry.name);
+    if fs::rename(&source, &entry.original).is_err() {
+        fs::copy(&source, &entry.original)?;
+        fs::remove_file(&source)?;
+    }
+    forget(entry)
+}
+
+pub fn forget(entry: &TrashEntry) -> io::Result<()> {
+    let _ = fs::remove_file(trash_dir().join("files").join(&entry.name));
+    fs::remove_file(
+        trash_dir()
+            .join("info")
+            .join(format!("{}.trashinfo", entry.name)),
+    )
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        env::current_dir().unwrap_or_default().join(path)
+    }
+}
+
+// Picks a free name in `files/` and atomically claims it by creating the
+// matching `.trashinfo`, as the spec requires.
+fn reserve_name(original: &Path, suffix: &str) -> io::Result<String> {
+    let dir = trash_dir();
+    fs::create_dir_all(dir.join("files"))?;
+    fs::create_dir_all(dir.join("info"))?;
+    let base = original
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "buffer".to_string());
+    let info = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        percent_encode(&original.to_string_lossy()),
+        now_iso8601()
+    );
+    for n in 0.. {
+        let name = if n == 0 {
+            format!("{}{}", base, suffix)
+        } else {
+            format!("{}.{}{}", base, n, suffix)
+        };
+        let info_path = dir.join("info").join(format!("{}.trashinfo", name));
+        if dir.join("files").join(&name).exists() {
+            continue;
+        }
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&info_path)
+        {
+            Ok(_) => {
+                fs::write(&info_path, &info)?;
+                return Ok(name);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!()
+}
+
+pub fn now_iso8601() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (y, m, d) = civil_from_days((secs / 86400) as i64);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        y,
+        m,
+        d,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+// Howard Hinnant's days-to-civil conversion.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (era * 400 + yoe + i64::from(m <= 2), m, d)
+}
+
+fn percent_encode(path: &str) -> String {
+    let mut out = String::new();
+    for b in path.bytes() {
+        if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(b) = u8::from_str_radix(hex, 16) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
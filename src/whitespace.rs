@@ -0,0 +1,54 @@
+use crate::width;
+
+// What `:set list` shows in place of whitespace, from `listchars`, as in
+// `tab:>,trail:-,nbsp:+`. Tabs take one cell like any other character, so
+// each symbol is a single narrow character. A kind left out is not shown.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct ListChars {
+    pub tab: Option<char>,
+    pub trail: Option<char>,
+    pub nbsp: Option<char>,
+}
+
+impl ListChars {
+    pub fn parse(spec: &str) -> Result<ListChars, String> {
+        let mut chars = ListChars::default();
+        for item in spec.split(',').filter(|i| !i.is_empty()) {
+            let (name, symbol) = item
+                .split_once(':')
+                .ok_or_else(|| format!("Invalid listchars entry: {}", item))?;
+            let mut symbols = symbol.chars();
+            let symbol = match (symbols.next(), symbols.next()) {
+                (Some(c), None) if width::width(symbol) == 1 => c,
+                _ => return Err(format!("Not a single narrow character: {}", item)),
+            };
+            match name {
+                "tab" => chars.tab = Some(symbol),
+                "trail" => chars.trail = Some(symbol),
+                "nbsp" => chars.nbsp = Some(symbol),
+                _ => return Err(format!("Unknown listchars entry: {}", name)),
+            }
+        }
+        Ok(chars)
+    }
+
+    // The whitespace in `line` to show, as character columns and symbols.
+    pub fn marks(&self, line: &str) -> Vec<(usize, char)> {
+        let trail_start = line.trim_end_matches(' ').chars().count();
+        line.chars()
+            .enumerate()
+            .filter_map(|(col, c)| match c {
+                '\t' => self.tab.map(|s| (col, s)),
+                '\u{a0}' => self.nbsp.map(|s| (col, s)),
+                ' ' if col >= trail_start => self.trail.map(|s| (col, s)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+// `line` without its trailing whitespace, if it has any.
+pub fn strip(line: &str) -> Option<String> {
+    let stripped = line.trim_end();
+    (stripped.len() < line.len()).then(|| stripped.to_string())
+}
@@ -0,0 +1,128 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+const ZERO_WIDTH_JOINER: char = '\u{200d}';
+const TEXT_PRESENTATION: char = '\u{fe0e}';
+const EMOJI_PRESENTATION: char = '\u{fe0f}';
+
+// The screen cells a grapheme cluster takes. An emoji joined into a
+// sequence, or asked for with a variation selector, is drawn as one double
+// width glyph; the rest go by their first character, so combining marks
+// add nothing. Control characters are drawn as a blank, and clusters of
+// nothing but zero width characters are not drawn at all.
+pub fn cluster_width(cluster: &str) -> usize {
+    let Some(first) = cluster.chars().next() else {
+        return 0;
+    };
+    if first.is_control() {
+        return 1;
+    }
+    let base = first.width().unwrap_or(1);
+    if base == 0 {
+        0
+    } else if cluster.contains(EMOJI_PRESENTATION) {
+        2
+    } else if cluster.contains(TEXT_PRESENTATION) {
+        1
+    } else if is_regional_indicator(first)
+        && cluster.chars().nth(1).is_some_and(is_regional_indicator)
+    {
+        2
+    } else if cluster.contains(ZERO_WIDTH_JOINER) {
+        base.max(2)
+    } else {
+        base
+    }
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1f1e6}'..='\u{1f1ff}').contains(&c)
+}
+
+// The grapheme clusters of `text` with their widths.
+pub fn clusters(text: &str) -> impl Iterator<Item = (&str, usize)> {
+    text.graphemes(true).map(|g| (g, cluster_width(g)))
+}
+
+pub fn width(text: &str) -> usize {
+    clusters(text).map(|(_, w)| w).sum()
+}
+
+// The screen column, relative to the start of `line`, of its character
+// `col`. Columns past the end count as one cell each.
+pub fn column(line: &str, col: usize) -> usize {
+    let mut chars = 0;
+    let mut cells = 0;
+    for (cluster, w) in clusters(line) {
+        if chars >= col {
+            return cells;
+        }
+        chars += cluster.chars().count();
+        cells += w;
+    }
+    cells + col.saturating_sub(chars)
+}
+
+// As much of `text` as fits in `cells` cells.
+pub fn truncate(text: &str, cells: usize) -> &str {
+    let mut used = 0;
+    for (i, cluster) in text.grapheme_indices(true) {
+        used += cluster_width(cluster);
+        if used > cells {
+            return &text[..i];
+        }
+    }
+    text
+}
+
+// `text` cut or padded with blanks to exactly `cells` cells. A double width
+// cluster that would straddle the end is left out for a blank.
+pub fn fit(text: &str, cells: usize) -> String {
+    let text = truncate(text, cells);
+    format!("{}{}", text, " ".repeat(cells - width(text)))
+}
+
+// The character columns at which `line` is broken to show it in rows of
+// `cells` cells; the first is always 0. A double width cluster that doesn't
+// fit at the end of a row starts the next one.
+pub fn breaks(line: &str, cells: usize) -> Vec<usize> {
+    let mut breaks = vec![0];
+    let (mut chars, mut used) = (0, 0);
+    for (cluster, w) in clusters(line) {
+        if used + w > cells && used > 0 {
+            breaks.push(chars);
+            used = 0;
+        }
+        chars += cluster.chars().count();
+        used += w;
+    }
+    breaks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_clusters() {
+        assert_eq!(width("abc"), 3);
+        assert_eq!(width("日本"), 4);
+        assert_eq!(width("e\u{301}"), 1);
+        assert_eq!(width("👩\u{200d}💻"), 2);
+        assert_eq!(width("❤\u{fe0f}"), 2);
+        assert_eq!(width("🇫🇷"), 2);
+        assert_eq!(width("\u{200b}"), 0);
+    }
+
+    #[test]
+    fn fits_to_exact_widths() {
+        assert_eq!(fit("日本語", 5), "日本 ");
+        assert_eq!(fit("ab", 4), "ab  ");
+        assert_eq!(truncate("a👩\u{200d}💻b", 3), "a👩\u{200d}💻");
+        assert_eq!(column("日本語", 2), 4);
+        assert_eq!(column("ab", 4), 4);
+        assert_eq!(breaks("abcdefg", 3), [0, 3, 6]);
+        assert_eq!(breaks("ab日本", 3), [0, 2, 3]);
+        assert_eq!(breaks("", 3), [0]);
+    }
+}
@@ -0,0 +1,57 @@
+use crate::aliases;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// Commands that files are saved through instead of being written to disk,
+// keyed by extension or file name: `:writefilter crontab crontab -`. The
+// encoded file contents go to the command's stdin.
+pub struct WriteFilters {
+    filters: BTreeMap<String, String>,
+}
+
+impl Default for WriteFilters {
+    fn default() -> Self {
+        WriteFilters::new()
+    }
+}
+
+impl WriteFilters {
+    pub fn new() -> Self {
+        WriteFilters {
+            filters: BTreeMap::new(),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, cmd: &str) {
+        self.filters.insert(key.to_string(), cmd.to_string());
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.filters.get(key).map(|cmd| cmd.as_str())
+    }
+
+    pub fn remove(&mut self, key: &str) -> bool {
+        self.filters.remove(key).is_some()
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        aliases::listing(&self.filters)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.filters
+            .iter()
+            .map(|(k, cmd)| (k.as_str(), cmd.as_str()))
+    }
+
+    // A filter for the file's name wins over one for its extension.
+    pub fn for_path(&self, path: &str) -> Option<&str> {
+        let path = Path::new(path);
+        let name = path.file_name()?.to_string_lossy();
+        let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+        self.filters
+            .get(name.as_ref())
+            .or_else(|| self.filters.get(ext.as_deref()?))
+            .map(|cmd| cmd.as_str())
+    }
+}
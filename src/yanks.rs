@@ -0,0 +1,153 @@
+use crate::position;
+use std::collections::VecDeque;
+use unicode_segmentation::UnicodeSegmentation;
+
+// How many yanks the history keeps.
+const HISTORY_LEN: usize = 50;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum YankKind {
+    Chars,
+    Lines,
+    Block,
+}
+
+pub struct Yank {
+    pub text: Vec<String>,
+    pub kind: YankKind,
+    pub time: String,
+}
+
+impl Yank {
+    // What the picker shows: when, what shape and how it starts, with line
+    // breaks shown as `↵`.
+    pub fn describe(&self) -> String {
+        let shape = match self.kind {
+            YankKind::Chars => "chars".to_string(),
+            YankKind::Lines if self.text.len() == 1 => "1 line".to_string(),
+            YankKind::Lines => format!("{} lines", self.text.len()),
+            YankKind::Block => format!("block {}x{}", self.text.len(), block_width(&self.text)),
+        };
+        format!("{}  {:<10} {}", self.time, shape, self.text.join("↵"))
+    }
+}
+
+fn block_width(text: &[String]) -> usize {
+    text.iter()
+        .map(|l| position::graphemes(l))
+        .max()
+        .unwrap_or(0)
+}
+
+// Everything yanked, newest first. The newest is what `p` puts; picking an
+// older one from `:Yanks` yanks it again, moving it to the front.
+pub struct Yanks {
+    entries: VecDeque<Yank>,
+}
+
+impl Default for Yanks {
+    fn default() -> Self {
+        Yanks::new()
+    }
+}
+
+impl Yanks {
+    pub fn new() -> Self {
+        Yanks {
+            entries: VecDeque::new(),
+        }
+    }
+
+    // Yanking text that is already in the history moves it to the front.
+    pub fn push(&mut self, text: Vec<String>, kind: YankKind) {
+        self.entries
+            .retain(|yank| yank.text != text || yank.kind != kind);
+        self.entries.push_front(Yank {
+            text,
+            kind,
+            time: crate::output::timestamp(),
+        });
+        self.entries.truncate(HISTORY_LEN);
+    }
+
+    pub fn current(&self) -> Option<&Yank> {
+        self.entries.front()
+    }
+
+    // Makes entry `index` the newest again.
+    pub fn promote(&mut self, index: usize) -> Option<&Yank> {
+        let yank = self.entries.remove(index)?;
+        self.entries.push_front(yank);
+        self.entries.front()
+    }
+
+    pub fn remove(&mut self, index: usize) -> bool {
+        self.entries.remove(index).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.entries.iter().map(|yank| yank.describe()).collect()
+    }
+}
+
+// The yank put in at grapheme `col` of `existing`, the lines from where it
+// goes on. Characters run on from `col` and a block goes into that column
+// of as many lines, padded or added as needed; whole lines go in above the
+// first. Returns the new lines, how many of `existing` they replace and
+// the grapheme of the first the cursor goes to.
+pub fn put(existing: &[String], col: usize, yank: &Yank) -> (Vec<String>, usize, usize) {
+    match yank.kind {
+        YankKind::Lines => (yank.text.clone(), 0, 0),
+        YankKind::Chars => {
+            let line = existing.first().map_or("", |l| l.as_str());
+            let (before, after) = line.split_at(byte_index(line, col));
+            let mut out = yank.text.clone();
+            let last = out.len() - 1;
+            out[0].insert_str(0, before);
+            let end = position::graphemes(&out[last]);
+            out[last].push_str(after);
+            let cursor = if last == 0 {
+                end.saturating_sub(1)
+            } else {
+                col
+            };
+            (out, 1, cursor)
+        }
+        YankKind::Block => {
+            let width = block_width(&yank.text);
+            let mut out = Vec::new();
+            for (i, text) in yank.text.iter().enumerate() {
+                let mut line = existing.get(i).cloned().unwrap_or_default();
+                let len = position::graphemes(&line);
+                if len < col {
+                    line.push_str(&" ".repeat(col - len));
+                }
+                let at = byte_index(&line, col);
+                // Keeps what follows the block in one column.
+                let pad = if at < line.len() {
+                    width - position::graphemes(text)
+                } else {
+                    0
+                };
+                line.insert_str(at, &format!("{}{}", text, " ".repeat(pad)));
+                out.push(line);
+            }
+            let replaced = existing.len().min(out.len());
+            (out, replaced, col)
+        }
+    }
+}
+
+fn byte_index(line: &str, col: usize) -> usize {
+    line.grapheme_indices(true)
+        .nth(col)
+        .map_or(line.len(), |(i, _)| i)
+}
@@ -0,0 +1,1060 @@
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+use text_editor::buffer::Buffer;
+use text_editor::editor::Mode;
+use text_editor::headless::Headless;
+use text_editor::swap;
+
+// A scratch file holding `text`, unique to the calling test.
+fn scratch(name: &str, text: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rvex-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    fs::write(&path, text).unwrap();
+    path
+}
+
+fn open(name: &str, text: &str) -> (Headless, PathBuf) {
+    let path = scratch(name, text);
+    (Headless::open(path.to_str().unwrap()), path)
+}
+
+#[test]
+fn inserts_text_and_writes_it() {
+    let (mut editor, path) = open("insert.txt", "world\n");
+    editor.feed("ihello <Esc>:w<CR>").unwrap();
+    assert_eq!(editor.mode(), Mode::Normal);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "hello world\n");
+    assert!(!editor.editor.is_modified());
+}
+
+#[test]
+fn moves_the_cursor() {
+    let (mut editor, _) = open("motion.txt", "one\ntwo\nthree\n");
+    editor.feed("jjl").unwrap();
+    assert_eq!(editor.cursor(), (2, 1));
+    editor.feed("$k").unwrap();
+    assert_eq!(editor.cursor(), (1, 3));
+}
+
+#[test]
+fn substitutes_in_a_range() {
+    let (mut editor, _) = open("range.txt", "a a\na a\na a\n");
+    editor.feed(":2,3s/a/b/g<CR>").unwrap();
+    assert_eq!(editor.lines(), ["a a", "b b", "b b"]);
+    assert_eq!(editor.status(), Some("4 substitution(s) on 2 line(s)"));
+}
+
+#[test]
+fn confirms_each_substitution() {
+    let (mut editor, _) = open("confirm.txt", "x x\nx\n");
+    editor.feed(":%s/x/y/gc<CR>").unwrap();
+    assert_eq!(editor.mode(), Mode::Prompt);
+    editor.feed("ny").unwrap();
+    assert_eq!(editor.text(), "x y\nx");
+    editor.feed("q").unwrap();
+    assert_eq!(editor.mode(), Mode::Normal);
+}
+
+#[test]
+fn jumps_between_brackets() {
+    let (mut editor, _) = open("brackets.rs", "fn f() {\n    let s = \"}\";\n}\n");
+    editor.feed("jj%").unwrap();
+    assert_eq!(editor.cursor(), (0, 7));
+    editor.feed("%").unwrap();
+    assert_eq!(editor.cursor(), (2, 0));
+}
+
+#[test]
+fn opens_indented_lines() {
+    let (mut editor, _) = open("indent.rs", "fn main() {\n}\n");
+    editor.feed("olet x = 1;<Esc>").unwrap();
+    assert_eq!(editor.lines(), ["fn main() {", "    let x = 1;", "}"]);
+}
+
+#[test]
+fn filters_lines_through_a_command() {
+    let (mut editor, _) = open("filter.txt", "c\nb\na\n");
+    editor.feed(":%!sort<CR>").unwrap();
+    assert_eq!(editor.lines(), ["a", "b", "c"]);
+}
+
+#[test]
+fn expands_user_commands() {
+    let (mut editor, _) = open("alias.txt", "b\na\n");
+    editor.feed(":command Sort %!sort<CR>:Sort<CR>").unwrap();
+    assert_eq!(editor.lines(), ["a", "b"]);
+}
+
+#[test]
+fn cquit_sets_the_exit_code() {
+    let (mut editor, _) = open("cquit.txt", "text\n");
+    editor.feed(":cq 3<CR>").unwrap();
+    assert!(editor.editor.should_exit());
+    assert_eq!(editor.editor.exit_code(), 3);
+}
+
+#[test]
+fn draws_the_buffer_and_status_line() {
+    let path = scratch("screen.txt", "first\nsecond\n");
+    let mut editor = Headless::with_size(path.to_str().unwrap(), 5, 40);
+    let screen = editor.screen();
+    assert_eq!(screen.len(), 5);
+    assert_eq!(screen[0], "   1 first");
+    assert_eq!(screen[1], "   2 second");
+    assert!(screen[4].starts_with(" NORMAL |"));
+}
+
+#[test]
+fn previews_line_ranges_while_typing() {
+    let text: String = (1..=100).map(|n| format!("{}\n", n)).collect();
+    let path = scratch("preview.txt", &text);
+    let mut editor = Headless::with_size(path.to_str().unwrap(), 10, 40);
+    editor.feed(":50").unwrap();
+    assert_eq!(editor.cursor(), (49, 0));
+    assert!(editor.screen().iter().any(|row| row == "  50 50"));
+    editor.feed("<Esc>").unwrap();
+    assert_eq!(editor.cursor(), (0, 0));
+    assert_eq!(editor.screen()[0], "   1 1");
+    editor.feed(":+2<CR>").unwrap();
+    assert_eq!(editor.cursor(), (2, 0));
+}
+
+#[test]
+fn previews_substitutions_while_typing() {
+    let path = scratch("inccommand.txt", "foo one\nbar foo\nbaz\n");
+    let mut editor = Headless::with_size(path.to_str().unwrap(), 16, 40);
+    editor.feed(":%s/foo/XY/g").unwrap();
+    let screen = editor.screen();
+    assert_eq!(screen[..3], ["   1 XY one", "   2 bar XY", "   3 baz"]);
+    assert_eq!(editor.lines(), ["foo one", "bar foo", "baz"]);
+    editor
+        .feed("<Esc>:set inccommand=split<CR>:2,3s/foo/Z")
+        .unwrap();
+    let screen = editor.screen();
+    assert_eq!(screen[1], "   2 bar Z");
+    assert!(screen.iter().any(|row| row == " [Preview] 1 line(s)"));
+    assert!(screen.iter().any(|row| row == "    2| bar Z"));
+    editor.feed("<CR>").unwrap();
+    assert_eq!(editor.lines(), ["foo one", "bar Z", "baz"]);
+    assert_eq!(editor.screen()[0], "   1 foo one");
+}
+
+#[test]
+fn applies_key_mappings() {
+    let (mut editor, path) = open("mappings.txt", "one\ntwo\n");
+    editor
+        .feed(":inoremap jk <lt>Esc><CR>:set leader=,<CR>:nmap ,w :w<lt>CR><CR>")
+        .unwrap();
+    editor.feed("ijust<Esc>jijk").unwrap();
+    assert_eq!(editor.mode(), Mode::Normal);
+    assert_eq!(editor.lines(), ["justone", "two"]);
+    editor.feed(",w").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "justone\ntwo\n");
+    // A mapping that starts with its own keys doesn't expand them again.
+    editor.feed(":nmap k kl<CR>gg0k").unwrap();
+    assert_eq!(editor.cursor(), (0, 1));
+}
+
+#[test]
+fn stops_recursive_mappings() {
+    let (mut editor, _) = open("recursive.txt", "one\n");
+    editor.feed(":nmap a b<CR>:nmap b a<CR>a").unwrap();
+    assert_eq!(editor.status(), Some("Recursive mapping"));
+    assert_eq!(editor.lines(), ["one"]);
+}
+
+#[test]
+fn shows_linter_results_as_signs() {
+    let (mut editor, _) = open("lint.txt", "one\ntwo\n");
+    editor
+        .feed(":set gutter=sign,number<CR>:linter txt echo %:2:3: warning: bad word<CR>")
+        .unwrap();
+    // Unsaved text is checked through a copy, reported as the file itself.
+    editor.feed("iZ<Esc>:Lint<CR>").unwrap();
+    editor.wait();
+    assert_eq!(editor.status(), Some("Lint: 0 error(s), 1 warning(s)"));
+    let screen = editor.screen();
+    assert_eq!(screen[0], "     1 Zone");
+    assert_eq!(screen[1], ">    2 two");
+}
+
+#[test]
+fn shows_language_server_diagnostics() {
+    let (mut editor, path) = open("lsp.txt", "one\ntwo\n");
+    // A server that answers `initialize` and reports one diagnostic.
+    let uri = format!("file://{}", path.display());
+    let messages = [
+        r#"{"jsonrpc":"2.0","id":1,"result":{"capabilities":{}}}"#.to_string(),
+        format!(
+            r#"{{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{{"uri":"{}","diagnostics":[{{"range":{{"start":{{"line":1,"character":0}},"end":{{"line":1,"character":3}}}},"severity":2,"message":"odd"}}]}}}}"#,
+            uri
+        ),
+    ];
+    let wire: String = messages
+        .iter()
+        .map(|m| format!("Content-Length: {}\r\n\r\n{}", m.len(), m))
+        .collect();
+    let script = scratch("lsp-messages", &wire);
+    let server = format!("cat {}; cat >/dev/null", script.display());
+    editor
+        .feed(&format!(
+            ":set gutter=sign,number<CR>:lsp txt {}<CR>:LspRestart<CR>",
+            server
+        ))
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !editor.screen()[1].starts_with('W') && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(editor.screen()[1], "W    2 two");
+}
+
+#[test]
+fn completes_words_from_the_buffer() {
+    let path = scratch("complete.txt", "foobar football fix\n\n");
+    let mut editor = Headless::with_size(path.to_str().unwrap(), 10, 40);
+    editor.feed("jifo<C-n>").unwrap();
+    let screen = editor.screen();
+    assert_eq!(screen[2], "      foobar");
+    assert_eq!(screen[3], "      football");
+    editor.feed("<C-n><CR>").unwrap();
+    assert_eq!(editor.lines()[1], "football");
+    assert_eq!(editor.cursor(), (1, 8));
+    // Typing narrows the menu down.
+    editor.feed(" fo<C-p>ob<CR>").unwrap();
+    assert_eq!(editor.lines()[1], "football foobar");
+}
+
+#[test]
+fn completes_from_the_sources_set_for_the_filetype() {
+    let path = scratch("sources.txt", "format\n\n");
+    let mut editor = Headless::with_size(path.to_str().unwrap(), 10, 40);
+    editor.feed(":snippet forl for i in 0..n {}<CR>").unwrap();
+    editor
+        .feed(":completesources txt snippet buffer<CR>")
+        .unwrap();
+    editor.feed("jifo<C-n>").unwrap();
+    let screen = editor.screen();
+    assert_eq!(screen[2], "      forl  for i in 0..n {}");
+    assert_eq!(screen[3], "      format");
+    editor.feed("<CR>").unwrap();
+    assert_eq!(editor.lines()[1], "for i in 0..n {}");
+    // Without the snippet source only the buffer's words are offered.
+    editor
+        .feed("<Esc>:completesources txt buffer<CR>o fo<C-n>")
+        .unwrap();
+    let screen = editor.screen();
+    assert_eq!(screen[3], "       for");
+    assert_eq!(screen[4], "       format");
+    assert!(!screen.iter().any(|row| row.contains("forl")));
+}
+
+#[test]
+fn completes_from_a_plugin_source() {
+    let plugin = scratch(
+        "colors.rhai",
+        "register_completion(\"colors\", \"colors\");\nfn colors(word) { [\"crimson\", \"cyan\"] }\n",
+    );
+    let path = scratch("plugin-source.txt", "cat\n\n");
+    let mut editor = Headless::with_size(path.to_str().unwrap(), 10, 40);
+    editor
+        .feed(&format!(":source {}<CR>", plugin.display()))
+        .unwrap();
+    editor
+        .feed(":completesources txt colors buffer<CR>")
+        .unwrap();
+    editor.feed("jic<C-n>").unwrap();
+    let screen = editor.screen();
+    assert_eq!(screen[2], "      crimson");
+    assert_eq!(screen[3], "      cyan");
+    assert_eq!(screen[4], "      cat");
+}
+
+#[test]
+fn reindents_whole_lines_put_in_code() {
+    let (mut editor, _path) = open("put.rs", "if c {\n    d();\n}\nfn main() {\n    a();\n}\n");
+    editor.feed("Vjyjjjjp").unwrap();
+    assert_eq!(
+        editor.lines()[4..7],
+        ["    a();", "    if c {", "        d();"]
+    );
+    // Plain text is put as it was yanked.
+    let (mut plain, _path) = open("put.txt", "x\n    y\n");
+    plain.feed("Vyjp").unwrap();
+    assert_eq!(plain.lines(), ["x", "    y", "x"]);
+}
+
+#[test]
+fn reports_memory_use() {
+    let path = scratch("memory.txt", "one\ntwo\n");
+    let mut editor = Headless::with_size(path.to_str().unwrap(), 40, 40);
+    editor.feed(":set maxmemory=0<CR>:memory<CR>").unwrap();
+    let screen = editor.screen();
+    assert!(screen.iter().any(|row| row.starts_with("buffer ")));
+    assert!(screen.iter().any(|row| row.starts_with("total ")));
+    assert!(screen.iter().any(|row| row == "maxmemory=0 (no limit)"));
+}
+
+#[test]
+fn draws_on_tiny_terminals() {
+    let path = scratch("tiny.txt", "foo foobar\nfoo\n");
+    let keys = [
+        "",
+        ":memory<CR>",
+        "joi<C-n>",
+        ":set inccommand=split<CR>:%s/foo/x/",
+    ];
+    for (rows, cols) in [(0, 0), (1, 1), (2, 3), (3, 5), (10, 2)] {
+        for keys in keys {
+            let mut editor = Headless::with_size(path.to_str().unwrap(), rows, cols);
+            editor.feed(keys).unwrap();
+            assert_eq!(editor.screen().len(), rows);
+        }
+    }
+    // Without room for the file finder's box its prompt takes one line.
+    let mut editor = Headless::with_size(path.to_str().unwrap(), 3, 40);
+    editor.feed("<C-p>ab").unwrap();
+    assert!(editor.screen()[2].starts_with("Files ("));
+    assert!(editor.screen()[2].contains(") > ab"));
+}
+
+#[test]
+fn runs_commands_on_visual_selections() {
+    let (mut editor, _) = open("visual.txt", "d\nc\nb\na\n");
+    editor.feed("jVj").unwrap();
+    assert_eq!(editor.mode(), Mode::Visual);
+    editor.feed(":sort<CR>").unwrap();
+    assert_eq!(editor.lines(), ["d", "b", "c", "a"]);
+    editor.feed(":'<lt>,'>!sort -r<CR>").unwrap();
+    assert_eq!(editor.lines(), ["d", "c", "b", "a"]);
+}
+
+#[test]
+fn substitutes_in_a_visual_block() {
+    let path = scratch("block.txt", "aaaa\naaaa\naaaa\n");
+    let mut editor = Headless::with_size(path.to_str().unwrap(), 10, 40);
+    editor.feed("l<C-v>jl").unwrap();
+    assert!(editor.screen()[9].starts_with(" V-BLOCK |"));
+    editor.feed(":s/a/b/g<CR>").unwrap();
+    assert_eq!(editor.lines(), ["abba", "abba", "aaaa"]);
+}
+
+#[test]
+fn edits_at_every_cursor() {
+    let (mut editor, _) = open("cursors.txt", "let a = foo;\nfoo(foo);\n");
+    editor.feed("j<C-n><C-n><C-n>").unwrap();
+    assert_eq!(editor.status(), Some("No more matches for foo"));
+    editor.feed("imy_<Esc>").unwrap();
+    assert_eq!(editor.lines(), ["let a = my_foo;", "my_foo(my_foo);"]);
+    editor.feed("<Esc>").unwrap();
+    // Block-wise `A` appends after the block on each of its lines.
+    editor.feed("k0<C-v>jA|<CR>x<Esc>").unwrap();
+    assert_eq!(
+        editor.lines(),
+        ["l|", "xet a = my_foo;", "m|", "xy_foo(my_foo);"]
+    );
+}
+
+#[test]
+fn runs_user_defined_operators() {
+    let (mut editor, _) = open("operators.txt", "Uryyb\nnop\n\nzeta\nalpha\n");
+    editor
+        .feed(":operator gr !tr a-zA-Z n-za-mN-ZA-M<CR>grj")
+        .unwrap();
+    assert_eq!(editor.lines(), ["Hello", "abc", "", "zeta", "alpha"]);
+    // `g@` runs the user command named by operatorfunc.
+    editor
+        .feed(":command Sort sort<CR>:set opfunc=Sort<CR>jjjg@ip")
+        .unwrap();
+    assert_eq!(editor.lines(), ["Hello", "abc", "", "alpha", "zeta"]);
+    editor.feed("grr").unwrap();
+    assert_eq!(editor.lines()[3], "nycun");
+}
+
+#[test]
+fn sends_lines_to_a_repl() {
+    let path = scratch("repl.txt", "one\ntwo\nthree\n");
+    let mut editor = Headless::with_size(path.to_str().unwrap(), 20, 40);
+    editor
+        .feed(":Repl cat<CR>:ReplSend<CR>:ReplSend<CR>")
+        .unwrap();
+    assert_eq!(editor.cursor(), (2, 0));
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !editor.screen().iter().any(|row| row == "two") && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    let screen = editor.screen();
+    assert_eq!(screen[10], " [Output] REPL cat");
+    assert_eq!(screen[12..14], ["one", "two"]);
+}
+
+#[test]
+fn highlights_every_match_of_the_last_search() {
+    let (mut editor, _path) = open("hlsearch.txt", "foo bar foo\nbaz\nbar foo\n");
+    let highlighted =
+        |editor: &Headless, row: usize, col: usize| editor.cell(row, col).style.bg.is_some();
+
+    editor.feed("/baz").unwrap();
+    assert_eq!(editor.cursor(), (1, 0));
+    let screen = editor.screen();
+    let baz = screen[1].find("baz").unwrap();
+    assert!(highlighted(&editor, 1, baz));
+    editor.feed("<Esc>").unwrap();
+    assert_eq!(editor.cursor(), (0, 0));
+
+    editor.feed("/foo<CR>").unwrap();
+    assert_eq!(editor.cursor(), (0, 8));
+    let screen = editor.screen();
+    let start = screen[0].find("foo").unwrap();
+    assert!(highlighted(&editor, 0, start));
+    assert!(highlighted(&editor, 0, start + 8));
+    assert!(!highlighted(&editor, 0, start + 4));
+    assert!(highlighted(&editor, 2, start + 4));
+
+    editor.feed(":noh<CR>").unwrap();
+    editor.screen();
+    assert!(!highlighted(&editor, 0, start));
+    editor.feed("n").unwrap();
+    editor.screen();
+    assert!(highlighted(&editor, 0, start));
+}
+
+#[test]
+fn lays_out_wide_characters_by_cell() {
+    let path = scratch("日本語.txt", "日本 foo\n👩\u{200d}💻 bar\n");
+    let mut editor = Headless::with_size(path.to_str().unwrap(), 6, 30);
+    editor.feed("/foo<CR>").unwrap();
+    let screen = editor.screen();
+    let gutter = screen[1].find('👩').unwrap();
+    assert_eq!(editor.cell(0, gutter).symbol, "日");
+    assert_eq!(editor.cell(0, gutter + 1).symbol, "");
+    assert!(editor.cell(0, gutter + 5).style.bg.is_some());
+    assert_eq!(editor.cell(1, gutter + 3).symbol, "b");
+
+    // The status line ends in the same column whatever the file name.
+    assert!(editor.cell(5, 28).style.bg.is_some());
+    assert!(editor.cell(5, 29).style.bg.is_none());
+}
+
+#[test]
+fn shows_and_strips_trailing_whitespace() {
+    let (mut editor, path) = open("whitespace.txt", "a\tb  \nc\u{a0}d \ne\n");
+    editor.feed(":set list<CR>").unwrap();
+    let screen = editor.screen();
+    assert!(screen[0].ends_with("a>b--"), "{:?}", screen[0]);
+    assert!(screen[1].ends_with("c+d-"), "{:?}", screen[1]);
+    let gutter = screen[2].len() - 1;
+    assert!(editor.cell(0, gutter + 1).style.fg.is_some());
+
+    editor.feed(":1StripWhitespace<CR>").unwrap();
+    assert_eq!(editor.lines(), ["a\tb", "c\u{a0}d ", "e"]);
+    editor.feed(":set stripwhitespace<CR>:w<CR>").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "a\tb\nc\u{a0}d\ne\n");
+    editor.feed(":StripWhitespace<CR>").unwrap();
+    assert_eq!(
+        editor.status(),
+        Some("Stripped trailing whitespace from 0 line(s)")
+    );
+}
+
+#[test]
+fn shifts_and_reindents_lines() {
+    let (mut editor, _path) = open("shift.rs", "fn main() {\nlet a = 1;\nif a {\nb();\n}\n}\n");
+    editor.feed("j>>").unwrap();
+    assert_eq!(editor.lines()[1], "    let a = 1;");
+    editor.feed("<lt><lt>").unwrap();
+    assert_eq!(editor.lines()[1], "let a = 1;");
+
+    editor.feed(":set sw=2<CR>>j").unwrap();
+    assert_eq!(editor.lines()[1..3], ["  let a = 1;", "  if a {"]);
+    editor.feed("Vjj<lt>").unwrap();
+    assert_eq!(editor.lines()[1..4], ["let a = 1;", "if a {", "b();"]);
+    assert_eq!(editor.mode(), Mode::Normal);
+
+    editor.feed("=ip").unwrap();
+    assert_eq!(
+        editor.lines(),
+        [
+            "fn main() {",
+            "    let a = 1;",
+            "    if a {",
+            "        b();",
+            "    }",
+            "}"
+        ]
+    );
+    assert_eq!(editor.status(), Some("6 lines indented"));
+}
+
+#[test]
+fn shows_the_effective_config() {
+    let config = scratch("init.rvex", "\" startup\nset sw=3\nnmap Q :w<CR>\n");
+    let path = scratch("config.txt", "text\n");
+    let mut editor = Headless::with_size(path.to_str().unwrap(), 24, 400);
+    editor.editor.load_config(Some(config.to_str().unwrap()));
+    editor.feed(":ConfigShow<CR>").unwrap();
+    let screen = editor.screen().join("\n");
+    assert!(
+        screen.contains(&format!("\" Read {}", config.display())),
+        "{}",
+        screen
+    );
+    assert!(screen.contains("shiftwidth=3"));
+    assert!(screen.contains("nmap Q :w<CR>"));
+
+    let (mut clean, _path) = open("clean.txt", "text\n");
+    clean.editor.load_config(None);
+    clean.feed(":ConfigShow<CR>").unwrap();
+    assert!(clean
+        .screen()
+        .join("\n")
+        .contains("\" No startup file read"));
+}
+
+#[test]
+fn checks_the_config_for_problems() {
+    let config = scratch(
+        "health.rvex",
+        "set sw=3 gutter=git,number fenc=latin1\nset nosuchoption\nnmap x dd\nnmap xy yy\n",
+    );
+    let path = scratch("health.txt", "text\n");
+    let mut editor = Headless::with_size(path.to_str().unwrap(), 60, 200);
+    editor.editor.load_config(Some(config.to_str().unwrap()));
+    editor.feed(":checkhealth<CR>").unwrap();
+    let screen = editor.screen().join("\n");
+    let line = format!("{} line 2: Unknown option: nosuchoption", config.display());
+    assert!(screen.contains(&line), "{}", screen);
+    assert!(!screen.contains("line 1:"), "{}", screen);
+    assert!(screen.contains("n  x waits a second for xy before it runs"));
+    assert!(editor
+        .status()
+        .unwrap()
+        .ends_with("problems found; see the Health pane"));
+}
+
+#[test]
+fn runs_rhai_plugins() {
+    let plugin = scratch(
+        "upper.rhai",
+        r#"
+register_command("Upper", "upper");
+map("n", "U", ":Upper<CR>");
+
+fn upper(args) {
+    let row = cursor()[0];
+    set_line(row, get_line(row).to_upper() + args);
+    insert_line(row + 1, "added");
+    set_cursor(row + 1, 0);
+}
+
+fn on_save(path) {
+    message("saved " + line_count() + " lines");
+}
+"#,
+    );
+    let (mut editor, _path) = open("plugin.txt", "one\ntwo\n");
+    editor
+        .feed(&format!(":source {}<CR>", plugin.display()))
+        .unwrap();
+    editor.feed("jU").unwrap();
+    assert_eq!(editor.lines(), ["one", "TWO", "added"]);
+    assert_eq!(editor.cursor(), (2, 0));
+    editor.feed(":Upper !<CR>").unwrap();
+    assert_eq!(editor.lines(), ["one", "TWO", "ADDED!", "added"]);
+    editor.feed(":w<CR>").unwrap();
+    assert_eq!(editor.status(), Some("saved 4 lines"));
+
+    let bad = scratch("bad.rhai", "fn broken( {");
+    editor
+        .feed(&format!(":source {}<CR>", bad.display()))
+        .unwrap();
+    assert!(editor.status().unwrap().contains("bad.rhai"));
+}
+
+#[test]
+fn drops_what_a_failed_plugin_registered() {
+    let good = scratch(
+        "good.rhai",
+        "register_command(\"Hi\", \"hi\");\nfn hi(args) { message(\"hi\"); }\n",
+    );
+    let failing = scratch(
+        "failing.rhai",
+        "register_command(\"Foo\", \"foo\");\nregister_command(\"Hi\", \"foo\");\nthrow \"boom\";\n",
+    );
+    let (mut editor, _path) = open("failed-plugin.txt", "text\n");
+    editor
+        .feed(&format!(":source {}<CR>", good.display()))
+        .unwrap();
+    editor
+        .feed(&format!(":source {}<CR>", failing.display()))
+        .unwrap();
+    assert!(editor.status().unwrap().contains("boom"));
+    editor.feed(":Foo<CR>").unwrap();
+    assert_eq!(editor.status(), Some("Unknown command: Foo"));
+    editor.feed(":Hi<CR>").unwrap();
+    assert_eq!(editor.status(), Some("hi"));
+    editor.feed(":Plugins<CR>").unwrap();
+    assert!(!editor.screen().join("\n").contains(":Foo"));
+}
+
+#[test]
+fn writes_a_bug_report() {
+    let (mut editor, _path) = open("secret-name.txt", "text\n");
+    let report = scratch("bugreport.txt", "");
+    editor
+        .feed(&format!(
+            ":set keytrace<CR>ix<Esc>:bugreport {}<CR>",
+            report.display()
+        ))
+        .unwrap();
+    assert!(editor
+        .status()
+        .unwrap()
+        .ends_with("exists (add ! to override)"));
+    editor
+        .feed(&format!(":bugreport! {}<CR>", report.display()))
+        .unwrap();
+    assert!(editor.status().unwrap().contains("nothing was sent"));
+    let text = fs::read_to_string(&report).unwrap();
+    assert!(text.contains("== System ==\nrvex "), "{}", text);
+    assert!(text.contains("file type: txt"));
+    assert!(text.contains("ix<Esc>:bugreport"));
+    assert!(!text.contains("secret-name"));
+}
+
+#[test]
+fn leaves_paths_out_of_a_bug_report() {
+    let home = std::env::var("HOME").unwrap_or_else(|_| "/home/someone".to_string());
+    let (mut editor, _) = open("report-paths.txt", "text\n");
+    let report = scratch("report-paths-out.txt", "");
+    let linter = format!(
+        ":linter txt echo {}/private/notes.txt:1:1: warning /srv/private/x<CR>",
+        home
+    );
+    editor.feed(&linter).unwrap();
+    editor.feed(":Lint<CR>").unwrap();
+    editor.wait();
+    editor
+        .feed(&format!(":bugreport! {}<CR>", report.display()))
+        .unwrap();
+    let text = fs::read_to_string(&report).unwrap();
+    assert!(
+        text.contains("~/private/notes.txt:1:1: warning <path>"),
+        "{}",
+        text
+    );
+    assert!(!text.contains(&home), "{}", text);
+    assert!(!text.contains("/srv/private"), "{}", text);
+}
+
+#[test]
+fn knows_one_file_by_any_spelling() {
+    let path = scratch("spelled.txt", "").with_file_name("not-yet-written.txt");
+    let dir = path.parent().unwrap().display().to_string();
+    let mut editor = Headless::open(path.to_str().unwrap());
+    editor.feed("inew<Esc>").unwrap();
+    editor
+        .feed(&format!(":e {}/./sub/../not-yet-written.txt<CR>", dir))
+        .unwrap();
+    assert!(editor.status().unwrap().ends_with("not-yet-written.txt\""));
+    assert_eq!(editor.lines(), ["new"]);
+    editor.feed(&format!(":e {}/other.txt<CR>", dir)).unwrap();
+    assert_eq!(editor.status(), Some("No write since last change"));
+}
+
+#[test]
+fn moves_state_with_statedir() {
+    let (mut editor, path) = open("state.txt", "text\n");
+    let dir = path.parent().unwrap();
+    let (old, new) = (dir.join("state-old"), dir.join("state-new"));
+    editor
+        .feed(&format!(":set statedir={}<CR>", old.display()))
+        .unwrap();
+    fs::create_dir_all(old.join("swap")).unwrap();
+    fs::write(old.join("swap/other.swp"), "RVEX-SWAP 1\n").unwrap();
+    editor
+        .feed(&format!(":set statedir={}<CR>", new.display()))
+        .unwrap();
+    assert!(editor.status().unwrap().starts_with("Moved 1 swap file(s)"));
+    assert!(new.join("swap/other.swp").exists());
+    assert!(!old.join("swap").exists());
+}
+
+#[test]
+fn offers_fixes_from_a_prose_checker() {
+    let (mut editor, _path) = open("notes.md", "Intro\nThis is teh text.\n");
+    let answer = scratch(
+        "languagetool.json",
+        r#"{"matches": [{"message": "Possible spelling mistake", "offset": 14, "length": 3,
+            "replacements": [{"value": "the"}, {"value": "tea"}],
+            "rule": {"id": "MORFOLOGIK_RULE_EN_US", "issueType": "misspelling"}}]}"#,
+    );
+    editor
+        .feed(&format!(
+            ":ProseChecker cat {}<CR>:ProseCheck<CR>",
+            answer.display()
+        ))
+        .unwrap();
+    editor.wait();
+    assert_eq!(editor.status(), Some("Prose: 1 issue(s)"));
+    editor.feed("]s").unwrap();
+    assert_eq!(editor.cursor(), (1, 8));
+    assert!(editor
+        .status()
+        .unwrap()
+        .starts_with("Possible spelling mistake"));
+    editor.feed("z=").unwrap();
+    assert!(editor
+        .screen()
+        .iter()
+        .any(|row| row.contains("Replace with \"tea\"")));
+    editor.feed("j<CR>").unwrap();
+    assert_eq!(editor.lines(), ["Intro", "This is tea text."]);
+    editor.feed("z=").unwrap();
+    assert_eq!(editor.status(), Some("No code actions here"));
+}
+
+#[test]
+fn moves_by_display_lines_when_wrapping() {
+    let path = scratch("wrapped.md", "one two three four five six\nnext\nlast\n");
+    let mut editor = Headless::with_size(path.to_str().unwrap(), 10, 15);
+    editor.feed(":set wrap<CR>").unwrap();
+    assert_eq!(
+        editor.screen()[..4],
+        [
+            "   1 one two th",
+            "     ree four f",
+            "     ive six",
+            "   2 next"
+        ]
+    );
+    editor.feed("llllgj").unwrap();
+    assert_eq!(editor.cursor(), (0, 14));
+    editor.feed("g0").unwrap();
+    assert_eq!(editor.cursor(), (0, 10));
+    editor.feed("g$").unwrap();
+    assert_eq!(editor.cursor(), (0, 19));
+    editor.feed("gjgj").unwrap();
+    assert_eq!(editor.cursor(), (1, 4));
+    editor.feed("kk:set displaylines<CR>jj").unwrap();
+    assert_eq!(editor.cursor(), (0, 24));
+    editor.feed(":set sw=2<CR>>j").unwrap();
+    assert_eq!(
+        editor.lines(),
+        ["  one two three four five six", "  next", "last"]
+    );
+}
+
+#[test]
+fn exports_the_buffer_as_it_looks() {
+    let (mut editor, path) = open("export.rs", "fn main() {\n    let x = 1 < 2;\n}\n");
+    let dir = path.parent().unwrap();
+    let (html, ansi, pdf) = (
+        dir.join("out.html"),
+        dir.join("out.ansi"),
+        dir.join("out.pdf"),
+    );
+    editor.feed("/x<CR>").unwrap();
+    editor
+        .feed(&format!(":TOhtml {}<CR>", html.display()))
+        .unwrap();
+    assert_eq!(
+        editor.status(),
+        Some(format!("Exported 3 lines to {}", html.display()).as_str())
+    );
+    let page = fs::read_to_string(&html).unwrap();
+    assert!(page.contains(
+        "   2 </span>    let <span style=\"background: #cdcd00\">x</span> = 1 &lt; 2;\n"
+    ));
+    editor
+        .feed(&format!(":TOhtml {}<CR>", html.display()))
+        .unwrap();
+    assert!(editor
+        .status()
+        .unwrap()
+        .ends_with("exists (add ! to override)"));
+
+    editor
+        .feed(&format!(":TOansi {}<CR>", ansi.display()))
+        .unwrap();
+    let text = fs::read_to_string(&ansi).unwrap();
+    assert!(
+        text.contains("let \x1b[0;43mx\x1b[0m = 1 < 2;\n"),
+        "{:?}",
+        text
+    );
+
+    editor
+        .feed(&format!(
+            ":set printprg=cp\\ %<CR>:hardcopy {}<CR>",
+            pdf.display()
+        ))
+        .unwrap();
+    assert_eq!(
+        editor.status(),
+        Some(format!("Printing 3 lines to {}", pdf.display()).as_str())
+    );
+    editor.wait();
+    assert_eq!(
+        editor.status(),
+        Some(format!("Printed {}", pdf.display()).as_str())
+    );
+    assert!(fs::read_to_string(&pdf)
+        .unwrap()
+        .starts_with("<!DOCTYPE html>"));
+    editor.feed(":set printprg?<CR>").unwrap();
+    assert_eq!(editor.status(), Some("printprg=cp %"));
+}
+
+#[test]
+fn keeps_named_snapshots_of_a_buffer() {
+    let (mut editor, _path) = open("snapshot.txt", "one\ntwo\nthree\n");
+    editor.feed(":snapshot save before<CR>").unwrap();
+    assert_eq!(editor.status(), Some("Snapshot before saved (3 lines)"));
+    editor.feed("ji!<Esc>").unwrap();
+    editor.feed(":snapshot diff before<CR>").unwrap();
+    let screen = editor.screen();
+    assert!(screen.iter().any(|row| row.contains("@@ -1,3 +1,3 @@")));
+    assert!(screen.iter().any(|row| row.contains("-two")));
+    assert!(screen.iter().any(|row| row.contains("+!two")));
+    editor.feed("<Esc>:snapshot restore before<CR>").unwrap();
+    assert_eq!(editor.lines(), ["one", "two", "three"]);
+    assert_eq!(editor.cursor().0, 1);
+    editor.feed(":snapshot diff before<CR>").unwrap();
+    assert_eq!(editor.status(), Some("No changes since snapshot before"));
+    editor.feed(":snapshot restore after<CR>").unwrap();
+    assert_eq!(editor.status(), Some("No snapshot after"));
+}
+
+#[test]
+fn jumps_between_pinned_files() {
+    let project = scratch("pins.txt", "").with_file_name("pins");
+    fs::create_dir_all(project.join(".git")).unwrap();
+    let (a, b) = (project.join("a.txt"), project.join("b.txt"));
+    fs::write(&a, "one\ntwo\n").unwrap();
+    fs::write(&b, "three\n").unwrap();
+    let state = project.join("state");
+    let set = format!(":set statedir={}<CR>", state.display());
+    let mut editor = Headless::open(a.to_str().unwrap());
+    editor.feed(&format!("{}j:Pin<CR>", set)).unwrap();
+    assert_eq!(editor.status(), Some("Pinned as 1"));
+    editor
+        .feed(&format!(":e {}<CR>:Pin<CR>", b.display()))
+        .unwrap();
+    assert_eq!(editor.status(), Some("Pinned as 2"));
+    editor.feed("<A-1>").unwrap();
+    assert_eq!(
+        (editor.lines()[1].as_str(), editor.cursor()),
+        ("two", (1, 0))
+    );
+    assert_eq!(editor.status(), Some("[1/2] a.txt"));
+    editor.feed("l<A-2><A-1>").unwrap();
+    assert_eq!(editor.cursor(), (1, 1));
+    editor.feed("<A-3>").unwrap();
+    assert_eq!(editor.status(), Some("Nothing pinned as 3"));
+
+    let mut editor = Headless::open(b.to_str().unwrap());
+    editor.feed(&format!("{}:Unpin<CR>:Pins<CR>", set)).unwrap();
+    assert!(editor
+        .screen()
+        .iter()
+        .any(|row| row.contains(" 1  a.txt:2:2")));
+    assert!(!editor.screen().iter().any(|row| row.contains("b.txt:")));
+}
+
+#[test]
+fn plays_recorded_macros_and_triggers() {
+    let (mut editor, _path) = open("macros.txt", "one\ntwo\nthree\nfour\nfive\n");
+    editor.feed("qa").unwrap();
+    assert!(editor.screen().last().unwrap().contains("[recording @a]"));
+    editor.feed("0i-<Esc>jq").unwrap();
+    assert_eq!(editor.status(), Some("Recorded @a"));
+    editor.feed("@a@@").unwrap();
+    assert_eq!(editor.lines()[..4], ["-one", "-two", "-three", "four"]);
+
+    editor
+        .feed(":trigger dash @a<CR>:nmap <lt>leader>d :trigger dash<lt>CR><CR>")
+        .unwrap();
+    editor.feed("<leader>d").unwrap();
+    assert_eq!(editor.lines()[3], "-four");
+    editor.feed(":trigger<CR>").unwrap();
+    assert!(editor
+        .screen()
+        .iter()
+        .any(|row| row.contains("dash  0i-<Esc>j")));
+    editor
+        .feed("<Esc>:trigger! dash<CR>:trigger dash<CR>")
+        .unwrap();
+    assert_eq!(editor.status(), Some("No trigger dash"));
+
+    editor.feed("qbj@bq@b").unwrap();
+    assert_eq!(editor.status(), Some("Macro stopped after 100000 keys"));
+}
+
+#[test]
+fn puts_from_the_yank_history() {
+    let (mut editor, _path) = open("yanks.txt", "alpha beta\ngamma\ndelta\n");
+    editor.feed("vlly").unwrap();
+    assert_eq!(editor.cursor(), (0, 0));
+    editor.feed("j$p").unwrap();
+    assert_eq!(editor.lines()[1], "gammaalp");
+    editor.feed("Vjyp").unwrap();
+    assert_eq!(
+        editor.lines(),
+        ["alpha beta", "gammaalp", "gammaalp", "delta", "delta"]
+    );
+
+    editor.feed(":Yanks<CR>").unwrap();
+    let screen = editor.screen();
+    assert!(screen
+        .iter()
+        .any(|row| row.contains("2 lines    gammaalp↵delta")));
+    assert!(screen.iter().any(|row| row.contains("chars      alp")));
+    editor.feed("j<CR>").unwrap();
+    assert_eq!(editor.status(), Some("Yanked again"));
+    editor.feed("P").unwrap();
+    assert_eq!(editor.lines()[2], "alpgammaalp");
+
+    editor.feed("0<C-v>jlyP").unwrap();
+    assert_eq!(editor.lines()[2..4], ["alalpgammaalp", "dedelta"]);
+}
+
+#[test]
+fn keeps_a_byte_order_mark() {
+    let path = scratch("bom.txt", "\u{feff}hello\n");
+    let mut editor = Headless::open(path.to_str().unwrap());
+    assert_eq!(editor.lines(), ["hello"]);
+    assert!(editor.screen().last().unwrap().contains("utf-8 [BOM] unix"));
+    editor.feed("ix<Esc>:w<CR>").unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"\xef\xbb\xbfxhello\n");
+    editor.feed(":set nobomb<CR>:set bomb?<CR>").unwrap();
+    assert_eq!(editor.status(), Some("nobomb"));
+    editor.feed(":w<CR>").unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"xhello\n");
+    editor.feed(":set fenc=latin1<CR>:set bomb<CR>").unwrap();
+    assert_eq!(editor.status(), Some("latin1 files can't change 'bomb'"));
+}
+
+#[test]
+fn moves_and_edits_by_grapheme() {
+    let (mut editor, _) = open("graphemes.txt", "cafe\u{301} \u{1f1eb}\u{1f1f7}!\n");
+    editor.feed("$").unwrap();
+    assert_eq!(editor.cursor(), (0, 7));
+    editor.feed("0llll").unwrap();
+    assert_eq!(editor.cursor(), (0, 4));
+    editor.feed("i<BS><Esc>").unwrap();
+    assert_eq!(editor.lines(), ["caf \u{1f1eb}\u{1f1f7}!"]);
+    editor.feed("ie\u{301}<Esc>").unwrap();
+    assert_eq!(editor.lines(), ["cafe\u{301} \u{1f1eb}\u{1f1f7}!"]);
+    assert_eq!(editor.cursor(), (0, 4));
+    editor.feed("li<Del><Esc>").unwrap();
+    assert_eq!(editor.lines(), ["cafe\u{301} !"]);
+    editor.feed("0llli<CR><Esc>").unwrap();
+    assert_eq!(editor.lines(), ["caf", "e\u{301} !"]);
+    assert_eq!(editor.cursor(), (1, 0));
+}
+
+#[test]
+fn restores_the_session_of_a_crashed_editor() {
+    let (mut crashed, path) = open("crashed.txt", "saved\n");
+    let state = path.with_file_name("crash-state");
+    let set = format!(":set statedir={}<CR>", state.display());
+    crashed.feed(&set).unwrap();
+    crashed.editor.start_autosession();
+    crashed.feed("ounsaved<Esc>").unwrap();
+    crashed.editor.save_swap();
+    // The editor dies without quitting, and no process has its pid now.
+    let sessions = state.join("sessions");
+    for ext in ["lock", "rvex"] {
+        let from = sessions.join(format!("{}.{}", std::process::id(), ext));
+        fs::rename(from, sessions.join(format!("999999999.{}", ext))).unwrap();
+    }
+
+    let mut editor = Headless::open("");
+    editor.feed(&set).unwrap();
+    editor.editor.check_crash();
+    assert_eq!(editor.mode(), Mode::Prompt);
+    assert!(editor
+        .screen()
+        .last()
+        .unwrap()
+        .starts_with("RVex exited abnormally (pid 999999999"));
+    editor.feed("p").unwrap();
+    let screen = editor.screen();
+    assert!(screen
+        .iter()
+        .any(|row| row.ends_with("crashed.txt (unsaved)")));
+    assert!(screen.iter().any(|row| row == "+unsaved"));
+    assert_eq!(editor.mode(), Mode::Prompt);
+    editor.feed("r").unwrap();
+    assert_eq!(editor.lines(), ["saved", "unsaved"]);
+    assert_eq!(editor.cursor(), (1, 7));
+    assert_eq!(
+        editor.status(),
+        Some("Restored session and unsaved changes; :w to keep them")
+    );
+    assert!(!sessions.join("999999999.lock").exists());
+    editor.feed(":q!<CR>").unwrap();
+}
+
+#[test]
+fn keeps_a_crashed_session_until_every_file_is_recovered() {
+    let (mut crashed, path) = open("crashed-main.txt", "main\n");
+    let other = path.with_file_name("crashed-other.txt");
+    fs::write(&other, "other\n").unwrap();
+    let state = path.with_file_name("crash-state-other");
+    let set = format!(":set statedir={}<CR>", state.display());
+    crashed.feed(&set).unwrap();
+    crashed
+        .feed(&format!(":e {}<CR>", other.display()))
+        .unwrap();
+    crashed.feed(&format!(":e {}<CR>", path.display())).unwrap();
+    crashed.editor.start_autosession();
+    // The other file's unsaved changes were left in a swap file by the
+    // editor that died.
+    let other_name = other.to_string_lossy().into_owned();
+    let swaps = state.join("swap");
+    swap::write(
+        &swaps,
+        &other_name,
+        &Buffer::from_lines(vec!["changed".to_string()]),
+    )
+    .unwrap();
+    let swap_file = swap::swap_path(&swaps, &other_name);
+    let text = fs::read_to_string(&swap_file).unwrap();
+    let text = text.replace(&format!("pid={}", std::process::id()), "pid=999999998");
+    fs::write(&swap_file, text).unwrap();
+    let sessions = state.join("sessions");
+    for ext in ["lock", "rvex"] {
+        let from = sessions.join(format!("{}.{}", std::process::id(), ext));
+        fs::rename(from, sessions.join(format!("999999998.{}", ext))).unwrap();
+    }
+
+    let mut editor = Headless::with_size("", 10, 160);
+    editor.feed(&set).unwrap();
+    editor.editor.check_crash();
+    let prompt = editor.screen().last().unwrap().clone();
+    assert!(
+        prompt.contains("crashed-other.txt is not restored until opened"),
+        "{}",
+        prompt
+    );
+    editor.feed("r").unwrap();
+    assert_eq!(editor.lines(), ["main"]);
+    assert!(editor.status().unwrap().contains("not restored yet:"));
+    assert!(sessions.join("999999998.lock").exists());
+    editor.feed(&format!(":e {}<CR>", other.display())).unwrap();
+    assert_eq!(editor.mode(), Mode::Prompt);
+    editor.feed("e:q!<CR>").unwrap();
+
+    swap::remove(&swaps, &other_name);
+    let mut editor = Headless::open("");
+    editor.feed(&set).unwrap();
+    editor.editor.check_crash();
+    editor.feed("r").unwrap();
+    assert!(!sessions.join("999999998.lock").exists());
+    editor.feed(":q!<CR>").unwrap();
+}